@@ -0,0 +1,569 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::utils::utils::{associated_apps, flag_values};
+
+const PROC_ROOT: &str = "/proc";
+const ENFORCE_INTERVAL: Duration = Duration::from_secs(2);
+
+// Actions
+const STOP: &str = "stop";
+const KILL: &str = "kill";
+const REFUSE: &str = "refuse";
+
+/// Describes how a desktop application is recognised among the running processes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppMatcher {
+    /// Matches the process name or executable file name, case insensitive
+    Name(String),
+    /// Matches the full executable path
+    Path(PathBuf),
+    /// Matches a substring of the command line
+    Cmdline(String),
+}
+
+/// What happens to the matching processes during a session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AppAction {
+    /// Suspends them with `SIGSTOP` and resumes them with `SIGCONT` at the end
+    Stop,
+    /// Terminates them when the session starts
+    Kill,
+    /// Terminates them whenever they are started during the session
+    Refuse,
+}
+
+impl AppAction {
+    /// Parses the `--apps` flag value, `None` meaning that apps are not blocked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidInput` error if the action is not recognized
+    pub fn parse(action: &str) -> Result<Option<AppAction>, std::io::Error> {
+        match action.to_lowercase().as_str() {
+            STOP => Ok(Some(AppAction::Stop)),
+            KILL => Ok(Some(AppAction::Kill)),
+            REFUSE => Ok(Some(AppAction::Refuse)),
+            "off" => Ok(None),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "apps action {} not defined, use stop, kill, refuse or off",
+                    action
+                ),
+            )),
+        }
+    }
+}
+
+/// A running process as seen in `/proc`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: i32,
+    /// The parent pid, 0 when unknown
+    pub ppid: i32,
+    pub name: String,
+    pub exe: Option<PathBuf>,
+    pub cmdline: String,
+}
+
+impl ProcessInfo {
+    fn matches(&self, matcher: &AppMatcher) -> bool {
+        match matcher {
+            AppMatcher::Name(name) => {
+                let exe_name = self
+                    .exe
+                    .as_ref()
+                    .and_then(|exe| exe.file_name())
+                    .map(|exe| exe.to_string_lossy().to_lowercase());
+                self.name.eq_ignore_ascii_case(name)
+                    || exe_name.as_deref() == Some(name.to_lowercase().as_str())
+            }
+            AppMatcher::Path(path) => self.exe.as_deref() == Some(path.as_path()),
+            AppMatcher::Cmdline(pattern) => self.cmdline.contains(pattern.as_str()),
+        }
+    }
+}
+
+/// Lists the processes found under a `/proc` like directory.
+///
+/// Entries that disappear while scanning or can't be read are skipped.
+///
+/// # Arguments
+///
+/// * `proc_root` - The proc filesystem root, `/proc` outside of tests
+///
+/// # Returns
+///
+/// A vector with every readable process
+pub fn scan_processes(proc_root: &Path) -> Vec<ProcessInfo> {
+    let Ok(entries) = std::fs::read_dir(proc_root) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid: i32 = entry.file_name().to_str()?.parse().ok()?;
+            let dir = entry.path();
+            let name = std::fs::read_to_string(dir.join("comm")).ok()?;
+            let cmdline = std::fs::read(dir.join("cmdline")).unwrap_or_default();
+            let stat = std::fs::read_to_string(dir.join("stat")).unwrap_or_default();
+            Some(ProcessInfo {
+                pid,
+                ppid: parse_ppid(&stat).unwrap_or(0),
+                name: name.trim_end().to_string(),
+                exe: std::fs::read_link(dir.join("exe")).ok(),
+                cmdline: String::from_utf8_lossy(&cmdline)
+                    .replace('\0', " ")
+                    .trim_end()
+                    .to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Reads the parent pid from the content of `/proc/<pid>/stat`.
+///
+/// The process name may contain spaces and parentheses, the fields are read after
+/// its last `)`.
+fn parse_ppid(stat: &str) -> Option<i32> {
+    let fields = stat.get(stat.rfind(')')? + 1..)?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Returns our own pid and the pids of our ancestors, e.g. the shell and `sudo`.
+fn own_lineage(processes: &[ProcessInfo]) -> Vec<i32> {
+    let mut lineage = vec![std::process::id() as i32];
+    while let Some(parent) = processes
+        .iter()
+        .find(|process| Some(&process.pid) == lineage.last())
+        .map(|process| process.ppid)
+        .filter(|ppid| *ppid > 1 && !lineage.contains(ppid))
+    {
+        lineage.push(parent);
+    }
+    lineage
+}
+
+/// Returns the pids of the processes matching any of the matchers.
+///
+/// Our own process, its ancestors and `init` are never returned, a terminal
+/// matching the blocked apps would otherwise take the session down with it.
+pub fn find_matching(processes: &[ProcessInfo], matchers: &[AppMatcher]) -> Vec<i32> {
+    let lineage = own_lineage(processes);
+    processes
+        .iter()
+        .filter(|process| process.pid > 1 && !lineage.contains(&process.pid))
+        .filter(|process| matchers.iter().any(|matcher| process.matches(matcher)))
+        .map(|process| process.pid)
+        .collect()
+}
+
+/// Builds the matchers for the apps of the blocked websites and the `--app*` flags.
+///
+/// # Arguments
+///
+/// * `sites` - The websites blocked in the session
+/// * `flags` - The command line flags
+///
+/// # Returns
+///
+/// A vector with a matcher for each application to block, or an `InvalidInput`
+/// error if a flag is empty, which would match every process
+pub fn app_matchers(sites: &[&str], flags: &[String]) -> Result<Vec<AppMatcher>, std::io::Error> {
+    let mut matchers: Vec<AppMatcher> = Vec::new();
+    for site in sites {
+        for app in associated_apps(site) {
+            matchers.push(AppMatcher::Name(app.to_string()));
+        }
+    }
+    let values = |flag: &str| -> Result<Vec<&str>, std::io::Error> {
+        let values = flag_values(flags, flag);
+        if values.iter().any(|value| value.trim().is_empty()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{} expects a value, an empty one matches every process",
+                    flag
+                ),
+            ));
+        }
+        Ok(values)
+    };
+    for name in values("--app")? {
+        matchers.push(AppMatcher::Name(name.to_string()));
+    }
+    for path in values("--app-path")? {
+        matchers.push(AppMatcher::Path(PathBuf::from(path)));
+    }
+    for pattern in values("--app-cmdline")? {
+        matchers.push(AppMatcher::Cmdline(pattern.to_string()));
+    }
+    Ok(matchers)
+}
+
+/// Blocks desktop applications for the duration of a session.
+pub struct AppBlocker {
+    proc_root: PathBuf,
+    matchers: Vec<AppMatcher>,
+    action: AppAction,
+    stopped: Vec<i32>,
+    enforced_once: bool,
+    signal: fn(i32, libc::c_int) -> Result<(), std::io::Error>,
+}
+
+impl AppBlocker {
+    pub fn new(matchers: Vec<AppMatcher>, action: AppAction) -> AppBlocker {
+        AppBlocker::with_proc_root(PathBuf::from(PROC_ROOT), matchers, action)
+    }
+
+    pub fn with_proc_root(
+        proc_root: PathBuf,
+        matchers: Vec<AppMatcher>,
+        action: AppAction,
+    ) -> AppBlocker {
+        AppBlocker {
+            proc_root,
+            matchers,
+            action,
+            stopped: Vec::new(),
+            enforced_once: false,
+            signal: send_signal,
+        }
+    }
+
+    /// Signals the processes with `signal` instead of `kill`.
+    #[cfg(test)]
+    fn with_signal(
+        mut self,
+        signal: fn(i32, libc::c_int) -> Result<(), std::io::Error>,
+    ) -> AppBlocker {
+        self.signal = signal;
+        self
+    }
+
+    /// Applies the action to the matching processes currently running.
+    ///
+    /// `Kill` only acts the first time it is called, while `Stop` and `Refuse`
+    /// also catch applications started later in the session.
+    ///
+    /// # Returns
+    ///
+    /// The number of processes that were signalled, or the error of the first failed
+    /// signal. Processes which exited since the scan are skipped, and so are the
+    /// ones we may not signal, e.g. of other users.
+    pub fn enforce(&mut self) -> Result<usize, std::io::Error> {
+        if self.matchers.is_empty() || (self.action == AppAction::Kill && self.enforced_once) {
+            return Ok(0);
+        }
+        self.enforced_once = true;
+        let pids = find_matching(&scan_processes(&self.proc_root), &self.matchers);
+        let mut signalled = 0;
+        for pid in pids {
+            let result = match self.action {
+                AppAction::Stop if self.stopped.contains(&pid) => continue,
+                AppAction::Stop => (self.signal)(pid, libc::SIGSTOP),
+                AppAction::Kill | AppAction::Refuse => (self.signal)(pid, libc::SIGKILL),
+            };
+            match result {
+                // The process exited since the scan, or belongs to another user
+                Err(e) if matches!(e.raw_os_error(), Some(libc::ESRCH | libc::EPERM)) => continue,
+                result => result?,
+            }
+            if self.action == AppAction::Stop {
+                self.stopped.push(pid);
+            }
+            signalled += 1;
+        }
+        Ok(signalled)
+    }
+
+    /// Resumes the processes suspended by `enforce`.
+    pub fn release(&mut self) -> Result<(), std::io::Error> {
+        for pid in self.stopped.drain(..) {
+            match send_signal(pid, libc::SIGCONT) {
+                // The process may have exited during the session
+                Err(e) if e.raw_os_error() == Some(libc::ESRCH) => continue,
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `enforce` periodically on a background thread until `done` is set.
+    ///
+    /// The blocker is handed back when the thread is joined so that it can be released.
+    pub fn spawn(mut self, done: Arc<AtomicBool>) -> JoinHandle<AppBlocker> {
+        std::thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                if let Err(e) = self.enforce() {
+                    println!("Failed to block an application: {}", e);
+                }
+                std::thread::sleep(ENFORCE_INTERVAL);
+            }
+            self
+        })
+    }
+}
+
+fn send_signal(pid: i32, signal: libc::c_int) -> Result<(), std::io::Error> {
+    // SAFETY: kill only takes plain integers and reports failures through errno
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process::{Child, Command};
+    use tempfile::TempDir;
+
+    // Helper function adding a fake process to a fake proc directory
+    fn add_fake_process(root: &Path, pid: i32, name: &str, cmdline: &[&str]) {
+        let dir = root.join(pid.to_string());
+        fs::create_dir_all(&dir).expect("Failed to create process dir");
+        fs::write(dir.join("comm"), format!("{}\n", name)).unwrap();
+        fs::write(dir.join("cmdline"), cmdline.join("\0") + "\0").unwrap();
+    }
+
+    // Helper function giving a parent to a fake process
+    fn add_fake_parent(root: &Path, pid: i32, ppid: i32) {
+        let stat = format!("{} (some (name)) S {} {} 0 0", pid, ppid, ppid);
+        fs::write(root.join(pid.to_string()).join("stat"), stat).unwrap();
+    }
+
+    // Helper function spawning a long running process with a recognisable command line
+    fn spawn_sleeper(marker: &str) -> Child {
+        Command::new("sleep")
+            .arg(marker)
+            .spawn()
+            .expect("Failed to spawn sleep")
+    }
+
+    fn process_state(pid: u32) -> char {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+        let after_name = &stat[stat.rfind(')').unwrap() + 2..];
+        after_name.chars().next().unwrap()
+    }
+
+    #[test]
+    fn test_scan_processes_reads_fake_proc() {
+        let root = TempDir::new().unwrap();
+        add_fake_process(
+            root.path(),
+            42,
+            "Discord",
+            &["/opt/discord/Discord", "--flag"],
+        );
+        fs::create_dir_all(root.path().join("self")).unwrap();
+
+        let processes = scan_processes(root.path());
+
+        assert_eq!(processes.len(), 1, "Only numeric entries are processes");
+        assert_eq!(processes[0].pid, 42);
+        assert_eq!(processes[0].name, "Discord");
+        assert_eq!(processes[0].cmdline, "/opt/discord/Discord --flag");
+    }
+
+    #[test]
+    fn test_find_matching_by_name_and_cmdline() {
+        let root = TempDir::new().unwrap();
+        add_fake_process(root.path(), 42, "Discord", &["/opt/discord/Discord"]);
+        add_fake_process(root.path(), 43, "electron", &["electron", "whatsapp-web"]);
+        add_fake_process(root.path(), 44, "bash", &["bash"]);
+
+        let processes = scan_processes(root.path());
+        let mut pids = find_matching(
+            &processes,
+            &[
+                AppMatcher::Name("discord".to_string()),
+                AppMatcher::Cmdline("whatsapp".to_string()),
+            ],
+        );
+        pids.sort();
+
+        assert_eq!(pids, vec![42, 43]);
+    }
+
+    #[test]
+    fn test_find_matching_skips_own_ancestors() {
+        let root = TempDir::new().unwrap();
+        let own_pid = std::process::id() as i32;
+        add_fake_process(root.path(), own_pid, "focus", &["focus"]);
+        add_fake_parent(root.path(), own_pid, 42);
+        add_fake_process(root.path(), 42, "Discord", &["discord"]);
+        add_fake_parent(root.path(), 42, 1);
+        add_fake_process(root.path(), 43, "Discord", &["discord"]);
+        add_fake_parent(root.path(), 43, 1);
+
+        let processes = scan_processes(root.path());
+        let pids = find_matching(&processes, &[AppMatcher::Name("discord".to_string())]);
+
+        assert_eq!(
+            processes
+                .iter()
+                .find(|process| process.pid == 42)
+                .unwrap()
+                .ppid,
+            1
+        );
+        assert_eq!(pids, vec![43], "Our parent should never be signalled");
+    }
+
+    #[test]
+    fn test_enforce_skips_exited_processes() {
+        let root = TempDir::new().unwrap();
+        // Above the largest pid of Linux, no such process can exist
+        add_fake_process(root.path(), 1 << 30, "ghost", &["ghost"]);
+        let mut blocker = AppBlocker::with_proc_root(
+            root.path().to_path_buf(),
+            vec![AppMatcher::Name("ghost".to_string())],
+            AppAction::Stop,
+        );
+
+        assert_eq!(blocker.enforce().unwrap(), 0);
+        assert!(blocker.stopped.is_empty());
+    }
+
+    #[test]
+    fn test_enforce_skips_processes_of_other_users() {
+        let root = TempDir::new().unwrap();
+        for pid in [1 << 28, (1 << 28) + 1, (1 << 28) + 2] {
+            add_fake_process(root.path(), pid, "discord", &["discord"]);
+        }
+        let mut blocker = AppBlocker::with_proc_root(
+            root.path().to_path_buf(),
+            vec![AppMatcher::Name("discord".to_string())],
+            AppAction::Kill,
+        )
+        .with_signal(|pid, _| match pid {
+            pid if pid == (1 << 28) + 1 => Err(std::io::Error::from_raw_os_error(libc::EPERM)),
+            _ => Ok(()),
+        });
+
+        assert_eq!(
+            blocker.enforce().unwrap(),
+            2,
+            "The processes after the refused one are signalled"
+        );
+    }
+
+    #[test]
+    fn test_find_matching_by_path() {
+        let process = ProcessInfo {
+            pid: 42,
+            ppid: 1,
+            name: "discord".to_string(),
+            exe: Some(PathBuf::from("/usr/bin/discord")),
+            cmdline: String::new(),
+        };
+
+        let matching = find_matching(
+            std::slice::from_ref(&process),
+            &[AppMatcher::Path(PathBuf::from("/usr/bin/discord"))],
+        );
+        let not_matching = find_matching(
+            &[process],
+            &[AppMatcher::Path(PathBuf::from("/opt/discord"))],
+        );
+
+        assert_eq!(matching, vec![42]);
+        assert!(not_matching.is_empty());
+    }
+
+    #[test]
+    fn test_app_matchers_from_sites_and_flags() {
+        let flags = vec![
+            "--app=slack".to_string(),
+            "--app-path=/usr/bin/steam".to_string(),
+            "--app-cmdline=telegram".to_string(),
+        ];
+
+        let matchers = app_matchers(&["discord.com", "www.youtube.com"], &flags).unwrap();
+
+        assert_eq!(
+            matchers,
+            vec![
+                AppMatcher::Name("discord".to_string()),
+                AppMatcher::Name("slack".to_string()),
+                AppMatcher::Path(PathBuf::from("/usr/bin/steam")),
+                AppMatcher::Cmdline("telegram".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_app_matchers_reject_empty_flags() {
+        for flag in ["--app=", "--app-path=", "--app-cmdline=", "--app-cmdline= "] {
+            let error = app_matchers(&["discord.com"], &[flag.to_string()]).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput, "{}", flag);
+        }
+    }
+
+    #[test]
+    fn test_app_action_parse() {
+        assert_eq!(AppAction::parse("STOP").unwrap(), Some(AppAction::Stop));
+        assert_eq!(AppAction::parse("kill").unwrap(), Some(AppAction::Kill));
+        assert_eq!(AppAction::parse("refuse").unwrap(), Some(AppAction::Refuse));
+        assert_eq!(AppAction::parse("off").unwrap(), None);
+    }
+
+    #[test]
+    fn test_app_action_parse_invalid() {
+        let error = AppAction::parse("pause").unwrap_err();
+
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("pause"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_stop_and_release_process() {
+        let mut child = spawn_sleeper("987.654321");
+        let mut blocker = AppBlocker::new(
+            vec![AppMatcher::Cmdline("sleep 987.654321".to_string())],
+            AppAction::Stop,
+        );
+        std::thread::sleep(Duration::from_millis(100));
+
+        let signalled = blocker.enforce().expect("Failed to enforce");
+        std::thread::sleep(Duration::from_millis(100));
+        let stopped_state = process_state(child.id());
+        // A second pass must not signal the same process again
+        let signalled_again = blocker.enforce().expect("Failed to enforce");
+        blocker.release().expect("Failed to release");
+        std::thread::sleep(Duration::from_millis(100));
+        let resumed_state = process_state(child.id());
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        assert_eq!(signalled, 1, "The sleeper should be suspended");
+        assert_eq!(stopped_state, 'T', "The process should be stopped");
+        assert_eq!(signalled_again, 0);
+        assert_ne!(resumed_state, 'T', "The process should be resumed");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_refuse_kills_process() {
+        let mut child = spawn_sleeper("987.123456");
+        let mut blocker = AppBlocker::new(
+            vec![AppMatcher::Cmdline("sleep 987.123456".to_string())],
+            AppAction::Refuse,
+        );
+        std::thread::sleep(Duration::from_millis(100));
+
+        let signalled = blocker.enforce().expect("Failed to enforce");
+        let status = child.wait().expect("Failed to wait for child");
+
+        assert_eq!(signalled, 1, "The sleeper should be killed");
+        assert!(!status.success(), "The process should have been killed");
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// Firefox `network.trr.mode` values where DNS-over-HTTPS is used
+const FIREFOX_TRR_FIRST: &str = "2";
+const FIREFOX_TRR_ONLY: &str = "3";
+const FIREFOX_TRR_PREF: &str = "\"network.trr.mode\"";
+
+// Chromium `dns_over_https.mode` values where DNS-over-HTTPS is used
+const CHROMIUM_DOH_SECURE: &str = "secure";
+const CHROMIUM_DOH_AUTOMATIC: &str = "automatic";
+
+/// Profile locations relative to a home directory, for each known browser.
+const FIREFOX_PROFILE_ROOTS: [&str; 2] = [
+    ".mozilla/firefox",
+    "Library/Application Support/Firefox/Profiles",
+];
+const CHROMIUM_LOCAL_STATES: [(&str, &str); 3] = [
+    ("Chromium", ".config/chromium/Local State"),
+    ("Google Chrome", ".config/google-chrome/Local State"),
+    (
+        "Google Chrome",
+        "Library/Application Support/Google/Chrome/Local State",
+    ),
+];
+
+/// A browser profile configured to resolve names through DNS-over-HTTPS,
+/// hence ignoring `/etc/hosts`.
+#[derive(Debug, PartialEq)]
+pub struct DohWarning {
+    pub browser: &'static str,
+    pub profile: PathBuf,
+    pub mode: String,
+}
+
+impl fmt::Display for DohWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} uses DNS-over-HTTPS (mode {}) in {} and bypasses /etc/hosts",
+            self.browser,
+            self.mode,
+            self.profile.display()
+        )
+    }
+}
+
+/// Returns the home directories whose browser profiles should be inspected.
+///
+/// Every directory under `/home` and `/Users` is included, along with `/root`.
+pub fn system_homes() -> Vec<PathBuf> {
+    let mut homes: Vec<PathBuf> = vec![PathBuf::from("/root")];
+    for parent in ["/home", "/Users"] {
+        if let Ok(entries) = std::fs::read_dir(parent) {
+            homes.extend(entries.flatten().map(|entry| entry.path()));
+        }
+    }
+    homes
+}
+
+/// Looks for browser profiles with DNS-over-HTTPS enabled.
+///
+/// Firefox profiles are detected through the `network.trr.mode` preference of their
+/// `prefs.js`, Chromium and Chrome through the `dns_over_https.mode` entry of
+/// their `Local State` file.
+///
+/// # Arguments
+///
+/// * `homes` - The home directories to inspect
+///
+/// # Returns
+///
+/// A vector with a warning for each profile bypassing the hosts file
+pub fn detect_doh(homes: &[PathBuf]) -> Vec<DohWarning> {
+    let mut warnings: Vec<DohWarning> = Vec::new();
+    for home in homes {
+        for root in FIREFOX_PROFILE_ROOTS {
+            let Ok(profiles) = std::fs::read_dir(home.join(root)) else {
+                continue;
+            };
+            for profile in profiles.flatten() {
+                let prefs = profile.path().join("prefs.js");
+                if let Some(mode) = firefox_trr_mode(&prefs) {
+                    if mode == FIREFOX_TRR_FIRST || mode == FIREFOX_TRR_ONLY {
+                        warnings.push(DohWarning {
+                            browser: "Firefox",
+                            profile: profile.path(),
+                            mode,
+                        });
+                    }
+                }
+            }
+        }
+        for (browser, local_state) in CHROMIUM_LOCAL_STATES {
+            let path = home.join(local_state);
+            if let Some(mode) = chromium_doh_mode(&path) {
+                if mode == CHROMIUM_DOH_SECURE || mode == CHROMIUM_DOH_AUTOMATIC {
+                    warnings.push(DohWarning {
+                        browser,
+                        profile: path,
+                        mode,
+                    });
+                }
+            }
+        }
+    }
+    warnings
+}
+
+/// Prints the DNS-over-HTTPS warnings for the status view.
+///
+/// # Arguments
+///
+/// * `homes` - The home directories to inspect
+pub fn print_doh_warnings(homes: &[PathBuf]) {
+    let warnings = detect_doh(homes);
+    if warnings.is_empty() {
+        println!("No known browser is configured to bypass /etc/hosts");
+    }
+    for warning in warnings {
+        println!("Warning: {}", warning);
+    }
+}
+
+/// Reads the `network.trr.mode` value out of a Firefox `prefs.js` file.
+fn firefox_trr_mode(prefs: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(prefs).ok()?;
+    content
+        .lines()
+        .filter(|line| {
+            line.trim_start().starts_with("user_pref(") && line.contains(FIREFOX_TRR_PREF)
+        })
+        .filter_map(|line| {
+            let value = line.split(',').nth(1)?;
+            Some(
+                value
+                    .trim()
+                    .trim_end_matches(';')
+                    .trim_end_matches(')')
+                    .trim()
+                    .to_string(),
+            )
+        })
+        .next_back()
+}
+
+/// Reads the `dns_over_https.mode` value out of a Chromium `Local State` file.
+fn chromium_doh_mode(local_state: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(local_state).ok()?;
+    let state: serde_json::Value = serde_json::from_str(&content).ok()?;
+    state["dns_over_https"]["mode"].as_str().map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    // Helper function writing a file inside a fake home directory
+    fn write_home_file(home: &Path, relative: &str, content: &str) {
+        let path = home.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).expect("Failed to create dirs");
+        fs::write(path, content).expect("Failed to write file");
+    }
+
+    #[test]
+    fn test_detect_doh_firefox_trr_only() {
+        let home = TempDir::new().unwrap();
+        write_home_file(
+            home.path(),
+            ".mozilla/firefox/abc.default/prefs.js",
+            "user_pref(\"browser.startup.page\", 3);\nuser_pref(\"network.trr.mode\", 3);\n",
+        );
+
+        let warnings = detect_doh(&[home.path().to_path_buf()]);
+
+        assert_eq!(warnings.len(), 1, "Should detect one Firefox profile");
+        assert_eq!(warnings[0].browser, "Firefox");
+        assert_eq!(warnings[0].mode, "3");
+    }
+
+    #[test]
+    fn test_detect_doh_firefox_trr_off() {
+        let home = TempDir::new().unwrap();
+        write_home_file(
+            home.path(),
+            ".mozilla/firefox/abc.default/prefs.js",
+            "user_pref(\"network.trr.mode\", 5);\n",
+        );
+
+        let warnings = detect_doh(&[home.path().to_path_buf()]);
+
+        assert!(warnings.is_empty(), "Mode 5 disables DNS-over-HTTPS");
+    }
+
+    #[test]
+    fn test_detect_doh_chrome_secure() {
+        let home = TempDir::new().unwrap();
+        write_home_file(
+            home.path(),
+            ".config/google-chrome/Local State",
+            "{\"dns_over_https\": {\"mode\": \"secure\", \"templates\": \"\"}}",
+        );
+
+        let warnings = detect_doh(&[home.path().to_path_buf()]);
+
+        assert_eq!(warnings.len(), 1, "Should detect Chrome");
+        assert_eq!(warnings[0].browser, "Google Chrome");
+        assert_eq!(warnings[0].mode, "secure");
+    }
+
+    #[test]
+    fn test_detect_doh_chromium_off() {
+        let home = TempDir::new().unwrap();
+        write_home_file(
+            home.path(),
+            ".config/chromium/Local State",
+            "{\"dns_over_https\": {\"mode\": \"off\"}}",
+        );
+
+        let warnings = detect_doh(&[home.path().to_path_buf()]);
+
+        assert!(warnings.is_empty(), "DoH mode off should not warn");
+    }
+
+    #[test]
+    fn test_detect_doh_without_browsers() {
+        let home = TempDir::new().unwrap();
+
+        let warnings = detect_doh(&[home.path().to_path_buf()]);
+
+        assert!(warnings.is_empty(), "Empty home should not warn");
+    }
+
+    #[test]
+    fn test_doh_warning_display() {
+        let warning = DohWarning {
+            browser: "Firefox",
+            profile: PathBuf::from("/home/me/.mozilla/firefox/x"),
+            mode: "2".to_string(),
+        };
+
+        assert_eq!(
+            warning.to_string(),
+            "Firefox uses DNS-over-HTTPS (mode 2) in /home/me/.mozilla/firefox/x and bypasses /etc/hosts"
+        );
+    }
+}
@@ -0,0 +1,2 @@
+pub mod doh;
+pub mod policy;
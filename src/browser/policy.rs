@@ -0,0 +1,582 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Map, Value};
+
+use crate::proxy::rules::UrlRule;
+
+// Managed policy locations, only used when the browser is installed
+const CHROMIUM_ROOT: &str = "/etc/chromium";
+const CHROMIUM_POLICY_DIR: &str = "/etc/chromium/policies/managed";
+const CHROME_ROOT: &str = "/etc/opt/chrome";
+const CHROME_POLICY_DIR: &str = "/etc/opt/chrome/policies/managed";
+const FIREFOX_ROOT: &str = "/etc/firefox";
+const FIREFOX_POLICY_DIR: &str = "/etc/firefox/policies";
+
+const CHROMIUM_POLICY_FILE: &str = "rust_can_make_you_focus.json";
+const FIREFOX_POLICY_FILE: &str = "policies.json";
+const FIREFOX_BACKUP_FILE: &str = "policies.json.focus-backup";
+// Written instead of the backup when there was no `policies.json` to save
+const FIREFOX_ABSENT_FILE: &str = "policies.json.focus-absent";
+
+/// Directories where the managed browser policies are written.
+///
+/// Chromium based browsers read every `*.json` file of their managed directory,
+/// so we own a dedicated file there. Firefox only reads a single `policies.json`,
+/// which is merged and backed up instead.
+pub struct PolicyDirs {
+    pub chromium: Vec<PathBuf>,
+    pub firefox: Vec<PathBuf>,
+}
+
+impl PolicyDirs {
+    /// Returns the system policy directories of the browsers installed on this machine.
+    ///
+    /// A browser is considered installed when its configuration root exists
+    /// (e.g. `/etc/chromium`), so no directory is created for missing browsers.
+    pub fn system() -> PolicyDirs {
+        let installed = |root: &str, dir: &str| -> Option<PathBuf> {
+            if Path::new(root).is_dir() {
+                Some(PathBuf::from(dir))
+            } else {
+                None
+            }
+        };
+        PolicyDirs {
+            chromium: [
+                installed(CHROMIUM_ROOT, CHROMIUM_POLICY_DIR),
+                installed(CHROME_ROOT, CHROME_POLICY_DIR),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            firefox: installed(FIREFOX_ROOT, FIREFOX_POLICY_DIR)
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Returns whether the policies written to these directories reach `browser`,
+    /// named as in `doh::DohWarning`, and so turn its DNS-over-HTTPS off.
+    pub fn manages(&self, browser: &str) -> bool {
+        match browser {
+            "Firefox" => !self.firefox.is_empty(),
+            "Chromium" => self.chromium.contains(&PathBuf::from(CHROMIUM_POLICY_DIR)),
+            "Google Chrome" => self.chromium.contains(&PathBuf::from(CHROME_POLICY_DIR)),
+            _ => false,
+        }
+    }
+}
+
+/// What the managed browser policies enforce during a session.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BrowserPolicy {
+    /// The websites to block, as `host/path` patterns
+    pub blocked: Vec<String>,
+    /// The `host/path` patterns left reachable inside the blocked websites
+    pub exceptions: Vec<String>,
+    /// In allowlist mode, the only `host/path` patterns that are not blocked
+    pub allowed: Option<Vec<String>>,
+    /// The `host:port` of the filtering proxy browsers must go through, if any
+    pub proxy: Option<String>,
+    /// The certificate of the local CA signing the proxy and block page, if any
+    pub ca: Option<PathBuf>,
+}
+
+/// Writes managed browser policies blocking the given websites.
+///
+/// Chromium based browsers get an `URLBlocklist` policy and Firefox gets a
+/// `WebsiteFilter` policy. Unlike the hosts file, these policies are enforced by
+/// the browser itself, so they also work with DNS-over-HTTPS. DNS-over-HTTPS is
+/// turned off as well for the session so the hosts file is honoured again, and
+/// browsers are pointed at the filtering proxy when it runs. Firefox keeps its own
+/// certificate store, so it is also told to trust the local CA.
+///
+/// # Arguments
+///
+/// * `policy` - The websites to block and the proxy to use
+/// * `dirs` - The policy directories to write into
+///
+/// # Returns
+///
+/// A `Result` indicating success or an I/O error if file operations fail
+pub fn apply_browser_policies(
+    policy: &BrowserPolicy,
+    dirs: &PolicyDirs,
+) -> Result<(), std::io::Error> {
+    for dir in &dirs.chromium {
+        std::fs::create_dir_all(dir)?;
+        write_json(&dir.join(CHROMIUM_POLICY_FILE), &chromium_policy(policy))?;
+    }
+    for dir in &dirs.firefox {
+        std::fs::create_dir_all(dir)?;
+        apply_firefox_policy(dir, policy)?;
+    }
+    Ok(())
+}
+
+/// Removes the managed browser policies written by `apply_browser_policies`.
+///
+/// The Chromium policy file is deleted and the Firefox `policies.json` is restored
+/// to what it was before the session (or deleted if it didn't exist).
+///
+/// # Arguments
+///
+/// * `dirs` - The policy directories to clean up
+///
+/// # Returns
+///
+/// A `Result` indicating success or an I/O error if file operations fail
+pub fn remove_browser_policies(dirs: &PolicyDirs) -> Result<(), std::io::Error> {
+    for dir in &dirs.chromium {
+        let file = dir.join(CHROMIUM_POLICY_FILE);
+        if file.exists() {
+            std::fs::remove_file(file)?;
+        }
+    }
+    for dir in &dirs.firefox {
+        restore_firefox_policy(dir)?;
+    }
+    Ok(())
+}
+
+/// Builds the Chromium policy object blocking the given websites.
+///
+/// Chromium lets `URLAllowlist` win over `URLBlocklist`, which carries the
+/// exceptions. In allowlist mode it carries the allowlist instead, exceptions
+/// would otherwise let through websites outside of it.
+fn chromium_policy(policy: &BrowserPolicy) -> Value {
+    let mut blocked = chromium_url_filters(&policy.blocked);
+    let allowed = match &policy.allowed {
+        Some(allowed) => {
+            blocked.insert(0, "*".to_string());
+            chromium_url_filters(allowed)
+        }
+        None => chromium_url_filters(&policy.exceptions),
+    };
+    let mut chromium = json!({ "URLBlocklist": blocked, "DnsOverHttpsMode": "off" });
+    if !allowed.is_empty() {
+        chromium["URLAllowlist"] = json!(allowed);
+    }
+    if let Some(proxy) = &policy.proxy {
+        chromium["ProxySettings"] = json!({ "ProxyMode": "fixed_servers", "ProxyServer": proxy });
+    }
+    chromium
+}
+
+/// Builds the Firefox `WebsiteFilter` policy blocking the given websites.
+///
+/// Firefox lets `Exceptions` win over `Block`, like Chromium the exceptions are
+/// replaced by the allowlist in allowlist mode.
+fn firefox_website_filter(policy: &BrowserPolicy) -> Value {
+    let mut patterns = firefox_match_patterns(&policy.blocked);
+    let exceptions = match &policy.allowed {
+        Some(allowed) => {
+            patterns.insert(0, "<all_urls>".to_string());
+            firefox_match_patterns(allowed)
+        }
+        None => firefox_match_patterns(&policy.exceptions),
+    };
+    if exceptions.is_empty() {
+        json!({ "Block": patterns })
+    } else {
+        json!({ "Block": patterns, "Exceptions": exceptions })
+    }
+}
+
+fn chromium_url_filters(patterns: &[String]) -> Vec<String> {
+    patterns
+        .iter()
+        .map(|pattern| chromium_url_filter(pattern))
+        .collect()
+}
+
+fn firefox_match_patterns(patterns: &[String]) -> Vec<String> {
+    patterns
+        .iter()
+        .map(|pattern| firefox_match_pattern(pattern))
+        .collect()
+}
+
+/// Converts a `host/path` pattern to a Chromium URL filter.
+///
+/// Chromium filters match subdomains and path prefixes on their own, so the globs
+/// are cut at their first `*`.
+fn chromium_url_filter(pattern: &str) -> String {
+    let rule = UrlRule::parse(pattern);
+    let host = rule.host.trim_start_matches("*.");
+    if rule.is_host_wide() {
+        host.to_string()
+    } else {
+        let path = rule.path.split('*').next().unwrap_or_default();
+        format!("{}{}", host, path)
+    }
+}
+
+/// Converts a `host/path` pattern to a Firefox match pattern.
+fn firefox_match_pattern(pattern: &str) -> String {
+    let rule = UrlRule::parse(pattern);
+    let path = if rule.is_host_wide() {
+        "/*"
+    } else {
+        &rule.path
+    };
+    format!("*://{}{}", rule.host, path)
+}
+
+/// Merges the `WebsiteFilter` policy into the Firefox `policies.json` of `dir`.
+///
+/// The original file is saved next to it the first time, or an `.absent` marker
+/// when there was no file at all.
+fn apply_firefox_policy(dir: &Path, policy: &BrowserPolicy) -> Result<(), std::io::Error> {
+    let file = dir.join(FIREFOX_POLICY_FILE);
+    let backup = dir.join(FIREFOX_BACKUP_FILE);
+    let absent = dir.join(FIREFOX_ABSENT_FILE);
+    let saved = backup.exists() || absent.exists();
+    let original = if file.exists() {
+        std::fs::read_to_string(&file)?
+    } else {
+        String::new()
+    };
+    if !saved {
+        if file.exists() {
+            std::fs::write(&backup, &original)?;
+        } else {
+            std::fs::write(&absent, "")?;
+        }
+    }
+
+    let mut document: Value = if original.trim().is_empty() {
+        json!({})
+    } else {
+        serde_json::from_str(&original)?
+    };
+    let root = document.as_object_mut().ok_or_else(invalid_policies)?;
+    let policies = root
+        .entry("policies")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .ok_or_else(invalid_policies)?;
+    policies.insert("WebsiteFilter".to_string(), firefox_website_filter(policy));
+    policies.insert(
+        "DNSOverHTTPS".to_string(),
+        json!({ "Enabled": false, "Locked": true }),
+    );
+    if let Some(ca) = &policy.ca {
+        policies.insert(
+            "Certificates".to_string(),
+            json!({ "ImportEnterpriseRoots": true, "Install": [ca] }),
+        );
+    }
+    if let Some(proxy) = &policy.proxy {
+        policies.insert(
+            "Proxy".to_string(),
+            json!({
+                "Mode": "manual",
+                "HTTPProxy": proxy,
+                "UseHTTPProxyForAllProtocols": true,
+                "Locked": true,
+            }),
+        );
+    }
+
+    write_json(&file, &document)
+}
+
+/// Puts back the Firefox `policies.json` saved by `apply_firefox_policy`.
+fn restore_firefox_policy(dir: &Path) -> Result<(), std::io::Error> {
+    let file = dir.join(FIREFOX_POLICY_FILE);
+    let backup = dir.join(FIREFOX_BACKUP_FILE);
+    let absent = dir.join(FIREFOX_ABSENT_FILE);
+    if absent.exists() {
+        if file.exists() {
+            std::fs::remove_file(&file)?;
+        }
+        std::fs::remove_file(absent)
+    } else if backup.exists() {
+        std::fs::rename(backup, file)
+    } else {
+        Ok(())
+    }
+}
+
+fn invalid_policies() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "policies.json must contain a JSON object",
+    )
+}
+
+fn write_json(path: &Path, value: &Value) -> Result<(), std::io::Error> {
+    let content = serde_json::to_string_pretty(value)?;
+    std::fs::write(path, content + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    // Helper function pointing both browsers at a temporary policy directory
+    fn setup_test_dirs() -> (TempDir, PolicyDirs) {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let dirs = PolicyDirs {
+            chromium: vec![temp_dir.path().join("chromium")],
+            firefox: vec![temp_dir.path().join("firefox")],
+        };
+        (temp_dir, dirs)
+    }
+
+    fn blocking(sites: &[&str]) -> BrowserPolicy {
+        BrowserPolicy {
+            blocked: sites.iter().map(|site| site.to_string()).collect(),
+            exceptions: Vec::new(),
+            allowed: None,
+            proxy: None,
+            ca: None,
+        }
+    }
+
+    fn read_json(path: &Path) -> Value {
+        let content = fs::read_to_string(path).expect("Failed to read policy file");
+        serde_json::from_str(&content).expect("Policy file should be valid JSON")
+    }
+
+    #[test]
+    fn test_manages_installed_browsers() {
+        let dirs = PolicyDirs {
+            chromium: vec![PathBuf::from(CHROME_POLICY_DIR)],
+            firefox: Vec::new(),
+        };
+
+        assert!(dirs.manages("Google Chrome"));
+        assert!(
+            !dirs.manages("Chromium"),
+            "Chromium reads another directory"
+        );
+        assert!(!dirs.manages("Firefox"), "Firefox is not installed");
+    }
+
+    #[test]
+    fn test_apply_writes_chromium_blocklist() {
+        let (_temp_dir, dirs) = setup_test_dirs();
+
+        let result = apply_browser_policies(&blocking(&["www.youtube.com", "www.x.com"]), &dirs);
+
+        assert!(result.is_ok(), "Function should return Ok");
+        let policy = read_json(&dirs.chromium[0].join(CHROMIUM_POLICY_FILE));
+        assert_eq!(
+            policy["URLBlocklist"],
+            json!(["www.youtube.com", "www.x.com"])
+        );
+        assert_eq!(policy["DnsOverHttpsMode"], json!("off"));
+    }
+
+    #[test]
+    fn test_apply_writes_firefox_website_filter() {
+        let (_temp_dir, dirs) = setup_test_dirs();
+
+        apply_browser_policies(&blocking(&["www.youtube.com"]), &dirs).expect("Failed to apply");
+
+        let policy = read_json(&dirs.firefox[0].join(FIREFOX_POLICY_FILE));
+        assert_eq!(
+            policy["policies"]["WebsiteFilter"]["Block"],
+            json!(["*://www.youtube.com/*"])
+        );
+        assert_eq!(
+            policy["policies"]["DNSOverHTTPS"],
+            json!({ "Enabled": false, "Locked": true })
+        );
+    }
+
+    #[test]
+    fn test_apply_points_browsers_at_proxy() {
+        let (_temp_dir, dirs) = setup_test_dirs();
+        let policy = BrowserPolicy {
+            blocked: Vec::new(),
+            exceptions: Vec::new(),
+            allowed: None,
+            proxy: Some("127.0.0.1:8118".to_string()),
+            ca: None,
+        };
+
+        apply_browser_policies(&policy, &dirs).expect("Failed to apply");
+
+        let chromium = read_json(&dirs.chromium[0].join(CHROMIUM_POLICY_FILE));
+        assert_eq!(
+            chromium["ProxySettings"]["ProxyServer"],
+            json!("127.0.0.1:8118")
+        );
+        let firefox = read_json(&dirs.firefox[0].join(FIREFOX_POLICY_FILE));
+        assert_eq!(
+            firefox["policies"]["Proxy"]["HTTPProxy"],
+            json!("127.0.0.1:8118")
+        );
+    }
+
+    #[test]
+    fn test_apply_makes_firefox_trust_ca() {
+        let (_temp_dir, dirs) = setup_test_dirs();
+        let policy = BrowserPolicy {
+            ca: Some(PathBuf::from("/var/lib/rust_can_make_you_focus/ca.pem")),
+            ..blocking(&["www.youtube.com"])
+        };
+
+        apply_browser_policies(&policy, &dirs).expect("Failed to apply");
+
+        let firefox = read_json(&dirs.firefox[0].join(FIREFOX_POLICY_FILE));
+        assert_eq!(
+            firefox["policies"]["Certificates"],
+            json!({
+                "ImportEnterpriseRoots": true,
+                "Install": ["/var/lib/rust_can_make_you_focus/ca.pem"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_apply_exceptions() {
+        let (_temp_dir, dirs) = setup_test_dirs();
+        let policy = BrowserPolicy {
+            blocked: vec!["*.youtube.com".to_string()],
+            exceptions: vec!["music.youtube.com".to_string()],
+            allowed: None,
+            proxy: None,
+            ca: None,
+        };
+
+        apply_browser_policies(&policy, &dirs).expect("Failed to apply");
+
+        let chromium = read_json(&dirs.chromium[0].join(CHROMIUM_POLICY_FILE));
+        assert_eq!(chromium["URLBlocklist"], json!(["youtube.com"]));
+        assert_eq!(chromium["URLAllowlist"], json!(["music.youtube.com"]));
+        let firefox = read_json(&dirs.firefox[0].join(FIREFOX_POLICY_FILE));
+        assert_eq!(
+            firefox["policies"]["WebsiteFilter"],
+            json!({
+                "Block": ["*://*.youtube.com/*"],
+                "Exceptions": ["*://music.youtube.com/*"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_apply_allowlist() {
+        let (_temp_dir, dirs) = setup_test_dirs();
+        let policy = BrowserPolicy {
+            blocked: Vec::new(),
+            exceptions: Vec::new(),
+            allowed: Some(vec![
+                "docs.rs".to_string(),
+                "*.crates.io".to_string(),
+                "github.com/rust-lang*".to_string(),
+            ]),
+            proxy: None,
+            ca: None,
+        };
+
+        apply_browser_policies(&policy, &dirs).expect("Failed to apply");
+
+        let chromium = read_json(&dirs.chromium[0].join(CHROMIUM_POLICY_FILE));
+        assert_eq!(chromium["URLBlocklist"], json!(["*"]));
+        assert_eq!(
+            chromium["URLAllowlist"],
+            json!(["docs.rs", "crates.io", "github.com/rust-lang"])
+        );
+        let firefox = read_json(&dirs.firefox[0].join(FIREFOX_POLICY_FILE));
+        assert_eq!(
+            firefox["policies"]["WebsiteFilter"],
+            json!({
+                "Block": ["<all_urls>"],
+                "Exceptions": ["*://docs.rs/*", "*://*.crates.io/*", "*://github.com/rust-lang*"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_apply_keeps_existing_firefox_policies() {
+        let (_temp_dir, dirs) = setup_test_dirs();
+        let original = "{\"policies\": {\"DisableTelemetry\": true}}";
+        fs::create_dir_all(&dirs.firefox[0]).unwrap();
+        fs::write(dirs.firefox[0].join(FIREFOX_POLICY_FILE), original).unwrap();
+
+        apply_browser_policies(&blocking(&["netflix.com"]), &dirs).expect("Failed to apply");
+
+        let policy = read_json(&dirs.firefox[0].join(FIREFOX_POLICY_FILE));
+        assert_eq!(policy["policies"]["DisableTelemetry"], json!(true));
+        assert_eq!(
+            policy["policies"]["WebsiteFilter"]["Block"],
+            json!(["*://netflix.com/*"])
+        );
+    }
+
+    #[test]
+    fn test_remove_restores_original_firefox_policies() {
+        let (_temp_dir, dirs) = setup_test_dirs();
+        let original = "{\"policies\": {\"DisableTelemetry\": true}}";
+        fs::create_dir_all(&dirs.firefox[0]).unwrap();
+        fs::write(dirs.firefox[0].join(FIREFOX_POLICY_FILE), original).unwrap();
+
+        apply_browser_policies(&blocking(&["netflix.com"]), &dirs).expect("Failed to apply");
+        // Applying twice must not overwrite the backup with our own policy
+        apply_browser_policies(&blocking(&["netflix.com"]), &dirs).expect("Failed to apply");
+        let result = remove_browser_policies(&dirs);
+
+        assert!(result.is_ok(), "Function should return Ok");
+        let restored = fs::read_to_string(dirs.firefox[0].join(FIREFOX_POLICY_FILE)).unwrap();
+        assert_eq!(restored, original, "Original policies should be restored");
+        assert!(!dirs.firefox[0].join(FIREFOX_BACKUP_FILE).exists());
+    }
+
+    #[test]
+    fn test_remove_deletes_created_files() {
+        let (_temp_dir, dirs) = setup_test_dirs();
+
+        apply_browser_policies(&blocking(&["discord.com"]), &dirs).expect("Failed to apply");
+        remove_browser_policies(&dirs).expect("Failed to remove");
+
+        assert!(!dirs.chromium[0].join(CHROMIUM_POLICY_FILE).exists());
+        assert!(!dirs.firefox[0].join(FIREFOX_POLICY_FILE).exists());
+        assert!(!dirs.firefox[0].join(FIREFOX_BACKUP_FILE).exists());
+        assert!(!dirs.firefox[0].join(FIREFOX_ABSENT_FILE).exists());
+    }
+
+    #[test]
+    fn test_remove_restores_empty_firefox_policies() {
+        let (_temp_dir, dirs) = setup_test_dirs();
+        fs::create_dir_all(&dirs.firefox[0]).unwrap();
+        fs::write(dirs.firefox[0].join(FIREFOX_POLICY_FILE), "").unwrap();
+
+        apply_browser_policies(&blocking(&["netflix.com"]), &dirs).expect("Failed to apply");
+        assert!(!dirs.firefox[0].join(FIREFOX_ABSENT_FILE).exists());
+        remove_browser_policies(&dirs).expect("Failed to remove");
+
+        // An empty file the user had is not mistaken for no file at all
+        let restored = fs::read_to_string(dirs.firefox[0].join(FIREFOX_POLICY_FILE)).unwrap();
+        assert_eq!(restored, "");
+        assert!(!dirs.firefox[0].join(FIREFOX_BACKUP_FILE).exists());
+    }
+
+    #[test]
+    fn test_remove_without_session_is_noop() {
+        let (_temp_dir, dirs) = setup_test_dirs();
+
+        let result = remove_browser_policies(&dirs);
+
+        assert!(result.is_ok(), "Removing without a session should succeed");
+    }
+
+    #[test]
+    fn test_apply_rejects_invalid_firefox_policies() {
+        let (_temp_dir, dirs) = setup_test_dirs();
+        fs::create_dir_all(&dirs.firefox[0]).unwrap();
+        fs::write(dirs.firefox[0].join(FIREFOX_POLICY_FILE), "[1, 2]").unwrap();
+
+        let result = apply_browser_policies(&blocking(&["www.x.com"]), &dirs);
+
+        assert!(
+            result.is_err(),
+            "A non object policies.json should be refused"
+        );
+    }
+}
@@ -0,0 +1,372 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+pub const SOCKET_PATH: &str = "/run/rust_can_make_you_focus.sock";
+pub const FOCUS_GROUP: &str = "focus";
+/// The most minutes a request can carry, a day.
+pub const MAX_MINUTES: u64 = 24 * 60;
+
+// Commands
+const START: &str = "start";
+const STOP: &str = "stop";
+const STATUS: &str = "status";
+const EXTEND: &str = "extend";
+const UNLOCK: &str = "unlock";
+const ANSWER: &str = "answer";
+const CANCEL: &str = "cancel";
+const SHORTEN: &str = "shorten";
+const ADD: &str = "add";
+const REMOVE: &str = "remove";
+const PAUSE: &str = "pause";
+const RESUME: &str = "resume";
+const ALLOW: &str = "allow";
+const PEEK: &str = "peek";
+
+// Replies
+const OK: &str = "OK";
+const ERR: &str = "ERR";
+
+/// A request sent to the daemon, one per line on the control socket.
+///
+/// The line format is `START <minutes> <args...>`, `STOP`, `STATUS`,
+/// `EXTEND <minutes>`, `SHORTEN <minutes>`, `ADD <args...>`, `REMOVE <args...>`,
+/// `PAUSE`, `RESUME`, `ALLOW <minutes> <args...>`, `PEEK <minutes> <site> <reason>`,
+/// `UNLOCK <code>`, `ANSWER <passage>` or `CANCEL`, the commands
+/// being case insensitive. The `START` arguments are the websites and
+/// presets accepted on the command line, plus `--strict`, `--cooldown=MINUTES`
+/// and `--challenge[=WORDS]`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Start {
+        minutes: u64,
+        arguments: Vec<String>,
+    },
+    Stop,
+    Status,
+    Extend {
+        minutes: u64,
+    },
+    /// Brings the deadline of the running session forward
+    Shorten {
+        minutes: u64,
+    },
+    /// Blocks more websites or presets in the running session
+    Add {
+        arguments: Vec<String>,
+    },
+    /// Stops blocking some websites or presets of the running session
+    Remove {
+        arguments: Vec<String>,
+    },
+    /// Lifts the blocks and freezes the timer
+    Pause,
+    /// Applies the blocks again and restarts the timer
+    Resume,
+    /// Checks minutes out of the daily budget of a website, reachable meanwhile
+    Allow {
+        minutes: u64,
+        arguments: Vec<String>,
+    },
+    /// Unblocks a website of the running session for a few minutes, for a reason
+    Peek {
+        minutes: u64,
+        site: String,
+        reason: String,
+    },
+    /// Ends a strict session early with its emergency unlock code
+    Unlock {
+        code: String,
+    },
+    /// Answers the typing challenge of an early stop
+    Answer {
+        text: String,
+    },
+    /// Cancels an early stop during its cooldown
+    Cancel,
+}
+
+impl Request {
+    /// Parses a request line.
+    ///
+    /// # Returns
+    ///
+    /// The request, or an `InvalidInput` error describing what is wrong with it
+    pub fn parse(line: &str) -> Result<Request, std::io::Error> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default().to_lowercase();
+        let mut minutes = || -> Result<u64, std::io::Error> {
+            words
+                .next()
+                .and_then(|minutes| minutes.parse::<u64>().ok())
+                .filter(|minutes| (1..=MAX_MINUTES).contains(minutes))
+                .ok_or_else(|| {
+                    invalid_request(&format!(
+                        "expected a number of minutes, from 1 to {}",
+                        MAX_MINUTES
+                    ))
+                })
+        };
+        let request = match command.as_str() {
+            START => Request::Start {
+                minutes: minutes()?,
+                arguments: words.by_ref().map(String::from).collect(),
+            },
+            STOP => Request::Stop,
+            STATUS => Request::Status,
+            EXTEND => Request::Extend {
+                minutes: minutes()?,
+            },
+            UNLOCK => Request::Unlock {
+                code: words.by_ref().collect::<Vec<&str>>().join(""),
+            },
+            ANSWER => Request::Answer {
+                text: words.by_ref().collect::<Vec<&str>>().join(" "),
+            },
+            CANCEL => Request::Cancel,
+            SHORTEN => Request::Shorten {
+                minutes: minutes()?,
+            },
+            ADD => Request::Add {
+                arguments: words.by_ref().map(String::from).collect(),
+            },
+            REMOVE => Request::Remove {
+                arguments: words.by_ref().map(String::from).collect(),
+            },
+            PAUSE => Request::Pause,
+            RESUME => Request::Resume,
+            ALLOW => Request::Allow {
+                minutes: minutes()?,
+                arguments: words.by_ref().map(String::from).collect(),
+            },
+            PEEK => Request::Peek {
+                minutes: minutes()?,
+                site: words.next().unwrap_or_default().to_string(),
+                reason: words.by_ref().collect::<Vec<&str>>().join(" "),
+            },
+            _ => return Err(invalid_request("unknown command")),
+        };
+        match (words.next(), &request) {
+            (Some(_), _) => Err(invalid_request("unexpected argument")),
+            (None, Request::Unlock { code }) if code.is_empty() => {
+                Err(invalid_request("expected an unlock code"))
+            }
+            (None, Request::Answer { text }) if text.is_empty() => {
+                Err(invalid_request("expected the passage"))
+            }
+            (None, Request::Peek { site, .. }) if site.is_empty() => {
+                Err(invalid_request("expected a website"))
+            }
+            (None, Request::Peek { reason, .. }) if reason.is_empty() => {
+                Err(invalid_request("expected the reason of the peek"))
+            }
+            (
+                None,
+                Request::Add { arguments }
+                | Request::Remove { arguments }
+                | Request::Allow { arguments, .. },
+            ) if arguments.is_empty() => Err(invalid_request("expected websites or presets")),
+            (None, _) => Ok(request),
+        }
+    }
+
+    /// Formats the request as a line for the control socket, without the newline.
+    pub fn to_line(&self) -> String {
+        match self {
+            Request::Start { minutes, arguments } => {
+                let mut line = format!("{} {}", START.to_uppercase(), minutes);
+                for argument in arguments {
+                    line.push(' ');
+                    line.push_str(argument);
+                }
+                line
+            }
+            Request::Stop => STOP.to_uppercase(),
+            Request::Status => STATUS.to_uppercase(),
+            Request::Extend { minutes } => format!("{} {}", EXTEND.to_uppercase(), minutes),
+            Request::Unlock { code } => format!("{} {}", UNLOCK.to_uppercase(), code),
+            Request::Answer { text } => format!("{} {}", ANSWER.to_uppercase(), text),
+            Request::Cancel => CANCEL.to_uppercase(),
+            Request::Shorten { minutes } => format!("{} {}", SHORTEN.to_uppercase(), minutes),
+            Request::Add { arguments } => {
+                format!("{} {}", ADD.to_uppercase(), arguments.join(" "))
+            }
+            Request::Remove { arguments } => {
+                format!("{} {}", REMOVE.to_uppercase(), arguments.join(" "))
+            }
+            Request::Pause => PAUSE.to_uppercase(),
+            Request::Resume => RESUME.to_uppercase(),
+            Request::Allow { minutes, arguments } => format!(
+                "{} {} {}",
+                ALLOW.to_uppercase(),
+                minutes,
+                arguments.join(" ")
+            ),
+            Request::Peek {
+                minutes,
+                site,
+                reason,
+            } => format!("{} {} {} {}", PEEK.to_uppercase(), minutes, site, reason),
+        }
+    }
+}
+
+/// Formats the reply line to a request, `OK <message>` or `ERR <message>`.
+pub fn reply_line(reply: &Result<String, String>) -> String {
+    match reply {
+        Ok(message) => format!("{} {}", OK, message),
+        Err(message) => format!("{} {}", ERR, message),
+    }
+    .trim_end()
+    .to_string()
+}
+
+/// Parses a reply line written by `reply_line`.
+///
+/// # Returns
+///
+/// The message of an `OK` reply, or an error carrying the message of an `ERR` one
+pub fn parse_reply(line: &str) -> Result<String, std::io::Error> {
+    let line = line.trim_end();
+    let (status, message) = line.split_once(' ').unwrap_or((line, ""));
+    match status {
+        OK => Ok(message.to_string()),
+        ERR => Err(std::io::Error::other(message.to_string())),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid reply from the daemon: {}", line),
+        )),
+    }
+}
+
+/// Sends a request to the daemon listening on `socket` and waits for its reply.
+///
+/// # Returns
+///
+/// The message of the reply, or an error if the daemon is unreachable or refused
+/// the request
+pub fn send_request(socket: &Path, request: &Request) -> Result<String, std::io::Error> {
+    let mut stream = UnixStream::connect(socket)?;
+    writeln!(stream, "{}", request.to_line())?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    parse_reply(&reply)
+}
+
+fn invalid_request(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_start() {
+        let request = Request::parse("start 25 coding youtube\n").expect("Should parse");
+        assert_eq!(
+            request,
+            Request::Start {
+                minutes: 25,
+                arguments: vec!["coding".to_string(), "youtube".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_simple_commands() {
+        assert_eq!(Request::parse("STOP").unwrap(), Request::Stop);
+        assert_eq!(Request::parse("Status").unwrap(), Request::Status);
+        assert_eq!(
+            Request::parse("EXTEND 10").unwrap(),
+            Request::Extend { minutes: 10 }
+        );
+        assert_eq!(
+            Request::parse("unlock ABCD EFGH-JKLM").unwrap(),
+            Request::Unlock {
+                code: "ABCDEFGH-JKLM".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_requests() {
+        for line in [
+            "",
+            "REBOOT",
+            "START",
+            "START x youtube",
+            "EXTEND 0",
+            "EXTEND 1441",
+            "EXTEND 18446744073709551615",
+            "STOP now",
+            "UNLOCK",
+            "ANSWER",
+            "CANCEL now",
+            "SHORTEN",
+            "ADD",
+            "REMOVE",
+            "PAUSE 5",
+            "ALLOW 10",
+            "ALLOW youtube",
+            "PEEK 5",
+            "PEEK 5 youtube",
+        ] {
+            let error = Request::parse(line).expect_err(line);
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_request_line_round_trip() {
+        let requests = [
+            Request::Start {
+                minutes: 5,
+                arguments: vec!["netflix".to_string()],
+            },
+            Request::Stop,
+            Request::Status,
+            Request::Extend { minutes: 15 },
+            Request::Unlock {
+                code: "ABCD-EFGH-JKLM".to_string(),
+            },
+            Request::Answer {
+                text: "amber river castle".to_string(),
+            },
+            Request::Cancel,
+            Request::Shorten { minutes: 5 },
+            Request::Add {
+                arguments: vec!["youtube".to_string(), "netflix".to_string()],
+            },
+            Request::Remove {
+                arguments: vec!["x".to_string()],
+            },
+            Request::Pause,
+            Request::Resume,
+            Request::Allow {
+                minutes: 10,
+                arguments: vec!["youtube".to_string()],
+            },
+            Request::Peek {
+                minutes: 5,
+                site: "youtube".to_string(),
+                reason: "a tutorial on lifetimes".to_string(),
+            },
+        ];
+        for request in requests {
+            assert_eq!(Request::parse(&request.to_line()).unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn test_reply_round_trip() {
+        assert_eq!(
+            parse_reply(&reply_line(&Ok("stopped".to_string()))).unwrap(),
+            "stopped"
+        );
+        let error = parse_reply(&reply_line(&Err("no session".to_string())))
+            .expect_err("Should be an error");
+        assert_eq!(error.to_string(), "no session");
+        assert!(parse_reply("garbage").is_err());
+    }
+}
@@ -0,0 +1,2044 @@
+use std::ffi::CString;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Value};
+
+use crate::browser::policy::{BrowserPolicy, PolicyDirs};
+use crate::daemon::protocol::{reply_line, Request, MAX_MINUTES};
+use crate::helper::request::HelperRequest;
+use crate::hosts::store::HostsStore;
+use crate::hosts::watch::HostsGuard;
+use crate::notify::notifier::{send, FallbackNotifier, Notification, Notifier, WARNING_BEFORE};
+use crate::schedule::plan::{local_time, system_time, Scheduler};
+use crate::session::budget::{Budget, Budgets, BUDGET_FILE};
+use crate::session::friction::{EarlyUnlock, Friction, Step};
+use crate::session::history::{History, PAUSE, PEEK, RESUME, STOP_REFUSED, UNLOCK, UNLOCK_FAILED};
+use crate::session::state::{
+    format_remaining, start_of_local_day, unix_seconds, Session, BROWSER_POLICIES_BACKEND,
+    HOSTS_BACKEND,
+};
+use crate::session::status::status_json;
+use crate::session::strict::{
+    generate_unlock_code, hash_unlock_code, ignore_termination_signals, verify_unlock_code,
+    STRICT_FLAG,
+};
+use crate::session::usage::UsageProbe;
+use crate::utils::command::CommandRunner;
+use crate::utils::utils::{hosts_sites, match_args, match_exceptions, match_presets};
+
+pub const STATE_FILE: &str = "session.json";
+
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a client has to read the reply, the daemon waits meanwhile.
+const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+const SOCKET_MODE: u32 = 0o660;
+const DAY: Duration = Duration::from_secs(24 * 3600);
+
+/// Applies and lifts the blocks of a session on the system.
+pub trait Enforcer: Send {
+    /// Blocks the websites of `session`.
+    fn apply(&mut self, session: &Session) -> Result<(), std::io::Error>;
+    /// Lifts every block applied by `apply`.
+    fn lift(&mut self) -> Result<(), std::io::Error>;
+    /// Returns whether the blocks can let the exceptions of a session through,
+    /// which the hosts file alone can't.
+    fn honours_exceptions(&self) -> bool;
+}
+
+/// Blocks websites through the hosts file and the managed browser policies.
+///
+/// The hosts file is guarded against tampering while the blocks are applied, and
+/// the termination signals are ignored during strict sessions.
+pub struct SystemEnforcer {
+    policy_dirs: PolicyDirs,
+    hosts: Arc<dyn HostsStore>,
+    runner: Arc<dyn CommandRunner>,
+    history: History,
+    guard: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+}
+
+impl SystemEnforcer {
+    /// Creates an enforcer.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy_dirs` - Where the browser policies are written
+    /// * `hosts` - The hosts file, `FileHostsStore::system()` outside of tests
+    /// * `runner` - Runs the commands flushing the DNS cache
+    /// * `history` - Where the tampering with the hosts file is recorded
+    pub fn new(
+        policy_dirs: PolicyDirs,
+        hosts: Arc<dyn HostsStore>,
+        runner: Arc<dyn CommandRunner>,
+        history: History,
+    ) -> SystemEnforcer {
+        SystemEnforcer {
+            policy_dirs,
+            hosts,
+            runner,
+            history,
+            guard: None,
+        }
+    }
+}
+
+impl Enforcer for SystemEnforcer {
+    fn apply(&mut self, session: &Session) -> Result<(), std::io::Error> {
+        let policy = BrowserPolicy {
+            blocked: session.sites.clone(),
+            exceptions: session.exceptions.clone(),
+            ..BrowserPolicy::default()
+        };
+        crate::browser::policy::apply_browser_policies(&policy, &self.policy_dirs)?;
+        // The websites with exceptions are only blocked in the browsers, if any
+        let exceptions = if self.honours_exceptions() {
+            session.exceptions.as_slice()
+        } else {
+            &[]
+        };
+        let hosts = hosts_sites(&session.sites, exceptions);
+        HelperRequest::block(&hosts).execute_with(self.hosts.as_ref(), self.runner.as_ref())?;
+        let done = Arc::new(AtomicBool::new(false));
+        let guard = HostsGuard::new(
+            self.hosts.clone(),
+            hosts.iter().map(|site| site.to_string()).collect(),
+            self.history.clone(),
+        );
+        self.guard = Some((done.clone(), guard.spawn(done)));
+        if session.is_strict() {
+            ignore_termination_signals(true);
+        }
+        Ok(())
+    }
+
+    fn lift(&mut self) -> Result<(), std::io::Error> {
+        // The guard would write the blocks again otherwise
+        let guard = match self.guard.take() {
+            Some((done, guard)) => {
+                done.store(true, Ordering::Relaxed);
+                guard
+                    .join()
+                    .map_err(|_| std::io::Error::other("the hosts guard panicked"))
+            }
+            None => Ok(()),
+        };
+        ignore_termination_signals(false);
+        // Every block is lifted, even when an earlier step failed
+        let policies = crate::browser::policy::remove_browser_policies(&self.policy_dirs);
+        let hosts =
+            HelperRequest::unblock().execute_with(self.hosts.as_ref(), self.runner.as_ref());
+        guard.and(policies).and(hosts)
+    }
+
+    fn honours_exceptions(&self) -> bool {
+        !self.policy_dirs.chromium.is_empty() || !self.policy_dirs.firefox.is_empty()
+    }
+}
+
+/// The focus daemon: owns the blocks and the session timer.
+///
+/// Sessions are controlled through `Request`s on a Unix socket, so the CLI doesn't
+/// have to stay alive (or run as root) for the whole session. The running session
+/// is persisted, a restarted daemon picks it up where it was.
+///
+/// A strict session (started with `--strict`) refuses `STOP`: it only ends at its
+/// deadline or with the emergency unlock code given when it started. A session
+/// with friction (`--cooldown`, `--challenge`) walks `STOP` through its
+/// `EarlyUnlock`, answered with `ANSWER` and cancelled with `CANCEL`. Both refuse
+/// the requests loosening the session: `SHORTEN`, `REMOVE` and `PAUSE`.
+///
+/// The blocks of the configured schedules and calendars start on their own while no
+/// session is running, a block stopped early is not started again. A scheduled
+/// session ends when a meeting of the calendars starts.
+///
+/// The websites with a daily budget are reachable until the budget of the day is
+/// used up, whether a session runs or not. Their time is counted while they are
+/// used, or checked out in advance with `ALLOW`.
+///
+/// `PEEK` unblocks a website of the running session for a few minutes, for a reason
+/// recorded in the history. Strict sessions refuse it, and the number of peeks can
+/// be limited per session and per day.
+///
+/// The start of the sessions, the warning before their deadline, their end and the
+/// breaks between the scheduled blocks are notified.
+pub struct Daemon {
+    enforcer: Box<dyn Enforcer>,
+    state_file: PathBuf,
+    history: History,
+    session: Option<Session>,
+    early_unlock: Option<EarlyUnlock>,
+    max_pauses_per_day: Option<usize>,
+    scheduler: Scheduler,
+    last_scheduled: Option<chrono::NaiveDateTime>,
+    /// Whether the running session was started by the scheduler
+    scheduled: bool,
+    budgets: Budgets,
+    /// The websites of the budgets blocked by the last `apply`
+    budget_blocked: Vec<String>,
+    /// Tells when the websites of the budgets are used, their time isn't counted without
+    usage_probe: Option<Box<dyn UsageProbe>>,
+    peek: Option<Peek>,
+    max_peeks_per_session: Option<usize>,
+    max_peeks_per_day: Option<usize>,
+    notifier: Box<dyn Notifier>,
+    /// Whether the end of the running session was warned of
+    warned: bool,
+    /// The start of the next scheduled block, during the break before it
+    break_until: Option<chrono::NaiveDateTime>,
+}
+
+/// Websites of the running session unblocked until `until`.
+struct Peek {
+    sites: Vec<String>,
+    until: SystemTime,
+}
+
+impl Daemon {
+    /// Creates an idle daemon.
+    ///
+    /// # Arguments
+    ///
+    /// * `enforcer` - Applies and lifts the blocks
+    /// * `state_file` - Where the running session is persisted
+    /// * `history` - Where refused stops and unlock attempts are recorded
+    pub fn new(enforcer: Box<dyn Enforcer>, state_file: PathBuf, history: History) -> Daemon {
+        Daemon {
+            enforcer,
+            state_file,
+            history,
+            session: None,
+            early_unlock: None,
+            max_pauses_per_day: None,
+            scheduler: Scheduler::default(),
+            last_scheduled: None,
+            scheduled: false,
+            budgets: Budgets::default(),
+            budget_blocked: Vec::new(),
+            usage_probe: None,
+            peek: None,
+            max_peeks_per_session: None,
+            max_peeks_per_day: None,
+            notifier: Box::new(FallbackNotifier::new(Vec::new())),
+            warned: false,
+            break_until: None,
+        }
+    }
+
+    /// Notifies the sessions with `notifier`, nothing is notified by default.
+    pub fn with_notifier(mut self, notifier: Box<dyn Notifier>) -> Daemon {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Starts the blocks of `scheduler` when they are due.
+    pub fn with_scheduler(mut self, scheduler: Scheduler) -> Daemon {
+        self.scheduler = scheduler;
+        self
+    }
+
+    /// Blocks the websites of `budgets` once their time of the day is used up, the
+    /// usage of the day being saved next to the state file.
+    pub fn with_budgets(mut self, budgets: Vec<Budget>) -> Daemon {
+        let file = self.state_file.with_file_name(BUDGET_FILE);
+        self.budgets = Budgets::load(budgets, file);
+        self
+    }
+
+    /// Counts the time of the budgets while `probe` tells their websites are used.
+    pub fn with_usage_probe(mut self, probe: Box<dyn UsageProbe>) -> Daemon {
+        self.usage_probe = Some(probe);
+        self
+    }
+
+    /// Limits how many times sessions can be paused per day, in local time.
+    pub fn with_pause_limit(mut self, max_pauses_per_day: usize) -> Daemon {
+        self.max_pauses_per_day = Some(max_pauses_per_day);
+        self
+    }
+
+    /// Limits how many times websites can be peeked at per session and per day, in
+    /// local time, `None` for no limit.
+    pub fn with_peek_limits(
+        mut self,
+        max_per_session: Option<usize>,
+        max_per_day: Option<usize>,
+    ) -> Daemon {
+        self.max_peeks_per_session = max_per_session;
+        self.max_peeks_per_day = max_per_day;
+        self
+    }
+
+    /// Returns the running session, if any.
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    /// Resumes the session persisted by a previous daemon.
+    ///
+    /// Its blocks are applied again if it is still running, unless it is paused,
+    /// and lifted if it ended while the daemon was down.
+    pub fn restore(&mut self, now: SystemTime) -> Result<(), std::io::Error> {
+        let content = match std::fs::read_to_string(&self.state_file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let session = serde_json::from_str::<Value>(&content)
+            .ok()
+            .and_then(|value| Session::from_json(&value));
+        self.lift()?;
+        match session {
+            Some(session) if session.remaining(now) > Duration::ZERO => {
+                if !session.is_paused() {
+                    self.apply(&session, now)?;
+                }
+                self.early_unlock = session.friction.map(EarlyUnlock::new);
+                self.session = Some(session);
+                Ok(())
+            }
+            _ => std::fs::remove_file(&self.state_file),
+        }
+    }
+
+    /// Executes a request.
+    ///
+    /// # Returns
+    ///
+    /// The message replied to the client, as an error if the request was refused
+    pub fn handle(&mut self, request: Request, now: SystemTime) -> Result<String, String> {
+        match request {
+            Request::Start { minutes, arguments } => {
+                let reply = self.start(&arguments, now, minutes_duration(minutes)?)?;
+                self.notify_start(now, false);
+                Ok(reply)
+            }
+            Request::Stop => match &self.session {
+                None => Err("no session is running".to_string()),
+                Some(session) if session.is_strict() => {
+                    self.record(now, STOP_REFUSED, "stop requested during a strict session")?;
+                    Err("the session is strict, it only ends early with UNLOCK <code>".to_string())
+                }
+                Some(_) => match self.early_unlock.as_mut() {
+                    Some(unlock) => {
+                        let step = unlock.request(now, &self.history);
+                        self.early_step(step.map_err(|e| e.to_string())?)
+                    }
+                    None => self.early_step(Step::Unlocked),
+                },
+            },
+            Request::Answer { text } => {
+                let Some(unlock) = self.early_unlock.as_mut() else {
+                    return Err("the session has no challenge, use STOP".to_string());
+                };
+                if unlock.pending_challenge().is_none() {
+                    return Err("no passage to type, send STOP first".to_string());
+                }
+                match unlock.answer(&text, now, &self.history) {
+                    Some(step) => self.early_step(step),
+                    None => Err("the passage doesn't match, send STOP for a new one".to_string()),
+                }
+            }
+            Request::Cancel => {
+                let cancelled = match self.early_unlock.as_mut() {
+                    Some(unlock) => unlock.cancel(now, &self.history),
+                    None => false,
+                };
+                if cancelled {
+                    Ok("cancelled, the session goes on".to_string())
+                } else {
+                    Err("no early stop to cancel".to_string())
+                }
+            }
+            Request::Unlock { code } => {
+                let Some(session) = &self.session else {
+                    return Err("no session is running".to_string());
+                };
+                let Some(hash) = &session.unlock_hash else {
+                    return Err("the session is not strict, use STOP".to_string());
+                };
+                if !verify_unlock_code(&code, hash) {
+                    self.history
+                        .record_or_warn(now, UNLOCK_FAILED, "wrong emergency code");
+                    return Err("wrong unlock code".to_string());
+                }
+                // A history which can't be written never keeps the user locked out
+                self.history
+                    .record_or_warn(now, UNLOCK, "unlocked with the emergency code");
+                self.end().map_err(|e| e.to_string())?;
+                Ok("unlocked".to_string())
+            }
+            Request::Status => Ok(self.status(now).to_string()),
+            Request::Extend { minutes } => {
+                let Some(session) = self.session.as_mut() else {
+                    return Err("no session is running".to_string());
+                };
+                session.deadline = session
+                    .deadline
+                    .checked_add(minutes_duration(minutes)?)
+                    .ok_or("the deadline would be too far away")?;
+                let remaining = format_remaining(session.remaining(now));
+                self.persist().map_err(|e| e.to_string())?;
+                Ok(format!("{} left", remaining))
+            }
+            Request::Shorten { minutes } => {
+                self.refuse_loosening(now, "shorten")?;
+                let Some(session) = self.session.as_mut() else {
+                    return Err("no session is running".to_string());
+                };
+                let shortened = minutes_duration(minutes)?;
+                if session.remaining(now) <= shortened {
+                    return Err("the session would be over, use STOP".to_string());
+                }
+                session.deadline -= shortened;
+                let remaining = format_remaining(session.remaining(now));
+                self.persist().map_err(|e| e.to_string())?;
+                Ok(format!("{} left", remaining))
+            }
+            Request::Add { arguments } => {
+                let Some(session) = self.session.as_mut() else {
+                    return Err("no session is running".to_string());
+                };
+                let added: Vec<String> = match_args(&arguments)
+                    .into_iter()
+                    .filter(|site| !session.sites.iter().any(|blocked| blocked == site))
+                    .map(String::from)
+                    .collect();
+                if added.is_empty() {
+                    return Err("no new website to block".to_string());
+                }
+                session.sites.extend(added.iter().cloned());
+                session.hosts.extend(
+                    hosts_sites(&added, &session.exceptions)
+                        .into_iter()
+                        .map(String::from),
+                );
+                self.reapply(now).map_err(|e| e.to_string())?;
+                Ok(format!("also blocking {}", added.join(", ")))
+            }
+            Request::Remove { arguments } => {
+                self.refuse_loosening(now, "remove websites from")?;
+                let Some(session) = self.session.as_mut() else {
+                    return Err("no session is running".to_string());
+                };
+                let removed = match_args(&arguments);
+                let (dropped, kept): (Vec<String>, Vec<String>) = session
+                    .sites
+                    .iter()
+                    .cloned()
+                    .partition(|site| removed.contains(&site.as_str()));
+                if dropped.is_empty() {
+                    return Err("none of these websites is blocked".to_string());
+                }
+                if kept.is_empty() {
+                    return Err("no website would be left, use STOP".to_string());
+                }
+                session.sites = kept;
+                session.hosts.retain(|site| !dropped.contains(site));
+                self.reapply(now).map_err(|e| e.to_string())?;
+                Ok(format!("no longer blocking {}", dropped.join(", ")))
+            }
+            Request::Pause => {
+                self.refuse_loosening(now, "pause")?;
+                match &self.session {
+                    None => return Err("no session is running".to_string()),
+                    Some(session) if session.is_paused() => {
+                        return Err("the session is already paused".to_string())
+                    }
+                    Some(_) => {}
+                }
+                if let Some(limit) = self.max_pauses_per_day {
+                    let today = self
+                        .history
+                        .count_since(PAUSE, start_of_local_day(now))
+                        .map_err(|e| e.to_string())?;
+                    if today >= limit {
+                        return Err(format!(
+                            "already paused {} times today, the limit is {}",
+                            today, limit
+                        ));
+                    }
+                }
+                self.lift().map_err(|e| e.to_string())?;
+                if let Some(session) = self.session.as_mut() {
+                    session.pause(now);
+                }
+                self.persist().map_err(|e| e.to_string())?;
+                self.record(now, PAUSE, "blocks lifted, timer frozen")?;
+                Ok("paused, the blocks are lifted until RESUME".to_string())
+            }
+            Request::Resume => {
+                let Some(session) = self.session.as_mut().filter(|s| s.is_paused()) else {
+                    return Err("no session is paused".to_string());
+                };
+                let paused = session.resume(now);
+                let remaining = format_remaining(session.remaining(now));
+                self.reapply(now).map_err(|e| e.to_string())?;
+                self.record(
+                    now,
+                    RESUME,
+                    &format!("paused for {}", format_remaining(paused)),
+                )?;
+                Ok(format!("resumed, {} left", remaining))
+            }
+            Request::Allow { minutes, arguments } => {
+                let sites = match_args(&arguments);
+                if let Some(session) = self.session.as_ref().filter(|s| !s.is_paused()) {
+                    if sites
+                        .iter()
+                        .any(|site| session.sites.iter().any(|s| s == site))
+                    {
+                        return Err(
+                            "the running session blocks it, it can't be allowed".to_string()
+                        );
+                    }
+                }
+                let reply = self
+                    .budgets
+                    .allow(&arguments, minutes_duration(minutes)?, now)?;
+                self.enforce_budgets(now).map_err(|e| e.to_string())?;
+                Ok(reply)
+            }
+            Request::Peek {
+                minutes,
+                site,
+                reason,
+            } => self.peek(&site, minutes_duration(minutes)?, &reason, now),
+        }
+    }
+
+    /// Unblocks the websites of `site` blocked by the running session for `duration`.
+    fn peek(
+        &mut self,
+        site: &str,
+        duration: Duration,
+        reason: &str,
+        now: SystemTime,
+    ) -> Result<String, String> {
+        let Some(session) = &self.session else {
+            return Err("no session is running".to_string());
+        };
+        if session.is_strict() {
+            self.record(now, STOP_REFUSED, "peek requested during a strict session")?;
+            return Err("the session is strict, nothing can be unblocked".to_string());
+        }
+        if session.is_paused() {
+            return Err("the session is paused, nothing is blocked".to_string());
+        }
+        if let Some(peek) = &self.peek {
+            return Err(format!(
+                "already peeking at {}, {} left",
+                peek.sites.join(", "),
+                format_remaining(peek.until.duration_since(now).unwrap_or(Duration::ZERO))
+            ));
+        }
+        let sites: Vec<String> = match_args(&[site.to_string()])
+            .into_iter()
+            .filter(|site| session.sites.iter().any(|blocked| blocked == site))
+            .map(String::from)
+            .collect();
+        if sites.is_empty() {
+            return Err(format!("{} is not blocked by the session", site));
+        }
+        let limits = [
+            (
+                self.max_peeks_per_session,
+                session.started_at,
+                "during this session",
+            ),
+            (self.max_peeks_per_day, start_of_local_day(now), "today"),
+        ];
+        for (limit, since, period) in limits {
+            let Some(limit) = limit else {
+                continue;
+            };
+            let peeks = self
+                .history
+                .count_since(PEEK, since)
+                .map_err(|e| e.to_string())?;
+            if peeks >= limit {
+                return Err(format!(
+                    "already peeked {} times {}, the limit is {}",
+                    peeks, period, limit
+                ));
+            }
+        }
+        self.peek = Some(Peek {
+            sites: sites.clone(),
+            until: now + duration,
+        });
+        self.reapply(now).map_err(|e| e.to_string())?;
+        let unblocked = format!("{} for {}", sites.join(", "), format_remaining(duration));
+        self.record(now, PEEK, &format!("{}: {}", unblocked, reason))?;
+        Ok(format!("unblocked {}, blocked again after", unblocked))
+    }
+
+    /// Refuses to loosen a strict session or a session with friction, which only
+    /// end early through `STOP`.
+    fn refuse_loosening(&self, now: SystemTime, action: &str) -> Result<(), String> {
+        match &self.session {
+            Some(session) if session.is_strict() || session.friction.is_some() => {
+                self.record(now, STOP_REFUSED, &format!("{} requested", action))?;
+                Err(format!(
+                    "can't {} this session, it only ends early through STOP",
+                    action
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies the blocks of the changed session, unless it is paused, and saves it.
+    fn reapply(&mut self, now: SystemTime) -> Result<(), std::io::Error> {
+        if let Some(session) = self.session.clone().filter(|s| !s.is_paused()) {
+            self.lift()?;
+            self.apply(&session, now)?;
+        }
+        self.persist()
+    }
+
+    /// Applies the blocks of `session`, but the peeked websites, with the websites of
+    /// the budgets not allowed.
+    fn apply(&mut self, session: &Session, now: SystemTime) -> Result<(), std::io::Error> {
+        let blocked = self.budgets.blocked(now);
+        let mut applied = session.clone();
+        if let Some(peek) = &self.peek {
+            applied.sites.retain(|site| !peek.sites.contains(site));
+        }
+        applied.sites.extend(
+            blocked
+                .iter()
+                .filter(|site| !session.sites.contains(site))
+                .cloned(),
+        );
+        self.enforcer.apply(&applied)?;
+        self.budget_blocked = blocked;
+        Ok(())
+    }
+
+    fn lift(&mut self) -> Result<(), std::io::Error> {
+        self.budget_blocked.clear();
+        self.enforcer.lift()
+    }
+
+    /// Applies the blocks again when the websites of the budgets to block changed,
+    /// e.g. at the end of their allowed time.
+    fn enforce_budgets(&mut self, now: SystemTime) -> Result<(), std::io::Error> {
+        let blocked = self.budgets.blocked(now);
+        if blocked == self.budget_blocked {
+            return Ok(());
+        }
+        let session = self.session.clone().filter(|s| !s.is_paused());
+        if session.is_some() || !self.budget_blocked.is_empty() {
+            self.lift()?;
+        }
+        match session {
+            Some(session) => self.apply(&session, now),
+            None if blocked.is_empty() => Ok(()),
+            None => self.apply(&Session::new(Vec::new(), Vec::new(), now, DAY), now),
+        }
+    }
+
+    /// Ends the session once its deadline has passed, or its early stop cooldown,
+    /// and starts the scheduled blocks which are due. The end is warned of
+    /// `WARNING_BEFORE` the deadline.
+    pub fn tick(&mut self, now: SystemTime) -> Result<(), std::io::Error> {
+        if let Some(unlock) = self.early_unlock.as_mut() {
+            if unlock.poll(now, &self.history) {
+                return self.end();
+            }
+        }
+        self.scheduler.refresh();
+        if self.peek.as_ref().is_some_and(|peek| peek.until <= now) {
+            self.peek = None;
+            self.reapply(now)?;
+        }
+        if let Some(probe) = self.usage_probe.as_mut() {
+            if let Err(e) = self.budgets.track(now, probe.as_mut()) {
+                println!("Failed to save the budgets: {}", e);
+            }
+        }
+        self.enforce_budgets(now)?;
+        match &self.session {
+            Some(session) if session.remaining(now) == Duration::ZERO => self.end_at_deadline(now),
+            Some(session) => {
+                let left = session.remaining(now);
+                if left > WARNING_BEFORE {
+                    // The session was extended after the warning
+                    self.warned = false;
+                } else if !self.warned && !session.is_paused() {
+                    self.warned = true;
+                    send(self.notifier.as_ref(), &Notification::ending_soon(left));
+                }
+                if self.scheduled {
+                    return self.end_for_meeting(now);
+                }
+                Ok(())
+            }
+            None => self.start_scheduled(now),
+        }
+    }
+
+    /// Ends the session at its deadline, notifying the break until the next
+    /// scheduled block of the day, if any.
+    fn end_at_deadline(&mut self, now: SystemTime) -> Result<(), std::io::Error> {
+        let scheduled = self.scheduled;
+        self.end()?;
+        let today = local_time(now).date();
+        let next = self
+            .scheduler
+            .upcoming(local_time(now), 0)
+            .into_iter()
+            .find(|block| block.start > local_time(now) && block.start.date() == today);
+        match next {
+            Some(block) if scheduled => {
+                self.break_until = Some(block.start);
+                send(self.notifier.as_ref(), &Notification::on_break(block.start));
+            }
+            _ => send(self.notifier.as_ref(), &Notification::ended()),
+        }
+        Ok(())
+    }
+
+    /// Ends the scheduled session when a meeting starts.
+    fn end_for_meeting(&mut self, now: SystemTime) -> Result<(), std::io::Error> {
+        let Some(meeting) = self.scheduler.meeting(local_time(now)) else {
+            return Ok(());
+        };
+        println!("Lifting the blocks for the {} meeting", meeting.schedule);
+        self.end()
+    }
+
+    /// Starts the scheduled block running at `now`, unless it already ran.
+    fn start_scheduled(&mut self, now: SystemTime) -> Result<(), std::io::Error> {
+        let Some(block) = self.scheduler.active(local_time(now)) else {
+            return Ok(());
+        };
+        if self.last_scheduled == Some(block.start) {
+            return Ok(());
+        }
+        self.last_scheduled = Some(block.start);
+        let Some(end) = system_time(block.end) else {
+            return Ok(());
+        };
+        let duration = end.duration_since(now).unwrap_or(Duration::ZERO);
+        let reply = self
+            .start(&block.arguments, now, duration)
+            .map_err(std::io::Error::other)?;
+        self.scheduled = true;
+        let break_over = self.break_until.take() == Some(block.start);
+        self.notify_start(now, break_over);
+        println!("Started the {} schedule, {}", block.schedule, reply);
+        Ok(())
+    }
+
+    /// Notifies the start of the running session, or the end of the break before it.
+    fn notify_start(&mut self, now: SystemTime, break_over: bool) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        let duration = session.remaining(now);
+        let notification = if break_over {
+            Notification::break_over(&session.sites, duration)
+        } else {
+            Notification::started(&session.sites, duration)
+        };
+        self.warned = duration <= WARNING_BEFORE;
+        send(self.notifier.as_ref(), &notification);
+    }
+
+    /// Starts a session blocking `arguments` for `duration`.
+    fn start(
+        &mut self,
+        arguments: &[String],
+        now: SystemTime,
+        duration: Duration,
+    ) -> Result<String, String> {
+        if self.session.is_some() {
+            return Err("a session is already running".to_string());
+        }
+        let sites: Vec<String> = match_args(arguments)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        if sites.is_empty() {
+            return Err("no website to block".to_string());
+        }
+        let mut session = Session::new(match_presets(arguments), sites, now, duration);
+        let unlock_code = if arguments.iter().any(|arg| arg == STRICT_FLAG) {
+            let code = generate_unlock_code().map_err(|e| e.to_string())?;
+            session.unlock_hash = Some(hash_unlock_code(&code).map_err(|e| e.to_string())?);
+            Some(code)
+        } else {
+            None
+        };
+        session.friction = Friction::from_flags(arguments).map_err(|e| e.to_string())?;
+        session.backends = vec![
+            HOSTS_BACKEND.to_string(),
+            BROWSER_POLICIES_BACKEND.to_string(),
+        ];
+        session.exceptions = match_exceptions(&session.presets, arguments);
+        if !session.exceptions.is_empty() && !self.enforcer.honours_exceptions() {
+            // The hosts file blocks the whole websites
+            println!(
+                "No browser to manage, the exceptions can't be honoured: {}",
+                session.exceptions.join(", ")
+            );
+            session.exceptions.clear();
+        }
+        session.hosts = hosts_sites(&session.sites, &session.exceptions)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        if let Err(e) = self.apply(&session, now) {
+            // Nothing half applied must outlive the failed start
+            if let Err(lift_error) = self.lift() {
+                println!("Failed to lift the blocks: {}", lift_error);
+            }
+            return Err(e.to_string());
+        }
+        self.early_unlock = session.friction.map(EarlyUnlock::new);
+        self.session = Some(session);
+        self.persist().map_err(|e| e.to_string())?;
+        let minutes = duration.as_secs() / 60;
+        match unlock_code {
+            Some(code) => Ok(format!(
+                "blocking for {} minutes in strict mode, emergency unlock code: {}",
+                minutes, code
+            )),
+            None => Ok(format!("blocking for {} minutes", minutes)),
+        }
+    }
+
+    /// Answers the requests of `listener` until `done` is set.
+    ///
+    /// Each request is read on its own thread, a client slow to send it doesn't
+    /// hold the session timer back.
+    pub fn serve(
+        &mut self,
+        listener: UnixListener,
+        done: Arc<AtomicBool>,
+    ) -> Result<(), std::io::Error> {
+        listener.set_nonblocking(true)?;
+        let (requests, received) = mpsc::channel();
+        while !done.load(Ordering::Relaxed) {
+            let request = match listener.accept() {
+                Ok((stream, _)) => {
+                    let requests = requests.clone();
+                    std::thread::spawn(move || match read_request(&stream) {
+                        Ok(line) => {
+                            let _ = requests.send((stream, line));
+                        }
+                        Err(e) => println!("Control request failed: {}", e),
+                    });
+                    None
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    received.recv_timeout(ACCEPT_INTERVAL).ok()
+                }
+                Err(e) => {
+                    println!("Control connection failed: {}", e);
+                    None
+                }
+            };
+            for (stream, line) in request.into_iter().chain(received.try_iter()) {
+                if let Err(e) = self.answer(stream, &line) {
+                    println!("Control request failed: {}", e);
+                }
+            }
+            if let Err(e) = self.tick(SystemTime::now()) {
+                println!("Failed to end the session: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the request read from `stream` and writes the reply.
+    fn answer(&mut self, mut stream: UnixStream, line: &str) -> Result<(), std::io::Error> {
+        stream.set_write_timeout(Some(REPLY_TIMEOUT))?;
+        let reply = match Request::parse(line) {
+            Ok(request) => self.handle(request, SystemTime::now()),
+            Err(e) => Err(e.to_string()),
+        };
+        writeln!(stream, "{}", reply_line(&reply))
+    }
+
+    /// The JSON document replied to `STATUS`.
+    fn status(&self, now: SystemTime) -> Value {
+        let hosts = std::fs::read_to_string(crate::FILE_PATH).ok();
+        let mut status = status_json(self.session.as_ref(), now, hosts.as_deref());
+        if let Some(until) = self.early_unlock.as_ref().and_then(|u| u.waiting_until()) {
+            status["stopping_in_seconds"] = until
+                .duration_since(now)
+                .unwrap_or(Duration::ZERO)
+                .as_secs()
+                .into();
+        }
+        if !self.budgets.is_empty() {
+            status["budgets"] = self.budgets.status_json(now);
+        }
+        if let Some(until) = self.break_until.and_then(system_time) {
+            if self.session.is_none() && until > now {
+                status["break_until"] = unix_seconds(until).into();
+            }
+        }
+        if let Some(peek) = &self.peek {
+            status["peek"] = json!({
+                "sites": peek.sites,
+                "remaining_seconds": peek.until.duration_since(now).unwrap_or(Duration::ZERO).as_secs(),
+            });
+        }
+        status
+    }
+
+    /// Replies to a step of an early stop, ending the session once unlocked.
+    fn early_step(&mut self, step: Step) -> Result<String, String> {
+        if step == Step::Unlocked {
+            self.end().map_err(|e| e.to_string())?;
+        }
+        Ok(step.message())
+    }
+
+    fn end(&mut self) -> Result<(), std::io::Error> {
+        self.session = None;
+        self.early_unlock = None;
+        self.scheduled = false;
+        self.peek = None;
+        self.lift()?;
+        match std::fs::remove_file(&self.state_file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn record(&self, now: SystemTime, event: &str, detail: &str) -> Result<(), String> {
+        self.history
+            .record(now, event, detail)
+            .map_err(|e| e.to_string())
+    }
+
+    fn persist(&self) -> Result<(), std::io::Error> {
+        if let Some(session) = &self.session {
+            if let Some(dir) = self.state_file.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            // It holds the unlock hash, private from the start and replaced at once
+            let temp_file = self.state_file.with_extension("tmp");
+            match std::fs::remove_file(&temp_file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&temp_file)?
+                .write_all(session.to_json().to_string().as_bytes())?;
+            std::fs::rename(&temp_file, &self.state_file)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the request line sent on `stream`, waiting at most `REQUEST_TIMEOUT`.
+fn read_request(stream: &UnixStream) -> Result<String, std::io::Error> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(line)
+}
+
+/// Converts the minutes of a request, at most `MAX_MINUTES`, to a duration.
+fn minutes_duration(minutes: u64) -> Result<Duration, String> {
+    if minutes > MAX_MINUTES {
+        return Err(format!("at most {} minutes", MAX_MINUTES));
+    }
+    Ok(Duration::from_secs(minutes * 60))
+}
+
+/// Binds the control socket, readable and writable by root and `group`.
+///
+/// A stale socket left by a previous daemon is replaced, but a running daemon is
+/// never hijacked. Without the group only root can control sessions.
+pub fn bind_control_socket(path: &Path, group: &str) -> Result<UnixListener, std::io::Error> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("a daemon is already listening on {}", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    match group_id(group) {
+        Some(gid) => {
+            std::os::unix::fs::chown(path, None, Some(gid))?;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(SOCKET_MODE))?;
+        }
+        None => {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+            println!(
+                "Group {} not found, only root can control sessions (groupadd {} to fix it)",
+                group, group
+            );
+        }
+    }
+    Ok(listener)
+}
+
+fn group_id(group: &str) -> Option<u32> {
+    let name = CString::new(group).ok()?;
+    // SAFETY: getgrnam takes a valid C string, the returned entry is only read
+    // before any other call that could overwrite it
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        None
+    } else {
+        // SAFETY: entry is a non null pointer returned by getgrnam
+        Some(unsafe { (*entry).gr_gid })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::protocol::send_request;
+    use crate::hosts::file_edit::is_managed_block_intact;
+    use crate::hosts::store::MemoryHostsStore;
+    use crate::notify::notifier::RecordingNotifier;
+    use crate::schedule::calendar::{Calendar, CalendarSource};
+    use crate::schedule::plan::{Pomodoro, Schedule};
+    use crate::session::history::HISTORY_FILE;
+    use crate::utils::command::RecordingRunner;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    // Enforcer recording its calls instead of touching the system
+    #[derive(Clone, Default)]
+    struct RecordingEnforcer {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Enforcer for RecordingEnforcer {
+        fn apply(&mut self, session: &Session) -> Result<(), std::io::Error> {
+            let mut calls = self.calls.lock().unwrap();
+            calls.push(format!("apply {}", session.sites.join(",")));
+            Ok(())
+        }
+
+        fn lift(&mut self) -> Result<(), std::io::Error> {
+            self.calls.lock().unwrap().push("lift".to_string());
+            Ok(())
+        }
+
+        fn honours_exceptions(&self) -> bool {
+            true
+        }
+    }
+
+    fn setup_daemon() -> (TempDir, RecordingEnforcer, Daemon) {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let enforcer = RecordingEnforcer::default();
+        let daemon = new_daemon(&temp_dir, &enforcer);
+        (temp_dir, enforcer, daemon)
+    }
+
+    fn new_daemon(temp_dir: &TempDir, enforcer: &RecordingEnforcer) -> Daemon {
+        Daemon::new(
+            Box::new(enforcer.clone()),
+            temp_dir.path().join(STATE_FILE),
+            History::new(temp_dir.path().join(HISTORY_FILE)),
+        )
+    }
+
+    fn start(minutes: u64, arguments: &[&str]) -> Request {
+        Request::Start {
+            minutes,
+            arguments: arguments.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_start_applies_and_persists() {
+        let (temp_dir, enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+
+        let reply = daemon.handle(start(25, &["youtube", "netflix"]), now);
+
+        assert_eq!(reply, Ok("blocking for 25 minutes".to_string()));
+        assert_eq!(
+            *enforcer.calls.lock().unwrap(),
+            vec!["apply www.youtube.com,netflix.com"]
+        );
+        let content = std::fs::read_to_string(temp_dir.path().join(STATE_FILE))
+            .expect("Session should be persisted");
+        let persisted = Session::from_json(&serde_json::from_str(&content).unwrap());
+        assert_eq!(persisted.as_ref(), daemon.session());
+        let metadata = std::fs::metadata(temp_dir.path().join(STATE_FILE)).unwrap();
+        assert_eq!(
+            metadata.permissions().mode() & 0o777,
+            0o600,
+            "The unlock hash should only be readable by root"
+        );
+        assert_eq!(
+            std::fs::read_dir(temp_dir.path()).unwrap().count(),
+            1,
+            "No temporary file should be left"
+        );
+    }
+
+    #[test]
+    fn test_start_refuses_second_session_and_empty_sessions() {
+        let (_temp_dir, _enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::now();
+
+        assert!(daemon.handle(start(25, &["unknown"]), now).is_err());
+        assert!(daemon.handle(start(25, &["coding"]), now).is_ok());
+        assert_eq!(
+            daemon.handle(start(5, &["netflix"]), now),
+            Err("a session is already running".to_string())
+        );
+    }
+
+    #[test]
+    fn test_stop_and_extend() {
+        let (temp_dir, enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+
+        assert!(daemon.handle(Request::Stop, now).is_err());
+        assert!(daemon.handle(Request::Extend { minutes: 5 }, now).is_err());
+        daemon.handle(start(10, &["x"]), now).unwrap();
+        assert_eq!(
+            daemon.handle(Request::Extend { minutes: 5 }, now),
+            Ok("15m 00s left".to_string())
+        );
+        for minutes in [MAX_MINUTES + 1, u64::MAX] {
+            assert!(daemon.handle(Request::Extend { minutes }, now).is_err());
+            assert!(daemon.handle(start(minutes, &["x"]), now).is_err());
+        }
+        assert_eq!(daemon.handle(Request::Stop, now), Ok("stopped".to_string()));
+
+        assert!(daemon.session().is_none());
+        assert!(!temp_dir.path().join(STATE_FILE).exists());
+        assert_eq!(enforcer.calls.lock().unwrap().last().unwrap(), "lift");
+    }
+
+    #[test]
+    fn test_strict_session_refuses_stop() {
+        let (temp_dir, enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+
+        let reply = daemon
+            .handle(start(10, &["netflix", STRICT_FLAG]), now)
+            .unwrap();
+        let code = reply
+            .rsplit(' ')
+            .next()
+            .expect("The reply should end with the code");
+        assert!(daemon.session().unwrap().is_strict());
+        assert!(!std::fs::read_to_string(temp_dir.path().join(STATE_FILE))
+            .unwrap()
+            .contains(code));
+
+        assert!(daemon.handle(Request::Stop, now).is_err());
+        let wrong = Request::Unlock {
+            code: "AAAA-AAAA-AAAA".to_string(),
+        };
+        assert_eq!(
+            daemon.handle(wrong, now),
+            Err("wrong unlock code".to_string())
+        );
+        assert!(daemon.session().is_some(), "Session should still run");
+        let unlock = Request::Unlock {
+            code: code.to_string(),
+        };
+        assert_eq!(daemon.handle(unlock, now), Ok("unlocked".to_string()));
+
+        assert!(daemon.session().is_none());
+        assert_eq!(enforcer.calls.lock().unwrap().last().unwrap(), "lift");
+        let events: Vec<Value> = daemon.history.events().unwrap();
+        let names: Vec<&str> = events
+            .iter()
+            .map(|event| event["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec![STOP_REFUSED, UNLOCK_FAILED, UNLOCK]);
+    }
+
+    #[test]
+    fn test_unlock_without_history() {
+        let (temp_dir, _enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+        let reply = daemon
+            .handle(start(10, &["netflix", STRICT_FLAG]), now)
+            .unwrap();
+        let code = reply.rsplit(' ').next().unwrap().to_string();
+        // The history can't be written under a regular file
+        let blocker = temp_dir.path().join("blocker");
+        std::fs::write(&blocker, "").unwrap();
+        daemon.history = History::new(blocker.join(HISTORY_FILE));
+
+        assert_eq!(
+            daemon.handle(Request::Unlock { code }, now),
+            Ok("unlocked".to_string())
+        );
+        assert!(daemon.session().is_none());
+    }
+
+    #[test]
+    fn test_unlock_needs_strict_session() {
+        let (_temp_dir, _enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+        let unlock = Request::Unlock {
+            code: "AAAA-AAAA-AAAA".to_string(),
+        };
+
+        assert!(daemon.handle(unlock.clone(), now).is_err());
+        daemon.handle(start(10, &["netflix"]), now).unwrap();
+        assert_eq!(
+            daemon.handle(unlock, now),
+            Err("the session is not strict, use STOP".to_string())
+        );
+    }
+
+    #[test]
+    fn test_stop_with_challenge_and_cooldown() {
+        let (_temp_dir, enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+        daemon
+            .handle(
+                start(30, &["netflix", "--cooldown=5", "--challenge=3"]),
+                now,
+            )
+            .unwrap();
+
+        assert!(daemon.handle(Request::Cancel, now).is_err());
+        let reply = daemon.handle(Request::Stop, now).unwrap();
+        let passage = reply.rsplit(": ").next().unwrap().to_string();
+        assert_eq!(passage.split(' ').count(), 3);
+        let wrong = Request::Answer {
+            text: "not the passage".to_string(),
+        };
+        assert!(daemon.handle(wrong, now).is_err());
+        let passage = daemon.handle(Request::Stop, now).unwrap();
+        let passage = passage.rsplit(": ").next().unwrap().to_string();
+        assert_eq!(
+            daemon.handle(Request::Answer { text: passage }, now),
+            Ok("stopping in 5m 00s, cancel to keep the session".to_string())
+        );
+
+        let status: Value =
+            serde_json::from_str(&daemon.handle(Request::Status, now).unwrap()).unwrap();
+        assert_eq!(status["stopping_in_seconds"], 300);
+        daemon.tick(now + Duration::from_secs(299)).unwrap();
+        assert!(daemon.session().is_some(), "The cooldown is not over");
+        daemon.tick(now + Duration::from_secs(300)).unwrap();
+        assert!(
+            daemon.session().is_none(),
+            "The session should have stopped"
+        );
+        assert_eq!(enforcer.calls.lock().unwrap().last().unwrap(), "lift");
+    }
+
+    #[test]
+    fn test_cancel_cooldown() {
+        let (_temp_dir, _enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+        daemon
+            .handle(start(30, &["netflix", "--cooldown=5"]), now)
+            .unwrap();
+
+        daemon.handle(Request::Stop, now).unwrap();
+        assert_eq!(
+            daemon.handle(Request::Cancel, now),
+            Ok("cancelled, the session goes on".to_string())
+        );
+        daemon.tick(now + Duration::from_secs(600)).unwrap();
+        assert!(daemon.session().is_some(), "The session should go on");
+    }
+
+    #[test]
+    fn test_shorten() {
+        let (_temp_dir, _enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+        daemon.handle(start(30, &["netflix"]), now).unwrap();
+
+        assert_eq!(
+            daemon.handle(Request::Shorten { minutes: 10 }, now),
+            Ok("20m 00s left".to_string())
+        );
+        assert!(daemon
+            .handle(Request::Shorten { minutes: 20 }, now)
+            .is_err());
+    }
+
+    #[test]
+    fn test_add_and_remove_sites() {
+        let (_temp_dir, enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+        daemon.handle(start(30, &["netflix"]), now).unwrap();
+
+        let add = Request::Add {
+            arguments: vec!["youtube".to_string(), "netflix".to_string()],
+        };
+        assert_eq!(
+            daemon.handle(add, now),
+            Ok("also blocking www.youtube.com".to_string())
+        );
+        let remove = |site: &str| Request::Remove {
+            arguments: vec![site.to_string()],
+        };
+        assert_eq!(
+            daemon.handle(remove("netflix"), now),
+            Ok("no longer blocking netflix.com".to_string())
+        );
+        assert!(daemon.handle(remove("youtube"), now).is_err(), "Use STOP");
+
+        let session = daemon.session().unwrap();
+        assert_eq!(session.sites, vec!["www.youtube.com"]);
+        assert_eq!(session.hosts, vec!["www.youtube.com"]);
+        assert_eq!(
+            *enforcer.calls.lock().unwrap(),
+            vec![
+                "apply netflix.com",
+                "lift",
+                "apply netflix.com,www.youtube.com",
+                "lift",
+                "apply www.youtube.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let (_temp_dir, enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+        daemon.handle(start(10, &["netflix"]), now).unwrap();
+
+        assert!(daemon.handle(Request::Resume, now).is_err());
+        daemon.handle(Request::Pause, now).unwrap();
+        assert!(daemon.handle(Request::Pause, now).is_err());
+        let later = now + Duration::from_secs(3600);
+        daemon.tick(later).unwrap();
+        assert!(daemon.session().is_some(), "The timer should be frozen");
+        assert_eq!(
+            daemon.handle(Request::Resume, later),
+            Ok("resumed, 10m 00s left".to_string())
+        );
+
+        assert_eq!(
+            *enforcer.calls.lock().unwrap(),
+            vec!["apply netflix.com", "lift", "lift", "apply netflix.com"]
+        );
+        let events: Vec<Value> = daemon.history.events().unwrap();
+        assert_eq!(events[0]["event"], PAUSE);
+        assert_eq!(events[1]["event"], RESUME);
+        assert_eq!(events[1]["detail"], "paused for 1h 00m 00s");
+    }
+
+    #[test]
+    fn test_pause_limit_per_day() {
+        let (_temp_dir, _enforcer, daemon) = setup_daemon();
+        let mut daemon = daemon.with_pause_limit(1);
+        let now = SystemTime::now();
+        daemon.handle(start(10, &["netflix"]), now).unwrap();
+
+        daemon.handle(Request::Pause, now).unwrap();
+        daemon.handle(Request::Resume, now).unwrap();
+        assert_eq!(
+            daemon.handle(Request::Pause, now),
+            Err("already paused 1 times today, the limit is 1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_strict_session_refuses_loosening() {
+        let (_temp_dir, _enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+        daemon
+            .handle(start(10, &["netflix", "youtube", STRICT_FLAG]), now)
+            .unwrap();
+
+        assert!(daemon.handle(Request::Pause, now).is_err());
+        assert!(daemon.handle(Request::Shorten { minutes: 1 }, now).is_err());
+        let remove = Request::Remove {
+            arguments: vec!["netflix".to_string()],
+        };
+        assert!(daemon.handle(remove, now).is_err());
+        assert!(daemon.handle(Request::Extend { minutes: 5 }, now).is_ok());
+        assert_eq!(daemon.history.count_since(STOP_REFUSED, now).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_status() {
+        let (_temp_dir, _enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(60);
+
+        let idle: Value = serde_json::from_str(&daemon.handle(Request::Status, now).unwrap())
+            .expect("Status should be JSON");
+        assert_eq!(idle["active"], false);
+
+        daemon.handle(start(1, &["netflix"]), now).unwrap();
+        let later = now + Duration::from_secs(20);
+        let active: Value =
+            serde_json::from_str(&daemon.handle(Request::Status, later).unwrap()).unwrap();
+        assert_eq!(active["active"], true);
+        assert_eq!(active["sites"], serde_json::json!(["netflix.com"]));
+        assert_eq!(active["remaining_seconds"], 40);
+        assert_eq!(active["strict"], false);
+        assert!(active.get("unlock_hash").is_none());
+    }
+
+    #[test]
+    fn test_tick_ends_session_at_deadline() {
+        let (_temp_dir, enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+        daemon.handle(start(1, &["x"]), now).unwrap();
+
+        daemon.tick(now + Duration::from_secs(59)).unwrap();
+        assert!(daemon.session().is_some(), "Session should still run");
+        daemon.tick(now + Duration::from_secs(60)).unwrap();
+        assert!(daemon.session().is_none(), "Session should have ended");
+        assert_eq!(enforcer.calls.lock().unwrap().last().unwrap(), "lift");
+    }
+
+    #[test]
+    fn test_restore_resumes_running_session() {
+        let (temp_dir, _enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        daemon.handle(start(10, &["netflix"]), now).unwrap();
+
+        let enforcer = RecordingEnforcer::default();
+        let mut restarted = new_daemon(&temp_dir, &enforcer);
+        restarted.restore(now + Duration::from_secs(60)).unwrap();
+
+        assert_eq!(restarted.session(), daemon.session());
+        assert_eq!(
+            *enforcer.calls.lock().unwrap(),
+            vec!["lift", "apply netflix.com"]
+        );
+    }
+
+    #[test]
+    fn test_restore_lifts_expired_session() {
+        let (temp_dir, _enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+        daemon.handle(start(10, &["netflix"]), now).unwrap();
+
+        let enforcer = RecordingEnforcer::default();
+        let mut restarted = new_daemon(&temp_dir, &enforcer);
+        restarted.restore(now + Duration::from_secs(3600)).unwrap();
+
+        assert!(restarted.session().is_none());
+        assert!(!temp_dir.path().join(STATE_FILE).exists());
+        assert_eq!(*enforcer.calls.lock().unwrap(), vec!["lift"]);
+    }
+
+    #[test]
+    fn test_serve_answers_over_socket() {
+        let (temp_dir, _enforcer, mut daemon) = setup_daemon();
+        let socket = temp_dir.path().join("focus.sock");
+        let listener = bind_control_socket(&socket, "focus-missing-group").unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let server_done = done.clone();
+        let server = std::thread::spawn(move || {
+            daemon.serve(listener, server_done).unwrap();
+            daemon
+        });
+
+        let started = send_request(&socket, &start(5, &["netflix"]));
+        let refused = send_request(&socket, &start(5, &["netflix"]));
+        let status = send_request(&socket, &Request::Status);
+        done.store(true, Ordering::Relaxed);
+        let daemon = server.join().unwrap();
+
+        assert_eq!(started.unwrap(), "blocking for 5 minutes");
+        assert_eq!(
+            refused.expect_err("Should be refused").to_string(),
+            "a session is already running"
+        );
+        assert!(status.unwrap().contains("netflix.com"));
+        assert!(daemon.session().is_some());
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600, "Socket should be root only");
+    }
+
+    #[test]
+    fn test_serve_is_not_held_by_a_silent_client() {
+        let (temp_dir, _enforcer, mut daemon) = setup_daemon();
+        let socket = temp_dir.path().join("focus.sock");
+        let listener = bind_control_socket(&socket, "focus-missing-group").unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let server_done = done.clone();
+        let server = std::thread::spawn(move || daemon.serve(listener, server_done).unwrap());
+
+        // Connected but never sending its request
+        let _silent = UnixStream::connect(&socket).unwrap();
+        let asked = std::time::Instant::now();
+        let status = send_request(&socket, &Request::Status);
+        let waited = asked.elapsed();
+        done.store(true, Ordering::Relaxed);
+        server.join().unwrap();
+
+        assert!(status.is_ok());
+        assert!(waited < REQUEST_TIMEOUT, "Answered after {:?}", waited);
+    }
+
+    #[test]
+    fn test_tick_starts_scheduled_block() {
+        let (_temp_dir, enforcer, daemon) = setup_daemon();
+        let schedule = Schedule {
+            name: "mornings".to_string(),
+            days: vec![chrono::Weekday::Mon],
+            start: chrono::NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            end: chrono::NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            arguments: vec!["netflix".to_string()],
+            pomodoro: None,
+            exceptions: Vec::new(),
+        };
+        let mut daemon = daemon.with_scheduler(Scheduler::new(vec![schedule], Vec::new()));
+        // 2026-10-19 is a Monday
+        let local = |time: &str| {
+            system_time(
+                chrono::NaiveDateTime::parse_from_str(
+                    &format!("2026-10-19 {}", time),
+                    "%Y-%m-%d %H:%M",
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+
+        daemon.tick(local("08:59")).unwrap();
+        assert!(daemon.session().is_none());
+        daemon.tick(local("10:30")).unwrap();
+        let session = daemon.session().expect("The block should start");
+        assert_eq!(session.deadline, local("12:00"));
+        assert_eq!(*enforcer.calls.lock().unwrap(), vec!["apply netflix.com"]);
+
+        daemon.handle(Request::Stop, local("10:31")).unwrap();
+        daemon.tick(local("10:32")).unwrap();
+        assert!(
+            daemon.session().is_none(),
+            "A block stopped early should not start again"
+        );
+    }
+
+    #[test]
+    fn test_tick_notifies_sessions_and_breaks() {
+        let (_temp_dir, _enforcer, daemon) = setup_daemon();
+        let schedule = Schedule {
+            name: "mornings".to_string(),
+            days: vec![chrono::Weekday::Mon],
+            start: chrono::NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            end: chrono::NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
+            arguments: vec!["netflix".to_string()],
+            pomodoro: Some(Pomodoro {
+                focus: chrono::TimeDelta::minutes(25),
+                pause: chrono::TimeDelta::minutes(5),
+            }),
+            exceptions: Vec::new(),
+        };
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut daemon = daemon
+            .with_scheduler(Scheduler::new(vec![schedule], Vec::new()))
+            .with_notifier(Box::new(notifier.clone()));
+        // 2026-10-19 is a Monday
+        let local = |time: &str| {
+            system_time(
+                chrono::NaiveDateTime::parse_from_str(
+                    &format!("2026-10-19 {}", time),
+                    "%Y-%m-%d %H:%M",
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+
+        for time in ["09:00", "09:19", "09:20", "09:21", "09:25"] {
+            daemon.tick(local(time)).unwrap();
+        }
+        let status: Value =
+            serde_json::from_str(&daemon.handle(Request::Status, local("09:26")).unwrap()).unwrap();
+        assert_eq!(status["break_until"], unix_seconds(local("09:30")));
+        for time in ["09:30", "09:50", "09:55"] {
+            daemon.tick(local(time)).unwrap();
+        }
+        let status: Value =
+            serde_json::from_str(&daemon.handle(Request::Status, local("09:56")).unwrap()).unwrap();
+        assert!(
+            status["break_until"].is_null(),
+            "The last block of the day ended"
+        );
+        assert_eq!(
+            notifier.summaries(),
+            vec![
+                "Focus session started",
+                "Focus session ending soon",
+                "Break time",
+                "Break over",
+                "Focus session ending soon",
+                "Focus session ended"
+            ]
+        );
+        assert_eq!(notifier.sent()[2].body, "The blocks are lifted until 09:30");
+    }
+
+    #[test]
+    fn test_extend_warns_again() {
+        let (_temp_dir, _enforcer, daemon) = setup_daemon();
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut daemon = daemon.with_notifier(Box::new(notifier.clone()));
+        let now = SystemTime::now();
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+
+        daemon.handle(start(10, &["netflix"]), now).unwrap();
+        daemon.tick(now + minutes(6)).unwrap();
+        daemon
+            .handle(Request::Extend { minutes: 10 }, now + minutes(6))
+            .unwrap();
+        daemon.tick(now + minutes(7)).unwrap();
+        daemon.tick(now + minutes(16)).unwrap();
+        daemon.tick(now + minutes(17)).unwrap();
+        daemon.handle(Request::Stop, now + minutes(18)).unwrap();
+
+        assert_eq!(
+            notifier.summaries(),
+            vec![
+                "Focus session started",
+                "Focus session ending soon",
+                "Focus session ending soon"
+            ],
+            "A stop asked for is not notified"
+        );
+    }
+
+    #[test]
+    fn test_tick_lifts_blocks_for_meetings() {
+        let (temp_dir, enforcer, daemon) = setup_daemon();
+        let path = temp_dir.path().join("calendar.ics");
+        let focus = "BEGIN:VEVENT\nUID:1\nSUMMARY:Focus\nDESCRIPTION:netflix\n\
+                     DTSTART:20261019T090000\nDTEND:20261019T120000\nEND:VEVENT\n";
+        std::fs::write(&path, focus).unwrap();
+        let calendar = Calendar::new(CalendarSource {
+            path: path.clone(),
+            focus: "focus".to_string(),
+            arguments: Vec::new(),
+        });
+        let mut daemon = daemon.with_scheduler(Scheduler::new(Vec::new(), vec![calendar]));
+        let local = |time: &str| {
+            system_time(
+                chrono::NaiveDateTime::parse_from_str(
+                    &format!("2026-10-19 {}", time),
+                    "%Y-%m-%d %H:%M",
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+
+        daemon.tick(local("09:00")).unwrap();
+        assert_eq!(
+            daemon
+                .session()
+                .expect("The focus event should start")
+                .deadline,
+            local("12:00")
+        );
+        // A meeting is added to the running focus event
+        let meeting = "BEGIN:VEVENT\nUID:2\nSUMMARY:Review\n\
+                       DTSTART:20261019T100000\nDTEND:20261019T103000\nEND:VEVENT\n";
+        std::fs::write(&path, format!("{}{}", focus, meeting)).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        daemon.tick(local("10:10")).unwrap();
+        assert!(
+            daemon.session().is_none(),
+            "The meeting should lift the blocks"
+        );
+        daemon.tick(local("10:30")).unwrap();
+        assert_eq!(
+            daemon.session().expect("The block should resume").deadline,
+            local("12:00")
+        );
+        assert_eq!(
+            *enforcer.calls.lock().unwrap(),
+            vec!["apply netflix.com", "lift", "apply netflix.com"]
+        );
+    }
+
+    /// Tells the websites are always used.
+    struct BusyProbe;
+
+    impl UsageProbe for BusyProbe {
+        fn in_use(&mut self, _sites: &[String]) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_budgets_block_once_used_up() {
+        let (_temp_dir, enforcer, daemon) = setup_daemon();
+        let mut daemon = daemon.with_budgets(vec![Budget {
+            name: "youtube".to_string(),
+            daily: Duration::from_secs(15 * 60),
+        }]);
+        let now = start_of_local_day(SystemTime::now()) + Duration::from_secs(10 * 3600);
+        let minutes = |minutes: u64| now + Duration::from_secs(minutes * 60);
+        let allow = |minutes: u64| Request::Allow {
+            minutes,
+            arguments: vec!["youtube".to_string()],
+        };
+
+        daemon.tick(now).unwrap();
+        daemon.tick(minutes(1)).unwrap();
+        assert_eq!(
+            daemon.handle(allow(10), minutes(1)),
+            Ok("youtube allowed for 10m 00s, 5m 00s left today".to_string())
+        );
+        daemon.tick(minutes(5)).unwrap();
+        daemon.handle(start(30, &["netflix"]), minutes(5)).unwrap();
+        assert_eq!(
+            daemon.handle(
+                Request::Allow {
+                    minutes: 5,
+                    arguments: vec!["netflix".to_string()],
+                },
+                minutes(6)
+            ),
+            Err("the running session blocks it, it can't be allowed".to_string())
+        );
+        daemon.tick(minutes(11)).unwrap();
+        daemon.handle(Request::Stop, minutes(12)).unwrap();
+        daemon.tick(minutes(12)).unwrap();
+        assert_eq!(
+            *enforcer.calls.lock().unwrap(),
+            vec!["apply netflix.com", "lift"],
+            "The budget should stay reachable until used up"
+        );
+
+        let status = daemon.handle(Request::Status, minutes(12)).unwrap();
+        let status: Value = serde_json::from_str(&status).unwrap();
+        assert_eq!(status["budgets"][0]["remaining_seconds"], 5 * 60);
+        daemon.handle(allow(10), minutes(13)).unwrap();
+        assert!(daemon
+            .handle(allow(1), minutes(14))
+            .unwrap_err()
+            .contains("used up"));
+        daemon.tick(minutes(18)).unwrap();
+        assert_eq!(
+            enforcer.calls.lock().unwrap().last().unwrap(),
+            "apply www.youtube.com",
+            "The budget should be blocked once the time checked out is over"
+        );
+    }
+
+    #[test]
+    fn test_budgets_count_the_time_used() {
+        let (_temp_dir, enforcer, daemon) = setup_daemon();
+        let mut daemon = daemon
+            .with_budgets(vec![Budget {
+                name: "youtube".to_string(),
+                daily: Duration::from_secs(15 * 60),
+            }])
+            .with_usage_probe(Box::new(BusyProbe));
+        let now = start_of_local_day(SystemTime::now()) + Duration::from_secs(10 * 3600);
+
+        for minute in 0..15 {
+            daemon.tick(now + Duration::from_secs(minute * 60)).unwrap();
+        }
+        assert!(enforcer.calls.lock().unwrap().is_empty());
+
+        daemon.tick(now + Duration::from_secs(15 * 60)).unwrap();
+        assert_eq!(
+            *enforcer.calls.lock().unwrap(),
+            vec!["apply www.youtube.com"]
+        );
+    }
+
+    #[test]
+    fn test_peek_unblocks_a_website_for_a_while() {
+        let (_temp_dir, enforcer, daemon) = setup_daemon();
+        let mut daemon = daemon.with_peek_limits(Some(1), None);
+        let now = SystemTime::UNIX_EPOCH;
+        let minutes = |minutes: u64| now + Duration::from_secs(minutes * 60);
+        let peek = |site: &str| Request::Peek {
+            minutes: 5,
+            site: site.to_string(),
+            reason: "a tutorial".to_string(),
+        };
+        assert!(daemon.handle(peek("youtube"), now).is_err());
+        daemon
+            .handle(start(30, &["netflix", "youtube"]), now)
+            .unwrap();
+
+        assert_eq!(
+            daemon.handle(peek("discord"), now),
+            Err("discord is not blocked by the session".to_string())
+        );
+        assert_eq!(
+            daemon.handle(peek("YouTube"), minutes(1)),
+            Ok("unblocked www.youtube.com for 5m 00s, blocked again after".to_string())
+        );
+        let status: Value =
+            serde_json::from_str(&daemon.handle(Request::Status, minutes(2)).unwrap()).unwrap();
+        assert_eq!(status["peek"]["remaining_seconds"], 4 * 60);
+        daemon.tick(minutes(5)).unwrap();
+        daemon.tick(minutes(6)).unwrap();
+        assert!(daemon
+            .handle(peek("netflix"), minutes(7))
+            .unwrap_err()
+            .contains("already peeked 1 times during this session"));
+
+        assert_eq!(
+            *enforcer.calls.lock().unwrap(),
+            vec![
+                "apply netflix.com,www.youtube.com",
+                "lift",
+                "apply netflix.com",
+                "lift",
+                "apply netflix.com,www.youtube.com",
+            ]
+        );
+        let events = daemon.history.events().unwrap();
+        assert_eq!(events[0]["event"], PEEK);
+        assert_eq!(
+            events[0]["detail"],
+            "www.youtube.com for 5m 00s: a tutorial"
+        );
+    }
+
+    #[test]
+    fn test_peek_refused_during_strict_session() {
+        let (_temp_dir, _enforcer, mut daemon) = setup_daemon();
+        let now = SystemTime::UNIX_EPOCH;
+        daemon
+            .handle(start(30, &["youtube", STRICT_FLAG]), now)
+            .unwrap();
+
+        let refused = daemon.handle(
+            Request::Peek {
+                minutes: 5,
+                site: "youtube".to_string(),
+                reason: "a tutorial".to_string(),
+            },
+            now,
+        );
+
+        assert!(refused.unwrap_err().contains("strict"));
+        assert_eq!(daemon.history.events().unwrap()[0]["event"], STOP_REFUSED);
+    }
+
+    // A daemon enforcing through the real code, on an in-memory hosts file
+    fn system_daemon(
+        temp_dir: &TempDir,
+        hosts: &Arc<MemoryHostsStore>,
+        runner: &Arc<RecordingRunner>,
+    ) -> Daemon {
+        let history = History::new(temp_dir.path().join(HISTORY_FILE));
+        let enforcer = SystemEnforcer::new(
+            PolicyDirs {
+                chromium: vec![temp_dir.path().join("chromium")],
+                firefox: vec![temp_dir.path().join("firefox")],
+            },
+            hosts.clone(),
+            runner.clone(),
+            history.clone(),
+        );
+        Daemon::new(
+            Box::new(enforcer),
+            temp_dir.path().join(STATE_FILE),
+            history,
+        )
+    }
+
+    #[test]
+    fn test_system_enforcer_start_wait_restore() {
+        let temp_dir = TempDir::new().unwrap();
+        let hosts = Arc::new(MemoryHostsStore::new("127.0.0.1 localhost\n"));
+        let runner = Arc::new(RecordingRunner::default());
+        let chromium_policy = temp_dir
+            .path()
+            .join("chromium/rust_can_make_you_focus.json");
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let mut daemon = system_daemon(&temp_dir, &hosts, &runner);
+
+        daemon.handle(start(25, &["netflix"]), now).unwrap();
+        assert!(is_managed_block_intact(
+            &hosts.content().unwrap(),
+            &["netflix.com"]
+        ));
+        assert!(chromium_policy.exists());
+        assert_eq!(runner.calls().len(), 1, "The DNS cache should be flushed");
+
+        // The daemon restarts halfway, its successor applies the blocks again
+        drop(daemon);
+        hosts.tamper(Some("127.0.0.1 localhost\n"));
+        let mut daemon = system_daemon(&temp_dir, &hosts, &runner);
+        daemon.restore(now + Duration::from_secs(600)).unwrap();
+        assert!(daemon.session().is_some());
+        assert!(is_managed_block_intact(
+            &hosts.content().unwrap(),
+            &["netflix.com"]
+        ));
+
+        daemon.tick(now + Duration::from_secs(25 * 60)).unwrap();
+        assert!(daemon.session().is_none());
+        assert_eq!(hosts.content().unwrap(), "127.0.0.1 localhost\n");
+        assert!(!chromium_policy.exists());
+        assert!(!temp_dir.path().join(STATE_FILE).exists());
+    }
+
+    #[test]
+    fn test_system_enforcer_leaves_exceptions_to_the_browsers() {
+        let temp_dir = TempDir::new().unwrap();
+        let hosts = Arc::new(MemoryHostsStore::new("127.0.0.1 localhost\n"));
+        let runner = Arc::new(RecordingRunner::default());
+        let mut daemon = system_daemon(&temp_dir, &hosts, &runner);
+
+        daemon
+            .handle(
+                start(
+                    25,
+                    &["netflix", "youtube", "--except=www.youtube.com/watch*"],
+                ),
+                SystemTime::UNIX_EPOCH,
+            )
+            .unwrap();
+
+        let content = hosts.content().unwrap();
+        assert!(is_managed_block_intact(&content, &["netflix.com"]));
+        assert!(!content.contains("www.youtube.com"));
+        assert_eq!(daemon.session().unwrap().hosts, vec!["netflix.com"]);
+        let policy = std::fs::read_to_string(
+            temp_dir
+                .path()
+                .join("chromium/rust_can_make_you_focus.json"),
+        )
+        .unwrap();
+        assert!(policy.contains("www.youtube.com"));
+        assert!(policy.contains("URLAllowlist"));
+    }
+
+    #[test]
+    fn test_system_enforcer_blocks_whole_websites_without_browsers() {
+        let temp_dir = TempDir::new().unwrap();
+        let hosts = Arc::new(MemoryHostsStore::new("127.0.0.1 localhost\n"));
+        let runner = Arc::new(RecordingRunner::default());
+        let history = History::new(temp_dir.path().join(HISTORY_FILE));
+        let enforcer = SystemEnforcer::new(
+            PolicyDirs {
+                chromium: Vec::new(),
+                firefox: Vec::new(),
+            },
+            hosts.clone(),
+            runner,
+            history.clone(),
+        );
+        let mut daemon = Daemon::new(
+            Box::new(enforcer),
+            temp_dir.path().join(STATE_FILE),
+            history,
+        );
+
+        daemon
+            .handle(start(25, &["studying"]), SystemTime::UNIX_EPOCH)
+            .unwrap();
+
+        assert!(hosts.content().unwrap().contains("www.youtube.com"));
+        let session = daemon.session().unwrap();
+        assert!(session.exceptions.is_empty());
+        assert!(session.hosts.contains(&"www.youtube.com".to_string()));
+    }
+
+    #[test]
+    fn test_system_enforcer_write_error() {
+        let temp_dir = TempDir::new().unwrap();
+        let hosts = Arc::new(MemoryHostsStore::new("127.0.0.1 localhost\n"));
+        hosts.fail_writes(true);
+        let runner = Arc::new(RecordingRunner::default());
+        let mut daemon = system_daemon(&temp_dir, &hosts, &runner);
+
+        let reply = daemon.handle(start(25, &["netflix"]), SystemTime::UNIX_EPOCH);
+
+        assert!(reply.unwrap_err().contains("injected write failure"));
+        assert!(daemon.session().is_none());
+        assert!(!temp_dir.path().join(STATE_FILE).exists());
+        assert!(
+            !temp_dir
+                .path()
+                .join("chromium/rust_can_make_you_focus.json")
+                .exists(),
+            "The browser policies must be lifted again"
+        );
+        assert!(runner.calls().is_empty());
+    }
+
+    #[test]
+    fn test_system_enforcer_flush_error() {
+        let temp_dir = TempDir::new().unwrap();
+        let hosts = Arc::new(MemoryHostsStore::new("127.0.0.1 localhost\n"));
+        let runner = Arc::new(RecordingRunner::default());
+        runner.fail(true);
+        let mut daemon = system_daemon(&temp_dir, &hosts, &runner);
+
+        let reply = daemon.handle(start(25, &["netflix"]), SystemTime::UNIX_EPOCH);
+
+        assert!(reply.is_ok(), "A failed DNS flush is only a warning");
+        assert!(daemon.session().is_some());
+        assert!(is_managed_block_intact(
+            &hosts.content().unwrap(),
+            &["netflix.com"]
+        ));
+    }
+
+    #[test]
+    fn test_system_enforcer_lifts_hosts_when_policies_fail() {
+        let temp_dir = TempDir::new().unwrap();
+        let hosts = Arc::new(MemoryHostsStore::new("127.0.0.1 localhost\n"));
+        let runner = Arc::new(RecordingRunner::default());
+        let chromium_policy = temp_dir
+            .path()
+            .join("chromium/rust_can_make_you_focus.json");
+        let mut daemon = system_daemon(&temp_dir, &hosts, &runner);
+        daemon
+            .handle(start(25, &["netflix"]), SystemTime::UNIX_EPOCH)
+            .unwrap();
+        // A directory in place of the policy can't be removed with remove_file
+        std::fs::remove_file(&chromium_policy).unwrap();
+        std::fs::create_dir(&chromium_policy).unwrap();
+
+        let reply = daemon.handle(Request::Stop, SystemTime::UNIX_EPOCH);
+
+        assert!(reply.is_err(), "The failed removal should be reported");
+        assert_eq!(
+            hosts.content().unwrap(),
+            "127.0.0.1 localhost\n",
+            "The hosts file should be unblocked anyway"
+        );
+    }
+
+    #[test]
+    fn test_bind_refuses_running_daemon() {
+        let temp_dir = TempDir::new().unwrap();
+        let socket = temp_dir.path().join("focus.sock");
+        let _listener = bind_control_socket(&socket, "focus-missing-group").unwrap();
+
+        let error = bind_control_socket(&socket, "focus-missing-group")
+            .expect_err("Should not replace a live socket");
+        assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+    }
+}
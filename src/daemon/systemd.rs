@@ -0,0 +1,549 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use chrono::{Datelike, NaiveDate, NaiveTime, Weekday};
+
+use crate::schedule::plan::Schedule;
+use crate::utils::command::CommandRunner;
+
+/// The prefix of every generated unit, used to find them again on uninstall.
+pub const UNIT_PREFIX: &str = "rust_can_make_you_focus";
+pub const DAEMON_UNIT: &str = "rust_can_make_you_focus.service";
+pub const RECOVER_UNIT: &str = "rust_can_make_you_focus-recover.service";
+pub const SYSTEM_UNIT_DIR: &str = "/etc/systemd/system";
+
+const HEADER: &str = "# Generated by rust_can_make_you_focus install-systemd, do not edit\n";
+
+/// A systemd unit file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    pub name: String,
+    pub content: String,
+}
+
+/// Generates the systemd units running the focus daemon and the schedules.
+///
+/// The system units are the daemon, a recovery unit lifting at boot the blocks of a
+/// session which ended while the machine was off, and a timer per schedule asking
+/// the daemon to start its blocks. A user can't run the daemon, the `--user` units
+/// are only the timers, talking to the system daemon through its socket.
+///
+/// The output only depends on the arguments, in the order of the schedules.
+///
+/// # Arguments
+///
+/// * `exe` - The installed binary
+/// * `config` - The configuration file the schedules were read from
+/// * `schedules` - The schedules to generate timers for
+/// * `user` - Whether to generate the `--user` units
+pub fn systemd_units(exe: &Path, config: &Path, schedules: &[Schedule], user: bool) -> Vec<Unit> {
+    let exe = exec_argument(&exe.display().to_string());
+    let config = exec_argument(&format!("--config={}", config.display()));
+    let mut units = Vec::new();
+    if !user {
+        units.push(Unit {
+            name: DAEMON_UNIT.to_string(),
+            content: format!(
+                "{HEADER}[Unit]\n\
+                 Description=rust_can_make_you_focus daemon\n\
+                 After=network.target {RECOVER_UNIT}\n\
+                 \n\
+                 [Service]\n\
+                 ExecStart={exe} daemon {config}\n\
+                 Restart=on-failure\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy=multi-user.target\n"
+            ),
+        });
+        units.push(Unit {
+            name: RECOVER_UNIT.to_string(),
+            content: format!(
+                "{HEADER}[Unit]\n\
+                 Description=Lift the rust_can_make_you_focus blocks left by an ended session\n\
+                 After=local-fs.target\n\
+                 Before={DAEMON_UNIT}\n\
+                 \n\
+                 [Service]\n\
+                 Type=oneshot\n\
+                 ExecStart={exe} recover\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy=multi-user.target\n"
+            ),
+        });
+    }
+    for (schedule, slug) in schedules.iter().zip(schedule_slugs(schedules)) {
+        let name = format!("{}-schedule-{}", UNIT_PREFIX, slug);
+        // The specifiers are expanded in the descriptions as well
+        let description = schedule.name.replace('%', "%%");
+        let calendars: String = on_calendar(schedule)
+            .iter()
+            .map(|calendar| format!("OnCalendar={}\n", calendar))
+            .collect();
+        units.push(Unit {
+            name: format!("{}.timer", name),
+            content: format!(
+                "{HEADER}[Unit]\n\
+                 Description=rust_can_make_you_focus schedule {}\n\
+                 \n\
+                 [Timer]\n\
+                 {calendars}\
+                 AccuracySec=1s\n\
+                 Persistent=true\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy=timers.target\n",
+                description
+            ),
+        });
+        units.push(Unit {
+            name: format!("{}.service", name),
+            content: format!(
+                "{HEADER}[Unit]\n\
+                 Description=Start the blocks of the rust_can_make_you_focus schedule {}\n\
+                 \n\
+                 [Service]\n\
+                 Type=oneshot\n\
+                 ExecStart={exe} schedule run {slug} {config}\n",
+                description
+            ),
+        });
+    }
+    units
+}
+
+/// Quotes an argument of an `ExecStart=` line the way systemd splits them.
+///
+/// The `%` specifiers and the `$` variables are escaped by doubling them, the
+/// arguments with other characters than the safe ones are double quoted.
+fn exec_argument(argument: &str) -> String {
+    let escaped = argument.replace('%', "%%").replace('$', "$$");
+    let safe = !escaped.is_empty()
+        && escaped
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-+:=,@%$".contains(c));
+    if safe {
+        return escaped;
+    }
+    let mut quoted = String::from("\"");
+    for c in escaped.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns the name of the schedules in unit names and `schedule run`, e.g.
+/// `deep-work` for `Deep work`, unique even when the names are not.
+pub fn schedule_slugs(schedules: &[Schedule]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    schedules
+        .iter()
+        .enumerate()
+        .map(|(index, schedule)| {
+            let slug = schedule
+                .name
+                .to_lowercase()
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<_>>()
+                .join("-");
+            let slug = if slug.is_empty() || seen.contains(&slug) {
+                format!(
+                    "{}-{}",
+                    if slug.is_empty() { "schedule" } else { &slug },
+                    index + 1
+                )
+            } else {
+                slug
+            };
+            seen.insert(slug.clone());
+            slug
+        })
+        .collect()
+}
+
+/// Returns the `OnCalendar=` expressions of the block starts of a schedule.
+///
+/// A Pomodoro block starting after midnight is due the day after the range starts.
+fn on_calendar(schedule: &Schedule) -> Vec<String> {
+    // Any date works, only the times of the blocks and the day they fall on matter
+    let date = NaiveDate::from_ymd_opt(2001, 1, 1).unwrap_or_default();
+    let mut reference = schedule.clone();
+    reference.days = vec![date.weekday()];
+    reference.exceptions.clear();
+    reference
+        .blocks_on(date)
+        .iter()
+        .map(|block| {
+            let shift = (block.start.date() - date).num_days();
+            let days: Vec<String> = schedule
+                .days
+                .iter()
+                // systemd takes the English abbreviations chrono formats
+                .map(|day| day_after(*day, shift).to_string())
+                .collect();
+            format!("{} *-*-* {}", days.join(","), time(block.start.time()))
+        })
+        .collect()
+}
+
+fn day_after(day: Weekday, days: i64) -> Weekday {
+    (0..days).fold(day, |day, _| day.succ())
+}
+
+fn time(time: NaiveTime) -> String {
+    time.format("%H:%M:%S").to_string()
+}
+
+/// Returns where the units are installed, the user units under the user's
+/// configuration directory.
+pub fn unit_dir(user: bool) -> PathBuf {
+    if !user {
+        return PathBuf::from(SYSTEM_UNIT_DIR);
+    }
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(|| PathBuf::from(".config"))
+        .join("systemd/user")
+}
+
+/// Writes `units` to `dir` and enables them, replacing the ones installed before.
+///
+/// The timers and the daemon are started right away, the recovery unit only runs
+/// at the next boot.
+///
+/// # Returns
+///
+/// The paths written
+pub fn install_units(
+    units: &[Unit],
+    dir: &Path,
+    user: bool,
+    runner: &dyn CommandRunner,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let names: Vec<&str> = units.iter().map(|unit| unit.name.as_str()).collect();
+    // Units of a schedule removed from the configuration
+    let stale: Vec<String> = installed_units(dir)?
+        .into_iter()
+        .filter(|name| !names.contains(&name.as_str()))
+        .collect();
+    if !stale.is_empty() {
+        systemctl(runner, user, "disable --now", &stale)?;
+        for name in &stale {
+            std::fs::remove_file(dir.join(name))?;
+        }
+    }
+    std::fs::create_dir_all(dir)?;
+    let mut paths = Vec::new();
+    for unit in units {
+        let path = dir.join(&unit.name);
+        std::fs::write(&path, &unit.content)?;
+        paths.push(path);
+    }
+    systemctl(runner, user, "daemon-reload", &[])?;
+    let started: Vec<String> = names
+        .iter()
+        .filter(|name| **name == DAEMON_UNIT || name.ends_with(".timer"))
+        .map(|name| name.to_string())
+        .collect();
+    systemctl(runner, user, "enable --now", &started)?;
+    if names.contains(&RECOVER_UNIT) {
+        systemctl(runner, user, "enable", &[RECOVER_UNIT.to_string()])?;
+    }
+    Ok(paths)
+}
+
+/// Stops, disables and removes the units installed by `install_units`.
+///
+/// # Returns
+///
+/// The paths removed
+pub fn uninstall_units(
+    dir: &Path,
+    user: bool,
+    runner: &dyn CommandRunner,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let names = installed_units(dir)?;
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    systemctl(runner, user, "disable --now", &names)?;
+    let mut paths = Vec::new();
+    for name in names {
+        let path = dir.join(name);
+        std::fs::remove_file(&path)?;
+        paths.push(path);
+    }
+    systemctl(runner, user, "daemon-reload", &[])?;
+    Ok(paths)
+}
+
+/// The names of the generated units found in `dir`, sorted.
+fn installed_units(dir: &Path) -> Result<Vec<String>, std::io::Error> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if name.starts_with(UNIT_PREFIX) && (name.ends_with(".service") || name.ends_with(".timer"))
+        {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+fn systemctl(
+    runner: &dyn CommandRunner,
+    user: bool,
+    command: &str,
+    units: &[String],
+) -> Result<(), std::io::Error> {
+    let mut arguments: Vec<&str> = Vec::new();
+    if user {
+        arguments.push("--user");
+    }
+    arguments.extend(command.split(' '));
+    arguments.extend(units.iter().map(String::as_str));
+    runner.run("systemctl", &arguments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::config::Config;
+    use crate::utils::command::RecordingRunner;
+    use tempfile::TempDir;
+
+    const CONFIG: &str = r#"
+[[schedule]]
+name = "Mornings"
+days = ["weekdays"]
+start = "09:00"
+end = "12:00"
+block = ["coding"]
+
+[[schedule]]
+name = "late nights"
+days = ["fri", "sat"]
+start = "23:00"
+end = "01:00"
+block = ["netflix"]
+pomodoro = { focus = 50, break = 10 }
+"#;
+
+    const DAEMON: &str = "\
+# Generated by rust_can_make_you_focus install-systemd, do not edit
+[Unit]
+Description=rust_can_make_you_focus daemon
+After=network.target rust_can_make_you_focus-recover.service
+
+[Service]
+ExecStart=/usr/local/bin/focus daemon --config=/etc/focus.toml
+Restart=on-failure
+
+[Install]
+WantedBy=multi-user.target
+";
+
+    const RECOVER: &str = "\
+# Generated by rust_can_make_you_focus install-systemd, do not edit
+[Unit]
+Description=Lift the rust_can_make_you_focus blocks left by an ended session
+After=local-fs.target
+Before=rust_can_make_you_focus.service
+
+[Service]
+Type=oneshot
+ExecStart=/usr/local/bin/focus recover
+
+[Install]
+WantedBy=multi-user.target
+";
+
+    const MORNINGS_TIMER: &str = "\
+# Generated by rust_can_make_you_focus install-systemd, do not edit
+[Unit]
+Description=rust_can_make_you_focus schedule Mornings
+
+[Timer]
+OnCalendar=Mon,Tue,Wed,Thu,Fri *-*-* 09:00:00
+AccuracySec=1s
+Persistent=true
+
+[Install]
+WantedBy=timers.target
+";
+
+    const MORNINGS_SERVICE: &str = "\
+# Generated by rust_can_make_you_focus install-systemd, do not edit
+[Unit]
+Description=Start the blocks of the rust_can_make_you_focus schedule Mornings
+
+[Service]
+Type=oneshot
+ExecStart=/usr/local/bin/focus schedule run mornings --config=/etc/focus.toml
+";
+
+    const LATE_NIGHTS_TIMER: &str = "\
+# Generated by rust_can_make_you_focus install-systemd, do not edit
+[Unit]
+Description=rust_can_make_you_focus schedule late nights
+
+[Timer]
+OnCalendar=Fri,Sat *-*-* 23:00:00
+OnCalendar=Sat,Sun *-*-* 00:00:00
+AccuracySec=1s
+Persistent=true
+
+[Install]
+WantedBy=timers.target
+";
+
+    fn units(user: bool) -> Vec<Unit> {
+        let config = Config::parse(CONFIG).unwrap();
+        systemd_units(
+            Path::new("/usr/local/bin/focus"),
+            Path::new("/etc/focus.toml"),
+            &config.schedules,
+            user,
+        )
+    }
+
+    #[test]
+    fn test_systemd_units_golden() {
+        let units = units(false);
+
+        let names: Vec<&str> = units.iter().map(|unit| unit.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "rust_can_make_you_focus.service",
+                "rust_can_make_you_focus-recover.service",
+                "rust_can_make_you_focus-schedule-mornings.timer",
+                "rust_can_make_you_focus-schedule-mornings.service",
+                "rust_can_make_you_focus-schedule-late-nights.timer",
+                "rust_can_make_you_focus-schedule-late-nights.service",
+            ]
+        );
+        assert_eq!(units[0].content, DAEMON);
+        assert_eq!(units[1].content, RECOVER);
+        assert_eq!(units[2].content, MORNINGS_TIMER);
+        assert_eq!(units[3].content, MORNINGS_SERVICE);
+        assert_eq!(units[4].content, LATE_NIGHTS_TIMER);
+        assert_eq!(
+            units,
+            self::units(false),
+            "The output must be deterministic"
+        );
+    }
+
+    #[test]
+    fn test_systemd_units_user() {
+        let units = units(true);
+        assert_eq!(units.len(), 4, "Only the schedule timers and services");
+        assert!(units.iter().all(|unit| unit.name.contains("-schedule-")));
+    }
+
+    #[test]
+    fn test_exec_argument() {
+        assert_eq!(
+            exec_argument("/usr/local/bin/focus"),
+            "/usr/local/bin/focus"
+        );
+        assert_eq!(
+            exec_argument("--config=/home/me/My Focus/focus.toml"),
+            "\"--config=/home/me/My Focus/focus.toml\""
+        );
+        assert_eq!(exec_argument("/opt/100%/$HOME"), "/opt/100%%/$$HOME");
+        assert_eq!(exec_argument("/opt/a\"b\\c"), "\"/opt/a\\\"b\\\\c\"");
+        assert_eq!(exec_argument(""), "\"\"");
+
+        let units = systemd_units(
+            Path::new("/opt/my tools/focus"),
+            Path::new("/etc/focus 100%.toml"),
+            &[],
+            false,
+        );
+        assert!(units[0].content.contains(
+            "ExecStart=\"/opt/my tools/focus\" daemon \"--config=/etc/focus 100%%.toml\"\n"
+        ));
+        assert!(units[1]
+            .content
+            .contains("ExecStart=\"/opt/my tools/focus\" recover\n"));
+    }
+
+    #[test]
+    fn test_schedule_slugs() {
+        let config = Config::parse(CONFIG).unwrap();
+        let mut schedules = config.schedules.clone();
+        schedules.push(schedules[0].clone());
+        schedules[1].name = "!!".to_string();
+
+        assert_eq!(
+            schedule_slugs(&schedules),
+            vec!["mornings", "schedule-2", "mornings-3"]
+        );
+    }
+
+    #[test]
+    fn test_install_and_uninstall_units() {
+        let temp_dir = TempDir::new().unwrap();
+        let runner = RecordingRunner::default();
+        // A timer of a schedule since removed from the configuration
+        let stale = temp_dir
+            .path()
+            .join("rust_can_make_you_focus-schedule-evenings.timer");
+        std::fs::write(&stale, "").unwrap();
+        std::fs::write(temp_dir.path().join("other.service"), "").unwrap();
+
+        let units = units(false);
+        let paths = install_units(&units, temp_dir.path(), false, &runner).unwrap();
+
+        assert_eq!(paths.len(), 6);
+        assert_eq!(std::fs::read_to_string(&paths[0]).unwrap(), DAEMON);
+        assert!(!stale.exists());
+        assert_eq!(
+            runner.calls(),
+            vec![
+                "systemctl disable --now rust_can_make_you_focus-schedule-evenings.timer",
+                "systemctl daemon-reload",
+                "systemctl enable --now rust_can_make_you_focus.service \
+                 rust_can_make_you_focus-schedule-mornings.timer \
+                 rust_can_make_you_focus-schedule-late-nights.timer",
+                "systemctl enable rust_can_make_you_focus-recover.service",
+            ]
+        );
+
+        let runner = RecordingRunner::default();
+        let removed = uninstall_units(temp_dir.path(), true, &runner).unwrap();
+        assert_eq!(removed.len(), 6);
+        assert!(temp_dir.path().join("other.service").exists());
+        assert!(runner.calls()[0].starts_with("systemctl --user disable --now "));
+        assert!(uninstall_units(temp_dir.path(), true, &runner)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_install_units_systemctl_error() {
+        let temp_dir = TempDir::new().unwrap();
+        let runner = RecordingRunner::default();
+        runner.fail(true);
+
+        assert!(install_units(&units(true), temp_dir.path(), true, &runner).is_err());
+    }
+}
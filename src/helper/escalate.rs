@@ -0,0 +1,306 @@
+use std::ffi::{CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::helper::request::is_root;
+
+/// The polkit action shipped in `dist/`, letting `pkexec` explain what it runs.
+pub const POLKIT_ACTION: &str = "io.github.albibenni.rust_can_make_you_focus.run";
+/// The program `pkexec` runs, the only one the polkit action allows (`exec.path`).
+pub const PKEXEC_PATH: &str = "/usr/local/bin/rust_can_make_you_focus";
+
+const SUDO: &str = "sudo";
+const PKEXEC: &str = "pkexec";
+
+/// A program able to run a command as root.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Escalation {
+    /// Asks for the password on the terminal
+    Sudo,
+    /// Asks for the password through the polkit agent of the desktop session
+    Pkexec,
+}
+
+impl Escalation {
+    /// Finds the escalation program to use.
+    ///
+    /// `sudo` is preferred from a terminal, `pkexec` otherwise (e.g. from a
+    /// launcher), falling back on the other one when it is not installed.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The `PATH` to look the programs up in
+    /// * `interactive` - Whether a terminal is attached to the standard input
+    pub fn detect(path: Option<&OsStr>, interactive: bool) -> Option<Escalation> {
+        let order = if interactive {
+            [Escalation::Sudo, Escalation::Pkexec]
+        } else {
+            [Escalation::Pkexec, Escalation::Sudo]
+        };
+        order
+            .into_iter()
+            .find(|escalation| find_in_path(escalation.program(), path).is_some())
+    }
+
+    /// Finds the escalation program of the running process.
+    pub fn system() -> Option<Escalation> {
+        // SAFETY: isatty only checks the file descriptor and reports through its result
+        let interactive = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+        Escalation::detect(std::env::var_os("PATH").as_deref(), interactive)
+    }
+
+    pub fn program(&self) -> &'static str {
+        match self {
+            Escalation::Sudo => SUDO,
+            Escalation::Pkexec => PKEXEC,
+        }
+    }
+
+    /// Returns the program to run as root instead of `current`, the running one.
+    ///
+    /// `sudo` runs `current`, `pkexec` the program installed at `PKEXEC_PATH`,
+    /// which is the one its polkit action allows.
+    ///
+    /// # Returns
+    ///
+    /// A `NotFound` error when `pkexec` is used and nothing is installed there
+    pub fn executable(&self, current: &Path) -> Result<PathBuf, std::io::Error> {
+        self.executable_at(current, Path::new(PKEXEC_PATH))
+    }
+
+    fn executable_at(&self, current: &Path, installed: &Path) -> Result<PathBuf, std::io::Error> {
+        match self {
+            Escalation::Sudo => Ok(current.to_path_buf()),
+            Escalation::Pkexec if is_executable(installed) => Ok(installed.to_path_buf()),
+            Escalation::Pkexec => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "pkexec only runs {}, install the program there with the polkit action of dist/, or use sudo",
+                    installed.display()
+                ),
+            )),
+        }
+    }
+
+    /// Builds the command running `executable` with `arguments` as root.
+    pub fn command(&self, executable: &Path, arguments: &[String]) -> Command {
+        let mut command = Command::new(self.program());
+        command.arg(executable).args(arguments);
+        command
+    }
+}
+
+/// Returns the first executable file named `program` in the directories of `path`.
+pub fn find_in_path(program: &str, path: Option<&OsStr>) -> Option<PathBuf> {
+    std::env::split_paths(path?)
+        .map(|dir| dir.join(program))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// Returns whether `path` is an executable file.
+fn is_executable(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Returns whether the running process may write to `path`.
+///
+/// Unlike checking the permission bits, this also catches read-only file systems,
+/// and is what the kernel will decide when the file is opened.
+pub fn is_writable(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: access takes a valid C string and only reports through its result
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// Makes sure the program runs as root before anything is changed on the system.
+///
+/// When root is needed and missing, the program is started again through `sudo`
+/// or `pkexec` with the same arguments. With `escalate` unset, or when no
+/// escalation program is installed, instructions are printed instead.
+///
+/// # Arguments
+///
+/// * `reasons` - Why root is needed, empty when it isn't
+/// * `arguments` - The command line arguments, without the program name
+/// * `escalate` - Whether the program may be started again as root
+///
+/// # Returns
+///
+/// `None` to go on, the exit code of the escalated run to exit with, or a
+/// `PermissionDenied` error when root is needed but could not be obtained
+pub fn ensure_root(
+    reasons: &[&str],
+    arguments: &[String],
+    escalate: bool,
+) -> Result<Option<i32>, std::io::Error> {
+    if reasons.is_empty() || is_root() {
+        return Ok(None);
+    }
+    let executable = std::env::current_exe()?;
+    println!("Root privileges are needed: {}", reasons.join(", "));
+    match Escalation::system().filter(|_| escalate) {
+        Some(escalation) => {
+            println!("Starting again through {}", escalation.program());
+            let executable = escalation.executable(&executable)?;
+            let status = escalation.command(&executable, arguments).status()?;
+            Ok(Some(status.code().unwrap_or(1)))
+        }
+        None => {
+            println!("{}", instructions(&executable, arguments));
+            Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "root privileges are needed, nothing was changed",
+            ))
+        }
+    }
+}
+
+/// The instructions printed when the program can't escalate by itself.
+pub fn instructions(executable: &Path, arguments: &[String]) -> String {
+    let command = |executable: &Path| format!("{} {}", executable.display(), arguments.join(" "));
+    format!(
+        "Nothing was changed. Run it as root with:\n  sudo {}\nor, with a polkit agent and the `{}` action installed from dist/:\n  pkexec {}",
+        command(executable).trim_end(),
+        POLKIT_ACTION,
+        command(Path::new(PKEXEC_PATH)).trim_end()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+    use tempfile::TempDir;
+
+    // Helper function creating fake programs in a directory used as PATH
+    fn setup_path(programs: &[&str], mode: u32) -> (TempDir, OsString) {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        for program in programs {
+            let file = temp_dir.path().join(program);
+            std::fs::write(&file, "#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(&file, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        let path = std::env::join_paths([Path::new("/nonexistent"), temp_dir.path()]).unwrap();
+        (temp_dir, path)
+    }
+
+    #[test]
+    fn test_find_in_path() {
+        let (temp_dir, path) = setup_path(&[SUDO], 0o755);
+        assert_eq!(
+            find_in_path(SUDO, Some(&path)),
+            Some(temp_dir.path().join(SUDO))
+        );
+        assert_eq!(find_in_path(PKEXEC, Some(&path)), None);
+        assert_eq!(find_in_path(SUDO, None), None);
+    }
+
+    #[test]
+    fn test_find_in_path_skips_non_executables() {
+        let (_temp_dir, path) = setup_path(&[SUDO], 0o644);
+        assert_eq!(find_in_path(SUDO, Some(&path)), None);
+    }
+
+    #[test]
+    fn test_detect_prefers_sudo_in_terminal() {
+        let (_temp_dir, path) = setup_path(&[SUDO, PKEXEC], 0o755);
+        assert_eq!(
+            Escalation::detect(Some(&path), true),
+            Some(Escalation::Sudo)
+        );
+        assert_eq!(
+            Escalation::detect(Some(&path), false),
+            Some(Escalation::Pkexec)
+        );
+    }
+
+    #[test]
+    fn test_detect_falls_back() {
+        let (_temp_dir, path) = setup_path(&[PKEXEC], 0o755);
+        assert_eq!(
+            Escalation::detect(Some(&path), true),
+            Some(Escalation::Pkexec)
+        );
+        let (_temp_dir, empty) = setup_path(&[], 0o755);
+        assert_eq!(Escalation::detect(Some(&empty), true), None);
+    }
+
+    #[test]
+    fn test_command() {
+        let command = Escalation::Pkexec.command(
+            Path::new("/usr/local/bin/focus"),
+            &["coding".to_string(), "25".to_string()],
+        );
+        assert_eq!(command.get_program(), PKEXEC);
+        let arguments: Vec<&OsStr> = command.get_args().collect();
+        assert_eq!(arguments, ["/usr/local/bin/focus", "coding", "25"]);
+    }
+
+    #[test]
+    fn test_executable() {
+        let (temp_dir, _path) = setup_path(&["focus"], 0o755);
+        let installed = temp_dir.path().join("focus");
+        let current = Path::new("/home/me/target/debug/focus");
+
+        assert_eq!(
+            Escalation::Sudo.executable_at(current, &installed).unwrap(),
+            current
+        );
+        assert_eq!(
+            Escalation::Pkexec
+                .executable_at(current, &installed)
+                .unwrap(),
+            installed,
+            "pkexec runs the installed program"
+        );
+        let error = Escalation::Pkexec
+            .executable_at(current, &temp_dir.path().join("missing"))
+            .expect_err("Nothing is installed");
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_polkit_action_allows_pkexec_path() {
+        let policy = include_str!("../../dist/io.github.albibenni.rust_can_make_you_focus.policy");
+        assert!(policy.contains(&format!(
+            "<annotate key=\"org.freedesktop.policykit.exec.path\">{}</annotate>",
+            PKEXEC_PATH
+        )));
+        assert!(policy.contains(POLKIT_ACTION));
+    }
+
+    #[test]
+    fn test_is_writable() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("hosts");
+        std::fs::write(&file, "").unwrap();
+        assert!(is_writable(&file), "A new file should be writable");
+        assert!(!is_writable(&temp_dir.path().join("missing")));
+    }
+
+    #[test]
+    fn test_ensure_root_without_reasons() {
+        assert_eq!(ensure_root(&[], &[], false).unwrap(), None);
+    }
+
+    #[test]
+    fn test_instructions() {
+        let text = instructions(
+            Path::new("/home/me/focus"),
+            &[
+                "coding".to_string(),
+                "--proxy".to_string(),
+                "25".to_string(),
+            ],
+        );
+        assert!(text.contains("sudo /home/me/focus coding --proxy 25"));
+        assert!(text.contains(&format!("pkexec {} coding --proxy 25", PKEXEC_PATH)));
+        assert!(text.contains(POLKIT_ACTION));
+    }
+}
@@ -0,0 +1,2 @@
+pub mod escalate;
+pub mod request;
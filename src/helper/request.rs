@@ -0,0 +1,943 @@
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::browser::policy::{
+    apply_browser_policies, remove_browser_policies, BrowserPolicy, PolicyDirs,
+};
+use crate::daemon::service::STATE_FILE;
+use crate::helper::escalate::Escalation;
+use crate::hosts::file_edit::write_managed_block;
+use crate::hosts::store::{FileHostsStore, HostsStore};
+use crate::hosts::watch::HostsGuard;
+use crate::proxy::rules::Filter;
+use crate::proxy::server::PROXY_PORT;
+use crate::server::block_page::BlockPage;
+use crate::server::ca::CA_CERT_FILE;
+use crate::session::history::{history_file, History};
+use crate::session::state::Session;
+use crate::utils::command::{CommandRunner, SystemRunner};
+use crate::utils::utils::{execute_flux_cache, flush_dns_cache, reset_file};
+
+// Actions
+const BLOCK: &str = "block";
+const UNBLOCK: &str = "unblock";
+const FLUSH_DNS: &str = "flush-dns";
+const POLICIES: &str = "policies";
+const REMOVE_POLICIES: &str = "remove-policies";
+const PROXY: &str = "proxy";
+const GUARD: &str = "guard";
+const BLOCK_PAGE: &str = "block-page";
+const PERSIST: &str = "persist";
+const FORGET: &str = "forget";
+
+// Options of the `policies` and `proxy` actions
+const PROXY_OPTION: &str = "--proxy";
+const CA_OPTION: &str = "--ca";
+const ALLOWLIST_OPTION: &str = "--allowlist";
+const ALLOW_OPTION: &str = "--allow=";
+const EXCEPT_OPTION: &str = "--except=";
+// Option of the `block-page` action
+const TEMPLATE_OPTION: &str = "--template=";
+
+const MAX_DOMAINS: usize = 512;
+const MAX_DOMAIN_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+const MAX_TEMPLATE_LEN: usize = 64 * 1024;
+
+/// What the privileged helper is asked to do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HelperAction {
+    /// Adds hosts entries sinking the domains, then flushes the DNS cache, a failed
+    /// flush being only printed
+    Block,
+    /// Removes the managed block from the hosts file, then flushes the DNS cache
+    /// the same way
+    Unblock,
+    /// Only flushes the DNS cache
+    FlushDns,
+    /// Writes the managed browser policies
+    Policies,
+    /// Removes the managed browser policies
+    RemovePolicies,
+    /// Runs the filtering proxy, which intercepts HTTPS with the local CA, until
+    /// the standard input is closed
+    Proxy,
+    /// Writes the managed block sinking the domains again whenever the hosts file
+    /// is tampered with, until the standard input is closed
+    Guard,
+    /// Serves the block page of the session on ports 80 and 443, until the
+    /// standard input is closed. Each line read meanwhile is the session again,
+    /// e.g. once extended
+    BlockPage,
+    /// Saves a strict session for the daemon, which applies it again if the
+    /// program is killed before the deadline
+    Persist,
+    /// Removes the strict session saved by `Persist`
+    Forget,
+}
+
+/// A request to the privileged helper: an action and what it applies to.
+///
+/// The helper is the only part of the program that has to run as root. It is
+/// started as `rust_can_make_you_focus helper <action> <arguments...>` and refuses
+/// anything but plain domain names and `host/path` patterns, so it can't be used to
+/// write arbitrary content to the hosts file or the browser policies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HelperRequest {
+    pub action: HelperAction,
+    pub domains: Vec<String>,
+    /// The browser policies to write, or the rules of the proxy in its `blocked`,
+    /// `allowed` and `exceptions`
+    pub policy: BrowserPolicy,
+    /// The strict session to save, or the session of the block page
+    pub session: Option<Session>,
+    /// The custom template of the block page
+    pub template: Option<String>,
+}
+
+impl HelperRequest {
+    fn new(action: HelperAction) -> HelperRequest {
+        HelperRequest {
+            action,
+            domains: Vec::new(),
+            policy: BrowserPolicy::default(),
+            session: None,
+            template: None,
+        }
+    }
+
+    /// Creates a request blocking `domains`.
+    pub fn block(domains: &[&str]) -> HelperRequest {
+        HelperRequest {
+            domains: domains.iter().map(|domain| domain.to_string()).collect(),
+            ..HelperRequest::new(HelperAction::Block)
+        }
+    }
+
+    /// Creates a request lifting every block.
+    pub fn unblock() -> HelperRequest {
+        HelperRequest::new(HelperAction::Unblock)
+    }
+
+    /// Creates a request writing the browser policies.
+    ///
+    /// The helper only points the browsers at the local proxy and CA, whatever
+    /// `proxy` and `ca` hold.
+    pub fn policies(policy: &BrowserPolicy) -> HelperRequest {
+        HelperRequest {
+            policy: BrowserPolicy {
+                proxy: policy.proxy.as_ref().map(|_| local_proxy()),
+                ca: policy.ca.as_ref().map(|_| local_ca()),
+                ..policy.clone()
+            },
+            ..HelperRequest::new(HelperAction::Policies)
+        }
+    }
+
+    /// Creates a request removing the browser policies.
+    pub fn remove_policies() -> HelperRequest {
+        HelperRequest::new(HelperAction::RemovePolicies)
+    }
+
+    /// Creates a request running the filtering proxy, see `Filter::from_patterns`.
+    pub fn proxy(
+        blocked: Vec<String>,
+        allowed: Option<Vec<String>>,
+        exceptions: Vec<String>,
+    ) -> HelperRequest {
+        HelperRequest {
+            policy: BrowserPolicy {
+                blocked,
+                exceptions,
+                allowed,
+                ..BrowserPolicy::default()
+            },
+            ..HelperRequest::new(HelperAction::Proxy)
+        }
+    }
+
+    /// Creates a request guarding the managed block sinking `domains`, see
+    /// `HostsGuard`.
+    pub fn guard(domains: &[&str]) -> HelperRequest {
+        HelperRequest {
+            domains: domains.iter().map(|domain| domain.to_string()).collect(),
+            ..HelperRequest::new(HelperAction::Guard)
+        }
+    }
+
+    /// Creates a request serving the block page of `session`, with the built-in
+    /// template unless `template` is given.
+    ///
+    /// The command line of the helper can be seen by every user, the unlock hash
+    /// of the session is left out of it.
+    pub fn block_page(session: &Session, template: Option<String>) -> HelperRequest {
+        HelperRequest {
+            session: Some(Session {
+                unlock_hash: None,
+                ..session.clone()
+            }),
+            template,
+            ..HelperRequest::new(HelperAction::BlockPage)
+        }
+    }
+
+    /// Creates a request saving the strict `session` for the daemon.
+    pub fn persist(session: &Session) -> HelperRequest {
+        HelperRequest {
+            session: Some(session.clone()),
+            ..HelperRequest::new(HelperAction::Persist)
+        }
+    }
+
+    /// Creates a request removing the strict session saved by `persist`.
+    pub fn forget() -> HelperRequest {
+        HelperRequest::new(HelperAction::Forget)
+    }
+
+    /// Parses and validates the helper command line arguments.
+    ///
+    /// # Arguments
+    ///
+    /// * `arguments` - The action followed by the domains, without the `helper` subcommand
+    ///
+    /// # Returns
+    ///
+    /// The request, or an `InvalidInput` error if the action is unknown, a domain is
+    /// not a valid hostname, a pattern is not a valid `host/path` pattern or the
+    /// arguments don't fit the action
+    pub fn parse(arguments: &[String]) -> Result<HelperRequest, std::io::Error> {
+        let (action, domains) = arguments
+            .split_first()
+            .ok_or_else(|| invalid_request("missing helper action".to_string()))?;
+        let action = match action.as_str() {
+            BLOCK => HelperAction::Block,
+            UNBLOCK => HelperAction::Unblock,
+            FLUSH_DNS => HelperAction::FlushDns,
+            POLICIES => HelperAction::Policies,
+            REMOVE_POLICIES => HelperAction::RemovePolicies,
+            PROXY => HelperAction::Proxy,
+            GUARD => HelperAction::Guard,
+            BLOCK_PAGE => HelperAction::BlockPage,
+            PERSIST => HelperAction::Persist,
+            FORGET => HelperAction::Forget,
+            _ => return Err(invalid_request(format!("unknown helper action {}", action))),
+        };
+        match action {
+            HelperAction::Policies | HelperAction::Proxy => {
+                return Ok(HelperRequest {
+                    policy: parse_policy(domains, action == HelperAction::Policies)?,
+                    ..HelperRequest::new(action)
+                })
+            }
+            HelperAction::Persist => {
+                return Ok(HelperRequest {
+                    session: Some(parse_session(domains)?),
+                    ..HelperRequest::new(action)
+                })
+            }
+            HelperAction::BlockPage => {
+                let (templates, session): (Vec<&String>, Vec<&String>) = domains
+                    .iter()
+                    .partition(|argument| argument.starts_with(TEMPLATE_OPTION));
+                let template = match templates.as_slice() {
+                    [] => None,
+                    [template] if template.len() <= TEMPLATE_OPTION.len() + MAX_TEMPLATE_LEN => {
+                        Some(template[TEMPLATE_OPTION.len()..].to_string())
+                    }
+                    _ => {
+                        return Err(invalid_request(format!(
+                            "{} takes a template of at most {} bytes",
+                            BLOCK_PAGE, MAX_TEMPLATE_LEN
+                        )))
+                    }
+                };
+                let session: Vec<String> = session.into_iter().cloned().collect();
+                return Ok(HelperRequest {
+                    session: Some(parse_session(&session)?),
+                    template,
+                    ..HelperRequest::new(action)
+                });
+            }
+            HelperAction::Block | HelperAction::Guard if domains.is_empty() => {
+                return Err(invalid_request("no domain to block".to_string()))
+            }
+            HelperAction::Block | HelperAction::Guard => {}
+            _ if !domains.is_empty() => {
+                return Err(invalid_request(format!("{} takes no domain", arguments[0])))
+            }
+            _ => {}
+        }
+        if domains.len() > MAX_DOMAINS {
+            return Err(invalid_request(format!(
+                "too many domains, at most {}",
+                MAX_DOMAINS
+            )));
+        }
+        if let Some(domain) = domains.iter().find(|domain| !is_valid_domain(domain)) {
+            return Err(invalid_request(format!("invalid domain {:?}", domain)));
+        }
+        Ok(HelperRequest {
+            domains: domains.to_vec(),
+            ..HelperRequest::new(action)
+        })
+    }
+
+    /// Formats the request as the helper command line arguments.
+    pub fn to_args(&self) -> Vec<String> {
+        let action = match self.action {
+            HelperAction::Block => BLOCK,
+            HelperAction::Unblock => UNBLOCK,
+            HelperAction::FlushDns => FLUSH_DNS,
+            HelperAction::Policies => POLICIES,
+            HelperAction::RemovePolicies => REMOVE_POLICIES,
+            HelperAction::Proxy => PROXY,
+            HelperAction::Guard => GUARD,
+            HelperAction::BlockPage => BLOCK_PAGE,
+            HelperAction::Persist => PERSIST,
+            HelperAction::Forget => FORGET,
+        };
+        let mut arguments = vec![action.to_string()];
+        match self.action {
+            HelperAction::Policies | HelperAction::Proxy => {
+                let policy = &self.policy;
+                if policy.proxy.is_some() {
+                    arguments.push(PROXY_OPTION.to_string());
+                }
+                if policy.ca.is_some() {
+                    arguments.push(CA_OPTION.to_string());
+                }
+                if let Some(allowed) = &policy.allowed {
+                    arguments.push(ALLOWLIST_OPTION.to_string());
+                    arguments.extend(
+                        allowed
+                            .iter()
+                            .map(|pattern| ALLOW_OPTION.to_string() + pattern),
+                    );
+                }
+                arguments.extend(
+                    policy
+                        .exceptions
+                        .iter()
+                        .map(|pattern| EXCEPT_OPTION.to_string() + pattern),
+                );
+                arguments.extend(policy.blocked.iter().cloned());
+            }
+            HelperAction::Persist | HelperAction::BlockPage => {
+                arguments.extend(
+                    self.session
+                        .iter()
+                        .map(|session| session.to_json().to_string()),
+                );
+                arguments.extend(
+                    self.template
+                        .iter()
+                        .map(|template| TEMPLATE_OPTION.to_string() + template),
+                );
+            }
+            _ => arguments.extend(self.domains.iter().cloned()),
+        }
+        arguments
+    }
+
+    /// Executes the request on the system, which requires root privileges.
+    pub fn execute(&self) -> Result<(), std::io::Error> {
+        let state_file = Path::new(crate::STATE_DIR).join(STATE_FILE);
+        match (self.action, &self.session) {
+            (HelperAction::Policies, _) => {
+                apply_browser_policies(&self.policy, &PolicyDirs::system())
+            }
+            (HelperAction::RemovePolicies, _) => remove_browser_policies(&PolicyDirs::system()),
+            (HelperAction::Proxy, _) => run_proxy(&self.policy),
+            (HelperAction::Guard, _) => run_guard(&self.domains),
+            (HelperAction::BlockPage, Some(session)) => {
+                run_block_page(session, self.template.clone())
+            }
+            (HelperAction::Persist, Some(session)) => persist_session(session, &state_file),
+            (HelperAction::Forget, _) => match std::fs::remove_file(state_file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+            _ => self.execute_with(&FileHostsStore::system(), &SystemRunner),
+        }
+    }
+
+    /// Executes the request against `store`, running the commands through `runner`.
+    pub fn execute_with(
+        &self,
+        store: &dyn HostsStore,
+        runner: &dyn CommandRunner,
+    ) -> Result<(), std::io::Error> {
+        match self.action {
+            HelperAction::Block => {
+                let domains: Vec<&str> = self.domains.iter().map(String::as_str).collect();
+                write_managed_block(store, &domains)?;
+                flush_dns_cache(runner);
+                Ok(())
+            }
+            HelperAction::Unblock => reset_file(store, runner),
+            HelperAction::FlushDns => execute_flux_cache(runner),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{:?} only runs on the system", self.action),
+            )),
+        }
+    }
+}
+
+/// Returns whether the program runs with root privileges.
+pub fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Executes a request with root privileges.
+///
+/// As root the request is executed directly, otherwise the helper is started
+/// through `sudo` or `pkexec`, which ask for the password if needed. The rest of
+/// the program keeps running unprivileged.
+pub fn run_privileged(request: &HelperRequest) -> Result<(), std::io::Error> {
+    if is_root() {
+        return request.execute();
+    }
+    let status = helper_command(request)?.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "privileged helper failed: {}",
+            status
+        )))
+    }
+}
+
+/// Starts a request running until its standard input is closed, like the proxy,
+/// through `sudo` or `pkexec`.
+///
+/// Dropping the input of the returned child, or the program exiting, ends it.
+pub fn spawn_privileged(request: &HelperRequest) -> Result<Child, std::io::Error> {
+    helper_command(request)?.stdin(Stdio::piped()).spawn()
+}
+
+/// Builds the command starting the helper for `request` through `sudo` or `pkexec`.
+fn helper_command(request: &HelperRequest) -> Result<Command, std::io::Error> {
+    let escalation = Escalation::system().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "neither sudo nor pkexec is installed, run as root instead",
+        )
+    })?;
+    let mut arguments = vec!["helper".to_string()];
+    arguments.extend(request.to_args());
+    let executable = escalation.executable(&std::env::current_exe()?)?;
+    Ok(escalation.command(&executable, &arguments))
+}
+
+/// Parses the arguments of the `policies` and `proxy` actions: the blocked
+/// patterns, `--except=` and `--allow=` patterns, `--allowlist` and, for the
+/// policies only, `--proxy` and `--ca` standing for the local proxy and CA.
+fn parse_policy(arguments: &[String], policies: bool) -> Result<BrowserPolicy, std::io::Error> {
+    if arguments.len() > MAX_DOMAINS {
+        return Err(invalid_request(format!(
+            "too many patterns, at most {}",
+            MAX_DOMAINS
+        )));
+    }
+    let valid = |pattern: &str| -> Result<String, std::io::Error> {
+        if is_valid_pattern(pattern) {
+            Ok(pattern.to_string())
+        } else {
+            Err(invalid_request(format!("invalid pattern {:?}", pattern)))
+        }
+    };
+    let mut policy = BrowserPolicy::default();
+    for argument in arguments {
+        if let Some(pattern) = argument.strip_prefix(ALLOW_OPTION) {
+            policy
+                .allowed
+                .get_or_insert_with(Vec::new)
+                .push(valid(pattern)?);
+        } else if let Some(pattern) = argument.strip_prefix(EXCEPT_OPTION) {
+            policy.exceptions.push(valid(pattern)?);
+        } else if argument == ALLOWLIST_OPTION {
+            policy.allowed.get_or_insert_with(Vec::new);
+        } else if argument == PROXY_OPTION && policies {
+            policy.proxy = Some(local_proxy());
+        } else if argument == CA_OPTION && policies {
+            policy.ca = Some(local_ca());
+        } else if argument.starts_with("--") {
+            return Err(invalid_request(format!("unknown option {}", argument)));
+        } else {
+            policy.blocked.push(valid(argument)?);
+        }
+    }
+    Ok(policy)
+}
+
+/// Parses the argument of the `persist` action, a session as saved by the daemon
+/// whose websites are valid domains and patterns.
+fn parse_session(arguments: &[String]) -> Result<Session, std::io::Error> {
+    let [content] = arguments else {
+        return Err(invalid_request(
+            "the session is expected as JSON".to_string(),
+        ));
+    };
+    let session = serde_json::from_str(content)
+        .ok()
+        .and_then(|value| Session::from_json(&value))
+        .ok_or_else(|| invalid_request("invalid session".to_string()))?;
+    let valid = session.hosts.iter().all(|host| is_valid_domain(host))
+        && session
+            .sites
+            .iter()
+            .chain(&session.exceptions)
+            .all(|pattern| is_valid_pattern(pattern));
+    if !valid {
+        return Err(invalid_request(
+            "invalid website in the session".to_string(),
+        ));
+    }
+    Ok(session)
+}
+
+/// Runs the proxy until the standard input is closed, by the session ending or
+/// its program exiting.
+fn run_proxy(policy: &BrowserPolicy) -> Result<(), std::io::Error> {
+    let filter = Filter::from_patterns(
+        &policy.blocked,
+        policy.allowed.as_deref(),
+        &policy.exceptions,
+    );
+    let done = Arc::new(AtomicBool::new(false));
+    let proxy = crate::proxy::server::start(filter, Path::new(crate::STATE_DIR), done.clone())?;
+    let waited = std::io::copy(&mut std::io::stdin(), &mut std::io::sink());
+    done.store(true, Ordering::Relaxed);
+    proxy
+        .join()
+        .map_err(|_| std::io::Error::other("the proxy panicked"))?;
+    waited.map(|_| ())
+}
+
+/// Runs the hosts guard of a session sinking `domains` until the standard input
+/// is closed, recording the tampering in the history of root.
+fn run_guard(domains: &[String]) -> Result<(), std::io::Error> {
+    let done = Arc::new(AtomicBool::new(false));
+    let guard = HostsGuard::new(
+        Arc::new(FileHostsStore::system()),
+        domains.to_vec(),
+        History::new(history_file(true)),
+    )
+    .spawn(done.clone());
+    let waited = std::io::copy(&mut std::io::stdin(), &mut std::io::sink());
+    done.store(true, Ordering::Relaxed);
+    guard
+        .join()
+        .map_err(|_| std::io::Error::other("the hosts guard panicked"))?;
+    waited.map(|_| ())
+}
+
+/// Serves the block page of `session` until the standard input is closed, each
+/// line read replacing the session.
+fn run_block_page(session: &Session, template: Option<String>) -> Result<(), std::io::Error> {
+    let session = Arc::new(Mutex::new(session.clone()));
+    let page = match template {
+        Some(template) => BlockPage::with_template(template, session.clone()),
+        None => BlockPage::new(session.clone()),
+    };
+    let done = Arc::new(AtomicBool::new(false));
+    let servers = page.serve(Path::new(crate::STATE_DIR), done.clone())?;
+    let mut waited = Ok(());
+    for line in std::io::stdin().lines() {
+        match line.and_then(|line| parse_session(&[line])) {
+            Ok(updated) => *session.lock().unwrap() = updated,
+            Err(e) => {
+                waited = Err(e);
+                break;
+            }
+        }
+    }
+    done.store(true, Ordering::Relaxed);
+    for server in servers {
+        server
+            .join()
+            .map_err(|_| std::io::Error::other("the block page panicked"))?;
+    }
+    waited
+}
+
+/// Saves `session` to `state_file` for the daemon, never replacing the session
+/// of the daemon itself.
+fn persist_session(session: &Session, state_file: &Path) -> Result<(), std::io::Error> {
+    if let Some(dir) = state_file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // It holds the unlock hash, like the state files written by the daemon
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(state_file)?;
+    std::io::Write::write_all(&mut file, session.to_json().to_string().as_bytes())
+}
+
+fn local_proxy() -> String {
+    format!("{}:{}", crate::LOCALHOST, PROXY_PORT)
+}
+
+fn local_ca() -> PathBuf {
+    Path::new(crate::STATE_DIR).join(CA_CERT_FILE)
+}
+
+/// Returns whether `pattern` is a `host/path` pattern: a valid domain, optionally
+/// prefixed by `*.`, then a path without whitespace, quotes or backslashes.
+pub fn is_valid_pattern(pattern: &str) -> bool {
+    let (host, path) = pattern.split_at(pattern.find('/').unwrap_or(pattern.len()));
+    is_valid_domain(host.strip_prefix("*.").unwrap_or(host))
+        && path
+            .bytes()
+            .all(|byte| byte.is_ascii_graphic() && byte != b'"' && byte != b'\\')
+}
+
+/// Returns whether `domain` is a plain lowercase hostname with at least two labels.
+///
+/// IP addresses, wildcards, `localhost` and anything that could alter the hosts
+/// file format (whitespace, `#`, newlines) are rejected.
+pub fn is_valid_domain(domain: &str) -> bool {
+    if domain.is_empty() || domain.len() > MAX_DOMAIN_LEN {
+        return false;
+    }
+    let labels: Vec<&str> = domain.split('.').collect();
+    let valid_label = |label: &&str| {
+        !label.is_empty()
+            && label.len() <= MAX_LABEL_LEN
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'-')
+    };
+    let top_level = labels[labels.len() - 1];
+    labels.len() >= 2
+        && labels.iter().all(valid_label)
+        && !top_level.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+fn invalid_request(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hosts::file_edit::is_managed_block_intact;
+    use crate::hosts::store::MemoryHostsStore;
+    use crate::utils::command::RecordingRunner;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    fn args(arguments: &[&str]) -> Vec<String> {
+        arguments.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_block() {
+        let request = HelperRequest::parse(&args(&["block", "www.youtube.com", "netflix.com"]))
+            .expect("Should parse");
+        assert_eq!(
+            request,
+            HelperRequest::block(&["www.youtube.com", "netflix.com"])
+        );
+    }
+
+    #[test]
+    fn test_parse_actions_without_domains() {
+        assert_eq!(
+            HelperRequest::parse(&args(&["unblock"])).unwrap(),
+            HelperRequest::unblock()
+        );
+        assert_eq!(
+            HelperRequest::parse(&args(&["flush-dns"])).unwrap().action,
+            HelperAction::FlushDns
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_requests() {
+        let invalid = [
+            vec![],
+            vec!["write", "netflix.com"],
+            vec!["BLOCK", "netflix.com"],
+            vec!["block"],
+            vec!["unblock", "netflix.com"],
+            vec!["flush-dns", "netflix.com"],
+            vec!["block", "netflix.com", "127.0.0.1"],
+            vec!["guard"],
+            vec!["guard", "127.0.0.1 netflix.com"],
+        ];
+        for arguments in invalid {
+            let error = HelperRequest::parse(&args(&arguments)).expect_err(&arguments.join(" "));
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_too_many_domains() {
+        let mut arguments = vec!["block".to_string()];
+        arguments.extend((0..=MAX_DOMAINS).map(|i| format!("site{}.com", i)));
+        assert!(HelperRequest::parse(&arguments).is_err());
+        arguments.pop();
+        assert!(HelperRequest::parse(&arguments).is_ok());
+    }
+
+    #[test]
+    fn test_is_valid_domain() {
+        for domain in [
+            "netflix.com",
+            "web.whatsapp.com",
+            "a-b.c0.io",
+            "xn--bcher-kva.de",
+        ] {
+            assert!(is_valid_domain(domain), "{} should be valid", domain);
+        }
+        let too_long = format!("{}.com", "a".repeat(MAX_LABEL_LEN + 1));
+        let invalid = [
+            "",
+            "localhost",
+            "netflix",
+            "Netflix.com",
+            "*.youtube.com",
+            "10.0.0.1",
+            "-bad.com",
+            "bad-.com",
+            "a..com",
+            "netflix.com.",
+            "netflix.com\n127.0.0.1 evil.com",
+            "netflix.com #comment",
+            "netflix.com/path",
+            &too_long,
+        ];
+        for domain in invalid {
+            assert!(!is_valid_domain(domain), "{:?} should be invalid", domain);
+        }
+    }
+
+    #[test]
+    fn test_execute_with() {
+        let store = MemoryHostsStore::new("127.0.0.1 localhost\n");
+        let runner = RecordingRunner::default();
+
+        HelperRequest::block(&["netflix.com"])
+            .execute_with(&store, &runner)
+            .expect("Should block");
+        assert!(is_managed_block_intact(
+            &store.content().unwrap(),
+            &["netflix.com"]
+        ));
+        HelperRequest::unblock()
+            .execute_with(&store, &runner)
+            .expect("Should unblock");
+        assert_eq!(
+            store.content().unwrap(),
+            "127.0.0.1 localhost\n",
+            "The other entries should be kept"
+        );
+        assert_eq!(runner.calls().len(), 2, "Both should flush the DNS cache");
+
+        runner.fail(true);
+        HelperRequest::block(&["netflix.com"])
+            .execute_with(&store, &runner)
+            .expect("A failed flush should not fail the block");
+        assert_eq!(runner.calls().len(), 3);
+        runner.fail(false);
+
+        store.fail_writes(true);
+        assert!(HelperRequest::block(&["netflix.com"])
+            .execute_with(&store, &runner)
+            .is_err());
+        assert_eq!(
+            runner.calls().len(),
+            3,
+            "Nothing to flush after a failed write"
+        );
+    }
+
+    #[test]
+    fn test_to_args_round_trip() {
+        let request = HelperRequest::block(&["discord.com"]);
+        assert_eq!(request.to_args(), args(&["block", "discord.com"]));
+        assert_eq!(HelperRequest::parse(&request.to_args()).unwrap(), request);
+        assert_eq!(HelperRequest::unblock().to_args(), args(&["unblock"]));
+        let guard = HelperRequest::guard(&["discord.com"]);
+        assert_eq!(guard.to_args(), args(&["guard", "discord.com"]));
+        assert_eq!(HelperRequest::parse(&guard.to_args()).unwrap(), guard);
+    }
+
+    #[test]
+    fn test_parse_policies() {
+        let request = HelperRequest::parse(&args(&[
+            "policies",
+            "--proxy",
+            "--ca",
+            "--except=www.youtube.com/watch*",
+            "*.youtube.com",
+            "www.x.com",
+        ]))
+        .expect("Should parse");
+
+        assert_eq!(
+            request.policy,
+            BrowserPolicy {
+                blocked: args(&["*.youtube.com", "www.x.com"]),
+                exceptions: args(&["www.youtube.com/watch*"]),
+                allowed: None,
+                proxy: Some("127.0.0.1:8118".to_string()),
+                ca: Some(PathBuf::from(crate::STATE_DIR).join(CA_CERT_FILE)),
+            }
+        );
+        assert_eq!(HelperRequest::parse(&request.to_args()).unwrap(), request);
+    }
+
+    #[test]
+    fn test_policies_only_point_at_the_local_proxy_and_ca() {
+        let request = HelperRequest::policies(&BrowserPolicy {
+            proxy: Some("evil.com:80".to_string()),
+            ca: Some(PathBuf::from("/tmp/evil.pem")),
+            ..BrowserPolicy::default()
+        });
+
+        assert_eq!(request.policy.proxy.as_deref(), Some("127.0.0.1:8118"));
+        assert_eq!(request.policy.ca, Some(local_ca()));
+    }
+
+    #[test]
+    fn test_parse_proxy() {
+        let request = HelperRequest::proxy(
+            Vec::new(),
+            Some(args(&["docs.rs", "github.com/rust-lang*"])),
+            Vec::new(),
+        );
+
+        assert_eq!(
+            request.to_args(),
+            args(&[
+                "proxy",
+                "--allowlist",
+                "--allow=docs.rs",
+                "--allow=github.com/rust-lang*"
+            ])
+        );
+        assert_eq!(HelperRequest::parse(&request.to_args()).unwrap(), request);
+        let empty = HelperRequest::proxy(Vec::new(), Some(Vec::new()), Vec::new());
+        assert_eq!(HelperRequest::parse(&empty.to_args()).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_policies() {
+        let invalid = [
+            vec!["policies", "--except=\"evil\""],
+            vec!["policies", "--unknown"],
+            vec!["policies", "www.youtube.com/a b"],
+            vec!["policies", "www.youtube.com/a\\b"],
+            vec!["proxy", "--proxy"],
+            vec!["proxy", "--ca"],
+            vec!["proxy", "--allow=*"],
+            vec!["remove-policies", "netflix.com"],
+            vec!["forget", "netflix.com"],
+        ];
+        for arguments in invalid {
+            let error = HelperRequest::parse(&args(&arguments)).expect_err(&arguments.join(" "));
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_parse_persist() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut session = Session::new(
+            Vec::new(),
+            args(&["netflix.com", "www.youtube.com"]),
+            now,
+            Duration::from_secs(1_500),
+        );
+        session.hosts = args(&["netflix.com"]);
+        session.exceptions = args(&["www.youtube.com/watch*"]);
+        session.unlock_hash = Some("hash".to_string());
+        let request = HelperRequest::persist(&session);
+
+        assert_eq!(HelperRequest::parse(&request.to_args()).unwrap(), request);
+
+        session.hosts = args(&["netflix.com\n127.0.0.1 evil.com"]);
+        let tampered = HelperRequest::persist(&session).to_args();
+        assert!(HelperRequest::parse(&tampered).is_err());
+        assert!(HelperRequest::parse(&args(&["persist"])).is_err());
+        assert!(HelperRequest::parse(&args(&["persist", "{}"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_block_page() {
+        let mut session = Session::new(
+            args(&["coding"]),
+            args(&["www.youtube.com"]),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_000),
+            Duration::from_secs(1_500),
+        );
+        session.unlock_hash = Some("hash".to_string());
+        let request = HelperRequest::block_page(&session, Some("<p>{{site}}</p>".to_string()));
+
+        assert_eq!(request.session.as_ref().unwrap().unlock_hash, None);
+        assert_eq!(
+            request.to_args().last().unwrap(),
+            "--template=<p>{{site}}</p>"
+        );
+        assert_eq!(HelperRequest::parse(&request.to_args()).unwrap(), request);
+        let default = HelperRequest::block_page(&session, None);
+        assert_eq!(HelperRequest::parse(&default.to_args()).unwrap(), default);
+
+        let mut too_long = default.to_args();
+        too_long.push(TEMPLATE_OPTION.to_string() + &"a".repeat(MAX_TEMPLATE_LEN + 1));
+        assert!(HelperRequest::parse(&too_long).is_err());
+        assert!(HelperRequest::parse(&args(&["block-page"])).is_err());
+    }
+
+    #[test]
+    fn test_persist_session_never_replaces_one() {
+        let temp_dir = TempDir::new().unwrap();
+        let state_file = temp_dir.path().join("state").join(STATE_FILE);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let session = Session::new(
+            Vec::new(),
+            args(&["netflix.com"]),
+            now,
+            Duration::from_secs(60),
+        );
+
+        persist_session(&session, &state_file).expect("Should persist");
+        let error = persist_session(&session, &state_file).expect_err("Should not replace");
+
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        let saved: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&state_file).unwrap()).unwrap();
+        assert_eq!(Session::from_json(&saved), Some(session));
+    }
+
+    #[test]
+    fn test_is_valid_pattern() {
+        for pattern in [
+            "netflix.com",
+            "*.youtube.com",
+            "www.youtube.com/shorts*",
+            "www.youtube.com/watch?v=1&t=2",
+        ] {
+            assert!(is_valid_pattern(pattern), "{} should be valid", pattern);
+        }
+        for pattern in ["*", "/path", "*.com", "a.com/\"", "a.com/ b", "A.com"] {
+            assert!(
+                !is_valid_pattern(pattern),
+                "{:?} should be invalid",
+                pattern
+            );
+        }
+    }
+}
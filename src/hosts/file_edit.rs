@@ -1,97 +1,186 @@
-use crate::{FILE_PATH, LOCALHOST};
+use crate::hosts::store::HostsStore;
+use crate::LOCALHOST;
 
-/// Modifies the system hosts file to add new host entries.
+// Markers around the entries managed during a session
+pub const BLOCK_START: &str = "# >>> rust_can_make_you_focus";
+pub const BLOCK_END: &str = "# <<< rust_can_make_you_focus";
+
+/// Modifies the hosts file to add new host entries.
 ///
-/// This function reads the current content of the system hosts file, then appends
+/// This function reads the current content of the hosts file, then appends
 /// new entries mapping the localhost IP address (127.0.0.1) to each domain name
-/// provided in the arguments.
+/// provided in the arguments. The entries are written between `BLOCK_START` and
+/// `BLOCK_END`, replacing the ones of a previous session. A missing hosts file is
+/// created.
 ///
 /// # Arguments
 ///
-/// * `args` - A vector of strings representing domain names to add to the hosts file
+/// * `store` - The hosts file, `FileHostsStore::system()` outside of tests
+/// * `domains` - The domain names to add to the hosts file
 ///
 /// # Returns
 ///
 /// A `Result` indicating success or an I/O error if file operations fail
-pub fn parse_hosts_file(args: &Vec<&str>) -> Result<(), std::io::Error> {
-    let mut file: String = std::fs::read_to_string(FILE_PATH).expect("Failed to read file");
-    for arg in args {
+pub fn write_managed_block(store: &dyn HostsStore, domains: &[&str]) -> Result<(), std::io::Error> {
+    // A hosts file replaced through a rename may be briefly missing
+    let content = match store.read() {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    store.write(&with_managed_block(&content, domains))
+}
+
+/// Returns the hosts file content with the managed block replaced by `domains`.
+///
+/// An existing managed block is removed first, no block is added for no domains.
+pub fn with_managed_block(content: &str, domains: &[&str]) -> String {
+    let mut file = without_managed_block(content);
+    if domains.is_empty() {
+        return file;
+    }
+    if !file.is_empty() && !file.ends_with('\n') {
+        file.push('\n');
+    }
+    file.push_str(BLOCK_START);
+    file.push('\n');
+    for domain in domains {
         file.push_str(LOCALHOST);
         file.push_str("               ");
-        file.push_str(arg);
-        file.push_str("\n");
+        file.push_str(domain);
+        file.push('\n');
     }
+    file.push_str(BLOCK_END);
+    file.push('\n');
+    file
+}
 
-    return std::fs::write(FILE_PATH, file);
+/// Returns the hosts file content without the managed block.
+pub fn without_managed_block(content: &str) -> String {
+    let mut file = String::new();
+    let mut in_block = false;
+    for line in content.split_inclusive('\n') {
+        match line.trim_end() {
+            BLOCK_START => in_block = true,
+            BLOCK_END => in_block = false,
+            _ if !in_block => file.push_str(line),
+            _ => {}
+        }
+    }
+    file
+}
+
+/// Returns whether the managed block of the hosts file still sinks every domain.
+pub fn is_managed_block_intact(content: &str, domains: &[&str]) -> bool {
+    let mut in_block = false;
+    let mut sunk: Vec<&str> = Vec::new();
+    for line in content.lines() {
+        match line.trim() {
+            BLOCK_START => in_block = true,
+            BLOCK_END => in_block = false,
+            entry if in_block => {
+                let mut fields = entry.split_whitespace();
+                if fields.next() == Some(LOCALHOST) {
+                    sunk.extend(fields);
+                }
+            }
+            _ => {}
+        }
+    }
+    domains.iter().all(|domain| sunk.contains(domain))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
-
-    // Helper function to create a temporary hosts file for testing
-    fn setup_test_file(content: &str) -> (NamedTempFile, String) {
-        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
-        write!(temp_file, "{}", content).expect("Failed to write to temp file");
-        let file_path = temp_file.path().to_string_lossy().to_string();
-        (temp_file, file_path)
-    }
-    // Test version of parse_hosts_file that accepts a file path
-    fn parse_hosts_file_test(args: &Vec<String>, file_path: &str) -> Result<(), std::io::Error> {
-        let mut file: String = std::fs::read_to_string(file_path)?;
-        for arg in args {
-            file.push_str(LOCALHOST);
-            file.push_str("               ");
-            file.push_str(arg);
-            file.push_str("\n");
-        }
+    use crate::hosts::store::MemoryHostsStore;
 
-        return std::fs::write(file_path, file);
+    #[test]
+    fn test_write_managed_block_adds_entries() {
+        let store = MemoryHostsStore::new("# Initial hosts file\n127.0.0.1 localhost");
+
+        write_managed_block(&store, &["example.com", "test.local"]).expect("Failed to write");
+
+        assert_eq!(
+            store.content().unwrap(),
+            format!(
+                "# Initial hosts file\n127.0.0.1 localhost\n{}\n127.0.0.1               example.com\n127.0.0.1               test.local\n{}\n",
+                BLOCK_START, BLOCK_END
+            )
+        );
     }
 
     #[test]
-    fn test_parse_hosts_file_adds_entries() {
-        // Setup
+    fn test_write_managed_block_with_empty_args() {
         let initial_content = "# Initial hosts file\n127.0.0.1 localhost\n";
-        let (_temp_file, file_path) = setup_test_file(initial_content);
+        let store = MemoryHostsStore::new(initial_content);
 
-        // Test with mocked file operations
-        let args = vec!["example.com".to_string(), "test.local".to_string()];
+        write_managed_block(&store, &[]).expect("Should succeed with no domain");
 
-        // Since we can't modify the FILE_PATH constant, we'll create a modified version
-        // of the function for testing purposes
-        let result = parse_hosts_file_test(&args, &file_path);
+        assert_eq!(
+            store.content().unwrap(),
+            initial_content,
+            "File content should not change with empty args"
+        );
+    }
 
-        // Assert
-        assert!(result.is_ok(), "Function should return Ok");
+    #[test]
+    fn test_write_managed_block_creates_missing_file() {
+        let store = MemoryHostsStore::new("");
+        store.tamper(None);
 
-        // Read the modified file and verify content
-        let modified_content = fs::read_to_string(file_path).expect("Failed to read temp file");
-        assert!(modified_content.contains("127.0.0.1               example.com"));
-        assert!(modified_content.contains("127.0.0.1               test.local"));
-        assert!(modified_content.contains("# Initial hosts file"));
+        write_managed_block(&store, &["example.com"]).unwrap();
 
-        // Cleanup happens automatically when temp_file goes out of scope
+        assert!(is_managed_block_intact(
+            &store.content().unwrap(),
+            &["example.com"]
+        ));
     }
 
     #[test]
-    fn test_parse_hosts_file_with_empty_args() {
-        let initial_content = "# Initial hosts file\n127.0.0.1 localhost\n";
-        let (_temp_file, file_path) = setup_test_file(initial_content);
+    fn test_write_managed_block_write_error() {
+        let store = MemoryHostsStore::new("127.0.0.1 localhost\n");
+        store.fail_writes(true);
 
-        let args = Vec::new();
-        let result = parse_hosts_file_test(&args, &file_path);
+        let error = write_managed_block(&store, &["example.com"]).unwrap_err();
 
-        assert!(result.is_ok(), "Function should return Ok with empty args");
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(store.content().unwrap(), "127.0.0.1 localhost\n");
+    }
+
+    #[test]
+    fn test_with_managed_block_replaces_previous_block() {
+        let first = with_managed_block("127.0.0.1 localhost\n", &["a.com", "b.com"]);
+        let second = with_managed_block(&first, &["c.com"]);
 
-        // Content should be unchanged
-        let modified_content = fs::read_to_string(file_path).expect("Failed to read temp file");
+        assert!(!second.contains("a.com"), "The old entries should be gone");
+        assert_eq!(second.matches(BLOCK_START).count(), 1);
+        assert!(second.starts_with("127.0.0.1 localhost\n"));
         assert_eq!(
-            modified_content, initial_content,
-            "File content should not change with empty args"
+            with_managed_block(&second, &[]),
+            "127.0.0.1 localhost\n",
+            "No domain should remove the block"
+        );
+    }
+
+    #[test]
+    fn test_without_managed_block_keeps_other_lines() {
+        let content = format!(
+            "# top\n{}\n127.0.0.1 a.com\n{}\n# bottom\n",
+            BLOCK_START, BLOCK_END
         );
+        assert_eq!(without_managed_block(&content), "# top\n# bottom\n");
+    }
+
+    #[test]
+    fn test_is_managed_block_intact() {
+        let content = with_managed_block("127.0.0.1 localhost\n", &["a.com", "b.com"]);
+        assert!(is_managed_block_intact(&content, &["a.com", "b.com"]));
+
+        let edited = content.replace("127.0.0.1               b.com\n", "");
+        assert!(!is_managed_block_intact(&edited, &["a.com", "b.com"]));
+        let commented = content.replace("127.0.0.1               b.com", "# 127.0.0.1 b.com");
+        assert!(!is_managed_block_intact(&commented, &["b.com"]));
+        assert!(!is_managed_block_intact("127.0.0.1 a.com\n", &["a.com"]));
     }
 }
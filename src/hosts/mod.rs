@@ -1 +1,3 @@
 pub mod file_edit;
+pub mod store;
+pub mod watch;
@@ -0,0 +1,134 @@
+use std::path::{Path, PathBuf};
+
+use crate::FILE_PATH;
+
+/// Where the hosts file is read from and written to.
+///
+/// The session code only goes through this trait, so tests run the real code
+/// against an in-memory hosts file instead of `/etc/hosts`.
+pub trait HostsStore: Send + Sync {
+    /// Reads the whole hosts file, a `NotFound` error if it doesn't exist
+    fn read(&self) -> Result<String, std::io::Error>;
+    /// Replaces the whole hosts file
+    fn write(&self, content: &str) -> Result<(), std::io::Error>;
+    /// The file to watch for changes, `None` when there is no file to watch
+    fn path(&self) -> Option<&Path>;
+}
+
+/// A hosts file on disk.
+pub struct FileHostsStore {
+    path: PathBuf,
+}
+
+impl FileHostsStore {
+    pub fn new(path: PathBuf) -> FileHostsStore {
+        FileHostsStore { path }
+    }
+
+    /// The hosts file of the system, `/etc/hosts`.
+    pub fn system() -> FileHostsStore {
+        FileHostsStore::new(PathBuf::from(FILE_PATH))
+    }
+}
+
+impl HostsStore for FileHostsStore {
+    fn read(&self) -> Result<String, std::io::Error> {
+        std::fs::read_to_string(&self.path)
+    }
+
+    fn write(&self, content: &str) -> Result<(), std::io::Error> {
+        std::fs::write(&self.path, content)
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+/// A hosts file in memory, whose writes can be made to fail.
+#[cfg(test)]
+pub struct MemoryHostsStore {
+    content: std::sync::Mutex<Option<String>>,
+    fail_writes: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(test)]
+impl MemoryHostsStore {
+    pub fn new(content: &str) -> MemoryHostsStore {
+        MemoryHostsStore {
+            content: std::sync::Mutex::new(Some(content.to_string())),
+            fail_writes: std::sync::atomic::AtomicBool::new(false),
+        }
+    }
+
+    /// The current content, `None` once deleted.
+    pub fn content(&self) -> Option<String> {
+        self.content.lock().unwrap().clone()
+    }
+
+    /// Replaces the content behind the store's back, `None` deleting the file.
+    pub fn tamper(&self, content: Option<&str>) {
+        *self.content.lock().unwrap() = content.map(String::from);
+    }
+
+    /// Makes the following writes fail with `PermissionDenied`, or succeed again.
+    pub fn fail_writes(&self, fail: bool) {
+        self.fail_writes
+            .store(fail, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+impl HostsStore for MemoryHostsStore {
+    fn read(&self) -> Result<String, std::io::Error> {
+        self.content()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+    }
+
+    fn write(&self, content: &str) -> Result<(), std::io::Error> {
+        if self.fail_writes.load(std::sync::atomic::Ordering::Relaxed) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "injected write failure",
+            ));
+        }
+        self.tamper(Some(content));
+        Ok(())
+    }
+
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_file_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = FileHostsStore::new(temp_dir.path().join("hosts"));
+
+        let error = store.read().expect_err("The file doesn't exist yet");
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+        store.write("127.0.0.1 localhost\n").unwrap();
+        assert_eq!(store.read().unwrap(), "127.0.0.1 localhost\n");
+        assert_eq!(store.path(), Some(temp_dir.path().join("hosts").as_path()));
+    }
+
+    #[test]
+    fn test_memory_store_failures() {
+        let store = MemoryHostsStore::new("127.0.0.1 localhost\n");
+        store.fail_writes(true);
+        assert!(store.write("").is_err());
+        assert_eq!(store.read().unwrap(), "127.0.0.1 localhost\n");
+
+        store.tamper(None);
+        assert_eq!(
+            store.read().unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+}
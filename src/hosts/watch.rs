@@ -0,0 +1,297 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+use crate::hosts::file_edit::{is_managed_block_intact, write_managed_block};
+use crate::hosts::store::HostsStore;
+use crate::session::history::{History, TAMPER};
+
+/// How long to wait for a change before checking the file anyway.
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Keeps the managed block of the hosts file in place during a session.
+///
+/// Editing `/etc/hosts` by hand, or NetworkManager and cloud-init rewriting it,
+/// would silently end the session. The guard notices it (through inotify on Linux,
+/// by polling elsewhere), writes the block again and records the tampering in the
+/// session history.
+pub struct HostsGuard {
+    store: Arc<dyn HostsStore>,
+    domains: Vec<String>,
+    history: History,
+}
+
+impl HostsGuard {
+    /// Creates a guard for the hosts file of `store`, sinking `domains`.
+    pub fn new(store: Arc<dyn HostsStore>, domains: Vec<String>, history: History) -> HostsGuard {
+        HostsGuard {
+            store,
+            domains,
+            history,
+        }
+    }
+
+    /// Writes the managed block again if it was removed or edited.
+    ///
+    /// # Returns
+    ///
+    /// Whether the block had to be written again
+    pub fn check(&self, now: SystemTime) -> Result<bool, std::io::Error> {
+        let domains: Vec<&str> = self.domains.iter().map(String::as_str).collect();
+        // A hosts file replaced through a rename may be briefly missing
+        let content = match self.store.read() {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        if is_managed_block_intact(&content, &domains) {
+            return Ok(false);
+        }
+        write_managed_block(self.store.as_ref(), &domains)?;
+        self.history.record(
+            now,
+            TAMPER,
+            &format!(
+                "blocked entries removed from {}, written again",
+                self.name()
+            ),
+        )?;
+        Ok(true)
+    }
+
+    /// Checks the hosts file whenever it changes, until `done` is set.
+    ///
+    /// A store without a file on disk is polled.
+    pub fn spawn(self, done: Arc<AtomicBool>) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            let watcher = self.store.path().map(|path| {
+                let dir = path.parent().unwrap_or(Path::new("/"));
+                DirWatcher::new(dir).inspect_err(|e| {
+                    println!("Can't watch {}, polling it instead: {}", dir.display(), e)
+                })
+            });
+            while !done.load(Ordering::Relaxed) {
+                match self.check(SystemTime::now()) {
+                    Ok(true) => println!("{} was tampered with, blocks applied again", self.name()),
+                    Ok(false) => {}
+                    Err(e) => println!("Failed to check {}: {}", self.name(), e),
+                }
+                match &watcher {
+                    Some(Ok(watcher)) => watcher.wait(CHECK_INTERVAL),
+                    _ => sleep_unless_done(&done, CHECK_INTERVAL),
+                }
+            }
+        })
+    }
+
+    /// The hosts file, as shown in messages.
+    fn name(&self) -> String {
+        self.store
+            .path()
+            .map_or("the hosts file".to_string(), |path| {
+                path.display().to_string()
+            })
+    }
+}
+
+/// Sleeps `duration`, waking up early once `done` is set.
+fn sleep_unless_done(done: &AtomicBool, duration: Duration) {
+    let step = Duration::from_millis(50);
+    let mut slept = Duration::ZERO;
+    while slept < duration && !done.load(Ordering::Relaxed) {
+        std::thread::sleep(step);
+        slept += step;
+    }
+}
+
+/// Wakes up when a file of a directory changes, through inotify.
+///
+/// The directory is watched rather than the file, editors and tools replacing the
+/// file through a rename would otherwise escape the watch.
+#[cfg(target_os = "linux")]
+struct DirWatcher {
+    fd: libc::c_int,
+}
+
+#[cfg(target_os = "linux")]
+impl DirWatcher {
+    fn new(dir: &Path) -> Result<DirWatcher, std::io::Error> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+        // SAFETY: inotify_init1 takes plain flags and reports failures through errno
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let watcher = DirWatcher { fd };
+        let mask = libc::IN_MODIFY
+            | libc::IN_ATTRIB
+            | libc::IN_CLOSE_WRITE
+            | libc::IN_CREATE
+            | libc::IN_DELETE
+            | libc::IN_MOVED_FROM
+            | libc::IN_MOVED_TO;
+        // SAFETY: fd is an inotify descriptor and path a valid C string
+        if unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(watcher)
+    }
+
+    /// Waits for changes, at most `timeout`, and drains the pending events.
+    fn wait(&self, timeout: Duration) {
+        let mut poll_fd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: poll_fd is a single valid pollfd
+        unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) };
+        let mut buffer = [0u8; 4096];
+        // SAFETY: the descriptor is nonblocking and buffer is valid for its length
+        while unsafe { libc::read(self.fd, buffer.as_mut_ptr().cast(), buffer.len()) } > 0 {}
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for DirWatcher {
+    fn drop(&mut self) {
+        // SAFETY: fd was opened by inotify_init1 and is closed once
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Polling fallback where inotify is not available.
+#[cfg(not(target_os = "linux"))]
+struct DirWatcher;
+
+#[cfg(not(target_os = "linux"))]
+impl DirWatcher {
+    fn new(_dir: &Path) -> Result<DirWatcher, std::io::Error> {
+        Ok(DirWatcher)
+    }
+
+    fn wait(&self, timeout: Duration) {
+        std::thread::sleep(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hosts::file_edit::with_managed_block;
+    use crate::hosts::store::{FileHostsStore, MemoryHostsStore};
+    use crate::session::history::HISTORY_FILE;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    fn setup_guard() -> (TempDir, Arc<MemoryHostsStore>, HostsGuard) {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Arc::new(MemoryHostsStore::new(&with_managed_block(
+            "127.0.0.1 localhost\n",
+            &["netflix.com"],
+        )));
+        let history = History::new(temp_dir.path().join(HISTORY_FILE));
+        let guard = HostsGuard::new(store.clone(), vec!["netflix.com".to_string()], history);
+        (temp_dir, store, guard)
+    }
+
+    #[test]
+    fn test_check_intact_file() {
+        let (_temp_dir, _store, guard) = setup_guard();
+        assert!(!guard.check(SystemTime::now()).unwrap());
+        assert!(guard.history.events().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_check_reapplies_removed_block() {
+        let (_temp_dir, store, guard) = setup_guard();
+        store.tamper(Some("127.0.0.1 localhost\n"));
+
+        assert!(guard.check(SystemTime::now()).unwrap(), "Should re-apply");
+
+        let content = store.content().unwrap();
+        assert!(content.starts_with("127.0.0.1 localhost\n"));
+        assert!(is_managed_block_intact(&content, &["netflix.com"]));
+        let events = guard.history.events().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], TAMPER);
+    }
+
+    #[test]
+    fn test_check_recreates_deleted_file() {
+        let (_temp_dir, store, guard) = setup_guard();
+        store.tamper(None);
+
+        assert!(guard.check(SystemTime::now()).unwrap());
+        assert!(is_managed_block_intact(
+            &store.content().unwrap(),
+            &["netflix.com"]
+        ));
+    }
+
+    #[test]
+    fn test_check_write_error() {
+        let (_temp_dir, store, guard) = setup_guard();
+        store.tamper(Some("127.0.0.1 localhost\n"));
+        store.fail_writes(true);
+
+        assert!(guard.check(SystemTime::now()).is_err());
+        assert!(
+            guard.history.events().unwrap().is_empty(),
+            "Nothing was written again"
+        );
+    }
+
+    #[test]
+    fn test_spawn_polls_memory_store() {
+        let (_temp_dir, store, guard) = setup_guard();
+        let done = Arc::new(AtomicBool::new(false));
+        let handle = guard.spawn(done.clone());
+
+        done.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+
+        assert!(store.content().unwrap().contains("netflix.com"));
+    }
+
+    #[test]
+    fn test_spawn_reapplies_after_edit() {
+        let temp_dir = TempDir::new().unwrap();
+        let path: PathBuf = temp_dir.path().join("hosts");
+        std::fs::write(
+            &path,
+            with_managed_block("127.0.0.1 localhost\n", &["netflix.com"]),
+        )
+        .unwrap();
+        let guard = HostsGuard::new(
+            Arc::new(FileHostsStore::new(path.clone())),
+            vec!["netflix.com".to_string()],
+            History::new(temp_dir.path().join(HISTORY_FILE)),
+        );
+        let done = Arc::new(AtomicBool::new(false));
+        let handle = guard.spawn(done.clone());
+        std::thread::sleep(Duration::from_millis(200));
+
+        // Replace the file like an editor would
+        let edited = path.with_extension("swp");
+        std::fs::write(&edited, "127.0.0.1 localhost\n").unwrap();
+        std::fs::rename(&edited, &path).unwrap();
+        let mut intact = false;
+        for _ in 0..50 {
+            std::thread::sleep(Duration::from_millis(20));
+            let content = std::fs::read_to_string(&path).unwrap_or_default();
+            if is_managed_block_intact(&content, &["netflix.com"]) {
+                intact = true;
+                break;
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+
+        assert!(intact, "The block should be written again within a second");
+    }
+}
@@ -1,27 +1,59 @@
-use std::u64;
-
 mod utils;
 
 mod hosts;
 
-const RESET_FILE_PATH: &str = "
-##
-# Host Database
-#
-# localhost is used to configure the loopback interface
-# when the system is booting.  Do not change this entry.
-##
-127.0.0.1		localhost
-255.255.255.255		broadcasthost
-::1                          localhost
-";
+mod browser;
+
+mod apps;
+
+mod session;
+
+mod server;
+
+mod proxy;
+
+mod daemon;
+
+mod helper;
+
+mod schedule;
+
+mod notify;
+
+mod tui;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use apps::process::{AppAction, AppBlocker, AppMatcher};
+use browser::policy::BrowserPolicy;
+use daemon::protocol::{Request, FOCUS_GROUP, SOCKET_PATH};
+use daemon::service::{Daemon, SystemEnforcer, STATE_FILE};
+use helper::escalate::Escalation;
+use helper::request::{is_root, run_privileged, spawn_privileged, HelperRequest};
+use hosts::store::HostsStore;
+use notify::notifier::{notifier_from_flags, send, Notification, Notifier};
+use proxy::rules::Filter;
+use proxy::server::PROXY_PORT;
+use server::block_page::BlockPage;
+use server::ca::LocalCa;
+use session::clock::Clock;
+use session::friction::{EarlyUnlock, Friction, CHALLENGE_FLAG, COOLDOWN_FLAG};
+use session::history::{History, HISTORY_FILE};
+use session::state::{
+    Session, APPS_BACKEND, BLOCK_PAGE_BACKEND, BROWSER_POLICIES_BACKEND, HOSTS_BACKEND,
+    PROXY_BACKEND,
+};
+use session::strict::STRICT_FLAG;
+use tui::dashboard::{DaemonControls, Dashboard, ForegroundControls, TUI_FLAG};
+use utils::command::CommandRunner;
+
 const FILE_PATH: &str = "/etc/hosts";
 const LOCALHOST: &str = "127.0.0.1";
+const STATE_DIR: &str = "/var/lib/rust_can_make_you_focus";
 
 fn main() -> Result<(), std::io::Error> {
-    println!("Hello, Let's focus!");
-
-    let mut arguments: Vec<String> = std::env::args().map(|arg| arg).collect();
+    let (mut arguments, flags) = utils::utils::split_flags(std::env::args().collect());
     if arguments.len() <= 1 {
         panic!("arguments must be defined");
     }
@@ -34,20 +66,1222 @@ fn main() -> Result<(), std::io::Error> {
         utils::utils::help();
         return Ok(());
     }
+    if arguments[1].to_lowercase() == "status" {
+        return print_status(&flags);
+    }
+    if arguments[1].to_lowercase() == "watch" {
+        return watch();
+    }
+    if arguments[1].to_lowercase() == "schedule" {
+        return schedule_command(&arguments[2..], &flags);
+    }
+    if arguments[1].to_lowercase() == "install-systemd"
+        || arguments[1].to_lowercase() == "uninstall-systemd"
+    {
+        return manage_systemd(&arguments[1].to_lowercase(), &flags);
+    }
+    if arguments[1].to_lowercase() == "recover" {
+        require_root(&["the blocks left in /etc/hosts are removed"], &flags)?;
+        let lifted = session::recovery::recover(
+            &hosts::store::FileHostsStore::system(),
+            &browser::policy::PolicyDirs::system(),
+            &std::path::Path::new(STATE_DIR).join(STATE_FILE),
+            &session::status::foreground_state_file(true),
+            std::time::SystemTime::now(),
+        )?;
+        if lifted {
+            println!("Removed the blocks left by an ended session");
+        }
+        return Ok(());
+    }
+    if arguments[1].to_lowercase() == "helper" {
+        let request = HelperRequest::parse(&arguments[2..])?;
+        if !is_root() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "the helper must run as root",
+            ));
+        }
+        return request.execute();
+    }
+    if arguments[1].to_lowercase() == "daemon" {
+        require_root(&["the daemon writes /etc/hosts"], &flags)?;
+        return run_daemon(&flags);
+    }
+    if let Some(request) = control_request(&arguments, &flags) {
+        return control(&request?);
+    }
+    if arguments[1].to_lowercase() == "install-ca" {
+        require_root(&["the CA is installed in the system trust store"], &flags)?;
+        let ca = LocalCa::load_or_create(std::path::Path::new(STATE_DIR))?;
+        server::ca::install_ca(&ca)?;
+        println!(
+            "Installed {} into the system trust store",
+            ca.cert_path().display()
+        );
+        return Ok(());
+    }
+    if arguments[1].to_lowercase() == "uninstall-ca" {
+        require_root(&["the CA is removed from the system trust store"], &flags)?;
+        let Some(ca) = LocalCa::load(std::path::Path::new(STATE_DIR))? else {
+            println!("No local CA in {}, nothing to remove", STATE_DIR);
+            return Ok(());
+        };
+        server::ca::uninstall_ca(&ca)?;
+        println!(
+            "Removed {} from the system trust store",
+            ca.cert_path().display()
+        );
+        return Ok(());
+    }
+
+    // The daemon owns the managed block of the hosts file while it runs
+    if daemon_running() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "the daemon is running, start the session through it, e.g. `rust_can_make_you_focus start coding 25`",
+        ));
+    }
 
     let last_arg: String = arguments.pop().expect("Something went wrong");
     let sleep_time: u64 = utils::utils::parse_sleep_time(&last_arg);
     let match_arg: Vec<&str> = utils::utils::match_args(&arguments);
 
-    let parse_host_files = hosts::file_edit::parse_hosts_file(&match_arg);
+    let policy_dirs = browser::policy::PolicyDirs::system();
+    for warning in browser::doh::detect_doh(&browser::doh::system_homes()) {
+        // The browser policies turn it off, only for the browsers they reach
+        if policy_dirs.manages(warning.browser) {
+            println!("{}, turning it off for this session", warning);
+        } else {
+            println!(
+                "Warning: {}, it can't be turned off and the block may be bypassed",
+                warning
+            );
+        }
+    }
+
+    let mut session = Session::new(
+        utils::utils::match_presets(&arguments),
+        match_arg.iter().map(|site| site.to_string()).collect(),
+        std::time::SystemTime::now(),
+        std::time::Duration::from_secs(sleep_time),
+    );
+    let unlock_code = if flags.iter().any(|flag| flag == STRICT_FLAG) {
+        let code = session::strict::generate_unlock_code()?;
+        session.unlock_hash = Some(session::strict::hash_unlock_code(&code)?);
+        Some(code)
+    } else {
+        None
+    };
+    session.friction = Friction::from_flags(&flags)?;
+    let allowlist: Option<Vec<String>> =
+        utils::utils::match_allow_args(&arguments).map(|mut allowed| {
+            allowed.extend(utils::utils::flag_values(&flags, "--allow"));
+            allowed.into_iter().map(String::from).collect()
+        });
+    let proxy_enabled = allowlist.is_some() || flags.iter().any(|flag| flag == "--proxy");
+    // The browser policies live in /etc, they are only skipped without any browser
+    let browser_policies = !policy_dirs.chromium.is_empty() || !policy_dirs.firefox.is_empty();
+    let mut exceptions = utils::utils::match_exceptions(&session.presets, &flags);
+    if !exceptions.is_empty() && !browser_policies && !proxy_enabled {
+        // Without them the hosts file is all there is, and it blocks whole hosts
+        println!(
+            "No browser to manage, the exceptions can't be honoured without --proxy: {}",
+            exceptions.join(", ")
+        );
+        exceptions.clear();
+    }
+    let hosts_sites = utils::utils::hosts_sites(&match_arg, &exceptions);
+    for site in match_arg.iter().filter(|site| !hosts_sites.contains(site)) {
+        println!(
+            "{} has exceptions, it is only blocked in the browsers and the proxy",
+            site
+        );
+    }
+    session.exceptions = exceptions.clone();
+    let browser_policy = BrowserPolicy {
+        blocked: match_arg.iter().map(|site| site.to_string()).collect(),
+        exceptions: exceptions.clone(),
+        allowed: allowlist.clone(),
+        proxy: proxy_enabled.then(|| format!("{}:{}", LOCALHOST, PROXY_PORT)),
+        // The proxy and the HTTPS block page present certificates from the local CA
+        ca: (proxy_enabled || flags.iter().any(|flag| flag == "--block-page"))
+            .then(|| std::path::Path::new(STATE_DIR).join(server::ca::CA_CERT_FILE)),
+    };
+    let app_action = utils::utils::flag_values(&flags, "--apps")
+        .last()
+        .map_or(Ok(Some(AppAction::Stop)), |action| AppAction::parse(action))?;
+    let app_matchers = apps::process::app_matchers(&match_arg, &flags)?;
+
+    let mut root_reasons: Vec<&str> = Vec::new();
+    if !is_root() && Escalation::system().is_none() {
+        root_reasons.push("/etc/hosts can't be written without sudo or pkexec");
+    }
+    require_root(&root_reasons, &flags)?;
+    let notifier = notifier_from_flags(&flags, Arc::new(utils::command::SystemRunner))?;
+    if is_root() && !helper::escalate::is_writable(std::path::Path::new(FILE_PATH)) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!(
+                "{} is not writable (read-only file system?), nothing was changed",
+                FILE_PATH
+            ),
+        ));
+    }
+    println!("Hello, Let's focus!");
+
+    let plan = ForegroundPlan {
+        sites: match_arg.iter().map(|site| site.to_string()).collect(),
+        hosts_sites: hosts_sites.iter().map(|site| site.to_string()).collect(),
+        allowlist,
+        exceptions,
+        proxy: proxy_enabled,
+        browser_policy: browser_policies.then_some(browser_policy),
+        block_page: flags.iter().any(|flag| flag == "--block-page"),
+        hosts_guard: true,
+        apps: app_action.map(|action| (action, app_matchers)),
+        unlock_code,
+        state_file: session::status::foreground_state_file(is_root()),
+        through_helper: !is_root(),
+        arguments,
+        flags,
+    };
+    run_foreground(
+        plan,
+        Arc::new(Mutex::new(session)),
+        &hosts::store::FileHostsStore::system(),
+        &utils::command::SystemRunner,
+        &session::clock::SystemClock,
+        &notifier,
+    )
+}
+
+/// What a foreground session applies, decided from the command line by `main`.
+struct ForegroundPlan {
+    /// The command line, whose presets give the URL rules of the proxy
+    arguments: Vec<String>,
+    flags: Vec<String>,
+    /// The blocked websites
+    sites: Vec<String>,
+    /// The blocked websites written to the hosts file, see `utils::hosts_sites`
+    hosts_sites: Vec<String>,
+    allowlist: Option<Vec<String>>,
+    exceptions: Vec<String>,
+    proxy: bool,
+    /// `None` without any browser to manage
+    browser_policy: Option<BrowserPolicy>,
+    block_page: bool,
+    /// Whether /etc/hosts is written again when edited, see `start_hosts_guard`
+    hosts_guard: bool,
+    apps: Option<(AppAction, Vec<AppMatcher>)>,
+    /// The emergency unlock code of a strict session
+    unlock_code: Option<String>,
+    /// Where the running session is saved for `status`
+    state_file: std::path::PathBuf,
+    /// Whether the hosts file is written by the helper instead of through the store
+    through_helper: bool,
+}
+
+/// Runs a foreground session: applies the blocks of `plan`, waits for the deadline
+/// and lifts them again.
+///
+/// The blocks applied are lifted when a step fails to start, and every step of the
+/// end is tried whatever failed before.
+///
+/// # Arguments
+///
+/// * `plan` - What the session applies
+/// * `session` - The session, shared with the dashboard and the block page
+/// * `store` - The hosts file, the system one when `plan.through_helper`
+/// * `runner` - Runs the command flushing the DNS cache
+/// * `clock` - The clock a session without friction waits on
+/// * `notifier` - Sends the notifications of the session
+fn run_foreground(
+    plan: ForegroundPlan,
+    session: Arc<Mutex<Session>>,
+    store: &dyn HostsStore,
+    runner: &dyn CommandRunner,
+    clock: &dyn Clock,
+    notifier: &dyn Notifier,
+) -> Result<(), std::io::Error> {
+    let apply_hosts = |request: &HelperRequest| {
+        if plan.through_helper {
+            run_privileged(request)
+        } else {
+            request.execute_with(store, runner)
+        }
+    };
+    let sites: Vec<&str> = plan.sites.iter().map(String::as_str).collect();
+    let hosts_sites: Vec<&str> = plan.hosts_sites.iter().map(String::as_str).collect();
+    let browser_policies = plan.browser_policy.is_some();
+    if !hosts_sites.is_empty() {
+        if let Err(e) = apply_hosts(&HelperRequest::block(&hosts_sites)) {
+            // The helper may have failed after writing the hosts file
+            let written = store
+                .read()
+                .is_ok_and(|content| content.contains(hosts::file_edit::BLOCK_START));
+            if written {
+                let _ = apply_hosts(&HelperRequest::unblock());
+            }
+            return Err(e);
+        }
+    }
+    let session_done = Arc::new(AtomicBool::new(false));
+    // Lifts what was applied when the session fails to start
+    let rollback = |error: std::io::Error| -> std::io::Error {
+        session_done.store(true, Ordering::Relaxed);
+        if browser_policies {
+            let _ = run_privileged(&HelperRequest::remove_policies());
+        }
+        let _ = apply_hosts(&HelperRequest::unblock());
+        println!("The session could not start, the blocks were lifted");
+        error
+    };
+    // Listening first, the browser policies send every request to the proxy
+    let proxy = if plan.proxy {
+        let proxy = start_proxy(
+            &plan.arguments,
+            &sites,
+            &plan.flags,
+            plan.allowlist,
+            plan.exceptions,
+            session_done.clone(),
+        );
+        Some(proxy.map_err(&rollback)?)
+    } else {
+        None
+    };
+    if let Some(browser_policy) = &plan.browser_policy {
+        run_privileged(&HelperRequest::policies(browser_policy)).map_err(&rollback)?;
+    }
+    let block_page = if plan.block_page {
+        start_block_page(&plan.flags, session.clone(), session_done.clone()).map_err(&rollback)?
+    } else {
+        Vec::new()
+    };
+    let hosts_guard = if hosts_sites.is_empty() || !plan.hosts_guard {
+        None
+    } else {
+        start_hosts_guard(&hosts_sites, session_done.clone())
+            .inspect_err(|e| println!("No guard restores /etc/hosts if it is edited: {}", e))
+            .ok()
+    };
+    // Started last, suspended apps must not outlive a failed start
+    let app_blocker = plan
+        .apps
+        .map(|(action, matchers)| AppBlocker::new(matchers, action).spawn(session_done.clone()));
+    let running = {
+        let mut session = session.lock().unwrap();
+        session.hosts = hosts_sites.iter().map(|site| site.to_string()).collect();
+        session.backends = [
+            (HOSTS_BACKEND, !hosts_sites.is_empty()),
+            (BROWSER_POLICIES_BACKEND, browser_policies),
+            (BLOCK_PAGE_BACKEND, !block_page.is_empty()),
+            (PROXY_BACKEND, proxy.is_some()),
+            (APPS_BACKEND, app_blocker.is_some()),
+        ]
+        .iter()
+        .filter(|(_, active)| *active)
+        .map(|(backend, _)| backend.to_string())
+        .collect();
+        session.clone()
+    };
+    // Saved for `status`, a failure only leaves it without the session
+    let foreground_file = &plan.state_file;
+    if let Err(e) = std::fs::write(foreground_file, running.to_json().to_string()) {
+        println!(
+            "Failed to save the session to {}: {}",
+            foreground_file.display(),
+            e
+        );
+    }
+    let controls =
+        plan.flags.iter().any(|flag| flag == TUI_FLAG).then(|| {
+            ForegroundControls::new(session.clone()).with_state_file(foreground_file.clone())
+        });
+    let waited = match (&plan.unlock_code, running.friction) {
+        (Some(code), _) => run_strict(&running, code, notifier, controls),
+        (None, Some(friction)) => run_with_friction(&running, friction, notifier, controls),
+        (None, None) => {
+            let dashboard = controls.map(Dashboard::spawn);
+            utils::utils::plan_sleep(&session, clock, notifier);
+            if let Some(dashboard) = dashboard {
+                dashboard.close();
+            }
+            Ok(())
+        }
+    };
+    session_done.store(true, Ordering::Relaxed);
+    // Every step is tried, the hosts file must be unblocked whatever failed before
+    let mut errors: Vec<std::io::Error> = waited.err().into_iter().collect();
+    for server in block_page {
+        errors.extend(join(server, "The block page server").err());
+    }
+    if let Some(proxy) = proxy {
+        errors.extend(join(proxy, "The proxy").err());
+    }
+    if let Some(hosts_guard) = hosts_guard {
+        errors.extend(join(hosts_guard, "The hosts guard").err());
+    }
+    if let Some(app_blocker) = app_blocker {
+        let released = join(app_blocker, "The application blocker")
+            .and_then(|mut app_blocker| app_blocker.release());
+        errors.extend(released.err());
+    }
+    let _ = std::fs::remove_file(foreground_file);
+    if browser_policies {
+        errors.extend(run_privileged(&HelperRequest::remove_policies()).err());
+    }
+    errors.extend(apply_hosts(&HelperRequest::unblock()).err());
+    let mut errors = errors.into_iter();
+    let first = errors.next();
+    for e in errors {
+        println!("Failed to end the session: {}", e);
+    }
+    first.map_or(Ok(()), Err)
+}
+
+/// Joins a thread of the session, its panic becoming an error.
+///
+/// # Arguments
+///
+/// * `handle` - The thread to join
+/// * `name` - What the thread runs, for the error message
+fn join<T>(handle: std::thread::JoinHandle<T>, name: &str) -> Result<T, std::io::Error> {
+    handle
+        .join()
+        .map_err(|_| std::io::Error::other(format!("{} panicked", name)))
+}
+
+/// Prints what is blocked and for how long, see `session::status`.
+///
+/// The daemon is asked first, then the sessions saved by foreground runs are read.
+/// `--json` prints the status as JSON for scripts.
+fn print_status(flags: &[String]) -> Result<(), std::io::Error> {
+    let now = std::time::SystemTime::now();
+    let daemon_status =
+        match daemon::protocol::send_request(std::path::Path::new(SOCKET_PATH), &Request::Status) {
+            Ok(reply) => serde_json::from_str::<serde_json::Value>(&reply).ok(),
+            // No daemon, a stale socket left by one which was killed, or a user
+            // outside of the focus group, the saved state is reported then
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::ConnectionRefused
+                        | std::io::ErrorKind::NotFound
+                        | std::io::ErrorKind::PermissionDenied
+                ) =>
+            {
+                None
+            }
+            Err(e) => return Err(e),
+        };
+    let mut status = match &daemon_status {
+        Some(status) if status["active"] == true => status.clone(),
+        _ => {
+            let session = [
+                session::status::foreground_state_file(false),
+                session::status::foreground_state_file(true),
+                std::path::Path::new(STATE_DIR).join(STATE_FILE),
+            ]
+            .iter()
+            .filter_map(|file| std::fs::read_to_string(file).ok())
+            .filter_map(|content| serde_json::from_str(&content).ok())
+            .filter_map(|value| Session::from_json(&value))
+            .find(|session| session.remaining(now) > std::time::Duration::ZERO);
+            let hosts = std::fs::read_to_string(FILE_PATH).ok();
+            session::status::status_json(session.as_ref(), now, hosts.as_deref())
+        }
+    };
+    // The budgets are enforced by the daemon, even without a session
+    if let Some(budgets) = daemon_status.and_then(|status| status.get("budgets").cloned()) {
+        status["budgets"] = budgets;
+    }
+    if flags.iter().any(|flag| flag == "--json") {
+        println!("{}", status);
+        return Ok(());
+    }
+    println!("{}", session::status::format_status(&status));
+    browser::doh::print_doh_warnings(&browser::doh::system_homes());
+    Ok(())
+}
+
+/// Shows the dashboard of the session of the daemon, see `tui::dashboard`, until
+/// `q` is pressed.
+fn watch() -> Result<(), std::io::Error> {
+    let controls = DaemonControls::new(std::path::PathBuf::from(SOCKET_PATH));
+    match tui::dashboard::run(&controls, &AtomicBool::new(false)) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            println!(
+                "The daemon is not running, start it with `sudo rust_can_make_you_focus daemon`"
+            );
+            Err(e)
+        }
+        result => result,
+    }
+}
+
+/// Makes sure the program runs as root, see `helper::escalate::ensure_root`.
+///
+/// Exits with the status of the escalated run when the program was started again
+/// as root. `--no-escalate` prints the instructions instead.
+fn require_root(reasons: &[&str], flags: &[String]) -> Result<(), std::io::Error> {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let escalate = !flags.iter().any(|flag| flag == "--no-escalate");
+    if let Some(code) = helper::escalate::ensure_root(reasons, &arguments, escalate)? {
+        std::process::exit(code);
+    }
+    Ok(())
+}
+
+/// Waits for the deadline of a strict session, see `session::strict`.
+///
+/// Ctrl-C and the termination signals are ignored meanwhile. The session is
+/// persisted for the daemon through the helper, the daemon applies it again if the
+/// program is killed or the machine restarted before the deadline.
+///
+/// With `controls`, the dashboard is shown once the unlock code was noted down.
+fn run_strict(
+    session: &Session,
+    code: &str,
+    notifier: &dyn Notifier,
+    controls: Option<ForegroundControls>,
+) -> Result<(), std::io::Error> {
+    let state_file = std::path::Path::new(STATE_DIR).join(STATE_FILE);
+    let history = History::new(session::history::history_file(is_root()));
+    // A session of the daemon is never replaced
+    let persisted = !state_file.exists();
+    if persisted {
+        run_privileged(&HelperRequest::persist(session))?;
+    }
+    session::strict::ignore_termination_signals(true);
+    println!(
+        "Strict session: it can't be stopped before its deadline.\nEmergency unlock code, shown only once: {}\nType it and press Enter to end the session early.",
+        code
+    );
+    notify_start(session, notifier);
+    if controls.is_some() && tui::dashboard::is_interactive() {
+        // The dashboard hides the code
+        println!("Note it down, then press Enter to show the dashboard");
+        std::io::stdin().read_line(&mut String::new())?;
+    }
+    let (input, dashboard) = prompt_input(controls)?;
+    let hash = session.unlock_hash.as_deref().unwrap_or_default();
+    let unlocked =
+        session::strict::wait_for_deadline_or_unlock(input, session.deadline, hash, &history);
+    if let Some(dashboard) = dashboard {
+        dashboard.close();
+    }
+    session::strict::ignore_termination_signals(false);
+    if persisted {
+        run_privileged(&HelperRequest::forget())?;
+    }
+    if unlocked? {
+        println!("Unlocked, ending the session early");
+    } else {
+        send(notifier, &Notification::ended());
+    }
+    Ok(())
+}
+
+/// Waits for the deadline of a session with friction, see `session::friction`.
+///
+/// Ctrl-C and the termination signals are ignored meanwhile, typing `stop` is the
+/// only way to end the session early.
+///
+/// With `controls`, the dashboard is shown during the session.
+fn run_with_friction(
+    session: &Session,
+    friction: Friction,
+    notifier: &dyn Notifier,
+    controls: Option<ForegroundControls>,
+) -> Result<(), std::io::Error> {
+    let history = History::new(session::history::history_file(is_root()));
+    let mut unlock = EarlyUnlock::new(friction);
+    session::strict::ignore_termination_signals(true);
+    println!("Type `stop` and press Enter to end the session early, `cancel` to change your mind");
+    notify_start(session, notifier);
+    let (input, dashboard) = prompt_input(controls)?;
+    let stopped =
+        session::friction::wait_with_friction(input, session.deadline, &mut unlock, &history);
+    if let Some(dashboard) = dashboard {
+        dashboard.close();
+    }
+    session::strict::ignore_termination_signals(false);
+    if stopped {
+        println!("Stopping the session early");
+    } else {
+        send(notifier, &Notification::ended());
+    }
+    Ok(())
+}
+
+/// Shows the dashboard of `controls` during a strict session or one with friction.
+///
+/// An interactive dashboard reads the keys, its commands are sent to the prompt of
+/// the session through a pipe. Closing it with `q` closes the pipe, the prompt then
+/// reads stdin again.
+///
+/// # Returns
+///
+/// The input of the prompt, and the dashboard shown if any
+fn prompt_input(
+    controls: Option<ForegroundControls>,
+) -> Result<(Box<dyn std::io::Read + Send>, Option<Dashboard>), std::io::Error> {
+    match controls {
+        None => Ok((Box::new(std::io::stdin()), None)),
+        Some(controls) if !tui::dashboard::is_interactive() => {
+            Ok((Box::new(std::io::stdin()), Some(Dashboard::spawn(controls))))
+        }
+        Some(controls) => {
+            let (reader, writer) = std::io::pipe()?;
+            let dashboard = Dashboard::spawn(controls.with_input(Box::new(writer)));
+            Ok((
+                Box::new(std::io::Read::chain(reader, std::io::stdin())),
+                Some(dashboard),
+            ))
+        }
+    }
+}
+
+/// Notifies the start of a session waited for by `run_strict` or `run_with_friction`.
+fn notify_start(session: &Session, notifier: &dyn Notifier) {
+    let duration = session
+        .deadline
+        .duration_since(std::time::SystemTime::now())
+        .unwrap_or_default();
+    send(notifier, &Notification::started(&session.sites, duration));
+}
+
+/// Prints the configured schedules and calendars (`schedule list`), their next
+/// blocks (`schedule next [COUNT]`, 5 by default) or their blocks of the next 7 days
+/// (`schedule preview`), or asks the daemon to start the block of a schedule due
+/// now (`schedule run NAME`, run by the systemd timers).
+fn schedule_command(arguments: &[String], flags: &[String]) -> Result<(), std::io::Error> {
+    let path = schedule::config::config_path(flags);
+    let config = schedule::config::Config::load(&path)?;
+    if config.schedules.is_empty() && config.calendars.is_empty() {
+        println!("No schedule is configured in {}", path.display());
+        return Ok(());
+    }
+    match arguments
+        .first()
+        .map(|command| command.to_lowercase())
+        .as_deref()
+    {
+        None | Some("list") => {
+            for schedule in &config.schedules {
+                println!("{}", schedule::plan::format_schedule(schedule));
+            }
+            for calendar in &config.calendars {
+                println!("{}", schedule::calendar::format_calendar(calendar));
+            }
+        }
+        Some("next") => {
+            let count = match arguments.get(1) {
+                Some(count) => count.parse::<usize>().map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "schedule next expects a number of blocks",
+                    )
+                })?,
+                None => 5,
+            };
+            let now = schedule::plan::local_time(std::time::SystemTime::now());
+            let blocks = config.scheduler().upcoming(now, 14);
+            if blocks.is_empty() {
+                println!("No block in the next 14 days");
+            }
+            for block in blocks.iter().take(count) {
+                let running = if block.start <= now { " (running)" } else { "" };
+                println!("{}{}", schedule::plan::format_block(block), running);
+            }
+        }
+        Some("preview") => {
+            let now = schedule::plan::local_time(std::time::SystemTime::now());
+            let week = now + chrono::TimeDelta::days(7);
+            let blocks: Vec<schedule::plan::Block> = config
+                .scheduler()
+                .upcoming(now, 7)
+                .into_iter()
+                .filter(|block| block.start < week)
+                .collect();
+            if blocks.is_empty() {
+                println!("No block in the next 7 days");
+            }
+            for block in &blocks {
+                let running = if block.start <= now { " (running)" } else { "" };
+                println!("{}{}", schedule::plan::format_block(block), running);
+            }
+        }
+        Some("run") => {
+            let name = arguments.get(1).map(String::as_str).unwrap_or_default();
+            let slugs = daemon::systemd::schedule_slugs(&config.schedules);
+            let Some(schedule) = config
+                .schedules
+                .iter()
+                .zip(&slugs)
+                .find(|(schedule, slug)| schedule.name == name || *slug == name)
+                .map(|(schedule, _)| schedule)
+            else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("no schedule is named {:?}", name),
+                ));
+            };
+            let now = std::time::SystemTime::now();
+            let block = schedule::plan::active_block(
+                std::slice::from_ref(schedule),
+                schedule::plan::local_time(now),
+            );
+            let Some(end) = block
+                .as_ref()
+                .and_then(|b| schedule::plan::system_time(b.end))
+            else {
+                println!("No block of {} is due now", schedule.name);
+                return Ok(());
+            };
+            let seconds = end.duration_since(now).unwrap_or_default().as_secs();
+            let request = Request::Start {
+                minutes: seconds.div_ceil(60),
+                arguments: schedule.arguments.clone(),
+            };
+            match daemon::protocol::send_request(std::path::Path::new(SOCKET_PATH), &request) {
+                Ok(reply) => println!("{}", reply),
+                // The daemon started the block on its own
+                Err(e) if e.to_string() == "a session is already running" => {
+                    println!(
+                        "A session is already running, {} is not started",
+                        schedule.name
+                    )
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Some(command) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "unknown schedule command {}, expected list, next, preview or run",
+                    command
+                ),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Installs (`install-systemd`) or removes (`uninstall-systemd`) the systemd units
+/// of the daemon and the schedules, see `daemon::systemd::systemd_units`.
+///
+/// `--user` manages the timers of the user instead, `--print` only prints the units.
+/// The user timers talk to the system daemon, they are only installed with it.
+fn manage_systemd(command: &str, flags: &[String]) -> Result<(), std::io::Error> {
+    let user = flags.iter().any(|flag| flag == "--user");
+    let print = flags.iter().any(|flag| flag == "--print");
+    if !user && !print {
+        require_root(&["the units are installed in /etc/systemd/system"], flags)?;
+    }
+    let dir = daemon::systemd::unit_dir(user);
+    let runner = utils::command::SystemRunner;
+    if command == "uninstall-systemd" {
+        for path in daemon::systemd::uninstall_units(&dir, user, &runner)? {
+            println!("Removed {}", path.display());
+        }
+        return Ok(());
+    }
+    let daemon_unit =
+        std::path::Path::new(daemon::systemd::SYSTEM_UNIT_DIR).join(daemon::systemd::DAEMON_UNIT);
+    if user && !print && !daemon_unit.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "{} is not installed, the --user timers need the system daemon: run `sudo rust_can_make_you_focus install-systemd` first",
+                daemon_unit.display()
+            ),
+        ));
+    }
+    let config_path = std::path::absolute(schedule::config::config_path(flags))?;
+    let config = schedule::config::Config::load(&config_path)?;
+    let units = daemon::systemd::systemd_units(
+        &std::env::current_exe()?,
+        &config_path,
+        &config.schedules,
+        user,
+    );
+    if print {
+        for unit in &units {
+            println!("# {}\n{}", unit.name, unit.content);
+        }
+        return Ok(());
+    }
+    for path in daemon::systemd::install_units(&units, &dir, user, &runner)? {
+        println!("Installed {}", path.display());
+    }
+    Ok(())
+}
+
+/// Runs the focus daemon until it is killed, see `daemon::service::Daemon`.
+///
+/// `--max-pauses=N` limits how many times sessions can be paused per day,
+/// `--max-peeks=N` and `--max-peeks-per-session=N` how many times websites can be
+/// peeked at. The schedules, calendars and budgets are read from `--config=PATH`
+/// (or `schedule::config::CONFIG_PATH`). The sessions are notified as with
+/// `notifier_from_flags`.
+fn run_daemon(flags: &[String]) -> Result<(), std::io::Error> {
+    let config = schedule::config::Config::load(&schedule::config::config_path(flags))?;
+    let scheduler = config.scheduler();
+    let mut daemon = Daemon::new(
+        Box::new(SystemEnforcer::new(
+            browser::policy::PolicyDirs::system(),
+            std::sync::Arc::new(hosts::store::FileHostsStore::system()),
+            std::sync::Arc::new(utils::command::SystemRunner),
+            History::new(std::path::Path::new(STATE_DIR).join(HISTORY_FILE)),
+        )),
+        std::path::Path::new(STATE_DIR).join(STATE_FILE),
+        History::new(std::path::Path::new(STATE_DIR).join(HISTORY_FILE)),
+    )
+    .with_scheduler(scheduler)
+    .with_budgets(config.budgets)
+    .with_usage_probe(Box::new(session::usage::ConnectionProbe::system()))
+    .with_notifier(Box::new(notifier_from_flags(
+        flags,
+        Arc::new(utils::command::SystemRunner),
+    )?));
+    if let Some(limit) = limit_flag(flags, "--max-pauses")? {
+        daemon = daemon.with_pause_limit(limit);
+    }
+    daemon = daemon.with_peek_limits(
+        limit_flag(flags, "--max-peeks-per-session")?,
+        limit_flag(flags, "--max-peeks")?,
+    );
+    daemon.restore(std::time::SystemTime::now())?;
+    if let Some(session) = daemon.session() {
+        println!("Resumed the session blocking {}", session.sites.join(", "));
+    }
+    let listener =
+        daemon::service::bind_control_socket(std::path::Path::new(SOCKET_PATH), FOCUS_GROUP)?;
+    println!("Listening on {}", SOCKET_PATH);
+    daemon.serve(listener, Arc::new(AtomicBool::new(false)))
+}
+
+/// Returns the number given with the flag `name`, e.g. `--max-pauses=2`.
+fn limit_flag(flags: &[String], name: &str) -> Result<Option<usize>, std::io::Error> {
+    utils::utils::flag_values(flags, name)
+        .last()
+        .map(|limit| {
+            limit.parse::<usize>().map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("{} expects a number", name),
+                )
+            })
+        })
+        .transpose()
+}
+
+/// Builds the daemon request of the subcommands controlling a session: `start`,
+/// `stop`, `extend`, `shorten`, `add`, `remove`, `pause`, `resume`, `allow`,
+/// `peek`, `unlock`, `answer` and `cancel`.
+///
+/// # Returns
+///
+/// `None` when the arguments are not a daemon subcommand, the request otherwise
+fn control_request(
+    arguments: &[String],
+    flags: &[String],
+) -> Option<Result<Request, std::io::Error>> {
+    let command = arguments[1].to_lowercase();
+    match command.as_str() {
+        "start" => {
+            let mut line = command;
+            // `start` takes the minutes last like a foreground session
+            if let Some((minutes, sites)) = arguments[2..].split_last() {
+                line = format!("{} {} {}", line, minutes, sites.join(" "));
+            }
+            // The session flags the daemon understands, it has no allowlist mode
+            for flag in flags.iter().filter(|flag| {
+                *flag == STRICT_FLAG
+                    || flag.starts_with(COOLDOWN_FLAG)
+                    || flag.starts_with(CHALLENGE_FLAG)
+                    || flag.starts_with("--except=")
+            }) {
+                line = format!("{} {}", line, flag);
+            }
+            Some(Request::parse(&line))
+        }
+        "allow" => {
+            // `allow youtube 10m`, the minutes last with an optional unit
+            let mut line = command;
+            if let Some((minutes, sites)) = arguments[2..].split_last() {
+                let minutes = minutes.strip_suffix('m').unwrap_or(minutes);
+                line = format!("{} {} {}", line, minutes, sites.join(" "));
+            }
+            Some(Request::parse(&line))
+        }
+        "peek" => {
+            // `peek youtube 5m <reason>`
+            let mut line = command;
+            if let [site, minutes, reason @ ..] = &arguments[2..] {
+                let minutes = minutes.strip_suffix('m').unwrap_or(minutes);
+                line = format!("{} {} {} {}", line, minutes, site, reason.join(" "));
+            }
+            Some(Request::parse(&line))
+        }
+        "stop" | "extend" | "shorten" | "add" | "remove" | "pause" | "resume" | "unlock"
+        | "answer" | "cancel" => Some(Request::parse(&arguments[1..].join(" "))),
+        _ => None,
+    }
+}
+
+/// Returns whether a daemon answers on its socket, a stale socket left by a killed
+/// daemon refusing the connection.
+fn daemon_running() -> bool {
+    match daemon::protocol::send_request(std::path::Path::new(SOCKET_PATH), &Request::Status) {
+        Ok(_) => true,
+        Err(e) => !matches!(
+            e.kind(),
+            std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::NotFound
+        ),
+    }
+}
+
+/// Sends a request to the daemon and prints its reply.
+fn control(request: &Request) -> Result<(), std::io::Error> {
+    match daemon::protocol::send_request(std::path::Path::new(SOCKET_PATH), request) {
+        Ok(reply) => {
+            println!("{}", reply);
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            println!(
+                "The daemon is not running, start it with `sudo rust_can_make_you_focus daemon`"
+            );
+            Err(e)
+        }
+        Err(e) => Err(e),
+    }
+}
 
-    if parse_host_files.is_err() {
-        return parse_host_files;
+/// Starts the block page servers on the sink address, over HTTP and HTTPS, see
+/// `BlockPage::serve`.
+///
+/// Ports 80 and 443 and the CA key need root, so without root the page is served
+/// by the helper until the session is done. The session is sent to it again
+/// whenever it changes, e.g. once extended from the dashboard.
+fn start_block_page(
+    flags: &[String],
+    session: Arc<Mutex<Session>>,
+    done: Arc<AtomicBool>,
+) -> Result<Vec<std::thread::JoinHandle<()>>, std::io::Error> {
+    let template = match utils::utils::flag_values(flags, "--block-page-template").last() {
+        Some(template) => Some(std::fs::read_to_string(template)?),
+        None => None,
+    };
+    if is_root() {
+        let page = match template {
+            Some(template) => BlockPage::with_template(template, session),
+            None => BlockPage::new(session),
+        };
+        return page.serve(std::path::Path::new(STATE_DIR), done);
     }
-    let res_flux = utils::utils::execute_flux_cache();
-    if res_flux.is_err() {
-        return res_flux;
+
+    let mut sent = session.lock().unwrap().clone();
+    let mut helper = spawn_privileged(&HelperRequest::block_page(&sent, template))?;
+    let mut input = helper.stdin.take();
+    Ok(vec![std::thread::spawn(move || {
+        while !done.load(Ordering::Relaxed) {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            let running = session.lock().unwrap().clone();
+            if running == sent {
+                continue;
+            }
+            if let Some(stdin) = input.as_mut() {
+                let line = format!("{}\n", running.to_json());
+                if let Err(e) = std::io::Write::write_all(stdin, line.as_bytes()) {
+                    println!("The block page shows the session as it started: {}", e);
+                    input = None;
+                }
+            }
+            sent = running;
+        }
+        // Closing its input stops the helper
+        drop(input);
+        if let Err(e) = helper.wait() {
+            println!("Failed to stop the block page helper: {}", e);
+        }
+    })])
+}
+
+/// Starts the filtering proxy enforcing the URL rules of the session.
+///
+/// The rules are made of the blocked websites, the path rules of the presets and
+/// the `--block-url` flags, minus the `exceptions`. In allowlist mode everything
+/// else than `allowlist` is blocked as well.
+///
+/// The CA key is only readable by root, so without root the proxy runs in the
+/// helper until the session is done. The browser policies point at the proxy,
+/// failing to listen fails the session.
+fn start_proxy(
+    arguments: &[String],
+    sites: &[&str],
+    flags: &[String],
+    allowlist: Option<Vec<String>>,
+    exceptions: Vec<String>,
+    done: Arc<AtomicBool>,
+) -> Result<std::thread::JoinHandle<()>, std::io::Error> {
+    let mut rules: Vec<String> = sites.iter().map(|site| site.to_string()).collect();
+    for preset in utils::utils::match_presets(arguments) {
+        rules.extend(
+            utils::utils::preset_url_rules(&preset)
+                .into_iter()
+                .map(String::from),
+        );
+    }
+    rules.extend(
+        utils::utils::flag_values(flags, "--block-url")
+            .into_iter()
+            .map(String::from),
+    );
+    if is_root() {
+        let filter = Filter::from_patterns(&rules, allowlist.as_deref(), &exceptions);
+        return proxy::server::start(filter, std::path::Path::new(STATE_DIR), done);
+    }
+
+    let listening = || std::net::TcpStream::connect((LOCALHOST, PROXY_PORT)).is_ok();
+    if listening() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            format!(
+                "the proxy failed to listen on {}:{}, the port is in use",
+                LOCALHOST, PROXY_PORT
+            ),
+        ));
+    }
+    let mut helper = spawn_privileged(&HelperRequest::proxy(rules, allowlist, exceptions))?;
+    // sudo or pkexec may ask for the password first
+    while !listening() {
+        if let Some(status) = helper.try_wait()? {
+            return Err(std::io::Error::other(format!(
+                "the proxy helper failed: {}",
+                status
+            )));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    Ok(stop_helper_when_done(helper, "proxy", done))
+}
+
+/// Starts the guard writing the blocks of `sites` again when /etc/hosts is
+/// tampered with, see `hosts::watch::HostsGuard`.
+///
+/// Without root the guard runs in the helper until the session is done.
+fn start_hosts_guard(
+    sites: &[&str],
+    done: Arc<AtomicBool>,
+) -> Result<std::thread::JoinHandle<()>, std::io::Error> {
+    if is_root() {
+        let guard = hosts::watch::HostsGuard::new(
+            Arc::new(hosts::store::FileHostsStore::system()),
+            sites.iter().map(|site| site.to_string()).collect(),
+            History::new(std::path::Path::new(STATE_DIR).join(HISTORY_FILE)),
+        );
+        return Ok(guard.spawn(done));
+    }
+    let helper = spawn_privileged(&HelperRequest::guard(sites))?;
+    Ok(stop_helper_when_done(helper, "hosts guard", done))
+}
+
+/// Waits in the background for `done`, then stops `helper` by closing its input.
+///
+/// # Arguments
+///
+/// * `helper` - A helper started by `spawn_privileged`
+/// * `name` - What the helper runs, for the error message
+/// * `done` - Set at the end of the session
+fn stop_helper_when_done(
+    mut helper: std::process::Child,
+    name: &'static str,
+    done: Arc<AtomicBool>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        while !done.load(Ordering::Relaxed) {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        // Closing its input stops the helper
+        drop(helper.stdin.take());
+        if let Err(e) = helper.wait() {
+            println!("Failed to stop the {} helper: {}", name, e);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hosts::store::MemoryHostsStore;
+    use notify::notifier::RecordingNotifier;
+    use session::clock::ManualClock;
+    use std::time::{Duration, SystemTime};
+    use utils::command::RecordingRunner;
+
+    const HOSTS: &str = "127.0.0.1 localhost\n";
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    /// A hosts file in memory keeping every content written to it.
+    struct WrittenHosts {
+        store: MemoryHostsStore,
+        writes: Mutex<Vec<String>>,
+    }
+
+    impl HostsStore for WrittenHosts {
+        fn read(&self) -> Result<String, std::io::Error> {
+            self.store.read()
+        }
+
+        fn write(&self, content: &str) -> Result<(), std::io::Error> {
+            self.store.write(content)?;
+            self.writes.lock().unwrap().push(content.to_string());
+            Ok(())
+        }
+
+        fn path(&self) -> Option<&std::path::Path> {
+            None
+        }
+    }
+
+    /// A session blocking `sites` in the hosts file only.
+    fn hosts_plan(sites: &[&str], state_file: std::path::PathBuf) -> ForegroundPlan {
+        ForegroundPlan {
+            arguments: strings(sites),
+            flags: Vec::new(),
+            sites: strings(sites),
+            hosts_sites: strings(sites),
+            allowlist: None,
+            exceptions: Vec::new(),
+            proxy: false,
+            browser_policy: None,
+            block_page: false,
+            hosts_guard: false,
+            apps: None,
+            unlock_code: None,
+            state_file,
+            through_helper: false,
+        }
+    }
+
+    #[test]
+    fn test_run_foreground_blocks_until_the_deadline() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let state_file = temp_dir.path().join("foreground.json");
+        let hosts = WrittenHosts {
+            store: MemoryHostsStore::new(HOSTS),
+            writes: Mutex::new(Vec::new()),
+        };
+        let runner = RecordingRunner::default();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = ManualClock::new(start);
+        let notifier = RecordingNotifier::default();
+        let session = Session::new(
+            Vec::new(),
+            strings(&["youtube.com"]),
+            start,
+            Duration::from_secs(25 * 60),
+        );
+
+        run_foreground(
+            hosts_plan(&["youtube.com"], state_file.clone()),
+            Arc::new(Mutex::new(session)),
+            &hosts,
+            &runner,
+            &clock,
+            &notifier,
+        )
+        .unwrap();
+
+        let writes = hosts.writes.lock().unwrap().clone();
+        assert_eq!(writes.len(), 2, "The hosts file is blocked then restored");
+        assert_eq!(
+            writes[0],
+            hosts::file_edit::with_managed_block(HOSTS, &["youtube.com"])
+        );
+        assert_eq!(writes[1], HOSTS);
+        assert_eq!(hosts.store.content().as_deref(), Some(HOSTS));
+        assert_eq!(clock.now(), start + Duration::from_secs(25 * 60));
+        assert_eq!(runner.calls().len(), 2, "The DNS cache is flushed twice");
+        assert_eq!(
+            notifier.summaries(),
+            vec![
+                "Focus session started",
+                "Focus session ending soon",
+                "Focus session ended"
+            ]
+        );
+        assert!(!state_file.exists(), "The saved session should be removed");
+    }
+
+    #[test]
+    fn test_run_foreground_fails_when_the_block_is_not_written() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let store = MemoryHostsStore::new(HOSTS);
+        store.fail_writes(true);
+        let start = SystemTime::UNIX_EPOCH;
+        let clock = ManualClock::new(start);
+        let notifier = RecordingNotifier::default();
+        let session = Session::new(
+            Vec::new(),
+            strings(&["youtube.com"]),
+            start,
+            Duration::from_secs(60),
+        );
+
+        let error = run_foreground(
+            hosts_plan(&["youtube.com"], temp_dir.path().join("foreground.json")),
+            Arc::new(Mutex::new(session)),
+            &store,
+            &RecordingRunner::default(),
+            &clock,
+            &notifier,
+        )
+        .expect_err("The block failed to be written");
+
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(store.content().as_deref(), Some(HOSTS));
+        assert!(clock.sleeps().is_empty(), "No session should be waited for");
+        assert!(notifier.summaries().is_empty());
+    }
+
+    #[test]
+    fn test_control_request_start_forwards_session_flags() {
+        let arguments = strings(&["focus", "start", "youtube", "25"]);
+        let flags = strings(&[
+            "--strict",
+            "--cooldown=5",
+            "--except=www.youtube.com/watch*",
+            "--allow=docs.rs",
+            "--tui",
+        ]);
+        let request = control_request(&arguments, &flags).unwrap().unwrap();
+        assert_eq!(
+            request,
+            Request::Start {
+                minutes: 25,
+                arguments: strings(&[
+                    "youtube",
+                    "--strict",
+                    "--cooldown=5",
+                    "--except=www.youtube.com/watch*",
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_control_request_unknown_command() {
+        let arguments = strings(&["focus", "youtube", "25"]);
+        assert!(control_request(&arguments, &[]).is_none());
     }
-    utils::utils::plan_sleep(sleep_time);
-    return utils::utils::reset_file(RESET_FILE_PATH);
 }
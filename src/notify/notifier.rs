@@ -0,0 +1,635 @@
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+
+use crate::helper::request::is_root;
+use crate::session::state::format_remaining;
+use crate::utils::command::CommandRunner;
+use crate::utils::utils::flag_values;
+
+/// The application name the desktop notifications are sent with.
+pub const APP_NAME: &str = "rust_can_make_you_focus";
+/// How long before the end of a session the warning is sent.
+pub const WARNING_BEFORE: Duration = Duration::from_secs(5 * 60);
+/// The flag running a command instead of sending desktop notifications.
+pub const NOTIFY_COMMAND_FLAG: &str = "--notify-command";
+/// The flag disabling the notifications.
+pub const NO_NOTIFY_FLAG: &str = "--no-notify";
+
+/// How long a desktop notification stays on screen, in milliseconds.
+const EXPIRE_TIMEOUT: &str = "10000";
+/// Where the runtime directories of the logged-in users are, with their session bus.
+const RUN_USER: &str = "/run/user";
+/// The socket of a session bus, in the runtime directory of its user.
+const BUS_SOCKET: &str = "bus";
+
+/// A message about a session, e.g. its start or its end.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+}
+
+impl Notification {
+    /// A session blocking `sites` started for `duration`.
+    pub fn started(sites: &[String], duration: Duration) -> Notification {
+        Notification {
+            summary: "Focus session started".to_string(),
+            body: format!(
+                "Blocking {} for {}",
+                sites.join(", "),
+                format_remaining(duration)
+            ),
+        }
+    }
+
+    /// The session ends in `left`.
+    pub fn ending_soon(left: Duration) -> Notification {
+        Notification {
+            summary: "Focus session ending soon".to_string(),
+            body: format!("{} left, time to wrap up", format_remaining(left)),
+        }
+    }
+
+    /// A scheduled block ended, the next one starts at `until`, local time.
+    pub fn on_break(until: NaiveDateTime) -> Notification {
+        Notification {
+            summary: "Break time".to_string(),
+            body: format!("The blocks are lifted until {}", until.format("%H:%M")),
+        }
+    }
+
+    /// The scheduled block following a break started.
+    pub fn break_over(sites: &[String], duration: Duration) -> Notification {
+        Notification {
+            summary: "Break over".to_string(),
+            ..Notification::started(sites, duration)
+        }
+    }
+
+    /// The session reached its deadline.
+    pub fn ended() -> Notification {
+        Notification {
+            summary: "Focus session ended".to_string(),
+            body: "The blocks are lifted, good job".to_string(),
+        }
+    }
+}
+
+/// Delivers the notifications of the sessions.
+pub trait Notifier: Send + Sync {
+    /// Shows `notification` to the user.
+    ///
+    /// # Returns
+    ///
+    /// An error when it could not be delivered
+    fn notify(&self, notification: &Notification) -> Result<(), std::io::Error>;
+}
+
+/// The session bus of a logged-in user.
+///
+/// Root, e.g. the daemon, has no session bus of its own: its notifications are sent
+/// to the bus of the user, as that user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserBus {
+    pub uid: u32,
+    pub gid: u32,
+    pub socket: PathBuf,
+}
+
+impl UserBus {
+    /// Finds the session bus of a logged-in user.
+    ///
+    /// # Arguments
+    ///
+    /// * `run_user` - The runtime directories of the users, `/run/user` outside of tests
+    /// * `preferred` - The user who started the program, e.g. through `sudo`
+    ///
+    /// # Returns
+    ///
+    /// The bus of `preferred` if it has one, else the bus of the first user found
+    pub fn find(run_user: &Path, preferred: Option<u32>) -> Option<UserBus> {
+        let mut buses: Vec<UserBus> = std::fs::read_dir(run_user)
+            .ok()?
+            .flatten()
+            .filter_map(|entry| {
+                let uid: u32 = entry.file_name().to_str()?.parse().ok()?;
+                let socket = entry.path().join(BUS_SOCKET);
+                let gid = std::fs::metadata(entry.path()).ok()?.gid();
+                (uid != 0 && socket.exists()).then_some(UserBus { uid, gid, socket })
+            })
+            .collect();
+        buses.sort_by_key(|bus| bus.uid);
+        match buses.iter().position(|bus| Some(bus.uid) == preferred) {
+            Some(index) => Some(buses.swap_remove(index)),
+            None => buses.into_iter().next(),
+        }
+    }
+
+    /// Returns the D-Bus address of the bus, for `DBUS_SESSION_BUS_ADDRESS`.
+    pub fn address(&self) -> String {
+        format!("unix:path={}", self.socket.display())
+    }
+}
+
+/// Sends freedesktop notifications to the session bus over D-Bus, with `gdbus`.
+pub struct DesktopNotifier {
+    runner: Arc<dyn CommandRunner>,
+    bus: Option<UserBus>,
+}
+
+impl DesktopNotifier {
+    pub fn new(runner: Arc<dyn CommandRunner>) -> DesktopNotifier {
+        DesktopNotifier { runner, bus: None }
+    }
+
+    /// Sends the notifications to the bus of another user, as that user.
+    ///
+    /// # Arguments
+    ///
+    /// * `bus` - The bus, `None` for the session bus of the current user
+    pub fn with_bus(mut self, bus: Option<UserBus>) -> DesktopNotifier {
+        self.bus = bus;
+        self
+    }
+}
+
+impl Notifier for DesktopNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), std::io::Error> {
+        let summary = gvariant_string(&notification.summary);
+        let body = gvariant_string(&notification.body);
+        let app_name = gvariant_string(APP_NAME);
+        let call = [
+            "gdbus",
+            "call",
+            "--session",
+            "--dest",
+            "org.freedesktop.Notifications",
+            "--object-path",
+            "/org/freedesktop/Notifications",
+            "--method",
+            "org.freedesktop.Notifications.Notify",
+            &app_name,
+            "0",
+            "''",
+            &summary,
+            &body,
+            "[]",
+            "{}",
+            EXPIRE_TIMEOUT,
+        ];
+        let Some(bus) = &self.bus else {
+            return self.runner.run(call[0], &call[1..]);
+        };
+        let address = format!("DBUS_SESSION_BUS_ADDRESS={}", bus.address());
+        let mut command: Vec<&str> = vec!["env", &address];
+        command.extend(call);
+        // SAFETY: geteuid has no preconditions and cannot fail
+        if bus.uid == unsafe { libc::geteuid() } {
+            return self.runner.run(command[0], &command[1..]);
+        }
+        let reuid = format!("--reuid={}", bus.uid);
+        let regid = format!("--regid={}", bus.gid);
+        let mut arguments: Vec<&str> = vec![&reuid, &regid, "--clear-groups"];
+        arguments.extend(command);
+        self.runner.run("setpriv", &arguments)
+    }
+}
+
+/// Runs a command with the summary and the body as last arguments, e.g.
+/// `notify-send` or a script of the user.
+pub struct CommandNotifier {
+    command: Vec<String>,
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl CommandNotifier {
+    /// Creates the notifier of `command`, a program followed by its first arguments.
+    ///
+    /// The arguments are split like a shell does, quotes keeping spaces together.
+    ///
+    /// # Returns
+    ///
+    /// The notifier, or an `InvalidInput` error if `command` is blank or a quote
+    /// is not closed
+    pub fn new(
+        command: &str,
+        runner: Arc<dyn CommandRunner>,
+    ) -> Result<CommandNotifier, std::io::Error> {
+        let invalid = |message: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} {}", NOTIFY_COMMAND_FLAG, message),
+            )
+        };
+        let command = split_command(command).ok_or_else(|| invalid("has an unclosed quote"))?;
+        if command.is_empty() {
+            return Err(invalid("expects a command"));
+        }
+        Ok(CommandNotifier { command, runner })
+    }
+}
+
+/// Splits `command` into words like a shell: quotes keep the spaces of a word, and
+/// a backslash escapes the next character, except between single quotes.
+///
+/// # Returns
+///
+/// The words, or `None` if a quote is not closed
+fn split_command(command: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => word.get_or_insert_with(String::new).push(chars.next()?),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => word.push(chars.next()?),
+                        c => word.push(c),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Some(words)
+}
+
+impl Notifier for CommandNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), std::io::Error> {
+        let mut arguments: Vec<&str> = self.command[1..].iter().map(String::as_str).collect();
+        arguments.push(&notification.summary);
+        arguments.push(&notification.body);
+        self.runner.run(&self.command[0], &arguments)
+    }
+}
+
+/// Rings the terminal bell and prints the notification.
+pub struct BellNotifier;
+
+impl Notifier for BellNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), std::io::Error> {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(bell_line(notification).as_bytes())?;
+        stdout.flush()
+    }
+}
+
+/// Tries notifiers in turn until one delivers the notification.
+///
+/// Without notifiers, the notifications are dropped.
+pub struct FallbackNotifier {
+    notifiers: Vec<Box<dyn Notifier>>,
+}
+
+impl FallbackNotifier {
+    pub fn new(notifiers: Vec<Box<dyn Notifier>>) -> FallbackNotifier {
+        FallbackNotifier { notifiers }
+    }
+}
+
+impl Notifier for FallbackNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), std::io::Error> {
+        let mut result = Ok(());
+        for notifier in &self.notifiers {
+            result = notifier.notify(notification);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+}
+
+/// Builds the notifier selected by the flags.
+///
+/// The command of `--notify-command=COMMAND` is tried first, then the desktop
+/// notifications and the terminal bell. `--no-notify` disables them all.
+///
+/// # Returns
+///
+/// The notifier, or an `InvalidInput` error if the command is blank
+pub fn notifier_from_flags(
+    flags: &[String],
+    runner: Arc<dyn CommandRunner>,
+) -> Result<FallbackNotifier, std::io::Error> {
+    if flags.iter().any(|flag| flag == NO_NOTIFY_FLAG) {
+        return Ok(FallbackNotifier::new(Vec::new()));
+    }
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+    if let Some(command) = flag_values(flags, NOTIFY_COMMAND_FLAG).last() {
+        notifiers.push(Box::new(CommandNotifier::new(command, runner.clone())?));
+    }
+    // Root has no desktop, the user who is logged in gets the notifications
+    let bus = is_root()
+        .then(|| {
+            let preferred = ["SUDO_UID", "PKEXEC_UID"]
+                .iter()
+                .find_map(|name| std::env::var(name).ok()?.parse().ok());
+            UserBus::find(Path::new(RUN_USER), preferred)
+        })
+        .flatten();
+    notifiers.push(Box::new(DesktopNotifier::new(runner).with_bus(bus)));
+    notifiers.push(Box::new(BellNotifier));
+    Ok(FallbackNotifier::new(notifiers))
+}
+
+/// Sends `notification`, printing why it failed instead of failing the session.
+pub fn send(notifier: &dyn Notifier, notification: &Notification) {
+    if let Err(e) = notifier.notify(notification) {
+        println!("Failed to send the notification: {}", e);
+    }
+}
+
+/// Quotes `text` as a GVariant string, as parsed by `gdbus call`.
+fn gvariant_string(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn bell_line(notification: &Notification) -> String {
+    format!("\x07{}: {}\n", notification.summary, notification.body)
+}
+
+/// Records the notifications instead of showing them.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingNotifier {
+    sent: std::sync::Mutex<Vec<Notification>>,
+}
+
+#[cfg(test)]
+impl RecordingNotifier {
+    /// The summaries of the notifications sent so far.
+    pub fn summaries(&self) -> Vec<String> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|notification| notification.summary.clone())
+            .collect()
+    }
+
+    /// The notifications sent so far.
+    pub fn sent(&self) -> Vec<Notification> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Notifier for RecordingNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), std::io::Error> {
+        self.sent.lock().unwrap().push(notification.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Notifier for Arc<RecordingNotifier> {
+    fn notify(&self, notification: &Notification) -> Result<(), std::io::Error> {
+        self.as_ref().notify(notification)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::command::{RecordingRunner, SystemRunner};
+    use std::io::BufRead;
+    use tempfile::TempDir;
+
+    fn ended() -> Notification {
+        Notification::ended()
+    }
+
+    #[test]
+    fn test_desktop_notifier_calls_notify_over_dbus() {
+        let runner = Arc::new(RecordingRunner::default());
+        let notifier = DesktopNotifier::new(runner.clone());
+
+        notifier
+            .notify(&Notification {
+                summary: "Break time".to_string(),
+                body: "It's 10:00".to_string(),
+            })
+            .unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            vec![
+                "gdbus call --session --dest org.freedesktop.Notifications \
+                 --object-path /org/freedesktop/Notifications \
+                 --method org.freedesktop.Notifications.Notify \
+                 'rust_can_make_you_focus' 0 '' 'Break time' 'It\\'s 10:00' [] {} 10000"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_desktop_notifier_targets_the_user_bus() {
+        let runner = Arc::new(RecordingRunner::default());
+        let notifier = DesktopNotifier::new(runner.clone()).with_bus(Some(UserBus {
+            uid: 4242,
+            gid: 4343,
+            socket: PathBuf::from("/run/user/4242/bus"),
+        }));
+
+        notifier.notify(&ended()).unwrap();
+
+        let call = &runner.calls()[0];
+        assert!(
+            call.starts_with(
+                "setpriv --reuid=4242 --regid=4343 --clear-groups \
+                 env DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/4242/bus gdbus call --session"
+            ),
+            "Unexpected call: {}",
+            call
+        );
+    }
+
+    #[test]
+    fn test_user_bus_find() {
+        let run_user = TempDir::new().unwrap();
+        for (uid, with_bus) in [("0", true), ("1000", true), ("1001", true), ("1002", false)] {
+            let dir = run_user.path().join(uid);
+            std::fs::create_dir(&dir).unwrap();
+            if with_bus {
+                std::fs::write(dir.join(BUS_SOCKET), "").unwrap();
+            }
+        }
+        std::fs::create_dir(run_user.path().join("lost+found")).unwrap();
+
+        let uid = |preferred| UserBus::find(run_user.path(), preferred).map(|bus| bus.uid);
+
+        assert_eq!(uid(None), Some(1000), "Root's own bus is never used");
+        assert_eq!(uid(Some(1001)), Some(1001));
+        assert_eq!(uid(Some(1002)), Some(1000), "1002 has no bus");
+        assert_eq!(UserBus::find(&run_user.path().join("missing"), None), None);
+    }
+
+    #[test]
+    fn test_desktop_notifier_reaches_a_local_bus() {
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join(BUS_SOCKET);
+        let Ok(mut daemon) = std::process::Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .arg(format!("--address=unix:path={}", socket.display()))
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+        else {
+            println!("dbus-daemon is not installed, skipping");
+            return;
+        };
+        // The address is printed once the bus listens
+        let mut address = String::new();
+        std::io::BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        let metadata = std::fs::metadata(dir.path()).unwrap();
+        let notifier = DesktopNotifier::new(Arc::new(SystemRunner)).with_bus(Some(UserBus {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            socket,
+        }));
+
+        let result = notifier.notify(&ended());
+        daemon.kill().unwrap();
+        daemon.wait().unwrap();
+
+        // Nothing shows notifications on this bus, but the call reached it
+        let error = result
+            .expect_err("No notification service is running")
+            .to_string();
+        assert!(
+            error.contains("org.freedesktop.Notifications"),
+            "The call should reach the bus: {}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_split_command() {
+        let words = |command| split_command(command).unwrap();
+
+        assert_eq!(
+            words(r#"notify-send -a "Focus app"  'it"s here' a\ b"#),
+            vec!["notify-send", "-a", "Focus app", "it\"s here", "a b"]
+        );
+        assert_eq!(
+            words(r#"say "" "\"quoted\"""#),
+            vec!["say", "", "\"quoted\""]
+        );
+        assert_eq!(words("  "), Vec::<String>::new());
+        assert_eq!(split_command("say 'unclosed"), None);
+        assert_eq!(split_command("say \\"), None);
+    }
+
+    #[test]
+    fn test_command_notifier() {
+        let runner = Arc::new(RecordingRunner::default());
+        let notifier = CommandNotifier::new("notify-send -u critical", runner.clone()).unwrap();
+
+        notifier.notify(&ended()).unwrap();
+
+        assert_eq!(runner.calls().len(), 1);
+        let call = &runner.calls()[0];
+        assert!(call.starts_with("notify-send -u critical Focus session ended"));
+        assert!(CommandNotifier::new("  ", runner).is_err());
+    }
+
+    #[test]
+    fn test_fallback_notifier_tries_the_next_one() {
+        let runner = Arc::new(RecordingRunner::default());
+        let recording = Arc::new(RecordingNotifier::default());
+        let notifier = FallbackNotifier::new(vec![
+            Box::new(DesktopNotifier::new(runner.clone())),
+            Box::new(recording.clone()),
+        ]);
+
+        notifier.notify(&ended()).unwrap();
+        assert!(
+            recording.sent().is_empty(),
+            "The desktop notification worked"
+        );
+
+        runner.fail(true);
+        notifier.notify(&ended()).unwrap();
+        assert_eq!(recording.sent(), vec![ended()]);
+
+        let notifier = FallbackNotifier::new(vec![Box::new(DesktopNotifier::new(runner))]);
+        assert!(notifier.notify(&ended()).is_err());
+        assert!(FallbackNotifier::new(Vec::new()).notify(&ended()).is_ok());
+    }
+
+    #[test]
+    fn test_notifier_from_flags() {
+        let runner = Arc::new(RecordingRunner::default());
+        let flags = vec!["--notify-command=notify-send".to_string()];
+        runner.fail(true);
+
+        let _ = notifier_from_flags(&flags, runner.clone())
+            .unwrap()
+            .notify(&ended());
+
+        let programs: Vec<String> = runner
+            .calls()
+            .iter()
+            .map(|call| call.split(' ').next().unwrap().to_string())
+            .collect();
+        assert_eq!(programs, vec!["notify-send", "gdbus"]);
+
+        let silent = notifier_from_flags(&[NO_NOTIFY_FLAG.to_string()], runner.clone()).unwrap();
+        silent.notify(&ended()).unwrap();
+        assert_eq!(runner.calls().len(), 2, "Nothing should be sent");
+        assert!(notifier_from_flags(&["--notify-command=".to_string()], runner).is_err());
+    }
+
+    #[test]
+    fn test_messages() {
+        let sites = vec!["netflix.com".to_string()];
+        let duration = Duration::from_secs(25 * 60);
+        assert_eq!(
+            Notification::started(&sites, duration).body,
+            "Blocking netflix.com for 25m 00s"
+        );
+        assert_eq!(
+            Notification::break_over(&sites, duration),
+            Notification {
+                summary: "Break over".to_string(),
+                body: "Blocking netflix.com for 25m 00s".to_string(),
+            }
+        );
+        assert_eq!(
+            Notification::ending_soon(WARNING_BEFORE).body,
+            "5m 00s left, time to wrap up"
+        );
+        let until = NaiveDateTime::parse_from_str("2026-10-19 10:10", "%Y-%m-%d %H:%M").unwrap();
+        assert_eq!(
+            Notification::on_break(until).body,
+            "The blocks are lifted until 10:10"
+        );
+        assert_eq!(
+            bell_line(&ended()),
+            "\x07Focus session ended: The blocks are lifted, good job\n"
+        );
+    }
+}
@@ -0,0 +1,316 @@
+/// A URL pattern made of a host glob and a path glob, e.g. `www.youtube.com/shorts*`.
+///
+/// `*` matches any sequence of characters. A pattern without a path, like
+/// `netflix.com`, matches the whole host. Paths are matched without their query
+/// string, so `www.youtube.com/` only matches the home page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UrlRule {
+    pub host: String,
+    pub path: String,
+}
+
+impl UrlRule {
+    /// Parses a `host/path` pattern.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crate::proxy::rules::UrlRule;
+    ///
+    /// let rule = UrlRule::parse("www.youtube.com/shorts*");
+    /// assert!(rule.matches("www.youtube.com", "/shorts/abc"));
+    /// ```
+    pub fn parse(pattern: &str) -> UrlRule {
+        let pattern = pattern
+            .trim()
+            .trim_start_matches("http://")
+            .trim_start_matches("https://");
+        match pattern.find('/') {
+            Some(index) => UrlRule {
+                host: pattern[..index].to_lowercase(),
+                path: pattern[index..].to_string(),
+            },
+            None => UrlRule {
+                host: pattern.to_lowercase(),
+                path: "*".to_string(),
+            },
+        }
+    }
+
+    /// Returns whether the rule applies to the whole host, whatever the path.
+    pub fn is_host_wide(&self) -> bool {
+        self.path == "*" || self.path == "/*"
+    }
+
+    /// Returns whether the rule matches a host, ignoring its port.
+    pub fn matches_host(&self, host: &str) -> bool {
+        let host = host.split(':').next().unwrap_or_default().to_lowercase();
+        glob_match(&self.host, &host)
+    }
+
+    /// Returns whether the rule matches a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The requested host, a port is ignored
+    /// * `path` - The requested path, a query string is ignored
+    pub fn matches(&self, host: &str, path: &str) -> bool {
+        let path = path.split('?').next().unwrap_or_default();
+        self.matches_host(host) && glob_match(&self.path, path)
+    }
+}
+
+/// Returns whether any of the rules matches the request.
+pub fn any_matches(rules: &[UrlRule], host: &str, path: &str) -> bool {
+    rules.iter().any(|rule| rule.matches(host, path))
+}
+
+/// The URL rules of a session.
+///
+/// In the default mode only what matches `blocked` is blocked. In allowlist mode
+/// (`allowed` is set) everything not matching `allowed` is blocked as well.
+///
+/// `exceptions` carve reachable parts out of the blocked websites, e.g.
+/// `music.youtube.com` while `*.youtube.com` is blocked. A request is decided by
+/// the first of these that applies:
+///
+/// 1. Outside of the allowlist, it is blocked.
+/// 2. Matching an exception, it is let through.
+/// 3. Matching a blocked rule, it is blocked.
+///
+/// So an exception always wins over a blocked rule, however specific, but it
+/// never lets through a website the allowlist doesn't allow.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter {
+    pub blocked: Vec<UrlRule>,
+    pub allowed: Option<Vec<UrlRule>>,
+    pub exceptions: Vec<UrlRule>,
+}
+
+impl Filter {
+    /// Creates a filter from `host/path` patterns, see `UrlRule::parse`.
+    ///
+    /// # Arguments
+    ///
+    /// * `blocked` - The patterns to block
+    /// * `allowed` - In allowlist mode, the only patterns that are not blocked
+    /// * `exceptions` - The patterns left reachable inside the blocked ones
+    pub fn from_patterns(
+        blocked: &[String],
+        allowed: Option<&[String]>,
+        exceptions: &[String],
+    ) -> Filter {
+        let rules = |patterns: &[String]| -> Vec<UrlRule> {
+            patterns
+                .iter()
+                .map(|pattern| UrlRule::parse(pattern))
+                .collect()
+        };
+        Filter {
+            blocked: rules(blocked),
+            allowed: allowed.map(rules),
+            exceptions: rules(exceptions),
+        }
+    }
+
+    /// Creates a filter blocking what matches `rules`.
+    #[cfg(test)]
+    pub fn blocking(rules: Vec<UrlRule>) -> Filter {
+        Filter {
+            blocked: rules,
+            allowed: None,
+            exceptions: Vec::new(),
+        }
+    }
+
+    /// Creates a filter blocking everything but what matches `rules`.
+    #[cfg(test)]
+    pub fn allowing(rules: Vec<UrlRule>) -> Filter {
+        Filter {
+            blocked: Vec::new(),
+            allowed: Some(rules),
+            exceptions: Vec::new(),
+        }
+    }
+
+    /// Returns whether a request is blocked.
+    pub fn is_blocked(&self, host: &str, path: &str) -> bool {
+        if let Some(allowed) = &self.allowed {
+            if !any_matches(allowed, host, path) {
+                return true;
+            }
+        }
+        if any_matches(&self.exceptions, host, path) {
+            return false;
+        }
+        any_matches(&self.blocked, host, path)
+    }
+
+    /// Returns whether every request to `host` is blocked, whatever the path.
+    pub fn blocks_host(&self, host: &str) -> bool {
+        if let Some(allowed) = &self.allowed {
+            if !allowed.iter().any(|rule| rule.matches_host(host)) {
+                return true;
+            }
+        }
+        if self.exceptions.iter().any(|rule| rule.matches_host(host)) {
+            return false;
+        }
+        self.blocked
+            .iter()
+            .any(|rule| rule.is_host_wide() && rule.matches_host(host))
+    }
+
+    /// Returns whether requests to `host` must be inspected to decide on their path.
+    pub fn inspects_paths(&self, host: &str) -> bool {
+        self.blocked
+            .iter()
+            .chain(self.allowed.iter().flatten())
+            .chain(&self.exceptions)
+            .any(|rule| !rule.is_host_wide() && rule.matches_host(host))
+    }
+}
+
+/// Matches `text` against a pattern where `*` stands for any sequence of characters.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    // Both ends matching, the slice below starts and ends on char boundaries
+    if !text.starts_with(first) || !text.ends_with(last) || text.len() < first.len() + last.len() {
+        return false;
+    }
+    let mut rest = &text[first.len()..text.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_with_path() {
+        let rule = UrlRule::parse("https://WWW.YouTube.com/shorts*");
+        assert_eq!(rule.host, "www.youtube.com");
+        assert_eq!(rule.path, "/shorts*");
+        assert!(!rule.is_host_wide());
+    }
+
+    #[test]
+    fn test_parse_host_only() {
+        let rule = UrlRule::parse("netflix.com");
+        assert_eq!(rule.host, "netflix.com");
+        assert!(rule.is_host_wide());
+        assert!(rule.matches("netflix.com:443", "/browse"));
+    }
+
+    #[test]
+    fn test_matches_path_glob() {
+        let rule = UrlRule::parse("www.youtube.com/shorts*");
+        assert!(rule.matches("www.youtube.com", "/shorts/abc"));
+        assert!(!rule.matches("www.youtube.com", "/watch?v=abc"));
+        assert!(!rule.matches("music.youtube.com", "/shorts/abc"));
+    }
+
+    #[test]
+    fn test_matches_home_page_only() {
+        let rule = UrlRule::parse("www.youtube.com/");
+        assert!(rule.matches("www.youtube.com", "/"));
+        assert!(rule.matches("www.youtube.com", "/?app=desktop"));
+        assert!(!rule.matches("www.youtube.com", "/watch"));
+    }
+
+    #[test]
+    fn test_matches_host_glob() {
+        let rule = UrlRule::parse("*.reddit.com");
+        assert!(rule.matches("old.reddit.com", "/r/rust"));
+        assert!(!rule.matches("reddit.com", "/"));
+    }
+
+    #[test]
+    fn test_filter_blocking() {
+        let filter = Filter::blocking(vec![
+            UrlRule::parse("www.youtube.com/shorts*"),
+            UrlRule::parse("netflix.com"),
+        ]);
+        assert!(filter.is_blocked("netflix.com", "/"));
+        assert!(filter.is_blocked("www.youtube.com", "/shorts/x"));
+        assert!(!filter.is_blocked("docs.rs", "/"));
+        assert!(filter.blocks_host("netflix.com"));
+        assert!(!filter.blocks_host("www.youtube.com"));
+        assert!(filter.inspects_paths("www.youtube.com"));
+        assert!(!filter.inspects_paths("netflix.com"));
+    }
+
+    #[test]
+    fn test_filter_allowing() {
+        let filter = Filter::allowing(vec![
+            UrlRule::parse("docs.rs"),
+            UrlRule::parse("github.com/rust-lang*"),
+        ]);
+        assert!(!filter.is_blocked("docs.rs", "/serde"));
+        assert!(!filter.is_blocked("github.com", "/rust-lang/rust"));
+        assert!(filter.is_blocked("github.com", "/trending"));
+        assert!(filter.is_blocked("www.youtube.com", "/"));
+        assert!(filter.blocks_host("www.youtube.com"));
+        assert!(!filter.blocks_host("github.com"));
+        assert!(filter.inspects_paths("github.com"));
+    }
+
+    #[test]
+    fn test_filter_exception_wins_over_blocked() {
+        let filter = Filter {
+            exceptions: vec![
+                UrlRule::parse("music.youtube.com"),
+                UrlRule::parse("www.youtube.com/watch*"),
+            ],
+            ..Filter::blocking(vec![
+                UrlRule::parse("*.youtube.com"),
+                UrlRule::parse("music.youtube.com/explore*"),
+            ])
+        };
+        assert!(filter.is_blocked("www.youtube.com", "/"));
+        assert!(!filter.is_blocked("www.youtube.com", "/watch?v=abc"));
+        assert!(
+            !filter.is_blocked("music.youtube.com", "/explore"),
+            "An exception should win over a more specific blocked rule"
+        );
+        assert!(!filter.blocks_host("music.youtube.com"));
+        assert!(!filter.blocks_host("www.youtube.com"));
+        assert!(filter.blocks_host("m.youtube.com"));
+        assert!(filter.inspects_paths("www.youtube.com"));
+    }
+
+    #[test]
+    fn test_filter_exception_does_not_extend_allowlist() {
+        let filter = Filter {
+            exceptions: vec![UrlRule::parse("music.youtube.com")],
+            ..Filter::allowing(vec![UrlRule::parse("docs.rs")])
+        };
+        assert!(filter.is_blocked("music.youtube.com", "/"));
+        assert!(filter.blocks_host("music.youtube.com"));
+        assert!(!filter.is_blocked("docs.rs", "/"));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("a*c", "abbbc"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "a-b-c"));
+        assert!(!glob_match("a*b*c", "a-c"));
+        assert!(!glob_match("ab*ba", "aba"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+        assert!(!glob_match("a*c", "aé"), "Shouldn't slice inside a char");
+        assert!(!glob_match("ab*c", "éc"));
+        assert!(glob_match("/vidéo*é", "/vidéos/é"));
+    }
+}
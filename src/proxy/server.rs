@@ -0,0 +1,983 @@
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{
+    ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+};
+
+use crate::proxy::rules::Filter;
+use crate::server::ca::{server_config, LocalCa};
+
+pub const PROXY_PORT: u16 = 8118;
+
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long an origin may stay silent, long enough for the long-polling requests.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const MAX_HEADERS: usize = 100;
+const SYSTEM_CA_BUNDLES: [&str; 3] = [
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/cert.pem",
+];
+// Hop-by-hop headers that are not forwarded, and `Expect` which the proxy answers
+const DROPPED_HEADERS: [&str; 5] = [
+    "connection",
+    "proxy-connection",
+    "proxy-authorization",
+    "keep-alive",
+    "expect",
+];
+
+/// A local forward proxy enforcing URL rules during a session.
+///
+/// Plain HTTP requests are filtered on their host and path. HTTPS requests are
+/// tunnelled untouched unless a rule targets specific paths of their host: those
+/// connections are intercepted with a certificate from the local CA so that the
+/// path can be checked.
+pub struct FilteringProxy {
+    filter: Filter,
+    interception: Option<Arc<ServerConfig>>,
+    upstream_tls: Arc<ClientConfig>,
+}
+
+impl FilteringProxy {
+    /// Creates a proxy enforcing `filter`, trusting the system CA bundle upstream.
+    pub fn new(filter: Filter) -> FilteringProxy {
+        FilteringProxy::with_upstream_roots(filter, system_roots())
+    }
+
+    /// Creates a proxy enforcing `filter`, trusting `roots` upstream.
+    pub fn with_upstream_roots(filter: Filter, roots: RootCertStore) -> FilteringProxy {
+        let mut upstream_tls =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .expect("The ring provider supports the default protocol versions")
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        upstream_tls.alpn_protocols = vec![b"http/1.1".to_vec()];
+        FilteringProxy {
+            filter,
+            interception: None,
+            upstream_tls: Arc::new(upstream_tls),
+        }
+    }
+
+    /// Enables path filtering of HTTPS requests, using certificates issued by `ca`.
+    pub fn with_interception(mut self, ca: Arc<LocalCa>) -> Result<FilteringProxy, std::io::Error> {
+        self.interception = Some(server_config(ca)?);
+        Ok(self)
+    }
+
+    /// Serves a single client connection.
+    pub fn handle(&self, stream: TcpStream) -> Result<(), std::io::Error> {
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        let mut client = BufReader::new(stream);
+        let Some(head) = RequestHead::read_from(&mut client)? else {
+            return Ok(());
+        };
+
+        if head.method.eq_ignore_ascii_case("CONNECT") {
+            let (host, port) = split_host_port(&head.target, 443);
+            if self.filter.blocks_host(&host) {
+                return write_blocked(client.get_mut(), &host, "/");
+            }
+            match &self.interception {
+                Some(config) if self.filter.inspects_paths(&host) => {
+                    client
+                        .get_mut()
+                        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")?;
+                    let connection =
+                        ServerConnection::new(config.clone()).map_err(std::io::Error::other)?;
+                    let mut tls = StreamOwned::new(connection, client.into_inner());
+                    let mut tls_client = BufReader::new(&mut tls);
+                    if let Some(inner) = RequestHead::read_from(&mut tls_client)? {
+                        self.serve(&mut tls_client, inner, Some((&host, port)))?;
+                    }
+                    tls.conn.send_close_notify();
+                    tls.flush()
+                }
+                _ => tunnel(client, &host, port),
+            }
+        } else {
+            self.serve(&mut client, head, None)
+        }
+    }
+
+    /// Serves connections on `listener` from background threads until `done` is set.
+    pub fn spawn(
+        self: Arc<Self>,
+        listener: TcpListener,
+        done: Arc<AtomicBool>,
+    ) -> Result<JoinHandle<()>, std::io::Error> {
+        listener.set_nonblocking(true)?;
+        Ok(std::thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let _ = stream.set_nonblocking(false);
+                        let proxy = self.clone();
+                        std::thread::spawn(move || {
+                            if let Err(e) = proxy.handle(stream) {
+                                println!("Proxy request failed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(ACCEPT_INTERVAL)
+                    }
+                    Err(e) => println!("Proxy connection failed: {}", e),
+                }
+            }
+        }))
+    }
+
+    /// Answers the requests of a client connection, as long as both ends keep it
+    /// alive.
+    ///
+    /// `origin` is the host and port of an intercepted HTTPS connection, the plain
+    /// HTTP requests carry their own.
+    fn serve<S: Read + Write>(
+        &self,
+        client: &mut BufReader<S>,
+        mut head: RequestHead,
+        origin: Option<(&str, u16)>,
+    ) -> Result<(), std::io::Error> {
+        loop {
+            let (host, port) = match origin {
+                Some((host, port)) => (host.to_string(), port),
+                None => head.origin(),
+            };
+            if !self.exchange(client, head, &host, port, origin.is_some())? {
+                return Ok(());
+            }
+            head = match RequestHead::read_from(client) {
+                Ok(Some(head)) => head,
+                Ok(None) => return Ok(()),
+                // The browser kept an idle connection open
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(())
+                }
+                Err(e) => return Err(e),
+            };
+        }
+    }
+
+    /// Forwards one request to the origin, or answers it with the blocked page.
+    ///
+    /// # Returns
+    ///
+    /// Whether the client connection can carry another request
+    fn exchange<S: Read + Write>(
+        &self,
+        client: &mut BufReader<S>,
+        head: RequestHead,
+        host: &str,
+        port: u16,
+        tls: bool,
+    ) -> Result<bool, std::io::Error> {
+        let path = head.path();
+        if self.filter.is_blocked(host, &path) {
+            write_blocked(client.get_mut(), host, &path)?;
+            return Ok(false);
+        }
+
+        let upstream = TcpStream::connect((host, port))?;
+        upstream.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+        if tls {
+            let name = ServerName::try_from(host.to_string())
+                .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?;
+            let connection = ClientConnection::new(self.upstream_tls.clone(), name)
+                .map_err(std::io::Error::other)?;
+            forward(client, &head, &path, StreamOwned::new(connection, upstream))
+        } else {
+            forward(client, &head, &path, upstream)
+        }
+    }
+}
+
+/// Sends the request to `upstream`, its body included, and streams the response
+/// back to the client as it arrives.
+///
+/// The origin closes the connection after the response, the client connection is
+/// kept alive when the response is delimited and the client asked for it.
+fn forward<S: Read + Write, U: Read + Write>(
+    client: &mut BufReader<S>,
+    head: &RequestHead,
+    path: &str,
+    mut upstream: U,
+) -> Result<bool, std::io::Error> {
+    // `Expect` is not forwarded, the client is told to go on right away
+    if head.expects_continue() {
+        client
+            .get_mut()
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        client.get_mut().flush()?;
+    }
+    upstream.write_all(head.origin_form(path).as_bytes())?;
+    if head.is_chunked() {
+        copy_chunked(client, &mut upstream)?;
+    } else {
+        let length = head.content_length() as u64;
+        if std::io::copy(&mut client.by_ref().take(length), &mut upstream)? < length {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+    }
+    upstream.flush()?;
+
+    let mut upstream = BufReader::new(upstream);
+    let mut response = ResponseHead::read_from(&mut upstream)?;
+    while response.is_interim() {
+        client
+            .get_mut()
+            .write_all(response.to_head(None).as_bytes())?;
+        response = ResponseHead::read_from(&mut upstream)?;
+    }
+    let keep_alive = head.keeps_alive() && response.is_delimited(&head.method);
+    client
+        .get_mut()
+        .write_all(response.to_head(Some(keep_alive)).as_bytes())?;
+    stream_body(&mut upstream, client.get_mut())?;
+    Ok(keep_alive)
+}
+
+/// Starts the proxy enforcing `filter` on the local proxy port.
+///
+/// HTTPS connections are intercepted with certificates from the local CA of
+/// `ca_dir`, whose key only root can read.
+///
+/// # Returns
+///
+/// The thread serving the connections until `done` is set, or an error when the CA
+/// can't be loaded or the port is taken
+pub fn start(
+    filter: Filter,
+    ca_dir: &Path,
+    done: Arc<AtomicBool>,
+) -> Result<JoinHandle<()>, std::io::Error> {
+    let ca = Arc::new(LocalCa::load_or_create(ca_dir)?);
+    let proxy = Arc::new(FilteringProxy::new(filter).with_interception(ca)?);
+    let listener = TcpListener::bind((crate::LOCALHOST, PROXY_PORT)).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!(
+                "the proxy failed to listen on {}:{}: {}",
+                crate::LOCALHOST,
+                PROXY_PORT,
+                e
+            ),
+        )
+    })?;
+    proxy.spawn(listener, done)
+}
+
+/// The request line and headers of an HTTP request.
+#[derive(Debug, PartialEq)]
+struct RequestHead {
+    method: String,
+    target: String,
+    version: String,
+    headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    /// Reads a request head, `None` if the client closed the connection first.
+    fn read_from<R: BufRead>(reader: &mut R) -> Result<Option<RequestHead>, std::io::Error> {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line)? == 0 {
+            return Ok(None);
+        }
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(target), Some(version)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "malformed request line",
+            ));
+        };
+
+        Ok(Some(RequestHead {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: version.to_string(),
+            headers: read_headers(reader)?,
+        }))
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    fn is_chunked(&self) -> bool {
+        is_chunked(&self.headers)
+    }
+
+    fn expects_continue(&self) -> bool {
+        self.header("expect")
+            .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"))
+    }
+
+    /// Whether the client wants to send more requests on the connection.
+    fn keeps_alive(&self) -> bool {
+        let connection = self
+            .header("connection")
+            .or(self.header("proxy-connection"))
+            .unwrap_or_default()
+            .to_lowercase();
+        if self.version.eq_ignore_ascii_case("HTTP/1.0") {
+            connection.contains("keep-alive")
+        } else {
+            !connection.contains("close")
+        }
+    }
+
+    fn content_length(&self) -> usize {
+        self.header("content-length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Returns the origin host and port, from an absolute target or the `Host` header.
+    fn origin(&self) -> (String, u16) {
+        match self.target.strip_prefix("http://") {
+            Some(rest) => split_host_port(rest.split('/').next().unwrap_or_default(), 80),
+            None => split_host_port(self.header("host").unwrap_or_default(), 80),
+        }
+    }
+
+    /// Returns the path of the target, with its query string.
+    fn path(&self) -> String {
+        match self.target.strip_prefix("http://") {
+            Some(rest) => match rest.find('/') {
+                Some(index) => rest[index..].to_string(),
+                None => "/".to_string(),
+            },
+            None => self.target.clone(),
+        }
+    }
+
+    /// Rebuilds the head to be sent to the origin, closing the connection after the response.
+    fn origin_form(&self, path: &str) -> String {
+        let mut head = format!("{} {} {}\r\n", self.method, path, self.version);
+        for (name, value) in &self.headers {
+            if !DROPPED_HEADERS.contains(&name.to_lowercase().as_str()) {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str("Connection: close\r\n\r\n");
+        head
+    }
+}
+
+/// The status line and headers of an HTTP response.
+#[derive(Debug, PartialEq)]
+struct ResponseHead {
+    status_line: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+impl ResponseHead {
+    fn read_from<R: BufRead>(reader: &mut R) -> Result<ResponseHead, std::io::Error> {
+        let mut status_line = String::new();
+        if reader.read_line(&mut status_line)? == 0 {
+            return Err(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "the origin closed the connection without responding",
+            ));
+        }
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "malformed status line"))?;
+        Ok(ResponseHead {
+            status_line: status_line.trim_end().to_string(),
+            status,
+            headers: read_headers(reader)?,
+        })
+    }
+
+    /// Whether it is an informational response, followed by another one.
+    fn is_interim(&self) -> bool {
+        (100..200).contains(&self.status)
+    }
+
+    /// Whether the client can tell where the body ends without the connection
+    /// being closed.
+    fn is_delimited(&self, method: &str) -> bool {
+        method.eq_ignore_ascii_case("HEAD")
+            || self.status == 204
+            || self.status == 304
+            || is_chunked(&self.headers)
+            || find_header(&self.headers, "content-length").is_some()
+    }
+
+    /// Rebuilds the head sent to the client, telling whether the connection is
+    /// kept alive, or as it is for an interim response.
+    fn to_head(&self, keep_alive: Option<bool>) -> String {
+        let mut head = format!("{}\r\n", self.status_line);
+        for (name, value) in &self.headers {
+            if keep_alive.is_none() || !DROPPED_HEADERS.contains(&name.to_lowercase().as_str()) {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        match keep_alive {
+            Some(true) => head.push_str("Connection: keep-alive\r\n"),
+            Some(false) => head.push_str("Connection: close\r\n"),
+            None => {}
+        }
+        head.push_str("\r\n");
+        head
+    }
+}
+
+/// Reads header lines up to the empty line ending them.
+fn read_headers<R: BufRead>(reader: &mut R) -> Result<Vec<(String, String)>, std::io::Error> {
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            return Ok(headers);
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "too many headers",
+            ));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn is_chunked(headers: &[(String, String)]) -> bool {
+    find_header(headers, "transfer-encoding")
+        .is_some_and(|encoding| encoding.to_lowercase().contains("chunked"))
+}
+
+/// Copies a chunked body as it is, up to its last chunk and trailers.
+fn copy_chunked<R: BufRead, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<(), std::io::Error> {
+    loop {
+        let mut size_line = String::new();
+        if reader.read_line(&mut size_line)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        writer.write_all(size_line.as_bytes())?;
+        let size = size_line.split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "malformed chunk size"))?;
+        if size == 0 {
+            // The trailers, up to the empty line ending the body
+            loop {
+                let mut line = String::new();
+                let read = reader.read_line(&mut line)?;
+                writer.write_all(line.as_bytes())?;
+                if read == 0 || line.trim().is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+        // The data of the chunk and the line break after it
+        if std::io::copy(&mut reader.take(size + 2), writer)? < size + 2 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+    }
+}
+
+/// Copies a response body until the origin closes the connection, flushing each
+/// read right away for the streamed and long-polling responses.
+fn stream_body<R: Read, W: Write>(from: &mut R, to: &mut W) -> Result<(), std::io::Error> {
+    let mut buffer = [0; 16 * 1024];
+    loop {
+        let read = match from.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            // Servers often close without a TLS close_notify after `Connection: close`
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        to.write_all(&buffer[..read])?;
+        to.flush()?;
+    }
+    to.flush()
+}
+
+/// Relays bytes both ways between the client and the origin.
+fn tunnel(client: BufReader<TcpStream>, host: &str, port: u16) -> Result<(), std::io::Error> {
+    let upstream = TcpStream::connect((host, port))?;
+    let mut client = client.into_inner();
+    client.set_read_timeout(None)?;
+    client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")?;
+
+    let (mut client_read, mut upstream_write) = (client.try_clone()?, upstream.try_clone()?);
+    let upload = std::thread::spawn(move || {
+        let _ = std::io::copy(&mut client_read, &mut upstream_write);
+        let _ = upstream_write.shutdown(std::net::Shutdown::Write);
+    });
+    let mut upstream_read = upstream;
+    let _ = std::io::copy(&mut upstream_read, &mut client);
+    let _ = client.shutdown(std::net::Shutdown::Write);
+    let _ = upload.join();
+    Ok(())
+}
+
+fn write_blocked<W: Write>(client: &mut W, host: &str, path: &str) -> Result<(), std::io::Error> {
+    let body = format!(
+        "<!DOCTYPE html><html><body><h1>Blocked by your focus session</h1><p>{}{}</p></body></html>",
+        escape_html(host),
+        escape_html(path)
+    );
+    write!(
+        client,
+        "HTTP/1.1 403 Forbidden\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )?;
+    client.flush()
+}
+
+fn split_host_port(authority: &str, default_port: u16) -> (String, u16) {
+    match authority.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => {
+            (host.to_string(), port.parse().unwrap_or(default_port))
+        }
+        _ => (authority.to_string(), default_port),
+    }
+}
+
+/// Loads the system CA bundle used to verify the intercepted origins.
+fn system_roots() -> RootCertStore {
+    use rustls::pki_types::pem::PemObject;
+    let mut roots = RootCertStore::empty();
+    for bundle in SYSTEM_CA_BUNDLES {
+        if let Ok(certificates) = CertificateDer::pem_file_iter(bundle) {
+            roots.add_parsable_certificates(certificates.flatten());
+            break;
+        }
+    }
+    roots
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::rules::UrlRule;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    const ORIGIN_BODY: &str = "hello from origin";
+
+    // Helper function starting a stub origin answering every request with ORIGIN_BODY,
+    // over TLS when a configuration is given. The request lines it receives are recorded.
+    fn start_origin(tls: Option<Arc<ServerConfig>>) -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind origin");
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let recorded = recorded.clone();
+                let tls = tls.clone();
+                std::thread::spawn(move || {
+                    let respond = |stream: &mut dyn ReadWrite| {
+                        let mut reader = BufReader::new(stream);
+                        let head = RequestHead::read_from(&mut reader).unwrap().unwrap();
+                        let mut body = Vec::new();
+                        if head.is_chunked() {
+                            copy_chunked(&mut reader, &mut body).unwrap();
+                        }
+                        let mut request = format!("{} {}", head.method, head.target);
+                        if !body.is_empty() {
+                            request.push(' ');
+                            request.push_str(&unchunk(&body));
+                        }
+                        recorded.lock().unwrap().push(request);
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            ORIGIN_BODY.len(),
+                            ORIGIN_BODY
+                        );
+                        reader.get_mut().write_all(response.as_bytes()).unwrap();
+                    };
+                    match tls {
+                        Some(config) => {
+                            let connection = ServerConnection::new(config).unwrap();
+                            let mut stream = StreamOwned::new(connection, stream);
+                            respond(&mut stream);
+                            stream.conn.send_close_notify();
+                            let _ = stream.flush();
+                        }
+                        None => {
+                            let mut stream = stream;
+                            respond(&mut stream);
+                        }
+                    }
+                });
+            }
+        });
+        (port, requests)
+    }
+
+    // Helper function joining the data of the chunks of a body
+    fn unchunk(body: &[u8]) -> String {
+        let mut reader = body;
+        let mut data = String::new();
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).unwrap();
+            let size = usize::from_str_radix(size.split(';').next().unwrap().trim(), 16).unwrap();
+            if size == 0 {
+                return data;
+            }
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk).unwrap();
+            data.push_str(std::str::from_utf8(&chunk[..size]).unwrap());
+        }
+    }
+
+    trait ReadWrite: Read + Write {}
+    impl<T: Read + Write> ReadWrite for T {}
+
+    // Helper function starting the proxy on a random local port
+    fn start_proxy(proxy: FilteringProxy) -> (u16, Arc<AtomicBool>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind proxy");
+        let port = listener.local_addr().unwrap().port();
+        let done = Arc::new(AtomicBool::new(false));
+        Arc::new(proxy).spawn(listener, done.clone()).unwrap();
+        (port, done)
+    }
+
+    fn send(port: u16, request: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn read_connect_response(stream: &mut TcpStream) -> String {
+        let mut response = Vec::new();
+        let mut byte = [0u8; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        String::from_utf8(response).unwrap()
+    }
+
+    fn test_ca() -> (TempDir, Arc<LocalCa>) {
+        let dir = TempDir::new().unwrap();
+        let ca = Arc::new(LocalCa::load_or_create(dir.path()).unwrap());
+        (dir, ca)
+    }
+
+    fn trusting(ca: &LocalCa) -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.cert_der().clone()).unwrap();
+        roots
+    }
+
+    #[test]
+    fn test_read_request_head() {
+        let raw = "GET http://example.com:8080/a?b=c HTTP/1.1\r\nHost: example.com:8080\r\nProxy-Connection: keep-alive\r\n\r\n";
+        let head = RequestHead::read_from(&mut raw.as_bytes())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(head.origin(), ("example.com".to_string(), 8080));
+        assert_eq!(head.path(), "/a?b=c");
+        assert_eq!(
+            head.origin_form("/a?b=c"),
+            "GET /a?b=c HTTP/1.1\r\nHost: example.com:8080\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(
+            split_host_port("example.com:443", 80),
+            ("example.com".to_string(), 443)
+        );
+        assert_eq!(
+            split_host_port("example.com", 80),
+            ("example.com".to_string(), 80)
+        );
+    }
+
+    #[test]
+    fn test_proxy_forwards_allowed_request() {
+        let (origin_port, requests) = start_origin(None);
+        let (proxy_port, done) =
+            start_proxy(FilteringProxy::new(Filter::blocking(vec![UrlRule::parse(
+                "127.0.0.1/shorts*",
+            )])));
+
+        let response = send(
+            proxy_port,
+            &format!(
+                "GET http://127.0.0.1:{}/watch?v=1 HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nConnection: close\r\n\r\n",
+                origin_port, origin_port
+            ),
+        );
+        done.store(true, Ordering::Relaxed);
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(ORIGIN_BODY));
+        assert_eq!(*requests.lock().unwrap(), vec!["GET /watch?v=1"]);
+    }
+
+    #[test]
+    fn test_proxy_blocks_matching_path() {
+        let (origin_port, requests) = start_origin(None);
+        let (proxy_port, done) =
+            start_proxy(FilteringProxy::new(Filter::blocking(vec![UrlRule::parse(
+                "127.0.0.1/shorts*",
+            )])));
+
+        let response = send(
+            proxy_port,
+            &format!(
+                "GET http://127.0.0.1:{}/shorts/abc HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n",
+                origin_port
+            ),
+        );
+        done.store(true, Ordering::Relaxed);
+
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden"));
+        assert!(
+            requests.lock().unwrap().is_empty(),
+            "The origin must not be reached"
+        );
+    }
+
+    #[test]
+    fn test_proxy_blocks_connect_to_blocked_host() {
+        let (proxy_port, done) =
+            start_proxy(FilteringProxy::new(Filter::blocking(vec![UrlRule::parse(
+                "netflix.com",
+            )])));
+
+        let response = send(
+            proxy_port,
+            "CONNECT netflix.com:443 HTTP/1.1\r\nHost: netflix.com:443\r\n\r\n",
+        );
+        done.store(true, Ordering::Relaxed);
+
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden"));
+    }
+
+    #[test]
+    fn test_proxy_allowlist_blocks_everything_else() {
+        let (origin_port, requests) = start_origin(None);
+        let (proxy_port, done) =
+            start_proxy(FilteringProxy::new(Filter::allowing(vec![UrlRule::parse(
+                "127.0.0.1/docs*",
+            )])));
+
+        let get = |path: &str| {
+            send(
+                proxy_port,
+                &format!(
+                    "GET http://127.0.0.1:{}{} HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\r\n",
+                    origin_port, path
+                ),
+            )
+        };
+        let allowed = get("/docs/serde");
+        let blocked = get("/feed");
+        let connect = send(proxy_port, "CONNECT www.youtube.com:443 HTTP/1.1\r\n\r\n");
+        done.store(true, Ordering::Relaxed);
+
+        assert!(allowed.ends_with(ORIGIN_BODY));
+        assert!(blocked.starts_with("HTTP/1.1 403 Forbidden"));
+        assert!(connect.starts_with("HTTP/1.1 403 Forbidden"));
+        assert_eq!(*requests.lock().unwrap(), vec!["GET /docs/serde"]);
+    }
+
+    #[test]
+    fn test_proxy_tunnels_connect() {
+        let (origin_port, requests) = start_origin(None);
+        let (proxy_port, done) =
+            start_proxy(FilteringProxy::new(Filter::blocking(vec![UrlRule::parse(
+                "netflix.com",
+            )])));
+
+        let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).unwrap();
+        write!(stream, "CONNECT 127.0.0.1:{} HTTP/1.1\r\n\r\n", origin_port).unwrap();
+        let established = read_connect_response(&mut stream);
+        stream
+            .write_all(b"GET /inside HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        done.store(true, Ordering::Relaxed);
+
+        assert!(established.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with(ORIGIN_BODY));
+        assert_eq!(*requests.lock().unwrap(), vec!["GET /inside"]);
+    }
+
+    #[test]
+    fn test_proxy_intercepts_https_paths() {
+        let (_ca_dir, ca) = test_ca();
+        let (origin_port, requests) = start_origin(Some(server_config(ca.clone()).unwrap()));
+        let proxy = FilteringProxy::with_upstream_roots(
+            Filter::blocking(vec![UrlRule::parse("localhost/shorts*")]),
+            trusting(&ca),
+        )
+        .with_interception(ca.clone())
+        .unwrap();
+        let (proxy_port, done) = start_proxy(proxy);
+        let mut client_config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(trusting(&ca))
+                .with_no_client_auth();
+        client_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let client_config = Arc::new(client_config);
+
+        let get_through_proxy = |path: &str| -> String {
+            let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).unwrap();
+            write!(stream, "CONNECT localhost:{} HTTP/1.1\r\n\r\n", origin_port).unwrap();
+            read_connect_response(&mut stream);
+            let connection =
+                ClientConnection::new(client_config.clone(), "localhost".try_into().unwrap())
+                    .unwrap();
+            let mut tls = StreamOwned::new(connection, stream);
+            write!(
+                tls,
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                path
+            )
+            .unwrap();
+            assert_eq!(tls.conn.alpn_protocol(), Some(&b"http/1.1"[..]));
+            let mut response = String::new();
+            tls.read_to_string(&mut response).unwrap();
+            response
+        };
+        let blocked = get_through_proxy("/shorts/abc");
+        let allowed = get_through_proxy("/watch?v=1");
+        done.store(true, Ordering::Relaxed);
+
+        assert!(blocked.starts_with("HTTP/1.1 403 Forbidden"));
+        assert!(allowed.ends_with(ORIGIN_BODY));
+        assert_eq!(*requests.lock().unwrap(), vec!["GET /watch?v=1"]);
+    }
+
+    #[test]
+    fn test_proxy_keeps_connection_alive() {
+        let (origin_port, requests) = start_origin(None);
+        let (proxy_port, done) = start_proxy(FilteringProxy::new(Filter::blocking(vec![])));
+
+        let stream = TcpStream::connect(("127.0.0.1", proxy_port)).unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut responses = Vec::new();
+        for path in ["/a", "/b"] {
+            write!(
+                reader.get_mut(),
+                "GET http://127.0.0.1:{}{} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n",
+                origin_port,
+                path
+            )
+            .unwrap();
+            let head = ResponseHead::read_from(&mut reader).unwrap();
+            let length = find_header(&head.headers, "content-length")
+                .unwrap()
+                .parse()
+                .unwrap();
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            responses.push((head.to_head(None), String::from_utf8(body).unwrap()));
+        }
+        done.store(true, Ordering::Relaxed);
+
+        for (head, body) in responses {
+            assert!(head.contains("Connection: keep-alive"), "{}", head);
+            assert_eq!(body, ORIGIN_BODY);
+        }
+        assert_eq!(*requests.lock().unwrap(), vec!["GET /a", "GET /b"]);
+    }
+
+    #[test]
+    fn test_proxy_forwards_chunked_body() {
+        let (origin_port, requests) = start_origin(None);
+        let (proxy_port, done) = start_proxy(FilteringProxy::new(Filter::blocking(vec![])));
+
+        let response = send(
+            proxy_port,
+            &format!(
+                "POST http://127.0.0.1:{}/upload HTTP/1.1\r\nHost: 127.0.0.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\r\n",
+                origin_port
+            ),
+        );
+        done.store(true, Ordering::Relaxed);
+
+        assert!(response.ends_with(ORIGIN_BODY));
+        assert_eq!(*requests.lock().unwrap(), vec!["POST /upload hello world"]);
+    }
+
+    #[test]
+    fn test_proxy_streams_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let origin_port = listener.local_addr().unwrap().port();
+        let (finish, finished) = std::sync::mpsc::channel::<()>();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            RequestHead::read_from(&mut reader).unwrap();
+            let stream = reader.get_mut();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\nfirst\n")
+                .unwrap();
+            finished.recv().unwrap();
+            stream.write_all(b"second\n").unwrap();
+        });
+        let (proxy_port, done) = start_proxy(FilteringProxy::new(Filter::blocking(vec![])));
+
+        let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).unwrap();
+        write!(
+            stream,
+            "GET http://127.0.0.1:{}/events HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n",
+            origin_port
+        )
+        .unwrap();
+        let head = read_connect_response(&mut stream);
+        let mut first = [0; 6];
+        stream.read_exact(&mut first).unwrap();
+        finish.send(()).unwrap();
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        done.store(true, Ordering::Relaxed);
+
+        assert!(head.contains("Connection: close"), "{}", head);
+        assert_eq!(&first, b"first\n");
+        assert_eq!(rest, "second\n");
+    }
+}
@@ -0,0 +1,401 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeDelta, Utc};
+
+use crate::schedule::ics::{occurrences, parse_ics, Event, Occurrence};
+use crate::schedule::plan::Block;
+use crate::utils::utils::{match_args, match_presets};
+
+/// The summary keyword of the focus events, unless `focus` is configured.
+pub const FOCUS_KEYWORD: &str = "Focus";
+
+/// How far around the asked range the occurrences are expanded, so that the
+/// ticks of the daemon reuse them for about a day.
+const EXPANSION_MARGIN: TimeDelta = TimeDelta::days(1);
+
+/// An iCalendar file to block from, e.g.
+///
+/// ```toml
+/// [[calendar]]
+/// path = "/home/me/calendar.ics"
+/// focus = "Focus"
+/// block = ["coding"]
+/// ```
+///
+/// The events whose summary contains `focus` block the presets and websites named
+/// in their description, or `block` when it names none. The other busy events are
+/// meetings, which lift the blocks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarSource {
+    pub path: PathBuf,
+    pub focus: String,
+    /// What to block when the description of a focus event names nothing
+    pub arguments: Vec<String>,
+}
+
+/// The events of a calendar file, read again when the file changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calendar {
+    pub source: CalendarSource,
+    modified: Option<SystemTime>,
+    events: Vec<Event>,
+    error: Option<String>,
+    expanded: RefCell<Option<Expansion>>,
+}
+
+/// The occurrences of the timed events of a calendar from `from` to `to`.
+#[derive(Debug, Clone, PartialEq)]
+struct Expansion {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    occurrences: Vec<Occurrence>,
+}
+
+impl Calendar {
+    /// Creates a calendar which is read on the first `refresh`.
+    pub fn new(source: CalendarSource) -> Calendar {
+        Calendar {
+            source,
+            modified: None,
+            events: Vec::new(),
+            error: None,
+            expanded: RefCell::new(None),
+        }
+    }
+
+    /// Creates a calendar from the content of an iCalendar file.
+    #[cfg(test)]
+    pub fn from_ics(source: CalendarSource, content: &str) -> Calendar {
+        Calendar {
+            source,
+            modified: None,
+            events: parse_ics(content).unwrap(),
+            error: None,
+            expanded: RefCell::new(None),
+        }
+    }
+
+    /// Reads the file again if it changed since it was last read.
+    ///
+    /// The previous events are kept when it can't be read or parsed.
+    ///
+    /// # Returns
+    ///
+    /// Whether the events were read again
+    pub fn refresh(&mut self) -> Result<bool, std::io::Error> {
+        let modified = std::fs::metadata(&self.source.path)?.modified()?;
+        if self.modified == Some(modified) {
+            return Ok(false);
+        }
+        let content = std::fs::read_to_string(&self.source.path)?;
+        self.events = parse_ics(&content).map_err(|e| {
+            std::io::Error::new(e.kind(), format!("{}: {}", self.source.path.display(), e))
+        })?;
+        self.modified = Some(modified);
+        self.expanded.replace(None);
+        Ok(true)
+    }
+
+    /// Refreshes the events, see `refresh`.
+    ///
+    /// # Returns
+    ///
+    /// The error of a failed refresh, unless the previous one failed the same way
+    pub fn reload(&mut self) -> Option<String> {
+        let error = self.refresh().err().map(|e| e.to_string());
+        let changed = error != self.error;
+        self.error = error;
+        self.error.clone().filter(|_| changed)
+    }
+
+    /// Returns the focus events overlapping `from` to `to`, as blocks.
+    ///
+    /// Focus events naming nothing to block, without default, are skipped.
+    pub fn focus_blocks(&self, from: NaiveDateTime, to: NaiveDateTime) -> Vec<Block> {
+        self.timed_occurrences(from, to)
+            .filter(|occurrence| self.is_focus(occurrence))
+            .filter_map(|occurrence| {
+                let arguments = event_arguments(&occurrence.description);
+                let arguments = if match_args(&arguments).is_empty() {
+                    self.source.arguments.clone()
+                } else {
+                    arguments
+                };
+                if match_args(&arguments).is_empty() {
+                    return None;
+                }
+                Some(Block {
+                    schedule: occurrence.summary.clone(),
+                    start: local(occurrence.start),
+                    end: local(occurrence.end),
+                    arguments,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the meetings overlapping `from` to `to`, as blocks without arguments.
+    pub fn meetings(&self, from: NaiveDateTime, to: NaiveDateTime) -> Vec<Block> {
+        self.timed_occurrences(from, to)
+            .filter(|occurrence| occurrence.busy && !self.is_focus(occurrence))
+            .map(|occurrence| Block {
+                schedule: occurrence.summary.clone(),
+                start: local(occurrence.start),
+                end: local(occurrence.end),
+                arguments: Vec::new(),
+            })
+            .collect()
+    }
+
+    /// Returns the occurrences of the timed events overlapping `from` to `to`.
+    ///
+    /// They are expanded again only when the range leaves the one expanded last.
+    fn timed_occurrences(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> impl Iterator<Item = Occurrence> {
+        let (from, to) = (utc(from), utc(to));
+        let mut expanded = self.expanded.borrow_mut();
+        let covered = expanded
+            .as_ref()
+            .is_some_and(|expansion| expansion.from <= from && to <= expansion.to);
+        if !covered {
+            let events: Vec<Event> = self
+                .events
+                .iter()
+                .filter(|event| !event.is_all_day())
+                .cloned()
+                .collect();
+            let (start, end) = (from - EXPANSION_MARGIN, to + EXPANSION_MARGIN);
+            *expanded = Some(Expansion {
+                from: start,
+                to: end,
+                occurrences: occurrences(&events, start, end),
+            });
+        }
+        let occurrences: Vec<Occurrence> = expanded
+            .iter()
+            .flat_map(|expansion| &expansion.occurrences)
+            .filter(|occurrence| occurrence.end > occurrence.start)
+            .filter(|occurrence| occurrence.end > from && occurrence.start < to)
+            .cloned()
+            .collect();
+        occurrences.into_iter()
+    }
+
+    fn is_focus(&self, occurrence: &Occurrence) -> bool {
+        occurrence
+            .summary
+            .to_lowercase()
+            .contains(&self.source.focus.to_lowercase())
+    }
+}
+
+/// Returns the words of a description which are presets, websites or flags.
+///
+/// # Arguments
+///
+/// * `description` - The description of an event, e.g. `Blocking: coding, netflix`
+fn event_arguments(description: &str) -> Vec<String> {
+    description
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric() && c != '-' && c != '_'))
+        .filter(|word| {
+            let word = [word.to_string()];
+            word[0].starts_with("--")
+                || !match_args(&word).is_empty()
+                || !match_presets(&word).is_empty()
+        })
+        .map(String::from)
+        .collect()
+}
+
+/// Formats a calendar for `schedule list`.
+pub fn format_calendar(source: &CalendarSource) -> String {
+    let mut line = format!(
+        "calendar {}: events named {:?}, blocking",
+        source.path.display(),
+        source.focus
+    );
+    if source.arguments.is_empty() {
+        line.push_str(" their description");
+    } else {
+        line.push_str(&format!(
+            " their description or {}",
+            source.arguments.join(" ")
+        ));
+    }
+    line
+}
+
+fn local(time: DateTime<Utc>) -> NaiveDateTime {
+    time.with_timezone(&Local).naive_local()
+}
+
+fn utc(local: NaiveDateTime) -> DateTime<Utc> {
+    local
+        .and_local_timezone(Local)
+        .earliest()
+        .map_or(local.and_utc(), |time| time.to_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:focus-1\r
+SUMMARY:Focus time\r
+DESCRIPTION:Deep work\\, blocking coding and\r
+  netflix --strict\r
+DTSTART:20261019T090000\r
+DTEND:20261019T120000\r
+RRULE:FREQ=DAILY;COUNT=3\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:standup\r
+SUMMARY:Standup\r
+DTSTART:20261019T100000\r
+DURATION:PT15M\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,TU\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:lunch\r
+SUMMARY:Lunch\r
+TRANSP:TRANSPARENT\r
+DTSTART:20261019T113000\r
+DTEND:20261019T130000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:focus-2\r
+SUMMARY:focus\r
+DESCRIPTION:Nothing named\r
+DTSTART;VALUE=DATE:20261020\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn at(date: &str, time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn source(path: PathBuf) -> CalendarSource {
+        CalendarSource {
+            path,
+            focus: FOCUS_KEYWORD.to_string(),
+            arguments: vec!["youtube".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_focus_blocks() {
+        let calendar = Calendar::from_ics(source(PathBuf::new()), ICS);
+
+        let blocks = calendar.focus_blocks(at("2026-10-19", "00:00"), at("2026-10-26", "00:00"));
+
+        let starts: Vec<NaiveDateTime> = blocks.iter().map(|block| block.start).collect();
+        assert_eq!(
+            starts,
+            vec![
+                at("2026-10-19", "09:00"),
+                at("2026-10-20", "09:00"),
+                at("2026-10-21", "09:00")
+            ],
+            "The all day focus event should be ignored"
+        );
+        assert_eq!(blocks[0].end, at("2026-10-19", "12:00"));
+        assert_eq!(blocks[0].schedule, "Focus time");
+        assert_eq!(blocks[0].arguments, vec!["coding", "netflix", "--strict"]);
+    }
+
+    #[test]
+    fn test_meetings() {
+        let calendar = Calendar::from_ics(source(PathBuf::new()), ICS);
+
+        let meetings = calendar.meetings(at("2026-10-19", "00:00"), at("2026-10-26", "00:00"));
+
+        let ranges: Vec<String> = meetings
+            .iter()
+            .map(|meeting| format!("{} {}-{}", meeting.schedule, meeting.start, meeting.end))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                "Standup 2026-10-19 10:00:00-2026-10-19 10:15:00",
+                "Standup 2026-10-20 10:00:00-2026-10-20 10:15:00"
+            ],
+            "Transparent events are not meetings"
+        );
+    }
+
+    #[test]
+    fn test_occurrences_are_expanded_again_out_of_range() {
+        let calendar = Calendar::from_ics(source(PathBuf::new()), ICS);
+        let window = || {
+            let expanded = calendar.expanded.borrow();
+            expanded
+                .as_ref()
+                .map(|expansion| (expansion.from, expansion.to))
+        };
+
+        let day = (at("2026-10-19", "08:00"), at("2026-10-20", "00:00"));
+        assert_eq!(calendar.focus_blocks(day.0, day.1).len(), 1);
+        let expanded = window();
+        let later = (at("2026-10-19", "09:30"), at("2026-10-20", "00:00"));
+        assert_eq!(calendar.meetings(later.0, later.1).len(), 1);
+        assert_eq!(window(), expanded, "The range was expanded already");
+
+        let next_week = (at("2026-10-26", "00:00"), at("2026-10-27", "00:00"));
+        assert_eq!(calendar.meetings(next_week.0, next_week.1).len(), 1);
+        assert_ne!(window(), expanded);
+    }
+
+    #[test]
+    fn test_event_arguments() {
+        assert_eq!(
+            event_arguments("Blocking: Coding, (netflix) and x.com --cooldown=5"),
+            vec!["Coding", "netflix", "--cooldown=5"]
+        );
+        assert!(event_arguments("Write the report").is_empty());
+    }
+
+    #[test]
+    fn test_refresh() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("calendar.ics");
+        let mut calendar = Calendar::new(source(path.clone()));
+        assert!(calendar.refresh().is_err());
+
+        std::fs::write(&path, ICS).unwrap();
+        assert!(calendar.refresh().unwrap());
+        assert!(!calendar.refresh().unwrap(), "The file did not change");
+        let week = (at("2026-10-19", "00:00"), at("2026-10-26", "00:00"));
+        assert_eq!(calendar.focus_blocks(week.0, week.1).len(), 3);
+
+        std::fs::write(&path, "BEGIN:VEVENT\nDTSTART:someday\nEND:VEVENT\n").unwrap();
+        let modified = SystemTime::now() + std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let error = calendar.reload().expect("Should not parse");
+        assert!(error.contains("DTSTART"));
+        assert!(
+            calendar.reload().is_none(),
+            "The same error should be returned once"
+        );
+        assert_eq!(
+            calendar.focus_blocks(week.0, week.1).len(),
+            3,
+            "The previous events should be kept"
+        );
+    }
+}
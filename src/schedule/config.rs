@@ -0,0 +1,458 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{NaiveDate, NaiveTime, TimeDelta, Weekday};
+use toml::{Table, Value};
+
+use crate::schedule::calendar::{Calendar, CalendarSource, FOCUS_KEYWORD};
+use crate::schedule::plan::{Pomodoro, Schedule, Scheduler};
+use crate::session::budget::Budget;
+use crate::utils::utils::{flag_values, match_args};
+
+/// Where the configuration is read from, unless `--config=PATH` is given.
+pub const CONFIG_PATH: &str = "/etc/rust_can_make_you_focus/config.toml";
+pub const CONFIG_FLAG: &str = "--config";
+
+const WEEKDAYS: [Weekday; 5] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+];
+const SCHEDULE_KEYS: [&str; 7] = [
+    "name", "days", "start", "end", "block", "pomodoro", "except",
+];
+const CALENDAR_KEYS: [&str; 3] = ["path", "focus", "block"];
+
+/// The configuration file, e.g.
+///
+/// ```toml
+/// [[schedule]]
+/// name = "mornings"
+/// days = ["weekdays"]
+/// start = "09:00"
+/// end = "12:00"
+/// block = ["coding"]
+/// except = ["2026-12-24"]
+/// pomodoro = { focus = 50, break = 10 }
+///
+/// [[calendar]]
+/// path = "/home/me/calendar.ics"
+/// block = ["coding"]
+///
+/// [budget]
+/// youtube = 30
+/// ```
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub schedules: Vec<Schedule>,
+    pub calendars: Vec<CalendarSource>,
+    /// The daily time budgets, in minutes per website or preset
+    pub budgets: Vec<Budget>,
+}
+
+impl Config {
+    /// Reads the configuration at `path`, empty if there is none.
+    pub fn load(path: &Path) -> Result<Config, std::io::Error> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Config::parse(&content)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Parses the content of a configuration file.
+    ///
+    /// # Returns
+    ///
+    /// The configuration, or an `InvalidData` error naming the first invalid value
+    pub fn parse(content: &str) -> Result<Config, std::io::Error> {
+        let table: Table = content
+            .parse()
+            .map_err(|e: toml::de::Error| invalid_config(e.message().to_string()))?;
+        Ok(Config {
+            schedules: parse_tables(&table, "schedule", parse_schedule)?,
+            calendars: parse_tables(&table, "calendar", |value, _| parse_calendar(value))?,
+            budgets: match table.get("budget") {
+                Some(value) => parse_budgets(value)
+                    .map_err(|message| invalid_config(format!("budget: {}", message)))?,
+                None => Vec::new(),
+            },
+        })
+    }
+}
+
+/// Parses the array of tables `[[key]]` with `parse`, given each table and its index.
+fn parse_tables<T>(
+    table: &Table,
+    key: &str,
+    parse: impl Fn(&Value, usize) -> Result<T, String>,
+) -> Result<Vec<T>, std::io::Error> {
+    match table.get(key) {
+        None => Ok(Vec::new()),
+        Some(Value::Array(values)) => values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                parse(value, index).map_err(|message| {
+                    invalid_config(format!("{} {}: {}", key, index + 1, message))
+                })
+            })
+            .collect(),
+        Some(_) => Err(invalid_config(format!(
+            "{} must be an array of tables, [[{}]]",
+            key, key
+        ))),
+    }
+}
+
+impl Config {
+    /// Returns the scheduler of the schedules and calendars, with the calendars read.
+    pub fn scheduler(&self) -> Scheduler {
+        let calendars = self.calendars.iter().cloned().map(Calendar::new).collect();
+        let mut scheduler = Scheduler::new(self.schedules.clone(), calendars);
+        scheduler.refresh();
+        scheduler
+    }
+}
+
+/// Returns the configuration file given with `--config=PATH`, or `CONFIG_PATH`.
+pub fn config_path(flags: &[String]) -> PathBuf {
+    flag_values(flags, CONFIG_FLAG)
+        .last()
+        .map_or(PathBuf::from(CONFIG_PATH), PathBuf::from)
+}
+
+fn parse_schedule(value: &Value, index: usize) -> Result<Schedule, String> {
+    let table = value.as_table().ok_or("expected a table")?;
+    if let Some(key) = table
+        .keys()
+        .find(|key| !SCHEDULE_KEYS.contains(&key.as_str()))
+    {
+        return Err(format!("unknown key {}", key));
+    }
+    let name = match table.get("name") {
+        Some(name) => name.as_str().ok_or("name must be a string")?.to_string(),
+        None => format!("schedule {}", index + 1),
+    };
+    let days = strings(table, "days")?
+        .iter()
+        .map(|day| parse_days(day))
+        .collect::<Result<Vec<_>, _>>()?
+        .concat();
+    if days.is_empty() {
+        return Err("days is empty".to_string());
+    }
+    let arguments: Vec<String> = strings(table, "block")?;
+    if arguments.is_empty() {
+        return Err("block is empty".to_string());
+    }
+    let exceptions = match table.get("except") {
+        Some(_) => strings(table, "except")?
+            .iter()
+            .map(|date| {
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map_err(|_| format!("invalid date {:?}, expected YYYY-MM-DD", date))
+            })
+            .collect::<Result<_, _>>()?,
+        None => Vec::new(),
+    };
+    let pomodoro = match table.get("pomodoro") {
+        Some(value) => Some(parse_pomodoro(value)?),
+        None => None,
+    };
+    Ok(Schedule {
+        name,
+        days,
+        start: parse_time(table, "start")?,
+        end: parse_time(table, "end")?,
+        arguments,
+        pomodoro,
+        exceptions,
+    })
+}
+
+fn parse_calendar(value: &Value) -> Result<CalendarSource, String> {
+    let table = value.as_table().ok_or("expected a table")?;
+    if let Some(key) = table
+        .keys()
+        .find(|key| !CALENDAR_KEYS.contains(&key.as_str()))
+    {
+        return Err(format!("unknown key {}", key));
+    }
+    let path = table
+        .get("path")
+        .and_then(Value::as_str)
+        .ok_or("path must be the path of an .ics file")?;
+    let focus = match table.get("focus") {
+        Some(focus) => focus
+            .as_str()
+            .filter(|focus| !focus.is_empty())
+            .ok_or("focus must be a non empty string")?
+            .to_string(),
+        None => FOCUS_KEYWORD.to_string(),
+    };
+    let arguments = match table.get("block") {
+        Some(_) => strings(table, "block")?,
+        None => Vec::new(),
+    };
+    Ok(CalendarSource {
+        path: PathBuf::from(path),
+        focus,
+        arguments,
+    })
+}
+
+fn parse_budgets(value: &Value) -> Result<Vec<Budget>, String> {
+    let table = value
+        .as_table()
+        .ok_or("expected a table of minutes per day, [budget]")?;
+    table
+        .iter()
+        .map(|(name, minutes)| {
+            if match_args(std::slice::from_ref(name)).is_empty() {
+                return Err(format!("{} is not a website or preset", name));
+            }
+            let minutes = minutes
+                .as_integer()
+                .filter(|minutes| (1..=24 * 60).contains(minutes))
+                .ok_or(format!("{} must be a number of minutes per day", name))?;
+            Ok(Budget {
+                name: name.to_lowercase(),
+                daily: std::time::Duration::from_secs(minutes as u64 * 60),
+            })
+        })
+        .collect()
+}
+
+fn parse_days(day: &str) -> Result<Vec<Weekday>, String> {
+    match day.to_lowercase().as_str() {
+        "weekdays" => Ok(WEEKDAYS.to_vec()),
+        "weekend" => Ok(vec![Weekday::Sat, Weekday::Sun]),
+        "daily" => Ok([WEEKDAYS.as_slice(), &[Weekday::Sat, Weekday::Sun]].concat()),
+        other => other
+            .parse::<Weekday>()
+            .map(|day| vec![day])
+            .map_err(|_| format!("invalid day {:?}", day)),
+    }
+}
+
+fn parse_time(table: &Table, key: &str) -> Result<NaiveTime, String> {
+    let time = table
+        .get(key)
+        .and_then(Value::as_str)
+        .ok_or(format!("{} must be a time like \"09:00\"", key))?;
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| format!("invalid {} time {:?}, expected HH:MM", key, time))
+}
+
+fn parse_pomodoro(value: &Value) -> Result<Pomodoro, String> {
+    let minutes = |key: &str| -> Result<i64, String> {
+        value
+            .get(key)
+            .and_then(Value::as_integer)
+            .filter(|minutes| (1..=24 * 60).contains(minutes))
+            .ok_or(format!(
+                "pomodoro {} must be a number of minutes from 1 to {}",
+                key,
+                24 * 60
+            ))
+    };
+    Ok(Pomodoro {
+        focus: TimeDelta::minutes(minutes("focus")?),
+        pause: TimeDelta::minutes(minutes("break")?),
+    })
+}
+
+fn strings(table: &Table, key: &str) -> Result<Vec<String>, String> {
+    table
+        .get(key)
+        .and_then(Value::as_array)
+        .and_then(|items| {
+            items
+                .iter()
+                .map(|item| item.as_str().map(String::from))
+                .collect()
+        })
+        .ok_or(format!("{} must be an array of strings", key))
+}
+
+fn invalid_config(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const CONFIG: &str = r#"
+[[schedule]]
+name = "mornings"
+days = ["weekdays"]
+start = "09:00"
+end = "12:00"
+block = ["coding", "--strict"]
+except = ["2026-12-24"]
+
+[[schedule]]
+days = ["sat", "Sunday"]
+start = "14:00"
+end = "17:00"
+block = ["netflix"]
+pomodoro = { focus = 50, break = 10 }
+
+[[calendar]]
+path = "/home/me/calendar.ics"
+block = ["coding"]
+
+[budget]
+youtube = 30
+"#;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(CONFIG).expect("Should parse");
+
+        assert_eq!(config.schedules.len(), 2);
+        let mornings = &config.schedules[0];
+        assert_eq!(mornings.name, "mornings");
+        assert_eq!(mornings.days, WEEKDAYS.to_vec());
+        assert_eq!(mornings.start, NaiveTime::from_hms_opt(9, 0, 0).unwrap());
+        assert_eq!(mornings.arguments, vec!["coding", "--strict"]);
+        assert_eq!(
+            mornings.exceptions,
+            vec![NaiveDate::from_ymd_opt(2026, 12, 24).unwrap()]
+        );
+        let weekend = &config.schedules[1];
+        assert_eq!(weekend.name, "schedule 2");
+        assert_eq!(weekend.days, vec![Weekday::Sat, Weekday::Sun]);
+        assert_eq!(
+            weekend.pomodoro,
+            Some(Pomodoro {
+                focus: TimeDelta::minutes(50),
+                pause: TimeDelta::minutes(10),
+            })
+        );
+        assert_eq!(
+            config.calendars,
+            vec![CalendarSource {
+                path: PathBuf::from("/home/me/calendar.ics"),
+                focus: FOCUS_KEYWORD.to_string(),
+                arguments: vec!["coding".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_budgets() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(
+            config.budgets,
+            vec![Budget {
+                name: "youtube".to_string(),
+                daily: std::time::Duration::from_secs(30 * 60),
+            }]
+        );
+        for (content, expected) in [
+            (
+                "[budget]\nsomewhere = 10",
+                "budget: somewhere is not a website",
+            ),
+            ("[budget]\nyoutube = 0", "budget: youtube must be a number"),
+            (
+                "[budget]\nyoutube = \"30m\"",
+                "budget: youtube must be a number",
+            ),
+            ("budget = 30", "[budget]"),
+        ] {
+            let error = Config::parse(content).expect_err(content);
+            assert!(error.to_string().contains(expected), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_calendars() {
+        for (content, expected) in [
+            ("[[calendar]]\nblock = [\"coding\"]", "calendar 1: path"),
+            (
+                "[[calendar]]\npath = \"a.ics\"\nfocus = \"\"",
+                "calendar 1: focus",
+            ),
+            (
+                "[[calendar]]\npath = \"a.ics\"\nurl = \"\"",
+                "unknown key url",
+            ),
+            ("calendar = \"a.ics\"", "[[calendar]]"),
+        ] {
+            let error = Config::parse(content).expect_err(content);
+            assert!(error.to_string().contains(expected), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_schedules() {
+        let invalid = [
+            ("days = [\"someday\"]", "invalid day"),
+            ("start = \"9am\"", "invalid start time"),
+            ("block = []", "block is empty"),
+            ("except = [\"24/12\"]", "invalid date"),
+            ("pomodoro = { focus = 0, break = 5 }", "pomodoro focus"),
+            (
+                "pomodoro = { focus = 9223372036854775807, break = 5 }",
+                "pomodoro focus",
+            ),
+            ("pomodoro = { focus = 50, break = 1441 }", "pomodoro break"),
+            ("blocks = [\"coding\"]", "unknown key blocks"),
+        ];
+        for (line, expected) in invalid {
+            let (key, _) = line.split_once(" = ").unwrap();
+            let mut content = vec!["[[schedule]]".to_string(), line.to_string()];
+            for default in [
+                "days = [\"mon\"]",
+                "start = \"09:00\"",
+                "end = \"12:00\"",
+                "block = [\"coding\"]",
+            ] {
+                if !default.starts_with(&format!("{} ", key)) {
+                    content.push(default.to_string());
+                }
+            }
+            let error = Config::parse(&content.join("\n")).expect_err(line);
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+            assert!(
+                error.to_string().starts_with("schedule 1: ")
+                    && error.to_string().contains(expected),
+                "{}: {}",
+                line,
+                error
+            );
+        }
+        assert!(Config::parse("schedule = 1").is_err());
+        assert!(Config::parse("[[schedule]\n").is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+
+        std::fs::write(&path, CONFIG).unwrap();
+        assert_eq!(Config::load(&path).unwrap().schedules.len(), 2);
+
+        std::fs::write(&path, "[[schedule]]\n").unwrap();
+        let error = Config::load(&path).unwrap_err();
+        assert!(error.to_string().starts_with(&path.display().to_string()));
+    }
+
+    #[test]
+    fn test_config_path() {
+        assert_eq!(config_path(&[]), PathBuf::from(CONFIG_PATH));
+        assert_eq!(
+            config_path(&["--config=focus.toml".to_string()]),
+            PathBuf::from("focus.toml")
+        );
+    }
+}
@@ -0,0 +1,846 @@
+use chrono::{
+    DateTime, Datelike, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+
+/// How many periods of a recurrence are looked at, at most.
+const MAX_PERIODS: u32 = 100_000;
+
+/// A time of an iCalendar property, with the zone it is written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IcsTime {
+    /// An all day date, `VALUE=DATE`
+    Date(NaiveDate),
+    /// A time ending with `Z`
+    Utc(NaiveDateTime),
+    /// A time with a `TZID` parameter
+    Zoned(NaiveDateTime, Tz),
+    /// A time without zone, in the local time of the reader
+    Floating(NaiveDateTime),
+}
+
+impl IcsTime {
+    /// The time on the wall clock of its zone.
+    fn wall(&self) -> NaiveDateTime {
+        match *self {
+            IcsTime::Date(date) => date.and_time(chrono::NaiveTime::MIN),
+            IcsTime::Utc(time) | IcsTime::Zoned(time, _) | IcsTime::Floating(time) => time,
+        }
+    }
+
+    /// Converts `wall`, a time on the wall clock of this zone, to UTC.
+    ///
+    /// `None` for a time skipped by a DST change.
+    fn in_zone(&self, wall: NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            IcsTime::Utc(_) => Some(Utc.from_utc_datetime(&wall)),
+            IcsTime::Zoned(_, tz) => tz.from_local_datetime(&wall).earliest().map(|t| t.to_utc()),
+            IcsTime::Date(_) | IcsTime::Floating(_) => Local
+                .from_local_datetime(&wall)
+                .earliest()
+                .map(|t| t.to_utc()),
+        }
+    }
+
+    fn to_utc(self) -> Option<DateTime<Utc>> {
+        self.in_zone(self.wall())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// The supported part of an `RRULE`.
+#[derive(Debug, Clone, PartialEq)]
+struct Recurrence {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<DateTime<Utc>>,
+    /// The weekdays, with their position in the month for `MONTHLY`, e.g. `-1FR`
+    by_day: Vec<(Option<i32>, Weekday)>,
+    by_month_day: Vec<i32>,
+    /// The first day of the weeks of `WEEKLY`, `WKST`
+    week_start: Weekday,
+}
+
+/// An event of an iCalendar file, a `VEVENT`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub uid: String,
+    pub summary: String,
+    pub description: String,
+    pub start: IcsTime,
+    end: Option<IcsTime>,
+    duration: Option<TimeDelta>,
+    recurrence: Option<Recurrence>,
+    exceptions: Vec<IcsTime>,
+    /// Set on the events replacing a single occurrence of a recurring event
+    recurrence_id: Option<IcsTime>,
+    /// Whether the event shows as busy, i.e. is not `TRANSP:TRANSPARENT`
+    pub busy: bool,
+    pub cancelled: bool,
+}
+
+/// A single occurrence of an event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Occurrence {
+    pub summary: String,
+    pub description: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub busy: bool,
+}
+
+impl Event {
+    /// Whether the event lasts whole days, which never block.
+    pub fn is_all_day(&self) -> bool {
+        matches!(self.start, IcsTime::Date(_))
+    }
+
+    fn length(&self) -> TimeDelta {
+        if let Some(duration) = self.duration {
+            return duration;
+        }
+        let end = self.end.and_then(IcsTime::to_utc);
+        match (self.start.to_utc(), end) {
+            (Some(start), Some(end)) if end > start => end - start,
+            // An all day event without end lasts a day, others are instants
+            _ if self.is_all_day() => TimeDelta::days(1),
+            _ => TimeDelta::zero(),
+        }
+    }
+
+    /// The wall clock starts of the event, in its zone, in order.
+    ///
+    /// Without `COUNT`, which counts from the first start, the periods ending
+    /// before `after` are skipped. Stops after the first start from `before`,
+    /// `COUNT` or `UNTIL`.
+    fn starts(&self, after: DateTime<Utc>, before: DateTime<Utc>) -> Vec<NaiveDateTime> {
+        let first = self.start.wall();
+        let Some(recurrence) = &self.recurrence else {
+            return vec![first];
+        };
+        let skipped = match recurrence.count {
+            Some(_) => 0,
+            // The zone of the event is at most a day away from UTC
+            None => first_period(
+                recurrence,
+                first.date(),
+                after.date_naive().pred_opt().unwrap_or(NaiveDate::MIN),
+            ),
+        };
+        let mut starts = Vec::new();
+        for period in skipped..skipped.saturating_add(MAX_PERIODS) {
+            let mut candidates = period_dates(
+                recurrence,
+                first.date(),
+                period.saturating_mul(recurrence.interval),
+            );
+            candidates.sort();
+            for date in candidates {
+                let start = date.and_time(first.time());
+                if start < first {
+                    continue;
+                }
+                let utc = self.start.in_zone(start);
+                if utc.is_some_and(|utc| utc >= before)
+                    || recurrence
+                        .count
+                        .is_some_and(|count| starts.len() as u32 >= count)
+                    || recurrence
+                        .until
+                        .zip(utc)
+                        .is_some_and(|(until, utc)| utc > until)
+                {
+                    return starts;
+                }
+                starts.push(start);
+            }
+        }
+        starts
+    }
+}
+
+/// The period of `recurrence` running on `date`, counted from `first`, the dates
+/// of the periods before it all being before `date`.
+fn first_period(recurrence: &Recurrence, first: NaiveDate, date: NaiveDate) -> u32 {
+    let elapsed = match recurrence.frequency {
+        Frequency::Daily => (date - first).num_days(),
+        Frequency::Weekly => match week_of(first, recurrence.week_start) {
+            Some(week) => (date - week).num_weeks(),
+            None => 0,
+        },
+        Frequency::Monthly => {
+            (date.year() as i64 * 12 + date.month0() as i64)
+                - (first.year() as i64 * 12 + first.month0() as i64)
+        }
+        Frequency::Yearly => (date.year() - first.year()) as i64,
+    };
+    elapsed
+        .div_euclid(recurrence.interval as i64)
+        .clamp(0, u32::MAX as i64) as u32
+}
+
+/// The dates of the `period`th period after `first`, before the `BY` filters
+/// which don't apply to its frequency.
+fn period_dates(recurrence: &Recurrence, first: NaiveDate, period: u32) -> Vec<NaiveDate> {
+    let weekday_matches = |date: &NaiveDate| {
+        recurrence.by_day.is_empty()
+            || recurrence
+                .by_day
+                .iter()
+                .any(|(_, weekday)| *weekday == date.weekday())
+    };
+    match recurrence.frequency {
+        Frequency::Daily => TimeDelta::try_days(period as i64)
+            .and_then(|days| first.checked_add_signed(days))
+            .filter(weekday_matches)
+            .into_iter()
+            .collect(),
+        Frequency::Weekly => {
+            let weeks = TimeDelta::try_weeks(period as i64);
+            if recurrence.by_day.is_empty() {
+                return weeks
+                    .and_then(|weeks| first.checked_add_signed(weeks))
+                    .into_iter()
+                    .collect();
+            }
+            let Some(week) = week_of(first, recurrence.week_start)
+                .zip(weeks)
+                .and_then(|(week, weeks)| week.checked_add_signed(weeks))
+            else {
+                return Vec::new();
+            };
+            recurrence
+                .by_day
+                .iter()
+                .filter_map(|(_, weekday)| {
+                    week.checked_add_signed(TimeDelta::days(
+                        weekday.days_since(recurrence.week_start) as i64,
+                    ))
+                })
+                .collect()
+        }
+        Frequency::Monthly => {
+            let months = first.year() as i64 * 12 + first.month0() as i64 + period as i64;
+            let Ok(year) = i32::try_from(months.div_euclid(12)) else {
+                return Vec::new();
+            };
+            let month = months.rem_euclid(12) as u32 + 1;
+            let days_in_month = (1..=31)
+                .filter_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+                .collect::<Vec<_>>();
+            if !recurrence.by_month_day.is_empty() {
+                return recurrence
+                    .by_month_day
+                    .iter()
+                    .filter_map(|day| nth(&days_in_month, *day))
+                    .collect();
+            }
+            if recurrence.by_day.is_empty() {
+                return NaiveDate::from_ymd_opt(year, month, first.day())
+                    .into_iter()
+                    .collect();
+            }
+            recurrence
+                .by_day
+                .iter()
+                .flat_map(|(position, weekday)| {
+                    let matching: Vec<NaiveDate> = days_in_month
+                        .iter()
+                        .filter(|date| date.weekday() == *weekday)
+                        .copied()
+                        .collect();
+                    match position {
+                        Some(position) => nth(&matching, *position).into_iter().collect(),
+                        None => matching,
+                    }
+                })
+                .collect()
+        }
+        Frequency::Yearly => i32::try_from(first.year() as i64 + period as i64)
+            .ok()
+            .and_then(|year| NaiveDate::from_ymd_opt(year, first.month(), first.day()))
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// The first day of the week of `date`, `None` before the first representable week.
+fn week_of(date: NaiveDate, week_start: Weekday) -> Option<NaiveDate> {
+    date.checked_sub_signed(TimeDelta::days(date.weekday().days_since(week_start) as i64))
+}
+
+/// The `position`th date, counted from the end when negative, from 1.
+fn nth(dates: &[NaiveDate], position: i32) -> Option<NaiveDate> {
+    match position {
+        1.. => dates.get(position as usize - 1).copied(),
+        ..=-1 => dates
+            .len()
+            .checked_sub(position.unsigned_abs() as usize)
+            .map(|i| dates[i]),
+        0 => None,
+    }
+}
+
+/// Returns the occurrences of `events` overlapping `from` to `to`, by start time.
+///
+/// Recurring events are expanded in their own time zone, so an event at 09:00 in
+/// `Europe/Paris` stays at 09:00 there across DST changes. Occurrences replaced
+/// by another event (`RECURRENCE-ID`) or excluded (`EXDATE`) are skipped, and so
+/// are cancelled events.
+pub fn occurrences(events: &[Event], from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Occurrence> {
+    let replaced: Vec<(&str, DateTime<Utc>)> = events
+        .iter()
+        .filter_map(|event| Some((event.uid.as_str(), event.recurrence_id?.to_utc()?)))
+        .collect();
+    let mut occurrences: Vec<Occurrence> = events
+        .iter()
+        .filter(|event| !event.cancelled)
+        .flat_map(|event| {
+            let length = event.length();
+            // Started later, an occurrence would not overlap `from`
+            let after = from
+                .checked_sub_signed(length)
+                .unwrap_or(DateTime::<Utc>::MIN_UTC);
+            let excluded: Vec<DateTime<Utc>> = event
+                .exceptions
+                .iter()
+                .filter_map(|exception| exception.to_utc())
+                .collect();
+            event
+                .starts(after, to)
+                .into_iter()
+                .filter_map(|start| event.start.in_zone(start))
+                .filter(|start| !excluded.contains(start))
+                .filter(|start| {
+                    event.recurrence.is_none() || !replaced.contains(&(event.uid.as_str(), *start))
+                })
+                // An end past the representable times is skipped with its start
+                .filter_map(move |start| Some((start, start.checked_add_signed(length)?)))
+                .filter(move |(start, end)| *end > from && *start < to)
+                .map(move |(start, end)| Occurrence {
+                    summary: event.summary.clone(),
+                    description: event.description.clone(),
+                    start,
+                    end,
+                    busy: event.busy,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    occurrences.sort_by_key(|occurrence| occurrence.start);
+    occurrences
+}
+
+/// Parses the events of an iCalendar file.
+///
+/// The properties of the components nested in an event, e.g. its `VALARM`s, are
+/// ignored. An event with a rule which is not supported is skipped with a warning.
+///
+/// # Returns
+///
+/// The events, or an `InvalidData` error naming the first invalid line or the
+/// first event without `DTSTART`
+pub fn parse_ics(content: &str) -> Result<Vec<Event>, std::io::Error> {
+    let mut events = Vec::new();
+    let mut event: Option<Event> = None;
+    // How deep in the components nested in the event, and what was read of it
+    let mut depth = 0;
+    let mut has_start = false;
+    let mut unsupported: Option<String> = None;
+    for (index, line) in unfold(content).iter().enumerate() {
+        let (name, params, value) = split_property(line)
+            .ok_or_else(|| invalid_ics(format!("line {}: expected NAME:VALUE", index + 1)))?;
+        let invalid = |what: &str| invalid_ics(format!("{}: invalid {} {:?}", name, what, value));
+        if event.is_some() {
+            match name.as_str() {
+                "BEGIN" => depth += 1,
+                "END" if depth > 0 => depth -= 1,
+                _ => {}
+            }
+            if depth > 0 || (name == "END" && value != "VEVENT") {
+                continue;
+            }
+        }
+        match (name.as_str(), event.as_mut()) {
+            ("BEGIN", None) if value == "VEVENT" => {
+                (depth, has_start, unsupported) = (0, false, None);
+                event = Some(Event {
+                    uid: String::new(),
+                    summary: String::new(),
+                    description: String::new(),
+                    start: IcsTime::Date(NaiveDate::MIN),
+                    end: None,
+                    duration: None,
+                    recurrence: None,
+                    exceptions: Vec::new(),
+                    recurrence_id: None,
+                    busy: true,
+                    cancelled: false,
+                })
+            }
+            ("END", Some(_)) if value == "VEVENT" => {
+                let Some(ended) = event.take() else { continue };
+                if !has_start {
+                    return Err(invalid_ics(format!(
+                        "event {:?} has no DTSTART",
+                        ended.summary
+                    )));
+                }
+                match unsupported.take() {
+                    Some(rule) => println!(
+                        "Skipping the event {:?}, its rule {} is not supported",
+                        ended.summary, rule
+                    ),
+                    None => events.push(ended),
+                }
+            }
+            (_, None) => {}
+            ("UID", Some(event)) => event.uid = value.to_string(),
+            ("SUMMARY", Some(event)) => event.summary = unescape(value),
+            ("DESCRIPTION", Some(event)) => event.description = unescape(value),
+            ("DTSTART", Some(event)) => {
+                event.start = parse_time(&params, value).ok_or_else(|| invalid("time"))?;
+                has_start = true;
+            }
+            ("DTEND", Some(event)) => {
+                event.end = Some(parse_time(&params, value).ok_or_else(|| invalid("time"))?)
+            }
+            ("DURATION", Some(event)) => {
+                event.duration = Some(parse_duration(value).ok_or_else(|| invalid("duration"))?)
+            }
+            ("RRULE", Some(event)) => match parse_recurrence(value) {
+                Some(recurrence) => event.recurrence = Some(recurrence),
+                None => unsupported = Some(value.to_string()),
+            },
+            ("EXDATE", Some(event)) => {
+                for time in value.split(',') {
+                    event
+                        .exceptions
+                        .push(parse_time(&params, time).ok_or_else(|| invalid("time"))?);
+                }
+            }
+            ("RECURRENCE-ID", Some(event)) => {
+                event.recurrence_id =
+                    Some(parse_time(&params, value).ok_or_else(|| invalid("time"))?)
+            }
+            ("TRANSP", Some(event)) => event.busy = value != "TRANSPARENT",
+            ("STATUS", Some(event)) => event.cancelled = value == "CANCELLED",
+            _ => {}
+        }
+    }
+    Ok(events)
+}
+
+/// Joins the lines folded on several lines, starting with a space or a tab.
+fn unfold(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// The parameters of a property, e.g. `TZID=Europe/Paris`, with uppercase names.
+type Parameters = Vec<(String, String)>;
+
+/// Splits `NAME;PARAM=VALUE:VALUE` into its name, parameters and value.
+fn split_property(line: &str) -> Option<(String, Parameters, &str)> {
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(index, c)| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        (c == ':' && !quoted).then_some(index)
+    })?;
+    let mut parts = line[..colon].split(';');
+    let name = parts.next()?.to_uppercase();
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, value)| (key.to_uppercase(), value.trim_matches('"').to_string()))
+        .collect();
+    Some((name, params, &line[colon + 1..]))
+}
+
+fn parse_time(params: &[(String, String)], value: &str) -> Option<IcsTime> {
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    if param("VALUE") == Some("DATE") || value.len() == 8 {
+        return NaiveDate::parse_from_str(value, "%Y%m%d")
+            .ok()
+            .map(IcsTime::Date);
+    }
+    if let Some(utc) = value.strip_suffix('Z') {
+        return NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")
+            .ok()
+            .map(IcsTime::Utc);
+    }
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    // Zones unknown to the tz database, e.g. Windows names, are read as local time
+    match param("TZID").and_then(|tzid| tzid.parse::<Tz>().ok()) {
+        Some(tz) => Some(IcsTime::Zoned(time, tz)),
+        None => Some(IcsTime::Floating(time)),
+    }
+}
+
+/// Parses a duration like `PT1H30M` or `P1D`.
+fn parse_duration(value: &str) -> Option<TimeDelta> {
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(value) => (-1, value),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut duration = TimeDelta::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in value.strip_prefix('P')?.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => in_time = true,
+            _ => {
+                let amount: i64 = number.parse().ok()?;
+                number.clear();
+                // Out of range amounts are invalid rather than a panic
+                let part = match (c, in_time) {
+                    ('W', false) => TimeDelta::try_weeks(amount),
+                    ('D', false) => TimeDelta::try_days(amount),
+                    ('H', true) => TimeDelta::try_hours(amount),
+                    ('M', true) => TimeDelta::try_minutes(amount),
+                    ('S', true) => TimeDelta::try_seconds(amount),
+                    _ => return None,
+                };
+                duration = duration.checked_add(&part?)?;
+            }
+        }
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    duration.checked_mul(sign)
+}
+
+fn parse_recurrence(value: &str) -> Option<Recurrence> {
+    let mut recurrence = Recurrence {
+        frequency: Frequency::Daily,
+        interval: 1,
+        count: None,
+        until: None,
+        by_day: Vec::new(),
+        by_month_day: Vec::new(),
+        week_start: Weekday::Mon,
+    };
+    let mut frequency = None;
+    for part in value.split(';') {
+        let (key, value) = part.split_once('=')?;
+        match key.to_uppercase().as_str() {
+            "FREQ" => {
+                frequency = Some(match value {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    "YEARLY" => Frequency::Yearly,
+                    _ => return None,
+                })
+            }
+            "INTERVAL" => recurrence.interval = value.parse().ok().filter(|i| *i > 0)?,
+            "COUNT" => recurrence.count = Some(value.parse().ok()?),
+            "UNTIL" => recurrence.until = parse_time(&[], value)?.to_utc(),
+            "BYDAY" => {
+                for day in value.split(',') {
+                    // The weekday is the last two characters, which may not be ASCII
+                    let (split, _) = day.char_indices().rev().nth(1)?;
+                    let position = match day.get(..split)? {
+                        "" => None,
+                        position => Some(position.trim_start_matches('+').parse().ok()?),
+                    };
+                    recurrence
+                        .by_day
+                        .push((position, parse_weekday(day.get(split..)?)?));
+                }
+            }
+            "BYMONTHDAY" => {
+                for day in value.split(',') {
+                    recurrence.by_month_day.push(day.parse().ok()?);
+                }
+            }
+            "WKST" => recurrence.week_start = parse_weekday(value)?,
+            _ => return None,
+        }
+    }
+    recurrence.frequency = frequency?;
+    Some(recurrence)
+}
+
+fn parse_weekday(day: &str) -> Option<Weekday> {
+    Some(match day {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+fn unescape(value: &str) -> String {
+    let mut text = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, true) => match chars.next() {
+                Some('n') | Some('N') => text.push('\n'),
+                Some(other) => text.push(other),
+                None => {}
+            },
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
+fn invalid_ics(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc()
+    }
+
+    fn starts(content: &str, from: &str, to: &str) -> Vec<String> {
+        occurrences(&parse_ics(content).unwrap(), utc(from), utc(to))
+            .iter()
+            .map(|occurrence| occurrence.start.format("%Y-%m-%d %H:%M").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_weekly_in_time_zone_across_dst() {
+        // Paris leaves summer time on 2026-10-25, the event stays at 09:00 there
+        let ics = "BEGIN:VEVENT\nUID:a\nSUMMARY:Focus\n\
+                   DTSTART;TZID=Europe/Paris:20261019T090000\n\
+                   DTEND;TZID=Europe/Paris:20261019T110000\n\
+                   RRULE:FREQ=WEEKLY;BYDAY=MO,WE;UNTIL=20261031T000000Z\n\
+                   EXDATE;TZID=Europe/Paris:20261021T090000\n\
+                   END:VEVENT\n";
+        assert_eq!(
+            starts(ics, "2026-10-01 00:00", "2026-12-01 00:00"),
+            vec!["2026-10-19 07:00", "2026-10-26 08:00", "2026-10-28 08:00"]
+        );
+        let occurrence = &occurrences(
+            &parse_ics(ics).unwrap(),
+            utc("2026-10-19 00:00"),
+            utc("2026-10-20 00:00"),
+        )[0];
+        assert_eq!(occurrence.end - occurrence.start, TimeDelta::hours(2));
+    }
+
+    #[test]
+    fn test_monthly_and_count() {
+        let ics = "BEGIN:VEVENT\nUID:a\nDTSTART:20261030T150000Z\nDURATION:PT1H\n\
+                   RRULE:FREQ=MONTHLY;BYDAY=-1FR;COUNT=3\nEND:VEVENT\n\
+                   BEGIN:VEVENT\nUID:b\nDTSTART:20261031T080000Z\nDURATION:PT30M\n\
+                   RRULE:FREQ=MONTHLY;BYMONTHDAY=31;INTERVAL=2\nEND:VEVENT\n";
+        assert_eq!(
+            starts(ics, "2026-10-01 00:00", "2027-04-01 00:00"),
+            vec![
+                "2026-10-30 15:00",
+                "2026-10-31 08:00",
+                "2026-11-27 15:00",
+                "2026-12-25 15:00",
+                "2026-12-31 08:00"
+            ],
+            "February has no 31st"
+        );
+    }
+
+    #[test]
+    fn test_old_recurrences_start_near_from() {
+        let ics = "BEGIN:VEVENT\nUID:a\nDTSTART:19900101T090000Z\nDURATION:PT1H\n\
+                   RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TU\nEND:VEVENT\n\
+                   BEGIN:VEVENT\nUID:b\nDTSTART:19900101T220000Z\nDURATION:PT36H\n\
+                   RRULE:FREQ=DAILY;INTERVAL=3\nEND:VEVENT\n";
+        assert_eq!(
+            starts(ics, "2026-10-20 00:00", "2026-10-28 00:00"),
+            vec![
+                "2026-10-19 22:00",
+                "2026-10-20 09:00",
+                "2026-10-22 22:00",
+                "2026-10-25 22:00"
+            ],
+            "The daily event started the day before still runs"
+        );
+    }
+
+    #[test]
+    fn test_week_start() {
+        // The example of RFC 5545, the weeks starting on Sunday pair other days
+        let ics = |week_start: &str| {
+            format!(
+                "BEGIN:VEVENT\nUID:a\nDTSTART:19970805T090000Z\n\
+                 RRULE:FREQ=WEEKLY;INTERVAL=2;COUNT=4;BYDAY=TU,SU;WKST={}\nEND:VEVENT\n",
+                week_start
+            )
+        };
+        assert_eq!(
+            starts(&ics("MO"), "1997-08-01 00:00", "1997-09-30 00:00"),
+            vec![
+                "1997-08-05 09:00",
+                "1997-08-10 09:00",
+                "1997-08-19 09:00",
+                "1997-08-24 09:00"
+            ]
+        );
+        assert_eq!(
+            starts(&ics("SU"), "1997-08-01 00:00", "1997-09-30 00:00"),
+            vec![
+                "1997-08-05 09:00",
+                "1997-08-17 09:00",
+                "1997-08-19 09:00",
+                "1997-08-31 09:00"
+            ]
+        );
+        assert!(parse_recurrence("FREQ=WEEKLY;WKST=XX").is_none());
+    }
+
+    #[test]
+    fn test_out_of_range_recurrences_and_durations() {
+        let ics = "BEGIN:VEVENT\nUID:a\nDTSTART:20261019T090000Z\nDURATION:PT1H\n\
+                   RRULE:FREQ=WEEKLY;INTERVAL=4294967295;BYDAY=MO\nEND:VEVENT\n\
+                   BEGIN:VEVENT\nUID:b\nDTSTART:20261020T090000Z\nDURATION:PT1H\n\
+                   RRULE:FREQ=YEARLY;INTERVAL=4294967295\nEND:VEVENT\n\
+                   BEGIN:VEVENT\nUID:c\nDTSTART:20261021T090000Z\nDURATION:PT1H\n\
+                   RRULE:FREQ=MONTHLY;INTERVAL=4294967295\nEND:VEVENT\n\
+                   BEGIN:VEVENT\nUID:d\nDTSTART:20261022T090000Z\nDURATION:P15000000W\n\
+                   END:VEVENT\n";
+        assert_eq!(
+            starts(ics, "2026-10-01 00:00", "2026-11-01 00:00"),
+            vec!["2026-10-19 09:00", "2026-10-20 09:00", "2026-10-21 09:00"],
+            "The periods and the end past the representable dates are skipped"
+        );
+    }
+
+    #[test]
+    fn test_overrides_and_cancelled_events() {
+        let ics = "BEGIN:VEVENT\nUID:a\nSUMMARY:Focus\nDTSTART:20261019T090000Z\n\
+                   DTEND:20261019T100000Z\nRRULE:FREQ=DAILY;INTERVAL=2\nEND:VEVENT\n\
+                   BEGIN:VEVENT\nUID:a\nSUMMARY:Focus later\n\
+                   RECURRENCE-ID:20261021T090000Z\nDTSTART:20261021T140000Z\n\
+                   DTEND:20261021T150000Z\nEND:VEVENT\n\
+                   BEGIN:VEVENT\nUID:b\nSTATUS:CANCELLED\nDTSTART:20261019T120000Z\n\
+                   END:VEVENT\n";
+        assert_eq!(
+            starts(ics, "2026-10-19 00:00", "2026-10-24 00:00"),
+            vec!["2026-10-19 09:00", "2026-10-21 14:00", "2026-10-23 09:00"]
+        );
+    }
+
+    #[test]
+    fn test_parse_ics() {
+        let events = parse_ics(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:a\r\nSUMMARY:Focus\\; coding\r\n\
+             DESCRIPTION:Line one\\nline\r\n  two\r\nTRANSP:TRANSPARENT\r\n\
+             DTSTART;TZID=\"Mars/Olympus\":20261019T090000\r\nEND:VEVENT\r\n\
+             BEGIN:VEVENT\r\nDTSTART;VALUE=DATE:20261019\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].summary, "Focus; coding");
+        assert_eq!(events[0].description, "Line one\nline two");
+        assert!(!events[0].busy);
+        assert!(
+            matches!(events[0].start, IcsTime::Floating(_)),
+            "Unknown zones are read as local time"
+        );
+        assert!(events[1].is_all_day());
+        assert_eq!(events[1].length(), TimeDelta::days(1));
+
+        for invalid in [
+            "BEGIN:VEVENT\nDTSTART:tomorrow\nEND:VEVENT",
+            "BEGIN:VEVENT\nUID:a\nEND:VEVENT",
+            "BEGIN:VEVENT\nDURATION:1H\nEND:VEVENT",
+            "no colon",
+        ] {
+            let error = parse_ics(invalid).expect_err(invalid);
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_parse_recurrence_by_day() {
+        let recurrence = parse_recurrence("FREQ=MONTHLY;BYDAY=-1FR,+2MO,TU").unwrap();
+        assert_eq!(
+            recurrence.by_day,
+            vec![
+                (Some(-1), Weekday::Fri),
+                (Some(2), Weekday::Mon),
+                (None, Weekday::Tue)
+            ]
+        );
+        for invalid in ["BYDAY=M", "BYDAY=€", "BYDAY=1€", "BYDAY=éMO", "BYDAY=xFR"] {
+            assert!(
+                parse_recurrence(&format!("FREQ=WEEKLY;{}", invalid)).is_none(),
+                "{} should be rejected",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_alarms_and_unsupported_rules() {
+        let events = parse_ics(
+            "BEGIN:VEVENT\nUID:a\nSUMMARY:Focus\nDESCRIPTION:coding\n\
+             DTSTART:20261019T090000Z\nDURATION:PT2H\n\
+             BEGIN:VALARM\nACTION:DISPLAY\nDESCRIPTION:Reminder\nDURATION:PT5M\n\
+             TRIGGER:-PT15M\nEND:VALARM\nEND:VEVENT\n\
+             BEGIN:VEVENT\nUID:b\nSUMMARY:Birthday\nDTSTART:20261019T120000Z\n\
+             RRULE:FREQ=YEARLY;BYMONTH=10;BYMONTHDAY=19\nEND:VEVENT\n\
+             BEGIN:VEVENT\nUID:c\nDTSTART:20261019T150000Z\nRRULE:FREQ=HOURLY\nEND:VEVENT\n",
+        )
+        .unwrap();
+
+        assert_eq!(events.len(), 1, "The unsupported rules are skipped");
+        assert_eq!(events[0].description, "coding");
+        assert_eq!(events[0].length(), TimeDelta::hours(2));
+    }
+
+    #[test]
+    fn test_event_without_start_is_rejected() {
+        let error = parse_ics("BEGIN:VEVENT\nUID:a\nRRULE:FREQ=WEEKLY\nEND:VEVENT\n")
+            .expect_err("Should be rejected");
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("PT1H30M"), Some(TimeDelta::minutes(90)));
+        assert_eq!(parse_duration("P1W2D"), Some(TimeDelta::days(9)));
+        assert_eq!(parse_duration("-PT15M"), Some(TimeDelta::minutes(-15)));
+        assert_eq!(parse_duration("PT5"), None);
+        assert_eq!(parse_duration("P1H"), None);
+        assert_eq!(parse_duration("P99999999999999W"), None, "Out of range");
+        assert_eq!(parse_duration("PT9223372036854775807S"), None);
+    }
+}
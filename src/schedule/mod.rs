@@ -0,0 +1,4 @@
+pub mod calendar;
+pub mod config;
+pub mod ics;
+pub mod plan;
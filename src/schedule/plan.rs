@@ -0,0 +1,441 @@
+use std::time::SystemTime;
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Weekday};
+
+use crate::schedule::calendar::Calendar;
+
+/// Splits a scheduled range into focus blocks separated by breaks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pomodoro {
+    pub focus: TimeDelta,
+    pub pause: TimeDelta,
+}
+
+/// A recurring range of time during which websites are blocked automatically.
+///
+/// A range ending before it starts, e.g. `22:00` to `02:00`, ends the next day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub name: String,
+    pub days: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    /// What to block, as given on the command line, e.g. `["coding", "--strict"]`
+    pub arguments: Vec<String>,
+    pub pomodoro: Option<Pomodoro>,
+    /// The days the schedule is skipped, e.g. holidays
+    pub exceptions: Vec<NaiveDate>,
+}
+
+/// A single scheduled session, in local time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub schedule: String,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub arguments: Vec<String>,
+}
+
+impl Schedule {
+    /// Returns the blocks of the range starting on `date`.
+    ///
+    /// There are none on the other days of the week and on the exceptions, a
+    /// single one without Pomodoro settings.
+    pub fn blocks_on(&self, date: NaiveDate) -> Vec<Block> {
+        if !self.days.contains(&date.weekday()) || self.exceptions.contains(&date) {
+            return Vec::new();
+        }
+        let start = date.and_time(self.start);
+        let mut end = date.and_time(self.end);
+        if end <= start {
+            end += TimeDelta::days(1);
+        }
+        let Some(pomodoro) = self.pomodoro else {
+            return vec![self.block(start, end)];
+        };
+        let mut blocks = Vec::new();
+        let mut focus_start = start;
+        while focus_start < end {
+            let focus_end = (focus_start + pomodoro.focus).min(end);
+            blocks.push(self.block(focus_start, focus_end));
+            focus_start = focus_end + pomodoro.pause;
+        }
+        blocks
+    }
+
+    fn block(&self, start: NaiveDateTime, end: NaiveDateTime) -> Block {
+        Block {
+            schedule: self.name.clone(),
+            start,
+            end,
+            arguments: self.arguments.clone(),
+        }
+    }
+}
+
+/// Returns the blocks which are not over at `now`, by start time.
+///
+/// # Arguments
+///
+/// * `schedules` - The schedules of the configuration
+/// * `now` - The current local time
+/// * `days` - How many days after today to look at
+pub fn upcoming_blocks(schedules: &[Schedule], now: NaiveDateTime, days: u32) -> Vec<Block> {
+    // A range past midnight started the day before
+    let first = now.date().pred_opt().unwrap_or(now.date());
+    let mut blocks: Vec<Block> = first
+        .iter_days()
+        .take(days as usize + 2)
+        .flat_map(|date| schedules.iter().flat_map(move |s| s.blocks_on(date)))
+        .filter(|block| block.end > now)
+        .collect();
+    blocks.sort_by_key(|block| block.start);
+    blocks
+}
+
+/// Returns the block running at `now`, if any.
+pub fn active_block(schedules: &[Schedule], now: NaiveDateTime) -> Option<Block> {
+    upcoming_blocks(schedules, now, 0)
+        .into_iter()
+        .find(|block| block.start <= now)
+}
+
+/// The blocks of the schedules and of the focus events of the calendars.
+///
+/// The meetings of the calendars cut the blocks overlapping them, a block resumes
+/// after the meeting.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Scheduler {
+    pub schedules: Vec<Schedule>,
+    pub calendars: Vec<Calendar>,
+}
+
+impl Scheduler {
+    pub fn new(schedules: Vec<Schedule>, calendars: Vec<Calendar>) -> Scheduler {
+        Scheduler {
+            schedules,
+            calendars,
+        }
+    }
+
+    /// Reads the calendars which changed again, printing the ones which can't be read.
+    pub fn refresh(&mut self) {
+        for calendar in &mut self.calendars {
+            if let Some(error) = calendar.reload() {
+                println!("Failed to read the calendar: {}", error);
+            }
+        }
+    }
+
+    /// Returns the blocks which are not over at `now`, by start time.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current local time
+    /// * `days` - How many days after today to look at
+    pub fn upcoming(&self, now: NaiveDateTime, days: u32) -> Vec<Block> {
+        let mut blocks = upcoming_blocks(&self.schedules, now, days);
+        let to = (now.date() + TimeDelta::days(days as i64 + 1)).and_time(NaiveTime::MIN);
+        blocks.extend(
+            self.calendars
+                .iter()
+                .flat_map(|calendar| calendar.focus_blocks(now, to)),
+        );
+        // The blocks running at `now` are cut by the meetings which ended already
+        let from = blocks.iter().map(|block| block.start).min().unwrap_or(now);
+        let meetings = self.meetings(from, to);
+        let mut blocks: Vec<Block> = blocks
+            .into_iter()
+            .flat_map(|block| cut(block, &meetings))
+            .filter(|block| block.end > now)
+            .collect();
+        blocks.sort_by_key(|block| block.start);
+        blocks
+    }
+
+    /// Returns the block running at `now`, if any.
+    pub fn active(&self, now: NaiveDateTime) -> Option<Block> {
+        self.upcoming(now, 0)
+            .into_iter()
+            .find(|block| block.start <= now)
+    }
+
+    /// Returns the meeting running at `now`, if any.
+    pub fn meeting(&self, now: NaiveDateTime) -> Option<Block> {
+        self.meetings(now, now + TimeDelta::seconds(1))
+            .into_iter()
+            .find(|meeting| meeting.start <= now)
+    }
+
+    fn meetings(&self, from: NaiveDateTime, to: NaiveDateTime) -> Vec<Block> {
+        self.calendars
+            .iter()
+            .flat_map(|calendar| calendar.meetings(from, to))
+            .collect()
+    }
+}
+
+/// Removes the ranges of `meetings` from `block`.
+fn cut(block: Block, meetings: &[Block]) -> Vec<Block> {
+    let mut pieces = vec![block];
+    for meeting in meetings {
+        pieces = pieces
+            .into_iter()
+            .flat_map(|piece| {
+                if meeting.end <= piece.start || meeting.start >= piece.end {
+                    return vec![piece];
+                }
+                let before = Block {
+                    end: meeting.start,
+                    ..piece.clone()
+                };
+                let after = Block {
+                    start: meeting.end,
+                    ..piece
+                };
+                [before, after]
+                    .into_iter()
+                    .filter(|piece| piece.start < piece.end)
+                    .collect()
+            })
+            .collect();
+    }
+    pieces
+}
+
+/// Converts a time to the local time the schedules are written in.
+pub fn local_time(time: SystemTime) -> NaiveDateTime {
+    DateTime::<Local>::from(time).naive_local()
+}
+
+/// Converts a local time back, `None` for a time skipped by a DST change.
+pub fn system_time(local: NaiveDateTime) -> Option<SystemTime> {
+    local
+        .and_local_timezone(Local)
+        .earliest()
+        .map(SystemTime::from)
+}
+
+/// Formats a block for `schedule next`, e.g. `Mon 2026-10-19 09:00-12:00 mornings: coding`.
+pub fn format_block(block: &Block) -> String {
+    let next_day = if block.end.date() > block.start.date() {
+        " (next day)"
+    } else {
+        ""
+    };
+    format!(
+        "{}-{}{} {}: {}",
+        block.start.format("%a %Y-%m-%d %H:%M"),
+        block.end.format("%H:%M"),
+        next_day,
+        block.schedule,
+        block.arguments.join(" ")
+    )
+}
+
+/// Formats a schedule for `schedule list`.
+pub fn format_schedule(schedule: &Schedule) -> String {
+    let days: Vec<String> = schedule.days.iter().map(|day| day.to_string()).collect();
+    let mut line = format!(
+        "{}: {} {}-{}, blocking {}",
+        schedule.name,
+        days.join(", "),
+        schedule.start.format("%H:%M"),
+        schedule.end.format("%H:%M"),
+        schedule.arguments.join(" ")
+    );
+    if let Some(pomodoro) = schedule.pomodoro {
+        line.push_str(&format!(
+            ", {} minutes of focus then {} of break",
+            pomodoro.focus.num_minutes(),
+            pomodoro.pause.num_minutes()
+        ));
+    }
+    if !schedule.exceptions.is_empty() {
+        let dates: Vec<String> = schedule
+            .exceptions
+            .iter()
+            .map(|date| date.to_string())
+            .collect();
+        line.push_str(&format!(", except on {}", dates.join(", ")));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::calendar::CalendarSource;
+
+    fn at(date: &str, time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn mornings() -> Schedule {
+        Schedule {
+            name: "mornings".to_string(),
+            days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ],
+            start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            arguments: vec!["coding".to_string()],
+            pomodoro: None,
+            exceptions: vec![NaiveDate::from_ymd_opt(2026, 10, 21).unwrap()],
+        }
+    }
+
+    #[test]
+    fn test_blocks_on_weekdays_only() {
+        let schedule = mornings();
+        // 2026-10-19 is a Monday
+        let monday = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+
+        let blocks = schedule.blocks_on(monday);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].start, at("2026-10-19", "09:00"));
+        assert_eq!(blocks[0].end, at("2026-10-19", "12:00"));
+        assert!(schedule.blocks_on(monday + TimeDelta::days(5)).is_empty());
+        assert!(
+            schedule.blocks_on(monday + TimeDelta::days(2)).is_empty(),
+            "The exception should be skipped"
+        );
+    }
+
+    #[test]
+    fn test_blocks_on_with_pomodoro() {
+        let mut schedule = mornings();
+        schedule.end = NaiveTime::from_hms_opt(10, 50, 0).unwrap();
+        schedule.pomodoro = Some(Pomodoro {
+            focus: TimeDelta::minutes(25),
+            pause: TimeDelta::minutes(5),
+        });
+
+        let blocks = schedule.blocks_on(NaiveDate::from_ymd_opt(2026, 10, 19).unwrap());
+
+        let ranges: Vec<String> = blocks
+            .iter()
+            .map(|block| format!("{}-{}", block.start.time(), block.end.time()))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                "09:00:00-09:25:00",
+                "09:30:00-09:55:00",
+                "10:00:00-10:25:00",
+                "10:30:00-10:50:00"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_upcoming_blocks_past_midnight() {
+        let mut schedule = mornings();
+        schedule.name = "evenings".to_string();
+        schedule.start = NaiveTime::from_hms_opt(22, 0, 0).unwrap();
+        schedule.end = NaiveTime::from_hms_opt(2, 0, 0).unwrap();
+
+        // Tuesday 01:00, inside the range started on Monday evening
+        let now = at("2026-10-20", "01:00");
+        let active = active_block(&[schedule.clone()], now).expect("Should be running");
+        assert_eq!(active.start, at("2026-10-19", "22:00"));
+        assert_eq!(
+            format_block(&active),
+            "Mon 2026-10-19 22:00-02:00 (next day) evenings: coding"
+        );
+
+        let blocks = upcoming_blocks(&[schedule], now, 2);
+        let starts: Vec<NaiveDateTime> = blocks.iter().map(|block| block.start).collect();
+        assert_eq!(
+            starts,
+            vec![
+                at("2026-10-19", "22:00"),
+                at("2026-10-20", "22:00"),
+                at("2026-10-22", "22:00")
+            ],
+            "Wednesday is an exception"
+        );
+    }
+
+    #[test]
+    fn test_active_block() {
+        let schedules = [mornings()];
+        assert!(active_block(&schedules, at("2026-10-19", "08:59")).is_none());
+        assert!(active_block(&schedules, at("2026-10-19", "09:00")).is_some());
+        assert!(active_block(&schedules, at("2026-10-19", "12:00")).is_none());
+    }
+
+    #[test]
+    fn test_scheduler_cuts_blocks_for_meetings() {
+        let calendar = Calendar::from_ics(
+            CalendarSource {
+                path: std::path::PathBuf::new(),
+                focus: "focus".to_string(),
+                arguments: Vec::new(),
+            },
+            "BEGIN:VEVENT\nUID:1\nSUMMARY:Planning\nDTSTART:20261019T100000\n\
+             DTEND:20261019T110000\nEND:VEVENT\n\
+             BEGIN:VEVENT\nUID:2\nSUMMARY:Focus\nDESCRIPTION:netflix\n\
+             DTSTART:20261019T140000\nDURATION:PT1H\nEND:VEVENT\n",
+        );
+        let scheduler = Scheduler::new(vec![mornings()], vec![calendar]);
+
+        let blocks = scheduler.upcoming(at("2026-10-19", "08:00"), 0);
+        let ranges: Vec<String> = blocks
+            .iter()
+            .map(|block| {
+                format!(
+                    "{}-{} {}",
+                    block.start.time(),
+                    block.end.time(),
+                    block.schedule
+                )
+            })
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                "09:00:00-10:00:00 mornings",
+                "11:00:00-12:00:00 mornings",
+                "14:00:00-15:00:00 Focus"
+            ]
+        );
+        let now = at("2026-10-19", "11:30");
+        assert_eq!(
+            scheduler.active(now).unwrap().start,
+            at("2026-10-19", "11:00")
+        );
+        assert!(scheduler.meeting(now).is_none());
+        assert_eq!(
+            scheduler
+                .meeting(at("2026-10-19", "10:30"))
+                .unwrap()
+                .schedule,
+            "Planning"
+        );
+    }
+
+    #[test]
+    fn test_format_schedule() {
+        let mut schedule = mornings();
+        schedule.pomodoro = Some(Pomodoro {
+            focus: TimeDelta::minutes(50),
+            pause: TimeDelta::minutes(10),
+        });
+        assert_eq!(
+            format_schedule(&schedule),
+            "mornings: Mon, Tue, Wed, Thu, Fri 09:00-12:00, blocking coding, \
+             50 minutes of focus then 10 of break, except on 2026-10-21"
+        );
+    }
+
+    #[test]
+    fn test_local_time_round_trip() {
+        let local = at("2026-10-19", "09:30");
+        assert_eq!(local_time(system_time(local).unwrap()), local);
+    }
+}
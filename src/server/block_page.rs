@@ -0,0 +1,341 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use serde_json::json;
+
+use crate::server::ca::{server_config, LocalCa};
+use crate::session::state::{format_remaining, Session};
+
+pub const BLOCK_PAGE_PORT: u16 = 80;
+pub const BLOCK_PAGE_TLS_PORT: u16 = 443;
+pub const STATUS_PATH: &str = "/__focus/status";
+
+const DEFAULT_TEMPLATE: &str = include_str!("block_page.html");
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_REQUEST_HEAD: u64 = 8 * 1024;
+
+/// Serves the "this site is blocked" page for the current session.
+///
+/// Blocked domains resolve to the sink address, so every request reaching it is
+/// answered with a page naming the site, the preset and the remaining time.
+pub struct BlockPage {
+    template: String,
+    session: Arc<Mutex<Session>>,
+}
+
+impl BlockPage {
+    /// Creates the block page using the built-in template.
+    pub fn new(session: Arc<Mutex<Session>>) -> BlockPage {
+        BlockPage::with_template(DEFAULT_TEMPLATE.to_string(), session)
+    }
+
+    /// Creates the block page using a custom template.
+    ///
+    /// The `{{site}}`, `{{preset}}` and `{{remaining}}` placeholders are replaced
+    /// when the page is rendered.
+    pub fn with_template(template: String, session: Arc<Mutex<Session>>) -> BlockPage {
+        BlockPage { template, session }
+    }
+
+    /// Renders the HTML page for a blocked site.
+    ///
+    /// # Arguments
+    ///
+    /// * `site` - The blocked host, as sent by the browser
+    /// * `now` - The current time, used for the remaining time
+    pub fn render(&self, site: &str, now: SystemTime) -> String {
+        let session = self.session.lock().expect("Session lock poisoned");
+        self.template
+            .replace("{{site}}", &escape_html(site))
+            .replace("{{preset}}", &escape_html(&session.preset_label()))
+            .replace("{{remaining}}", &format_remaining(session.remaining(now)))
+    }
+
+    /// Renders the JSON document served on `STATUS_PATH`.
+    pub fn render_status(&self, site: &str, now: SystemTime) -> String {
+        let session = self.session.lock().expect("Session lock poisoned");
+        json!({
+            "site": site,
+            "blocked": session.sites.iter().any(|blocked| blocked == site),
+            "presets": session.presets,
+            "remaining_seconds": session.remaining(now).as_secs(),
+        })
+        .to_string()
+    }
+
+    /// Answers a single HTTP request read from `stream`.
+    pub fn handle<S: Read + Write>(&self, stream: &mut S) -> Result<(), std::io::Error> {
+        let (path, host) = read_request_head(stream)?;
+        let site = host.split(':').next().unwrap_or_default().to_string();
+        let now = SystemTime::now();
+        let (content_type, body) = if path == STATUS_PATH {
+            ("application/json", self.render_status(&site, now))
+        } else {
+            ("text/html; charset=utf-8", self.render(&site, now))
+        };
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+            content_type,
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+
+    /// Answers a single HTTPS request, terminating TLS with `config`.
+    pub fn handle_tls(
+        &self,
+        stream: TcpStream,
+        config: Arc<ServerConfig>,
+    ) -> Result<(), std::io::Error> {
+        let connection = ServerConnection::new(config).map_err(std::io::Error::other)?;
+        let mut tls = StreamOwned::new(connection, stream);
+        self.handle(&mut tls)?;
+        tls.conn.send_close_notify();
+        tls.flush()
+    }
+
+    /// Serves the page on `listener` from a background thread until `done` is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `listener` - The socket to accept connections on
+    /// * `tls` - The TLS configuration, `None` to serve plain HTTP
+    /// * `done` - Set at the end of the session to stop the server
+    pub fn spawn(
+        self: Arc<Self>,
+        listener: TcpListener,
+        tls: Option<Arc<ServerConfig>>,
+        done: Arc<AtomicBool>,
+    ) -> Result<JoinHandle<()>, std::io::Error> {
+        listener.set_nonblocking(true)?;
+        Ok(std::thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((mut stream, _)) => {
+                        let _ = stream.set_nonblocking(false);
+                        let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+                        let result = match &tls {
+                            Some(config) => self.handle_tls(stream, config.clone()),
+                            None => self.handle(&mut stream),
+                        };
+                        if let Err(e) = result {
+                            println!("Block page request failed: {}", e);
+                        }
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(ACCEPT_INTERVAL)
+                    }
+                    Err(e) => println!("Block page connection failed: {}", e),
+                }
+            }
+        }))
+    }
+
+    /// Serves the page on the sink address over HTTP and HTTPS until `done` is set.
+    ///
+    /// HTTPS certificates are issued by the local CA of `state_dir`, which must have
+    /// been trusted with `install-ca` for browsers to accept them. A port already in
+    /// use (e.g. by a local web server) only disables that server, the websites stay
+    /// blocked.
+    ///
+    /// # Returns
+    ///
+    /// The threads of the servers which are listening
+    pub fn serve(
+        self,
+        state_dir: &Path,
+        done: Arc<AtomicBool>,
+    ) -> Result<Vec<JoinHandle<()>>, std::io::Error> {
+        let page = Arc::new(self);
+        let tls = server_config(Arc::new(LocalCa::load_or_create(state_dir)?))?;
+        let mut servers = Vec::new();
+        for (port, tls) in [(BLOCK_PAGE_PORT, None), (BLOCK_PAGE_TLS_PORT, Some(tls))] {
+            match TcpListener::bind((crate::LOCALHOST, port)) {
+                Ok(listener) => servers.push(page.clone().spawn(listener, tls, done.clone())?),
+                Err(e) => println!(
+                    "Block page disabled on port {}, failed to listen on {}: {}",
+                    port,
+                    crate::LOCALHOST,
+                    e
+                ),
+            }
+        }
+        Ok(servers)
+    }
+}
+
+/// Reads the request line and headers, returning the path and the `Host` header.
+fn read_request_head<S: Read>(stream: &mut S) -> Result<(String, String), std::io::Error> {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_HEAD));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("/")
+        .to_string();
+
+    let mut host = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("host") {
+                host = value.trim().to_string();
+            }
+        }
+    }
+    Ok((path, host))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_session() -> Arc<Mutex<Session>> {
+        Arc::new(Mutex::new(Session::new(
+            vec!["coding".to_string()],
+            vec!["www.youtube.com".to_string()],
+            SystemTime::now(),
+            Duration::from_secs(25 * 60),
+        )))
+    }
+
+    // Helper function sending a plain HTTP request to the server
+    fn http_get(port: u16, host: &str, path: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).expect("Failed to connect");
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, host
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    // Helper function starting the server on a random local port
+    fn start_server(
+        page: BlockPage,
+        tls: Option<Arc<ServerConfig>>,
+    ) -> (u16, Arc<AtomicBool>, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+        let port = listener.local_addr().unwrap().port();
+        let done = Arc::new(AtomicBool::new(false));
+        let handle = Arc::new(page)
+            .spawn(listener, tls, done.clone())
+            .expect("Failed to spawn");
+        (port, done, handle)
+    }
+
+    #[test]
+    fn test_render_fills_placeholders() {
+        let session = test_session();
+        let now = session.lock().unwrap().started_at + Duration::from_secs(60);
+        let page =
+            BlockPage::with_template("{{site}}|{{preset}}|{{remaining}}".to_string(), session);
+
+        assert_eq!(
+            page.render("www.youtube.com", now),
+            "www.youtube.com|coding|24m 00s"
+        );
+    }
+
+    #[test]
+    fn test_render_escapes_site() {
+        let page = BlockPage::with_template("{{site}}".to_string(), test_session());
+
+        let rendered = page.render("<script>", SystemTime::now());
+
+        assert_eq!(rendered, "&lt;script&gt;");
+    }
+
+    #[test]
+    fn test_server_serves_block_page() {
+        let (port, done, handle) = start_server(BlockPage::new(test_session()), None);
+
+        let response = http_get(port, "www.youtube.com", "/watch?v=abc");
+        done.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("text/html"));
+        assert!(response.contains("www.youtube.com is blocked"));
+        assert!(response.contains("Preset: coding"));
+    }
+
+    #[test]
+    fn test_server_serves_status_json() {
+        let (port, done, handle) = start_server(BlockPage::new(test_session()), None);
+
+        let response = http_get(port, "www.youtube.com:80", STATUS_PATH);
+        done.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+
+        assert!(response.contains("application/json"));
+        let body = response.split("\r\n\r\n").nth(1).expect("Missing body");
+        let status: serde_json::Value = serde_json::from_str(body).expect("Invalid JSON");
+        assert_eq!(status["site"], "www.youtube.com");
+        assert_eq!(status["blocked"], true);
+        assert_eq!(status["presets"], json!(["coding"]));
+        assert!(status["remaining_seconds"].as_u64().unwrap() <= 25 * 60);
+    }
+
+    #[test]
+    fn test_server_serves_block_page_over_https() {
+        let ca_dir = tempfile::TempDir::new().unwrap();
+        let ca = Arc::new(LocalCa::load_or_create(ca_dir.path()).expect("Failed to create CA"));
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(ca.cert_der().clone()).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let tls = server_config(ca).expect("Failed to build TLS config");
+        let (port, done, handle) = start_server(BlockPage::new(test_session()), Some(tls));
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).expect("Failed to connect");
+        let connection = rustls::ClientConnection::new(
+            Arc::new(client_config),
+            "www.youtube.com".try_into().unwrap(),
+        )
+        .unwrap();
+        let mut tls_stream = StreamOwned::new(connection, stream);
+        write!(
+            tls_stream,
+            "GET / HTTP/1.1\r\nHost: www.youtube.com\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        tls_stream
+            .read_to_string(&mut response)
+            .expect("The certificate should be trusted");
+        done.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("www.youtube.com is blocked"));
+    }
+}
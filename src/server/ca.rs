@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use rcgen::{
+    date_time_ymd, BasicConstraints, CertificateParams, DistinguishedName, DnType, IsCa, Issuer,
+    KeyPair, KeyUsagePurpose,
+};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+
+const CA_NAME: &str = "rust_can_make_you_focus local CA";
+pub const CA_CERT_FILE: &str = "ca.pem";
+const CA_KEY_FILE: &str = "ca.key.pem";
+const CA_VALIDITY_YEARS: i32 = 10;
+const LEAF_VALIDITY_YEARS: i32 = 1;
+const SECONDS_PER_YEAR: u64 = 31_556_952;
+
+// System trust stores
+const DEBIAN_ANCHOR: &str = "/usr/local/share/ca-certificates/rust_can_make_you_focus.crt";
+const REDHAT_ANCHOR: &str = "/etc/pki/ca-trust/source/anchors/rust_can_make_you_focus.pem";
+const MACOS_KEYCHAIN: &str = "/Library/Keychains/System.keychain";
+
+/// The local root CA signing the block page certificates.
+///
+/// It is generated once and kept in the state directory, so it only has to be
+/// trusted once with `install-ca`.
+pub struct LocalCa {
+    key: KeyPair,
+    cert_der: CertificateDer<'static>,
+    cert_path: PathBuf,
+}
+
+impl LocalCa {
+    /// Loads the CA stored in `dir`, generating it the first time.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory holding `ca.pem` and `ca.key.pem`
+    ///
+    /// # Returns
+    ///
+    /// The CA or an I/O error if the files can't be read, written or parsed
+    pub fn load_or_create(dir: &Path) -> Result<LocalCa, std::io::Error> {
+        if let Some(ca) = LocalCa::load(dir)? {
+            return Ok(ca);
+        }
+        let cert_path = dir.join(CA_CERT_FILE);
+        let key_path = dir.join(CA_KEY_FILE);
+        std::fs::create_dir_all(dir)?;
+        // A key without its certificate is useless, a new pair replaces it
+        match std::fs::remove_file(&key_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let key = KeyPair::generate().map_err(to_io_error)?;
+        let cert = ca_params().self_signed(&key).map_err(to_io_error)?;
+        // Private from the start, never readable by other users even briefly
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&key_path)?
+            .write_all(key.serialize_pem().as_bytes())?;
+        std::fs::write(&cert_path, cert.pem())?;
+        Ok(LocalCa {
+            key,
+            cert_der: cert.der().clone(),
+            cert_path,
+        })
+    }
+
+    /// Loads the CA stored in `dir`, without generating it.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory holding `ca.pem` and `ca.key.pem`
+    ///
+    /// # Returns
+    ///
+    /// The CA, `None` if it was never generated, or an I/O error if the files
+    /// can't be read or parsed
+    pub fn load(dir: &Path) -> Result<Option<LocalCa>, std::io::Error> {
+        let cert_path = dir.join(CA_CERT_FILE);
+        let key_path = dir.join(CA_KEY_FILE);
+        if !cert_path.exists() || !key_path.exists() {
+            return Ok(None);
+        }
+        let key = KeyPair::from_pem(&std::fs::read_to_string(&key_path)?).map_err(to_io_error)?;
+        let cert_pem = std::fs::read_to_string(&cert_path)?;
+        let cert_der = pem_to_der(&cert_pem)?;
+        Ok(Some(LocalCa {
+            key,
+            cert_der,
+            cert_path,
+        }))
+    }
+
+    /// Returns the path of the CA certificate, in PEM format.
+    pub fn cert_path(&self) -> &Path {
+        &self.cert_path
+    }
+
+    /// Returns the CA certificate, in DER format.
+    #[cfg(test)]
+    pub fn cert_der(&self) -> &CertificateDer<'static> {
+        &self.cert_der
+    }
+
+    /// Issues a certificate for `domain`, signed by this CA.
+    ///
+    /// # Returns
+    ///
+    /// The certificate chain and its key, ready to be served by rustls
+    pub fn issue(&self, domain: &str) -> Result<CertifiedKey, std::io::Error> {
+        let mut params = CertificateParams::new(vec![domain.to_string()]).map_err(to_io_error)?;
+        params
+            .distinguished_name
+            .push(DnType::CommonName, domain.to_string());
+        let year = current_year();
+        params.not_before = date_time_ymd(year - 1, 1, 1);
+        params.not_after = date_time_ymd(year + LEAF_VALIDITY_YEARS, 1, 1);
+
+        let leaf_key = KeyPair::generate().map_err(to_io_error)?;
+        let issuer = Issuer::new(ca_params(), &self.key);
+        let leaf = params.signed_by(&leaf_key, &issuer).map_err(to_io_error)?;
+
+        let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(leaf_key.serialize_der()));
+        let signing_key =
+            rustls::crypto::ring::sign::any_supported_type(&key_der).map_err(to_io_error)?;
+        Ok(CertifiedKey::new(
+            vec![leaf.der().clone(), self.cert_der.clone()],
+            signing_key,
+        ))
+    }
+}
+
+/// Picks the certificate from the SNI of each TLS connection, issuing it on the fly.
+#[derive(Debug)]
+pub struct SniResolver {
+    ca: Arc<LocalCa>,
+    cache: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl SniResolver {
+    pub fn new(ca: Arc<LocalCa>) -> SniResolver {
+        SniResolver {
+            ca,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let domain = client_hello.server_name()?.to_lowercase();
+        let mut cache = self.cache.lock().ok()?;
+        if let Some(certified) = cache.get(&domain) {
+            return Some(certified.clone());
+        }
+        match self.ca.issue(&domain) {
+            Ok(certified) => {
+                let certified = Arc::new(certified);
+                cache.insert(domain, certified.clone());
+                Some(certified)
+            }
+            Err(e) => {
+                println!("Failed to issue a certificate for {}: {}", domain, e);
+                None
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for LocalCa {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalCa")
+            .field("cert_path", &self.cert_path)
+            .finish()
+    }
+}
+
+/// Builds the rustls configuration serving certificates issued by `ca`.
+pub fn server_config(ca: Arc<LocalCa>) -> Result<Arc<ServerConfig>, std::io::Error> {
+    let mut config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(to_io_error)?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(SniResolver::new(ca)));
+    // Only HTTP/1.1 is spoken, browsers would otherwise assume it without asking
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// A system certificate trust store the CA can be installed into.
+#[derive(Debug, PartialEq)]
+pub enum TrustStore {
+    /// `update-ca-certificates`, used by Debian, Ubuntu and Arch
+    Debian,
+    /// `update-ca-trust`, used by Fedora and RHEL
+    RedHat,
+    /// The macOS system keychain
+    MacOs,
+}
+
+impl TrustStore {
+    /// Detects the trust store of this machine.
+    pub fn detect() -> Option<TrustStore> {
+        if cfg!(target_os = "macos") {
+            Some(TrustStore::MacOs)
+        } else if Path::new(DEBIAN_ANCHOR).parent()?.is_dir() {
+            Some(TrustStore::Debian)
+        } else if Path::new(REDHAT_ANCHOR).parent()?.is_dir() {
+            Some(TrustStore::RedHat)
+        } else {
+            None
+        }
+    }
+
+    /// Returns where the CA certificate is copied, if the store uses anchor files.
+    pub fn anchor_path(&self) -> Option<&'static Path> {
+        match self {
+            TrustStore::Debian => Some(Path::new(DEBIAN_ANCHOR)),
+            TrustStore::RedHat => Some(Path::new(REDHAT_ANCHOR)),
+            TrustStore::MacOs => None,
+        }
+    }
+
+    /// Builds the command registering (or removing) the CA with the system.
+    pub fn refresh_command(&self, cert_path: &Path, install: bool) -> Command {
+        match self {
+            TrustStore::Debian => {
+                let mut command = Command::new("update-ca-certificates");
+                if !install {
+                    command.arg("--fresh");
+                }
+                command
+            }
+            TrustStore::RedHat => {
+                let mut command = Command::new("update-ca-trust");
+                command.arg("extract");
+                command
+            }
+            TrustStore::MacOs => {
+                let mut command = Command::new("security");
+                if install {
+                    command
+                        .args(["add-trusted-cert", "-d", "-r", "trustRoot", "-k"])
+                        .arg(MACOS_KEYCHAIN)
+                        .arg(cert_path);
+                } else {
+                    command.arg("remove-trusted-cert").arg("-d").arg(cert_path);
+                }
+                command
+            }
+        }
+    }
+}
+
+/// Installs the local CA into the system trust store.
+///
+/// # Arguments
+///
+/// * `ca` - The local CA
+///
+/// # Returns
+///
+/// A `Result` indicating success or an I/O error if the store is unknown or the
+/// refresh command fails
+pub fn install_ca(ca: &LocalCa) -> Result<(), std::io::Error> {
+    let store = TrustStore::detect().ok_or_else(unknown_trust_store)?;
+    if let Some(anchor) = store.anchor_path() {
+        std::fs::copy(ca.cert_path(), anchor)?;
+    }
+    run(store.refresh_command(ca.cert_path(), true))
+}
+
+/// Removes the local CA from the system trust store.
+pub fn uninstall_ca(ca: &LocalCa) -> Result<(), std::io::Error> {
+    let store = TrustStore::detect().ok_or_else(unknown_trust_store)?;
+    if let Some(anchor) = store.anchor_path() {
+        if anchor.exists() {
+            std::fs::remove_file(anchor)?;
+        }
+    }
+    run(store.refresh_command(ca.cert_path(), false))
+}
+
+fn run(mut command: Command) -> Result<(), std::io::Error> {
+    let status = command.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "{:?} failed: {}",
+            command, status
+        )))
+    }
+}
+
+fn ca_params() -> CertificateParams {
+    let mut params = CertificateParams::default();
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, CA_NAME);
+    params.distinguished_name = name;
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let year = current_year();
+    params.not_before = date_time_ymd(year - 1, 1, 1);
+    params.not_after = date_time_ymd(year + CA_VALIDITY_YEARS, 1, 1);
+    params
+}
+
+/// Returns the current year, close enough for certificate validity bounds.
+fn current_year() -> i32 {
+    let seconds = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    1970 + (seconds / SECONDS_PER_YEAR) as i32
+}
+
+fn pem_to_der(pem: &str) -> Result<CertificateDer<'static>, std::io::Error> {
+    use rustls::pki_types::pem::PemObject;
+    CertificateDer::from_pem_slice(pem.as_bytes()).map_err(to_io_error)
+}
+
+fn unknown_trust_store() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "no supported certificate trust store found",
+    )
+}
+
+fn to_io_error<E: std::fmt::Display>(error: E) -> std::io::Error {
+    std::io::Error::other(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_load_or_create_generates_once() {
+        let dir = TempDir::new().unwrap();
+
+        let first = LocalCa::load_or_create(dir.path()).expect("Failed to create CA");
+        let second = LocalCa::load_or_create(dir.path()).expect("Failed to load CA");
+
+        assert_eq!(
+            first.cert_der(),
+            second.cert_der(),
+            "The CA should be reused"
+        );
+        let mode = std::fs::metadata(dir.path().join(CA_KEY_FILE))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600, "The CA key should be private");
+    }
+
+    #[test]
+    fn test_load_without_ca() {
+        let dir = TempDir::new().unwrap();
+
+        let ca = LocalCa::load(dir.path()).expect("Failed to look for the CA");
+
+        assert!(ca.is_none());
+        assert!(
+            !dir.path().join(CA_KEY_FILE).exists(),
+            "Loading should never generate a CA"
+        );
+    }
+
+    #[test]
+    fn test_load_or_create_replaces_stale_key() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join(CA_KEY_FILE), "stale").unwrap();
+
+        let created = LocalCa::load_or_create(dir.path()).expect("Failed to create CA");
+        let loaded = LocalCa::load(dir.path()).unwrap().expect("Should be saved");
+
+        assert_eq!(created.cert_der(), loaded.cert_der());
+    }
+
+    #[test]
+    fn test_issue_chains_to_ca() {
+        let dir = TempDir::new().unwrap();
+        let ca = LocalCa::load_or_create(dir.path()).unwrap();
+
+        let certified = ca.issue("www.youtube.com").expect("Failed to issue");
+
+        assert_eq!(certified.cert.len(), 2, "Leaf and CA should be served");
+        assert_eq!(&certified.cert[1], ca.cert_der());
+    }
+
+    #[test]
+    fn test_trust_store_anchor_paths() {
+        assert_eq!(
+            TrustStore::Debian.anchor_path(),
+            Some(Path::new(DEBIAN_ANCHOR))
+        );
+        assert_eq!(
+            TrustStore::RedHat.anchor_path(),
+            Some(Path::new(REDHAT_ANCHOR))
+        );
+        assert_eq!(TrustStore::MacOs.anchor_path(), None);
+    }
+
+    #[test]
+    fn test_trust_store_refresh_command() {
+        let command = TrustStore::Debian.refresh_command(Path::new("/ca.pem"), true);
+        assert_eq!(command.get_program(), "update-ca-certificates");
+
+        let command = TrustStore::MacOs.refresh_command(Path::new("/ca.pem"), true);
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(command.get_program(), "security");
+        assert_eq!(args.first().unwrap().to_str(), Some("add-trusted-cert"));
+        assert_eq!(args.last().unwrap().to_str(), Some("/ca.pem"));
+    }
+}
@@ -0,0 +1,2 @@
+pub mod block_page;
+pub mod ca;
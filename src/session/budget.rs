@@ -0,0 +1,472 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Value};
+
+use crate::session::state::{format_remaining, start_of_local_day, unix_seconds};
+use crate::session::usage::UsageProbe;
+use crate::utils::utils::match_args;
+
+/// Where the daemon saves the budgets used today.
+pub const BUDGET_FILE: &str = "budget.json";
+
+/// The longest time counted between two checks of the usage, e.g. across a suspend.
+const MAX_TRACKED_STEP: Duration = Duration::from_secs(60);
+
+/// A daily time budget, e.g. 30 minutes of YouTube per day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Budget {
+    /// The website or preset, as given on the command line, e.g. `youtube`
+    pub name: String,
+    pub daily: Duration,
+}
+
+impl Budget {
+    /// Returns the websites of the budget.
+    pub fn sites(&self) -> Vec<String> {
+        match_args(std::slice::from_ref(&self.name))
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+}
+
+/// Tracks the budgets used today, saved to a file to survive a restart.
+///
+/// The websites of a budget are reachable until its time of the day is used up,
+/// then blocked until midnight, local time. The time is counted by `track` while
+/// they are used. `allow` checks time out of the budget instead, the websites being
+/// reachable for that time whether they are used or not.
+#[derive(Debug, Default)]
+pub struct Budgets {
+    budgets: Vec<Budget>,
+    file: PathBuf,
+    /// The local midnight starting the day of `used`
+    day: Option<SystemTime>,
+    used: BTreeMap<String, Duration>,
+    allowed_until: BTreeMap<String, SystemTime>,
+    /// When the usage was last tracked
+    tracked: Option<SystemTime>,
+}
+
+impl Budgets {
+    /// Creates the tracker of `budgets`, resuming the usage saved in `file`.
+    ///
+    /// A missing or unreadable file starts from unused budgets.
+    pub fn load(budgets: Vec<Budget>, file: PathBuf) -> Budgets {
+        let saved: Value = std::fs::read_to_string(&file)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        let seconds = |key: &str| -> BTreeMap<String, Duration> {
+            saved[key]
+                .as_object()
+                .map(|entries| {
+                    entries
+                        .iter()
+                        .filter_map(|(name, value)| {
+                            Some((name.clone(), Duration::from_secs(value.as_u64()?)))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        Budgets {
+            budgets,
+            file,
+            day: saved["day"]
+                .as_u64()
+                .map(|day| SystemTime::UNIX_EPOCH + Duration::from_secs(day)),
+            used: seconds("used_seconds"),
+            allowed_until: seconds("allowed_until")
+                .into_iter()
+                .map(|(name, until)| (name, SystemTime::UNIX_EPOCH + until))
+                .collect(),
+            tracked: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.budgets.is_empty()
+    }
+
+    /// Returns the websites to block at `now`, those of the budgets used up, sorted.
+    pub fn blocked(&self, now: SystemTime) -> Vec<String> {
+        let mut sites: Vec<String> = self
+            .budgets
+            .iter()
+            .filter(|budget| self.allowed(budget, now).is_none())
+            .filter(|budget| self.remaining(budget, now) == Duration::ZERO)
+            .flat_map(Budget::sites)
+            .collect();
+        sites.sort();
+        sites.dedup();
+        sites
+    }
+
+    /// Counts the time since the last call against the budgets used meanwhile.
+    ///
+    /// The budgets with time checked out are already counted, and those used up
+    /// are blocked. The usage is saved once a minute and when a budget is used up.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time, called regularly, e.g. every second
+    /// * `probe` - Tells whether the websites of a budget are used
+    pub fn track(
+        &mut self,
+        now: SystemTime,
+        probe: &mut dyn UsageProbe,
+    ) -> Result<(), std::io::Error> {
+        let elapsed = self
+            .tracked
+            .and_then(|tracked| now.duration_since(tracked).ok())
+            .unwrap_or_default()
+            .min(MAX_TRACKED_STEP);
+        self.tracked = Some(now);
+        if elapsed.is_zero() {
+            return Ok(());
+        }
+        let mut changed = false;
+        for budget in self.budgets.clone() {
+            let remaining = self.remaining(&budget, now);
+            if remaining == Duration::ZERO
+                || self.allowed(&budget, now).is_some()
+                || !probe.in_use(&budget.sites())
+            {
+                continue;
+            }
+            self.start_day(now);
+            let used = self.used.entry(budget.name.clone()).or_default();
+            let before = used.as_secs() / 60;
+            *used += elapsed.min(remaining);
+            changed |= used.as_secs() / 60 != before || elapsed >= remaining;
+        }
+        if changed {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Returns how much of the budget of `budget` is left today.
+    pub fn remaining(&self, budget: &Budget, now: SystemTime) -> Duration {
+        if self.day != Some(start_of_local_day(now)) {
+            return budget.daily;
+        }
+        let used = self.used.get(&budget.name).copied().unwrap_or_default();
+        budget.daily.saturating_sub(used)
+    }
+
+    /// Allows the websites of a budget for `duration`, or what is left of it today.
+    ///
+    /// # Arguments
+    ///
+    /// * `arguments` - The budget, by name or by one of its websites, e.g. `youtube`
+    /// * `duration` - How long to allow it
+    /// * `now` - The current time
+    ///
+    /// # Returns
+    ///
+    /// The message replied to the client, as an error if nothing was allowed
+    pub fn allow(
+        &mut self,
+        arguments: &[String],
+        duration: Duration,
+        now: SystemTime,
+    ) -> Result<String, String> {
+        let sites = match_args(arguments);
+        let Some(budget) = self
+            .budgets
+            .iter()
+            .find(|budget| {
+                arguments
+                    .iter()
+                    .any(|argument| argument.eq_ignore_ascii_case(&budget.name))
+                    || budget
+                        .sites()
+                        .iter()
+                        .any(|site| sites.contains(&site.as_str()))
+            })
+            .cloned()
+        else {
+            return Err(format!("no budget for {}", arguments.join(" ")));
+        };
+        let remaining = self.remaining(&budget, now);
+        if remaining == Duration::ZERO {
+            return Err(format!(
+                "the {} budget is used up, it is blocked until midnight",
+                budget.name
+            ));
+        }
+        let allowed = duration.min(remaining);
+        self.start_day(now);
+        *self.used.entry(budget.name.clone()).or_default() += allowed;
+        let from = self.allowed(&budget, now).unwrap_or(now);
+        self.allowed_until
+            .insert(budget.name.clone(), from + allowed);
+        self.save().map_err(|e| e.to_string())?;
+        Ok(format!(
+            "{} allowed for {}, {} left today",
+            budget.name,
+            format_remaining(allowed),
+            format_remaining(remaining - allowed)
+        ))
+    }
+
+    /// Builds the status of the budgets, as added to the reply to `STATUS`.
+    pub fn status_json(&self, now: SystemTime) -> Value {
+        self.budgets
+            .iter()
+            .map(|budget| {
+                json!({
+                    "name": budget.name,
+                    "sites": budget.sites(),
+                    "daily_seconds": budget.daily.as_secs(),
+                    "remaining_seconds": self.remaining(budget, now).as_secs(),
+                    "allowed_until": self.allowed(budget, now).map(unix_seconds),
+                })
+            })
+            .collect()
+    }
+
+    /// Starts counting the usage of the day of `now` afresh, on a new day.
+    fn start_day(&mut self, now: SystemTime) {
+        let today = start_of_local_day(now);
+        if self.day != Some(today) {
+            self.day = Some(today);
+            self.used.clear();
+        }
+    }
+
+    /// Returns until when the websites of `budget` are allowed, if they are at `now`.
+    fn allowed(&self, budget: &Budget, now: SystemTime) -> Option<SystemTime> {
+        self.allowed_until
+            .get(&budget.name)
+            .copied()
+            .filter(|until| *until > now)
+    }
+
+    fn save(&self) -> Result<(), std::io::Error> {
+        let seconds = |time: &SystemTime| unix_seconds(*time);
+        let saved = json!({
+            "day": self.day.as_ref().map(seconds),
+            "used_seconds": self
+                .used
+                .iter()
+                .map(|(name, used)| (name.clone(), json!(used.as_secs())))
+                .collect::<serde_json::Map<String, Value>>(),
+            "allowed_until": self
+                .allowed_until
+                .iter()
+                .map(|(name, until)| (name.clone(), json!(seconds(until))))
+                .collect::<serde_json::Map<String, Value>>(),
+        });
+        if let Some(dir) = self.file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.file, saved.to_string())
+    }
+}
+
+/// Formats the budgets of a status built by `status_json` for humans, a line each.
+pub fn format_budgets(status: &Value) -> Vec<String> {
+    let Some(budgets) = status["budgets"].as_array() else {
+        return Vec::new();
+    };
+    budgets
+        .iter()
+        .map(|budget| {
+            let seconds = |key: &str| Duration::from_secs(budget[key].as_u64().unwrap_or(0));
+            let mut line = format!(
+                "Budget {}: {} of {} left today",
+                budget["name"].as_str().unwrap_or_default(),
+                format_remaining(seconds("remaining_seconds")),
+                format_remaining(seconds("daily_seconds"))
+            );
+            match budget["allowed_until"].as_u64() {
+                Some(until) => {
+                    let now = status["now"].as_u64().unwrap_or(until);
+                    line.push_str(&format!(
+                        ", allowed for {}",
+                        format_remaining(Duration::from_secs(until.saturating_sub(now)))
+                    ));
+                }
+                None if seconds("remaining_seconds") == Duration::ZERO => {
+                    line.push_str(", blocked until midnight")
+                }
+                None => {}
+            }
+            line
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn youtube() -> Budget {
+        Budget {
+            name: "youtube".to_string(),
+            daily: Duration::from_secs(30 * 60),
+        }
+    }
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn test_allow_takes_from_the_budget() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut budgets = Budgets::load(vec![youtube()], temp_dir.path().join(BUDGET_FILE));
+        let now = start_of_local_day(SystemTime::now()) + minutes(600);
+        let sites = youtube().sites();
+        assert!(budgets.blocked(now).is_empty(), "Reachable until used up");
+
+        assert_eq!(
+            budgets.allow(&["YouTube".to_string()], minutes(10), now),
+            Ok("youtube allowed for 10m 00s, 20m 00s left today".to_string())
+        );
+        assert!(budgets.blocked(now).is_empty());
+        assert!(budgets.blocked(now + minutes(10)).is_empty());
+
+        // The check-outs add up, even while one is running
+        let reply = budgets.allow(&["youtube".to_string()], minutes(60), now + minutes(5));
+        assert_eq!(
+            reply,
+            Ok("youtube allowed for 20m 00s, 0m 00s left today".to_string())
+        );
+        assert!(budgets.blocked(now + minutes(29)).is_empty());
+        assert_eq!(budgets.blocked(now + minutes(30)), sites);
+        assert!(budgets
+            .allow(&["youtube".to_string()], minutes(1), now + minutes(40))
+            .unwrap_err()
+            .contains("used up"));
+        assert!(budgets
+            .allow(&["netflix".to_string()], minutes(1), now)
+            .is_err());
+
+        let tomorrow = start_of_local_day(now) + Duration::from_secs(36 * 3600);
+        assert_eq!(budgets.remaining(&youtube(), tomorrow), minutes(30));
+    }
+
+    /// Tells the websites are used while `in_use` is set.
+    struct FakeProbe {
+        in_use: bool,
+        asked: Vec<Vec<String>>,
+    }
+
+    impl UsageProbe for FakeProbe {
+        fn in_use(&mut self, sites: &[String]) -> bool {
+            self.asked.push(sites.to_vec());
+            self.in_use
+        }
+    }
+
+    #[test]
+    fn test_track_counts_the_time_used() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join(BUDGET_FILE);
+        let mut budgets = Budgets::load(vec![youtube()], file.clone());
+        let mut probe = FakeProbe {
+            in_use: false,
+            asked: Vec::new(),
+        };
+        let now = start_of_local_day(SystemTime::now()) + minutes(600);
+
+        budgets.track(now, &mut probe).unwrap();
+        budgets.track(now + minutes(10), &mut probe).unwrap();
+        assert_eq!(budgets.remaining(&youtube(), now), minutes(30));
+        assert_eq!(probe.asked, vec![youtube().sites()]);
+
+        probe.in_use = true;
+        // A suspended machine counts a minute at most
+        budgets.track(now + minutes(20), &mut probe).unwrap();
+        for minute in 21..=48 {
+            budgets.track(now + minutes(minute), &mut probe).unwrap();
+        }
+        assert_eq!(budgets.remaining(&youtube(), now), minutes(1));
+        assert!(budgets.blocked(now + minutes(48)).is_empty());
+        assert_eq!(
+            Budgets::load(vec![youtube()], file.clone()).remaining(&youtube(), now),
+            minutes(1),
+            "The usage should be saved"
+        );
+
+        // Checked out time is counted once
+        budgets
+            .allow(&["youtube".to_string()], minutes(1), now + minutes(48))
+            .unwrap();
+        budgets.track(now + minutes(49), &mut probe).unwrap();
+        assert_eq!(budgets.blocked(now + minutes(49)), youtube().sites());
+        let asked = probe.asked.len();
+        budgets.track(now + minutes(50), &mut probe).unwrap();
+        assert_eq!(probe.asked.len(), asked, "A used up budget isn't probed");
+    }
+
+    #[test]
+    fn test_blocked_sites_are_sorted_and_unique() {
+        let temp_dir = TempDir::new().unwrap();
+        let budget = |name: &str| Budget {
+            name: name.to_string(),
+            daily: Duration::ZERO,
+        };
+        let budgets = Budgets::load(
+            vec![budget("youtube"), budget("netflix"), budget("youtube")],
+            temp_dir.path().join(BUDGET_FILE),
+        );
+
+        let blocked = budgets.blocked(SystemTime::now());
+
+        let mut expected = [youtube().sites(), budget("netflix").sites()].concat();
+        expected.sort();
+        assert_eq!(blocked, expected);
+    }
+
+    #[test]
+    fn test_load_resumes_saved_usage() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("state").join(BUDGET_FILE);
+        let now = start_of_local_day(SystemTime::now()) + minutes(600);
+        let mut budgets = Budgets::load(vec![youtube()], file.clone());
+        budgets
+            .allow(&["youtube".to_string()], minutes(10), now)
+            .unwrap();
+
+        let budgets = Budgets::load(vec![youtube()], file.clone());
+        assert_eq!(budgets.remaining(&youtube(), now), minutes(20));
+        assert!(budgets.blocked(now + minutes(5)).is_empty());
+
+        std::fs::write(&file, "not json").unwrap();
+        let budgets = Budgets::load(vec![youtube()], file);
+        assert_eq!(budgets.remaining(&youtube(), now), minutes(30));
+    }
+
+    #[test]
+    fn test_format_budgets() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut budgets = Budgets::load(vec![youtube()], temp_dir.path().join(BUDGET_FILE));
+        let now = start_of_local_day(SystemTime::now()) + minutes(600);
+        let status = |budgets: &Budgets, now: SystemTime| json!({ "now": unix_seconds(now), "budgets": budgets.status_json(now) });
+        assert_eq!(
+            format_budgets(&status(&budgets, now)),
+            vec!["Budget youtube: 30m 00s of 30m 00s left today"]
+        );
+
+        budgets
+            .allow(&["youtube".to_string()], minutes(30), now)
+            .unwrap();
+        assert_eq!(
+            format_budgets(&status(&budgets, now + minutes(5))),
+            vec!["Budget youtube: 0m 00s of 30m 00s left today, allowed for 25m 00s"]
+        );
+        assert_eq!(
+            format_budgets(&status(&budgets, now + minutes(30))),
+            vec!["Budget youtube: 0m 00s of 30m 00s left today, blocked until midnight"]
+        );
+        assert!(format_budgets(&json!({ "active": false })).is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+use std::time::{Duration, SystemTime};
+
+/// How long to sleep at most before looking at the wall clock again.
+///
+/// `std::thread::sleep` follows a monotonic clock which stops while the machine is
+/// suspended, a single long sleep would make the session outlast its deadline by
+/// the length of the suspend.
+pub const WAKE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The source of time of a session, replaced in tests.
+pub trait Clock {
+    /// The current wall clock time
+    fn now(&self) -> SystemTime;
+    /// Blocks the current thread for `duration`
+    fn sleep(&self, duration: Duration);
+}
+
+/// The clock of the system.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// Waits until nothing is left of a session, on the wall clock.
+///
+/// The time left is asked again at every wake up, so a deadline moved meanwhile
+/// is followed, and the wait ends at most `WAKE_INTERVAL` after the deadline even
+/// if the machine was suspended or hibernated in between.
+///
+/// # Arguments
+///
+/// * `clock` - The clock to read and sleep on
+/// * `remaining` - The time left at a given time, zero once over
+pub fn wait_until_over(clock: &dyn Clock, remaining: impl Fn(SystemTime) -> Duration) {
+    loop {
+        let left = remaining(clock.now());
+        if left.is_zero() {
+            return;
+        }
+        clock.sleep(left.min(WAKE_INTERVAL));
+    }
+}
+
+/// A clock only moving when slept on, which can simulate a suspend.
+#[cfg(test)]
+pub struct ManualClock {
+    now: std::sync::Mutex<SystemTime>,
+    sleeps: std::sync::Mutex<Vec<Duration>>,
+    suspend: Option<(usize, Duration)>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(now: SystemTime) -> ManualClock {
+        ManualClock {
+            now: std::sync::Mutex::new(now),
+            sleeps: std::sync::Mutex::new(Vec::new()),
+            suspend: None,
+        }
+    }
+
+    /// Jumps `duration` ahead during the `nth` sleep, like a suspended laptop.
+    pub fn suspend_during(mut self, nth: usize, duration: Duration) -> ManualClock {
+        self.suspend = Some((nth, duration));
+        self
+    }
+
+    /// The durations slept so far.
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.sleeps.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let mut sleeps = self.sleeps.lock().unwrap();
+        let mut now = self.now.lock().unwrap();
+        *now += duration;
+        if let Some((nth, suspended)) = self.suspend {
+            if sleeps.len() == nth {
+                *now += suspended;
+            }
+        }
+        sleeps.push(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remaining_until(deadline: SystemTime) -> impl Fn(SystemTime) -> Duration {
+        move |now| deadline.duration_since(now).unwrap_or(Duration::ZERO)
+    }
+
+    #[test]
+    fn test_wait_wakes_up_periodically() {
+        let start = SystemTime::UNIX_EPOCH;
+        let clock = ManualClock::new(start);
+
+        wait_until_over(&clock, remaining_until(start + Duration::from_secs(12)));
+
+        assert_eq!(
+            clock.sleeps(),
+            vec![WAKE_INTERVAL, WAKE_INTERVAL, Duration::from_secs(2)]
+        );
+        assert_eq!(clock.now(), start + Duration::from_secs(12));
+    }
+
+    #[test]
+    fn test_wait_ends_after_suspend() {
+        let start = SystemTime::UNIX_EPOCH;
+        let deadline = start + Duration::from_secs(25 * 60);
+        // Suspended for an hour during the second sleep
+        let clock = ManualClock::new(start).suspend_during(1, Duration::from_secs(3600));
+
+        wait_until_over(&clock, remaining_until(deadline));
+
+        assert_eq!(clock.sleeps().len(), 2, "Should end on the first wake up");
+        assert!(clock.now() >= deadline);
+    }
+
+    #[test]
+    fn test_wait_follows_moved_deadline() {
+        let start = SystemTime::UNIX_EPOCH;
+        let clock = ManualClock::new(start);
+        let deadline = std::sync::Mutex::new(start + Duration::from_secs(60));
+
+        wait_until_over(&clock, |now| {
+            // Extended by a minute on the first wake up
+            let mut deadline = deadline.lock().unwrap();
+            if now == start + WAKE_INTERVAL {
+                *deadline += Duration::from_secs(60);
+            }
+            deadline.duration_since(now).unwrap_or(Duration::ZERO)
+        });
+
+        assert_eq!(clock.now(), start + Duration::from_secs(120));
+    }
+
+    #[test]
+    fn test_wait_without_time_left() {
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH);
+        wait_until_over(&clock, |_| Duration::ZERO);
+        assert!(clock.sleeps().is_empty());
+    }
+}
@@ -0,0 +1,558 @@
+use std::io::Read;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Value};
+
+use crate::session::clock::WAKE_INTERVAL;
+use crate::session::history::{
+    History, CHALLENGE_FAILED, CHALLENGE_PASSED, UNLOCK, UNLOCK_CANCELLED, UNLOCK_REQUESTED,
+};
+use crate::session::state::format_remaining;
+use crate::session::strict::{random_bytes, spawn_line_reader};
+use crate::utils::utils::flag_values;
+
+pub const COOLDOWN_FLAG: &str = "--cooldown";
+pub const CHALLENGE_FLAG: &str = "--challenge";
+pub const DEFAULT_CHALLENGE_WORDS: usize = 20;
+
+const MAX_CHALLENGE_WORDS: usize = 200;
+/// The longest cooldown, a day: longer ones never end before the session does.
+const MAX_COOLDOWN_MINUTES: u64 = 24 * 60;
+
+// 64 words, so that a random byte maps without bias
+const WORDS: [&str; 64] = [
+    "amber", "anchor", "autumn", "basket", "beacon", "bicycle", "blossom", "bridge", "candle",
+    "canyon", "carpet", "castle", "cedar", "copper", "cotton", "crystal", "desert", "dolphin",
+    "engine", "feather", "forest", "garden", "glacier", "granite", "harbor", "helmet", "island",
+    "jacket", "kettle", "ladder", "lantern", "lemon", "marble", "meadow", "mirror", "needle",
+    "orchard", "paddle", "pepper", "pillow", "planet", "pocket", "quarry", "rabbit", "ribbon",
+    "saddle", "silver", "spider", "summit", "tablet", "thunder", "timber", "tunnel", "velvet",
+    "violin", "walnut", "window", "winter", "yellow", "zipper", "oyster", "puzzle", "river",
+    "market",
+];
+
+/// How hard it is to stop a session before its deadline.
+///
+/// A softer alternative to strict sessions: stopping early is possible, but only
+/// after typing a random passage exactly and/or waiting for a cooldown, which can
+/// be cancelled anytime.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Friction {
+    /// How long to wait between asking to stop and the session ending
+    pub cooldown: Duration,
+    /// How many words to type before the cooldown starts, 0 for none
+    pub challenge_words: usize,
+}
+
+impl Friction {
+    /// Reads the friction from `--cooldown=MINUTES` and `--challenge[=WORDS]`.
+    ///
+    /// # Returns
+    ///
+    /// `None` without these flags, or an `InvalidInput` error if a value is not a
+    /// number, or a cooldown longer than a day
+    pub fn from_flags(flags: &[String]) -> Result<Option<Friction>, std::io::Error> {
+        let invalid = |flag: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} expects a positive number", flag),
+            )
+        };
+        let cooldown = match flag_values(flags, COOLDOWN_FLAG).last() {
+            Some(minutes) => match minutes.parse::<u64>() {
+                Ok(minutes) if minutes > 0 && minutes <= MAX_COOLDOWN_MINUTES => {
+                    Duration::from_secs(minutes * 60)
+                }
+                Ok(minutes) if minutes > 0 => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "{} is at most {} minutes",
+                            COOLDOWN_FLAG, MAX_COOLDOWN_MINUTES
+                        ),
+                    ))
+                }
+                _ => return Err(invalid(COOLDOWN_FLAG)),
+            },
+            None => Duration::ZERO,
+        };
+        let challenge_words = match flag_values(flags, CHALLENGE_FLAG).last() {
+            Some(words) => match words.parse::<usize>() {
+                Ok(words) if words > 0 => words.min(MAX_CHALLENGE_WORDS),
+                _ => return Err(invalid(CHALLENGE_FLAG)),
+            },
+            None if flags.iter().any(|flag| flag == CHALLENGE_FLAG) => DEFAULT_CHALLENGE_WORDS,
+            None => 0,
+        };
+        if cooldown.is_zero() && challenge_words == 0 {
+            return Ok(None);
+        }
+        Ok(Some(Friction {
+            cooldown,
+            challenge_words,
+        }))
+    }
+
+    pub fn to_json(self) -> Value {
+        json!({
+            "cooldown_seconds": self.cooldown.as_secs(),
+            "challenge_words": self.challenge_words,
+        })
+    }
+
+    /// Deserializes a friction written by `to_json`, `None` if it is malformed.
+    pub fn from_json(value: &Value) -> Option<Friction> {
+        Some(Friction {
+            cooldown: Duration::from_secs(value["cooldown_seconds"].as_u64()?),
+            challenge_words: value["challenge_words"].as_u64()? as usize,
+        })
+    }
+}
+
+/// Where an early stop stands after a step of `EarlyUnlock`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// The passage to type exactly
+    Challenge(String),
+    /// The session ends once the cooldown is over, unless cancelled
+    Waiting(Duration),
+    /// The session can end now
+    Unlocked,
+}
+
+impl Step {
+    /// Describes the step to the user, `stop`, `answer` and `cancel` being the
+    /// commands to go on.
+    pub fn message(&self) -> String {
+        match self {
+            Step::Challenge(passage) => format!("type this passage exactly to stop: {}", passage),
+            Step::Waiting(left) => format!(
+                "stopping in {}, cancel to keep the session",
+                format_remaining(*left)
+            ),
+            Step::Unlocked => "stopped".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    Idle,
+    Challenge(String),
+    Waiting(SystemTime),
+}
+
+/// Walks an early stop request through the friction of a session.
+///
+/// Every attempt and its outcome is recorded in the session history, to see how
+/// often the friction actually keeps the session going.
+#[derive(Debug, Clone)]
+pub struct EarlyUnlock {
+    friction: Friction,
+    state: State,
+}
+
+impl EarlyUnlock {
+    pub fn new(friction: Friction) -> EarlyUnlock {
+        EarlyUnlock {
+            friction,
+            state: State::Idle,
+        }
+    }
+
+    /// Asks to stop the session: shows the challenge, or starts the cooldown.
+    ///
+    /// Asking again shows the pending challenge or the time left.
+    pub fn request(&mut self, now: SystemTime, history: &History) -> Result<Step, std::io::Error> {
+        match &self.state {
+            State::Challenge(passage) => Ok(Step::Challenge(passage.clone())),
+            State::Waiting(until) => Ok(Step::Waiting(
+                until.duration_since(now).unwrap_or(Duration::ZERO),
+            )),
+            State::Idle => {
+                history.record_or_warn(now, UNLOCK_REQUESTED, "early stop requested");
+                if self.friction.challenge_words == 0 {
+                    return Ok(self.start_cooldown(now, history));
+                }
+                let passage = generate_passage(self.friction.challenge_words)?;
+                self.state = State::Challenge(passage.clone());
+                Ok(Step::Challenge(passage))
+            }
+        }
+    }
+
+    /// Returns the passage waiting to be typed, if any.
+    pub fn pending_challenge(&self) -> Option<&str> {
+        match &self.state {
+            State::Challenge(passage) => Some(passage),
+            _ => None,
+        }
+    }
+
+    /// Checks the passage typed for the pending challenge.
+    ///
+    /// # Returns
+    ///
+    /// The next step, or `None` if the passage doesn't match, the request being
+    /// dropped then
+    pub fn answer(&mut self, text: &str, now: SystemTime, history: &History) -> Option<Step> {
+        let passage = self.pending_challenge()?;
+        if text.trim() != passage {
+            history.record_or_warn(now, CHALLENGE_FAILED, "typed passage didn't match");
+            self.state = State::Idle;
+            return None;
+        }
+        history.record_or_warn(now, CHALLENGE_PASSED, "passage typed exactly");
+        Some(self.start_cooldown(now, history))
+    }
+
+    /// Drops the pending request, the session goes on.
+    ///
+    /// # Returns
+    ///
+    /// Whether there was a request to cancel
+    pub fn cancel(&mut self, now: SystemTime, history: &History) -> bool {
+        if self.state == State::Idle {
+            return false;
+        }
+        self.state = State::Idle;
+        history.record_or_warn(now, UNLOCK_CANCELLED, "early stop cancelled");
+        true
+    }
+
+    /// Returns when the cooldown ends, if it is running.
+    pub fn waiting_until(&self) -> Option<SystemTime> {
+        match self.state {
+            State::Waiting(until) => Some(until),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the cooldown is over, and the session can end.
+    pub fn poll(&mut self, now: SystemTime, history: &History) -> bool {
+        match self.state {
+            State::Waiting(until) if until <= now => {
+                self.state = State::Idle;
+                history.record_or_warn(now, UNLOCK, "stopped early after the cooldown");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn start_cooldown(&mut self, now: SystemTime, history: &History) -> Step {
+        if self.friction.cooldown.is_zero() {
+            self.state = State::Idle;
+            history.record_or_warn(now, UNLOCK, "stopped early after the challenge");
+            return Step::Unlocked;
+        }
+        // A cooldown read from a tampered state file may not fit in a time
+        let cooldown = self
+            .friction
+            .cooldown
+            .min(Duration::from_secs(MAX_COOLDOWN_MINUTES * 60));
+        let until = now.checked_add(cooldown).unwrap_or(now);
+        self.state = State::Waiting(until);
+        Step::Waiting(until.duration_since(now).unwrap_or(Duration::ZERO))
+    }
+}
+
+/// Waits for the deadline of a session with friction, reading the commands typed.
+///
+/// `stop` asks to end the session early, the next line answers the challenge, and
+/// `cancel` drops the request during the cooldown.
+///
+/// # Returns
+///
+/// Whether the session was stopped before its deadline
+pub fn wait_with_friction<R: Read + Send + 'static>(
+    input: R,
+    deadline: SystemTime,
+    unlock: &mut EarlyUnlock,
+    history: &History,
+) -> bool {
+    let received = spawn_line_reader(input);
+    loop {
+        let now = SystemTime::now();
+        if unlock.poll(now, history) {
+            return true;
+        }
+        let wake_up = unlock
+            .waiting_until()
+            .map_or(deadline, |until| until.min(deadline));
+        let timeout = wake_up.duration_since(now).unwrap_or(Duration::ZERO);
+        if timeout.is_zero() && wake_up == deadline {
+            return false;
+        }
+        // Woken up regularly, the timeout doesn't run while the machine is suspended
+        let timeout = timeout.min(WAKE_INTERVAL);
+        let line = match received.recv_timeout(timeout) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => continue,
+            // No more input, the cooldown or the deadline ends the session
+            Err(RecvTimeoutError::Disconnected) => {
+                std::thread::sleep(timeout);
+                continue;
+            }
+        };
+        let now = SystemTime::now();
+        let step = if unlock.pending_challenge().is_some() {
+            match unlock.answer(&line, now, history) {
+                Some(step) => step,
+                None => {
+                    println!("The passage doesn't match, the session goes on");
+                    continue;
+                }
+            }
+        } else {
+            match line.trim() {
+                "stop" => match unlock.request(now, history) {
+                    Ok(step) => step,
+                    Err(e) => {
+                        println!("Failed to ask to stop the session: {}", e);
+                        continue;
+                    }
+                },
+                "cancel" => {
+                    if unlock.cancel(now, history) {
+                        println!("Cancelled, the session goes on");
+                    }
+                    continue;
+                }
+                _ => continue,
+            }
+        };
+        if step == Step::Unlocked {
+            return true;
+        }
+        println!("{}", step.message());
+    }
+}
+
+/// Generates a passage of `words` random words separated by spaces.
+fn generate_passage(words: usize) -> Result<String, std::io::Error> {
+    Ok(random_bytes(words)?
+        .iter()
+        .map(|byte| WORDS[(*byte % 64) as usize])
+        .collect::<Vec<&str>>()
+        .join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::history::HISTORY_FILE;
+    use tempfile::TempDir;
+
+    fn flags(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(|flag| flag.to_string()).collect()
+    }
+
+    fn setup_history() -> (TempDir, History) {
+        let temp_dir = TempDir::new().unwrap();
+        let history = History::new(temp_dir.path().join(HISTORY_FILE));
+        (temp_dir, history)
+    }
+
+    fn event_names(history: &History) -> Vec<String> {
+        history
+            .events()
+            .unwrap()
+            .iter()
+            .map(|event| event["event"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_from_flags() {
+        assert_eq!(Friction::from_flags(&flags(&["--proxy"])).unwrap(), None);
+        assert_eq!(
+            Friction::from_flags(&flags(&["--cooldown=10", "--challenge"])).unwrap(),
+            Some(Friction {
+                cooldown: Duration::from_secs(600),
+                challenge_words: DEFAULT_CHALLENGE_WORDS,
+            })
+        );
+        assert_eq!(
+            Friction::from_flags(&flags(&["--challenge=5"])).unwrap(),
+            Some(Friction {
+                cooldown: Duration::ZERO,
+                challenge_words: 5,
+            })
+        );
+        for invalid in [
+            "--cooldown=0",
+            "--cooldown=soon",
+            "--cooldown=1441",
+            "--cooldown=200000000000000000",
+            "--challenge=-1",
+        ] {
+            assert!(
+                Friction::from_flags(&flags(&[invalid])).is_err(),
+                "{} should be rejected",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let friction = Friction {
+            cooldown: Duration::from_secs(300),
+            challenge_words: 12,
+        };
+        assert_eq!(Friction::from_json(&friction.to_json()), Some(friction));
+        assert_eq!(Friction::from_json(&Value::Null), None);
+    }
+
+    #[test]
+    fn test_generate_passage() {
+        let passage = generate_passage(12).unwrap();
+        let words: Vec<&str> = passage.split(' ').collect();
+        assert_eq!(words.len(), 12);
+        assert!(words.iter().all(|word| WORDS.contains(word)));
+    }
+
+    #[test]
+    fn test_challenge_then_cooldown() {
+        let (_temp_dir, history) = setup_history();
+        let now = SystemTime::UNIX_EPOCH;
+        let mut unlock = EarlyUnlock::new(Friction {
+            cooldown: Duration::from_secs(600),
+            challenge_words: 3,
+        });
+
+        assert!(
+            matches!(unlock.request(now, &history).unwrap(), Step::Challenge(_)),
+            "Should ask for the passage first"
+        );
+        assert_eq!(unlock.answer("wrong", now, &history), None);
+        assert_eq!(unlock.pending_challenge(), None, "A failed answer drops it");
+
+        let Step::Challenge(passage) = unlock.request(now, &history).unwrap() else {
+            panic!("Should ask for a new passage");
+        };
+        assert_eq!(
+            unlock.answer(&passage, now, &history),
+            Some(Step::Waiting(Duration::from_secs(600)))
+        );
+        assert!(!unlock.poll(now + Duration::from_secs(599), &history));
+        assert!(unlock.poll(now + Duration::from_secs(600), &history));
+
+        assert_eq!(
+            event_names(&history),
+            vec![
+                UNLOCK_REQUESTED,
+                CHALLENGE_FAILED,
+                UNLOCK_REQUESTED,
+                CHALLENGE_PASSED,
+                UNLOCK
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cancel_cooldown() {
+        let (_temp_dir, history) = setup_history();
+        let now = SystemTime::UNIX_EPOCH;
+        let mut unlock = EarlyUnlock::new(Friction {
+            cooldown: Duration::from_secs(60),
+            challenge_words: 0,
+        });
+
+        assert!(!unlock.cancel(now, &history), "Nothing to cancel");
+        unlock.request(now, &history).unwrap();
+        assert_eq!(
+            unlock
+                .request(now + Duration::from_secs(20), &history)
+                .unwrap(),
+            Step::Waiting(Duration::from_secs(40))
+        );
+        assert!(unlock.cancel(now, &history));
+        assert!(!unlock.poll(now + Duration::from_secs(3600), &history));
+        assert_eq!(
+            event_names(&history),
+            vec![UNLOCK_REQUESTED, UNLOCK_CANCELLED]
+        );
+    }
+
+    #[test]
+    fn test_huge_cooldown_does_not_overflow() {
+        let (_temp_dir, history) = setup_history();
+        let now = SystemTime::UNIX_EPOCH;
+        let mut unlock = EarlyUnlock::new(Friction {
+            cooldown: Duration::from_secs(200000000000000000 * 60),
+            challenge_words: 0,
+        });
+
+        assert_eq!(
+            unlock.request(now, &history).unwrap(),
+            Step::Waiting(Duration::from_secs(MAX_COOLDOWN_MINUTES * 60))
+        );
+        assert!(unlock.poll(
+            now + Duration::from_secs(MAX_COOLDOWN_MINUTES * 60),
+            &history
+        ));
+    }
+
+    #[test]
+    fn test_challenge_without_cooldown_unlocks() {
+        let (_temp_dir, history) = setup_history();
+        let now = SystemTime::UNIX_EPOCH;
+        let mut unlock = EarlyUnlock::new(Friction {
+            cooldown: Duration::ZERO,
+            challenge_words: 2,
+        });
+        let Step::Challenge(passage) = unlock.request(now, &history).unwrap() else {
+            panic!("Should ask for the passage");
+        };
+        assert_eq!(unlock.answer(&passage, now, &history), Some(Step::Unlocked));
+    }
+
+    #[test]
+    fn test_wait_with_friction_stops_after_cooldown() {
+        let (_temp_dir, history) = setup_history();
+        let mut unlock = EarlyUnlock::new(Friction {
+            cooldown: Duration::from_secs(1),
+            challenge_words: 0,
+        });
+        let deadline = SystemTime::now() + Duration::from_secs(60);
+
+        let stopped = wait_with_friction(
+            std::io::Cursor::new("stop\n"),
+            deadline,
+            &mut unlock,
+            &history,
+        );
+
+        assert!(stopped, "The session should stop after the cooldown");
+        assert!(SystemTime::now() < deadline);
+        assert_eq!(event_names(&history), vec![UNLOCK_REQUESTED, UNLOCK]);
+    }
+
+    #[test]
+    fn test_wait_with_friction_without_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let not_a_dir = temp_dir.path().join("not_a_dir");
+        std::fs::write(&not_a_dir, "").unwrap();
+        let history = History::new(not_a_dir.join(HISTORY_FILE));
+        let mut unlock = EarlyUnlock::new(Friction {
+            cooldown: Duration::ZERO,
+            challenge_words: 2,
+        });
+        let deadline = SystemTime::now() + Duration::from_secs(60);
+        let Step::Challenge(passage) = unlock.request(SystemTime::now(), &history).unwrap() else {
+            panic!("Should ask for the passage");
+        };
+
+        let stopped = wait_with_friction(
+            std::io::Cursor::new(format!("{}\n", passage)),
+            deadline,
+            &mut unlock,
+            &history,
+        );
+
+        assert!(stopped, "A history that can't be written shouldn't matter");
+    }
+}
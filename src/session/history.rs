@@ -0,0 +1,173 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use serde_json::json;
+
+use crate::session::state::unix_seconds;
+
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+// Events
+pub const TAMPER: &str = "tamper";
+pub const STOP_REFUSED: &str = "stop_refused";
+pub const UNLOCK: &str = "unlock";
+pub const UNLOCK_FAILED: &str = "unlock_failed";
+pub const UNLOCK_REQUESTED: &str = "unlock_requested";
+pub const UNLOCK_CANCELLED: &str = "unlock_cancelled";
+pub const CHALLENGE_PASSED: &str = "challenge_passed";
+pub const CHALLENGE_FAILED: &str = "challenge_failed";
+pub const PAUSE: &str = "pause";
+pub const RESUME: &str = "resume";
+pub const PEEK: &str = "peek";
+
+const USER_HISTORY_DIR: &str = "rust_can_make_you_focus";
+
+/// Returns where the foreground sessions record their history.
+///
+/// Root records it in the state directory, next to the history of the daemon.
+/// Other users record it in their own state directory (`$XDG_STATE_HOME`,
+/// `~/.local/state` by default), which they can always write to.
+pub fn history_file(root: bool) -> PathBuf {
+    if root {
+        return PathBuf::from(crate::STATE_DIR).join(HISTORY_FILE);
+    }
+    std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join(USER_HISTORY_DIR)
+        .join(HISTORY_FILE)
+}
+
+/// The session history: one JSON object per line, appended as things happen.
+///
+/// Each event has a `time` (seconds since the Unix epoch), an `event` name and a
+/// free form `detail`.
+#[derive(Debug, Clone)]
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new(path: PathBuf) -> History {
+        History { path }
+    }
+
+    /// Appends an event to the history, creating the file if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - When the event happened
+    /// * `event` - The event name, e.g. `TAMPER`
+    /// * `detail` - What happened, for humans
+    pub fn record(&self, now: SystemTime, event: &str, detail: &str) -> Result<(), std::io::Error> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let line = json!({ "time": unix_seconds(now), "event": event, "detail": detail });
+        writeln!(file, "{}", line)
+    }
+
+    /// Appends an event like `record`, only warning when it can't be written.
+    ///
+    /// Used while a session runs, a history that can't be written mustn't end it.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - When the event happened
+    /// * `event` - The event name, e.g. `UNLOCK`
+    /// * `detail` - What happened, for humans
+    pub fn record_or_warn(&self, now: SystemTime, event: &str, detail: &str) {
+        if let Err(e) = self.record(now, event, detail) {
+            println!(
+                "Failed to record {} in {}: {}",
+                event,
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// Reads back every event, skipping malformed lines.
+    pub fn events(&self) -> Result<Vec<serde_json::Value>, std::io::Error> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// Counts the `event`s recorded since `since`, e.g. today's pauses.
+    pub fn count_since(&self, event: &str, since: SystemTime) -> Result<usize, std::io::Error> {
+        let since = unix_seconds(since);
+        Ok(self
+            .events()?
+            .iter()
+            .filter(|entry| entry["event"] == event)
+            .filter(|entry| entry["time"].as_u64().is_some_and(|time| time >= since))
+            .count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_record_appends_events() {
+        let temp_dir = TempDir::new().unwrap();
+        let history = History::new(temp_dir.path().join("state").join(HISTORY_FILE));
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(42);
+
+        history
+            .record(now, TAMPER, "first")
+            .expect("Failed to record");
+        history
+            .record(now, TAMPER, "second")
+            .expect("Failed to record");
+
+        let events = history.events().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0],
+            json!({ "time": 42, "event": TAMPER, "detail": "first" })
+        );
+        assert_eq!(events[1]["detail"], "second");
+    }
+
+    #[test]
+    fn test_count_since() {
+        let temp_dir = TempDir::new().unwrap();
+        let history = History::new(temp_dir.path().join(HISTORY_FILE));
+        let day = SystemTime::UNIX_EPOCH + Duration::from_secs(86_400);
+        history
+            .record(day - Duration::from_secs(1), PAUSE, "yesterday")
+            .unwrap();
+        history.record(day, PAUSE, "today").unwrap();
+        history.record(day, RESUME, "today").unwrap();
+        history
+            .record(day + Duration::from_secs(60), PAUSE, "today")
+            .unwrap();
+
+        assert_eq!(history.count_since(PAUSE, day).unwrap(), 2);
+        assert_eq!(history.count_since(TAMPER, day).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_events_without_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let history = History::new(temp_dir.path().join(HISTORY_FILE));
+        assert!(history.events().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,9 @@
+pub mod budget;
+pub mod clock;
+pub mod friction;
+pub mod history;
+pub mod recovery;
+pub mod state;
+pub mod status;
+pub mod strict;
+pub mod usage;
@@ -0,0 +1,136 @@
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use serde_json::Value;
+
+use crate::browser::policy::{remove_browser_policies, PolicyDirs};
+use crate::hosts::file_edit::without_managed_block;
+use crate::hosts::store::HostsStore;
+use crate::session::state::Session;
+
+/// Lifts the blocks left behind by a session which ended while the machine was off.
+///
+/// Run at boot before the daemon. A session saved by the daemon which is still
+/// running is kept, the daemon applies it again. Otherwise the managed block of the
+/// hosts file and the browser policies are removed, with the saved sessions.
+///
+/// # Arguments
+///
+/// * `store` - The hosts file
+/// * `policy_dirs` - Where the browser policies were written
+/// * `state_file` - The session saved by the daemon
+/// * `foreground_file` - The session saved by a foreground run, which can't outlive a reboot
+/// * `now` - The current time
+///
+/// # Returns
+///
+/// Whether stale hosts entries were removed, `false` when a running session was kept
+pub fn recover(
+    store: &dyn HostsStore,
+    policy_dirs: &PolicyDirs,
+    state_file: &Path,
+    foreground_file: &Path,
+    now: SystemTime,
+) -> Result<bool, std::io::Error> {
+    let running = std::fs::read_to_string(state_file)
+        .ok()
+        .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+        .and_then(|value| Session::from_json(&value))
+        .is_some_and(|session| session.remaining(now) > Duration::ZERO);
+    if running {
+        return Ok(false);
+    }
+    for file in [state_file, foreground_file] {
+        match std::fs::remove_file(file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    remove_browser_policies(policy_dirs)?;
+    let content = match store.read() {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let cleaned = without_managed_block(&content);
+    if cleaned == content {
+        return Ok(false);
+    }
+    store.write(&cleaned)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hosts::file_edit::with_managed_block;
+    use crate::hosts::store::MemoryHostsStore;
+    use tempfile::TempDir;
+
+    fn setup() -> (TempDir, MemoryHostsStore, PolicyDirs) {
+        let temp_dir = TempDir::new().unwrap();
+        let store = MemoryHostsStore::new(&with_managed_block(
+            "127.0.0.1 localhost\n",
+            &["netflix.com"],
+        ));
+        let dirs = PolicyDirs {
+            chromium: vec![temp_dir.path().join("chromium")],
+            firefox: vec![temp_dir.path().join("firefox")],
+        };
+        (temp_dir, store, dirs)
+    }
+
+    fn save_session(path: &Path, minutes: u64) {
+        let session = Session::new(
+            Vec::new(),
+            vec!["netflix.com".to_string()],
+            SystemTime::UNIX_EPOCH,
+            Duration::from_secs(minutes * 60),
+        );
+        std::fs::write(path, session.to_json().to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_recover_lifts_ended_session() {
+        let (temp_dir, store, dirs) = setup();
+        let state_file = temp_dir.path().join("session.json");
+        let foreground_file = temp_dir.path().join("foreground.json");
+        save_session(&state_file, 25);
+        save_session(&foreground_file, 600);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(3600);
+
+        assert!(recover(&store, &dirs, &state_file, &foreground_file, now).unwrap());
+
+        assert_eq!(store.content().unwrap(), "127.0.0.1 localhost\n");
+        assert!(!state_file.exists());
+        assert!(
+            !foreground_file.exists(),
+            "A foreground run can't survive a reboot"
+        );
+        assert!(!recover(&store, &dirs, &state_file, &foreground_file, now).unwrap());
+    }
+
+    #[test]
+    fn test_recover_keeps_running_session() {
+        let (temp_dir, store, dirs) = setup();
+        let state_file = temp_dir.path().join("session.json");
+        save_session(&state_file, 25);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(600);
+        let before = store.content();
+
+        let lifted = recover(&store, &dirs, &state_file, Path::new("missing"), now).unwrap();
+
+        assert!(!lifted);
+        assert_eq!(store.content(), before);
+        assert!(state_file.exists());
+    }
+
+    #[test]
+    fn test_recover_write_error() {
+        let (temp_dir, store, dirs) = setup();
+        store.fail_writes(true);
+        let missing = temp_dir.path().join("session.json");
+
+        assert!(recover(&store, &dirs, &missing, &missing, SystemTime::now()).is_err());
+    }
+}
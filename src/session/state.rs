@@ -0,0 +1,303 @@
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local, Timelike};
+use serde_json::{json, Value};
+
+use crate::session::friction::Friction;
+
+// Backends
+pub const HOSTS_BACKEND: &str = "hosts";
+pub const BROWSER_POLICIES_BACKEND: &str = "browser-policies";
+pub const PROXY_BACKEND: &str = "proxy";
+pub const BLOCK_PAGE_BACKEND: &str = "block-page";
+pub const APPS_BACKEND: &str = "apps";
+
+/// A running focus session: what is blocked and until when.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub presets: Vec<String>,
+    pub sites: Vec<String>,
+    pub started_at: SystemTime,
+    pub deadline: SystemTime,
+    /// The hash of the emergency unlock code of a strict session, see `session::strict`
+    pub unlock_hash: Option<String>,
+    /// What it takes to stop the session early, see `session::friction`
+    pub friction: Option<Friction>,
+    /// The backends enforcing the session, e.g. `HOSTS_BACKEND`
+    pub backends: Vec<String>,
+    /// The parts of the blocked websites kept reachable, as `host/path` patterns
+    pub exceptions: Vec<String>,
+    /// The domains written to the hosts file, websites with exceptions being left out
+    pub hosts: Vec<String>,
+    /// When the session was paused, the timer being frozen until it resumes
+    pub paused_at: Option<SystemTime>,
+}
+
+impl Session {
+    /// Creates a session starting at `now` and lasting `duration`.
+    ///
+    /// # Arguments
+    ///
+    /// * `presets` - The presets given on the command line
+    /// * `sites` - The websites blocked during the session
+    /// * `now` - The start of the session
+    /// * `duration` - How long the websites stay blocked
+    pub fn new(
+        presets: Vec<String>,
+        sites: Vec<String>,
+        now: SystemTime,
+        duration: Duration,
+    ) -> Session {
+        Session {
+            presets,
+            sites,
+            started_at: now,
+            deadline: now + duration,
+            unlock_hash: None,
+            friction: None,
+            backends: Vec::new(),
+            exceptions: Vec::new(),
+            hosts: Vec::new(),
+            paused_at: None,
+        }
+    }
+
+    /// Returns whether the session can only end early with its unlock code.
+    pub fn is_strict(&self) -> bool {
+        self.unlock_hash.is_some()
+    }
+
+    /// Returns the time left before the deadline, zero once it has passed.
+    ///
+    /// The time left doesn't decrease while the session is paused.
+    pub fn remaining(&self, now: SystemTime) -> Duration {
+        let now = self.paused_at.unwrap_or(now);
+        self.deadline.duration_since(now).unwrap_or(Duration::ZERO)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    /// Freezes the timer at `now`.
+    pub fn pause(&mut self, now: SystemTime) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(now);
+        }
+    }
+
+    /// Restarts the timer, pushing the deadline back by the length of the pause.
+    ///
+    /// # Returns
+    ///
+    /// How long the session was paused
+    pub fn resume(&mut self, now: SystemTime) -> Duration {
+        let Some(paused_at) = self.paused_at.take() else {
+            return Duration::ZERO;
+        };
+        let paused = now.duration_since(paused_at).unwrap_or(Duration::ZERO);
+        self.deadline += paused;
+        paused
+    }
+
+    /// Serializes the session, times being stored as seconds since the Unix epoch.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "presets": self.presets,
+            "sites": self.sites,
+            "started_at": unix_seconds(self.started_at),
+            "deadline": unix_seconds(self.deadline),
+            "unlock_hash": self.unlock_hash,
+            "friction": self.friction.map(Friction::to_json),
+            "backends": self.backends,
+            "exceptions": self.exceptions,
+            "hosts": self.hosts,
+            "paused_at": self.paused_at.map(unix_seconds),
+        })
+    }
+
+    /// Deserializes a session written by `to_json`, `None` if it is malformed.
+    pub fn from_json(value: &Value) -> Option<Session> {
+        let strings = |key: &str| -> Option<Vec<String>> {
+            value[key]
+                .as_array()?
+                .iter()
+                .map(|item| item.as_str().map(String::from))
+                .collect()
+        };
+        let time = |key: &str| -> Option<SystemTime> {
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(value[key].as_u64()?))
+        };
+        Some(Session {
+            presets: strings("presets")?,
+            sites: strings("sites")?,
+            started_at: time("started_at")?,
+            deadline: time("deadline")?,
+            unlock_hash: value["unlock_hash"].as_str().map(String::from),
+            friction: Friction::from_json(&value["friction"]),
+            // Missing from sessions saved by older versions
+            backends: strings("backends").unwrap_or_default(),
+            exceptions: strings("exceptions").unwrap_or_default(),
+            hosts: strings("hosts").unwrap_or_default(),
+            paused_at: time("paused_at"),
+        })
+    }
+
+    /// Returns the presets joined for display, `custom` when only sites were given.
+    pub fn preset_label(&self) -> String {
+        if self.presets.is_empty() {
+            "custom".to_string()
+        } else {
+            self.presets.join(", ")
+        }
+    }
+}
+
+/// Returns the whole seconds elapsed since the Unix epoch, zero before it.
+pub fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Returns the last local midnight before `now`, e.g. to count today's events.
+pub fn start_of_local_day(now: SystemTime) -> SystemTime {
+    let since_midnight = DateTime::<Local>::from(now).num_seconds_from_midnight() as u64;
+    SystemTime::UNIX_EPOCH + Duration::from_secs(unix_seconds(now).saturating_sub(since_midnight))
+}
+
+/// Formats a remaining duration as `1h 05m 09s`, hours being omitted when zero.
+///
+/// # Examples
+///
+/// ```
+/// use crate::session::state::format_remaining;
+///
+/// assert_eq!(format_remaining(Duration::from_secs(125)), "2m 05s");
+/// ```
+pub fn format_remaining(remaining: Duration) -> String {
+    let seconds = remaining.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, seconds)
+    } else {
+        format!("{}m {:02}s", minutes, seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_session() -> Session {
+        Session::new(
+            vec!["coding".to_string()],
+            vec!["www.youtube.com".to_string()],
+            SystemTime::UNIX_EPOCH,
+            Duration::from_secs(25 * 60),
+        )
+    }
+
+    #[test]
+    fn test_session_deadline() {
+        let session = test_session();
+        assert_eq!(
+            session.deadline,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1500)
+        );
+    }
+
+    #[test]
+    fn test_session_remaining() {
+        let session = test_session();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(600);
+        assert_eq!(session.remaining(now), Duration::from_secs(900));
+    }
+
+    #[test]
+    fn test_session_remaining_after_deadline() {
+        let session = test_session();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(3600);
+        assert_eq!(session.remaining(now), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_pause_freezes_remaining() {
+        let mut session = test_session();
+        let paused_at = SystemTime::UNIX_EPOCH + Duration::from_secs(600);
+        session.pause(paused_at);
+
+        let later = paused_at + Duration::from_secs(300);
+        assert!(session.is_paused());
+        assert_eq!(session.remaining(later), Duration::from_secs(900));
+        assert_eq!(session.resume(later), Duration::from_secs(300));
+        assert!(!session.is_paused());
+        assert_eq!(session.remaining(later), Duration::from_secs(900));
+        assert_eq!(
+            session.deadline,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1800)
+        );
+    }
+
+    #[test]
+    fn test_start_of_local_day() {
+        let now = SystemTime::now();
+        let midnight = start_of_local_day(now);
+        assert!(midnight <= now);
+        assert!(now.duration_since(midnight).unwrap() < Duration::from_secs(25 * 3600));
+    }
+
+    #[test]
+    fn test_preset_label() {
+        let mut session = test_session();
+        assert_eq!(session.preset_label(), "coding");
+        session.presets.clear();
+        assert_eq!(session.preset_label(), "custom");
+    }
+
+    #[test]
+    fn test_session_json_round_trip() {
+        let mut session = test_session();
+        session.backends = vec![HOSTS_BACKEND.to_string(), PROXY_BACKEND.to_string()];
+        session.exceptions = vec!["www.youtube.com/watch*".to_string()];
+        session.hosts = Vec::new();
+        session.pause(SystemTime::UNIX_EPOCH + Duration::from_secs(60));
+        let restored = Session::from_json(&session.to_json()).expect("Should parse");
+        assert_eq!(restored, session);
+    }
+
+    #[test]
+    fn test_strict_session_json_round_trip() {
+        let mut session = test_session();
+        session.unlock_hash = Some("sha256$salt$digest".to_string());
+        let restored = Session::from_json(&session.to_json()).expect("Should parse");
+        assert!(restored.is_strict());
+        assert_eq!(restored, session);
+    }
+
+    #[test]
+    fn test_friction_json_round_trip() {
+        let mut session = test_session();
+        session.friction = Some(Friction {
+            cooldown: Duration::from_secs(600),
+            challenge_words: 20,
+        });
+        let restored = Session::from_json(&session.to_json()).expect("Should parse");
+        assert_eq!(restored, session);
+    }
+
+    #[test]
+    fn test_session_from_invalid_json() {
+        assert_eq!(Session::from_json(&json!({ "presets": [] })), None);
+        assert_eq!(Session::from_json(&json!("session")), None);
+    }
+
+    #[test]
+    fn test_format_remaining() {
+        assert_eq!(format_remaining(Duration::from_secs(125)), "2m 05s");
+        assert_eq!(
+            format_remaining(Duration::from_secs(3 * 3600 + 9)),
+            "3h 00m 09s"
+        );
+        assert_eq!(format_remaining(Duration::ZERO), "0m 00s");
+    }
+}
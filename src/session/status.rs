@@ -0,0 +1,240 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+
+use crate::hosts::file_edit::is_managed_block_intact;
+use crate::session::budget::format_budgets;
+use crate::session::state::{format_remaining, unix_seconds, Session};
+
+/// Where a foreground session run as root is saved for `status`.
+pub const FOREGROUND_FILE: &str = "foreground.json";
+
+const USER_FOREGROUND_FILE: &str = "rust_can_make_you_focus.json";
+
+/// Returns where the running foreground session is saved.
+///
+/// Root saves it to the state directory, other users to their runtime directory
+/// (or the temporary directory), which they can always write to.
+pub fn foreground_state_file(root: bool) -> PathBuf {
+    if root {
+        return PathBuf::from(crate::STATE_DIR).join(FOREGROUND_FILE);
+    }
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join(USER_FOREGROUND_FILE)
+}
+
+/// Builds the status of a session, as replied to `STATUS` and printed by `status --json`.
+///
+/// # Arguments
+///
+/// * `session` - The running session, `None` when idle
+/// * `now` - The current time, for the remaining time
+/// * `hosts_content` - The content of the hosts file, `None` if it can't be read
+pub fn status_json(
+    session: Option<&Session>,
+    now: SystemTime,
+    hosts_content: Option<&str>,
+) -> Value {
+    let Some(session) = session.filter(|session| session.remaining(now) > Duration::ZERO) else {
+        return json!({ "active": false, "now": unix_seconds(now) });
+    };
+    let mut status = session.to_json();
+    if let Some(status) = status.as_object_mut() {
+        // The hash stays on the machine, only whether there is one is shown
+        status.remove("unlock_hash");
+    }
+    status["active"] = Value::Bool(true);
+    status["strict"] = Value::Bool(session.is_strict());
+    status["remaining_seconds"] = session.remaining(now).as_secs().into();
+    let hosts: Vec<&str> = session.hosts.iter().map(String::as_str).collect();
+    status["hosts_intact"] = match hosts_content {
+        _ if hosts.is_empty() => Value::Null,
+        Some(content) => Value::Bool(is_managed_block_intact(content, &hosts)),
+        None => Value::Bool(false),
+    };
+    status
+}
+
+/// Formats a status built by `status_json` for humans, with the daily budgets of the
+/// daemon.
+pub fn format_status(status: &Value) -> String {
+    if status["active"] != true {
+        let mut lines = vec!["No session is running".to_string()];
+        lines.extend(format_budgets(status));
+        return lines.join("\n");
+    }
+    let strings = |key: &str| -> Vec<&str> {
+        status[key]
+            .as_array()
+            .map(|items| items.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default()
+    };
+    let time = |key: &str| {
+        status[key]
+            .as_u64()
+            .map_or("unknown".to_string(), |seconds| {
+                format_time(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            })
+    };
+    let presets = strings("presets");
+    let mut lines = vec![format!(
+        "Session: {}{}",
+        if presets.is_empty() {
+            "custom".to_string()
+        } else {
+            presets.join(", ")
+        },
+        if status["strict"] == true {
+            " (strict)"
+        } else {
+            ""
+        }
+    )];
+    lines.push(format!("Blocking: {}", strings("sites").join(", ")));
+    lines.push(format!("Started: {}", time("started_at")));
+    lines.push(format!(
+        "Ends: {} ({} left)",
+        time("deadline"),
+        format_remaining(Duration::from_secs(
+            status["remaining_seconds"].as_u64().unwrap_or(0)
+        ))
+    ));
+    if status["paused_at"].is_u64() {
+        lines.push(format!(
+            "Paused since {}, the blocks are lifted and the timer frozen",
+            time("paused_at")
+        ));
+    }
+    if let Some(sites) = status["peek"]["sites"].as_array() {
+        let sites: Vec<&str> = sites.iter().filter_map(Value::as_str).collect();
+        lines.push(format!(
+            "Peeking at {}, blocked again in {}",
+            sites.join(", "),
+            format_remaining(Duration::from_secs(
+                status["peek"]["remaining_seconds"].as_u64().unwrap_or(0)
+            ))
+        ));
+    }
+    if let Some(seconds) = status["stopping_in_seconds"].as_u64() {
+        lines.push(format!(
+            "Stopping early in {}",
+            format_remaining(Duration::from_secs(seconds))
+        ));
+    }
+    let backends = strings("backends");
+    if !backends.is_empty() {
+        lines.push(format!("Backends: {}", backends.join(", ")));
+    }
+    lines.push(format!(
+        "Hosts entries: {}",
+        match status["hosts_intact"].as_bool() {
+            Some(true) => "intact",
+            Some(false) => "missing or edited",
+            None => "not used",
+        }
+    ));
+    lines.extend(format_budgets(status));
+    lines.join("\n")
+}
+
+/// Formats a time in the local time zone, e.g. `2026-10-19 14:05`.
+fn format_time(time: SystemTime) -> String {
+    DateTime::<Local>::from(time)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hosts::file_edit::with_managed_block;
+    use crate::session::state::{BROWSER_POLICIES_BACKEND, HOSTS_BACKEND};
+
+    fn test_session() -> Session {
+        let mut session = Session::new(
+            vec!["coding".to_string()],
+            vec!["www.youtube.com".to_string(), "netflix.com".to_string()],
+            SystemTime::UNIX_EPOCH,
+            Duration::from_secs(25 * 60),
+        );
+        session.backends = vec![
+            HOSTS_BACKEND.to_string(),
+            BROWSER_POLICIES_BACKEND.to_string(),
+        ];
+        session.hosts = vec!["netflix.com".to_string()];
+        session
+    }
+
+    #[test]
+    fn test_status_json() {
+        let mut session = test_session();
+        session.unlock_hash = Some("sha256$salt$digest".to_string());
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(300);
+        let hosts = with_managed_block("127.0.0.1 localhost\n", &["netflix.com"]);
+
+        let status = status_json(Some(&session), now, Some(&hosts));
+
+        assert_eq!(status["active"], true);
+        assert_eq!(status["strict"], true);
+        assert!(
+            status.get("unlock_hash").is_none(),
+            "The hash must not leak"
+        );
+        assert_eq!(status["remaining_seconds"], 1200);
+        assert_eq!(status["deadline"], 1500);
+        assert_eq!(status["backends"], json!(["hosts", "browser-policies"]));
+        assert_eq!(status["hosts_intact"], true);
+    }
+
+    #[test]
+    fn test_status_json_tampered_hosts() {
+        let session = test_session();
+        let now = SystemTime::UNIX_EPOCH;
+
+        let tampered = status_json(Some(&session), now, Some("127.0.0.1 localhost\n"));
+        assert_eq!(tampered["hosts_intact"], false);
+        let unreadable = status_json(Some(&session), now, None);
+        assert_eq!(unreadable["hosts_intact"], false);
+    }
+
+    #[test]
+    fn test_status_json_idle() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(3600);
+        assert_eq!(status_json(None, now, None)["active"], false);
+        let ended = status_json(Some(&test_session()), now, None);
+        assert_eq!(ended["active"], false, "An ended session is not running");
+    }
+
+    #[test]
+    fn test_format_status() {
+        let session = test_session();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(300);
+        let text = format_status(&status_json(Some(&session), now, Some("")));
+
+        assert!(text.starts_with("Session: coding\n"), "{}", text);
+        assert!(text.contains("Blocking: www.youtube.com, netflix.com"));
+        assert!(text.contains("(20m 00s left)"));
+        assert!(text.contains("Backends: hosts, browser-policies"));
+        assert!(text.contains("Hosts entries: missing or edited"));
+        assert!(!text.contains("Paused"));
+        assert_eq!(
+            format_status(&status_json(None, now, None)),
+            "No session is running"
+        );
+        let mut idle = status_json(None, now, None);
+        idle["budgets"] = json!([{
+            "name": "youtube",
+            "daily_seconds": 1800,
+            "remaining_seconds": 600,
+            "allowed_until": null,
+        }]);
+        assert_eq!(
+            format_status(&idle),
+            "No session is running\nBudget youtube: 10m 00s of 30m 00s left today"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use std::io::{BufRead, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, SystemTime};
+
+use sha2::{Digest, Sha256};
+
+use crate::session::clock::{wait_until_over, SystemClock, WAKE_INTERVAL};
+use crate::session::history::{History, UNLOCK, UNLOCK_FAILED};
+
+/// The flag starting a strict session, which can't be stopped before its deadline.
+pub const STRICT_FLAG: &str = "--strict";
+
+// Unambiguous characters, 32 of them so that a random byte maps without bias
+const CODE_ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_GROUPS: usize = 3;
+const CODE_GROUP_LEN: usize = 4;
+const SALT_LEN: usize = 16;
+const HASH_SCHEME: &str = "sha256";
+
+/// Generates an emergency unlock code, e.g. `K7QD-2MXA-PW9H`.
+pub fn generate_unlock_code() -> Result<String, std::io::Error> {
+    let bytes = random_bytes(CODE_GROUPS * CODE_GROUP_LEN)?;
+    let groups: Vec<String> = bytes
+        .chunks(CODE_GROUP_LEN)
+        .map(|group| {
+            group
+                .iter()
+                .map(|byte| CODE_ALPHABET[(*byte % 32) as usize] as char)
+                .collect()
+        })
+        .collect();
+    Ok(groups.join("-"))
+}
+
+/// Hashes an unlock code with a random salt, as `sha256$<salt>$<digest>`.
+///
+/// Only the hash is stored, so the code can't be read back from the session state.
+pub fn hash_unlock_code(code: &str) -> Result<String, std::io::Error> {
+    let salt = hex(&random_bytes(SALT_LEN)?);
+    Ok(format!(
+        "{}${}${}",
+        HASH_SCHEME,
+        salt,
+        digest(&salt, &normalize(code))
+    ))
+}
+
+/// Returns whether `code` matches a hash made by `hash_unlock_code`.
+///
+/// Case, spaces and dashes are ignored, the code is usually typed by hand.
+pub fn verify_unlock_code(code: &str, hash: &str) -> bool {
+    let mut parts = hash.split('$');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(HASH_SCHEME), Some(salt), Some(expected), None) => {
+            digest(salt, &normalize(code)) == expected
+        }
+        _ => false,
+    }
+}
+
+/// Ignores (or restores) the signals that would end the program during a strict
+/// session: `SIGINT` (Ctrl-C), `SIGTERM`, `SIGHUP` and `SIGQUIT`.
+///
+/// `SIGKILL` can't be ignored, the blocks are left in place and the daemon
+/// re-applies the persisted session when it starts again.
+pub fn ignore_termination_signals(ignore: bool) {
+    let handler = if ignore { libc::SIG_IGN } else { libc::SIG_DFL };
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT] {
+        // SAFETY: SIG_IGN and SIG_DFL are valid dispositions for these signals
+        unsafe { libc::signal(signal, handler) };
+    }
+}
+
+/// Waits for the deadline of a strict session, unless the unlock code is typed.
+///
+/// Every line read from `input` is an unlock attempt, recorded in the history.
+///
+/// # Returns
+///
+/// Whether the session was unlocked before its deadline
+pub fn wait_for_deadline_or_unlock<R: Read + Send + 'static>(
+    input: R,
+    deadline: SystemTime,
+    unlock_hash: &str,
+    history: &History,
+) -> Result<bool, std::io::Error> {
+    let received = spawn_line_reader(input);
+    loop {
+        let remaining = deadline
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO);
+        if remaining == Duration::ZERO {
+            return Ok(false);
+        }
+        // Woken up regularly, the timeout doesn't run while the machine is suspended
+        match received.recv_timeout(remaining.min(WAKE_INTERVAL)) {
+            Ok(line) if line.trim().is_empty() => {}
+            Ok(line) if verify_unlock_code(&line, unlock_hash) => {
+                history.record_or_warn(
+                    SystemTime::now(),
+                    UNLOCK,
+                    "unlocked with the emergency code",
+                );
+                return Ok(true);
+            }
+            Ok(_) => {
+                history.record_or_warn(SystemTime::now(), UNLOCK_FAILED, "wrong emergency code");
+                println!("Wrong unlock code, the session goes on");
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            // No more input, only the deadline can end the session
+            Err(RecvTimeoutError::Disconnected) => {
+                wait_until_over(&SystemClock, |now| {
+                    deadline.duration_since(now).unwrap_or(Duration::ZERO)
+                });
+                return Ok(false);
+            }
+        }
+    }
+}
+
+/// Reads `input` line by line on a thread, the lines being sent to the receiver.
+///
+/// The receiver is disconnected once the input is closed.
+pub fn spawn_line_reader<R: Read + Send + 'static>(input: R) -> Receiver<String> {
+    let (lines, received) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::BufReader::new(input).lines() {
+            let Ok(line) = line else { break };
+            if lines.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    received
+}
+
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn digest(salt: &str, code: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(code.as_bytes());
+    hex(&hasher.finalize())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Reads `len` random bytes from the kernel.
+pub fn random_bytes(len: usize) -> Result<Vec<u8>, std::io::Error> {
+    let mut bytes = vec![0u8; len];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::history::HISTORY_FILE;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_generate_unlock_code_format() {
+        let code = generate_unlock_code().expect("Failed to generate");
+        assert_eq!(code.len(), 14);
+        let groups: Vec<&str> = code.split('-').collect();
+        assert_eq!(groups.len(), CODE_GROUPS);
+        assert!(code
+            .bytes()
+            .all(|byte| byte == b'-' || CODE_ALPHABET.contains(&byte)));
+        assert_ne!(code, generate_unlock_code().unwrap(), "Codes should differ");
+    }
+
+    #[test]
+    fn test_hash_and_verify() {
+        let code = generate_unlock_code().unwrap();
+        let hash = hash_unlock_code(&code).unwrap();
+
+        assert!(hash.starts_with("sha256$"));
+        assert!(!hash.contains(&code), "The code must not be stored");
+        assert!(verify_unlock_code(&code, &hash));
+        assert!(verify_unlock_code(
+            &code.to_lowercase().replace('-', " "),
+            &hash
+        ));
+        assert!(!verify_unlock_code("AAAA-AAAA-AAAA", &hash));
+        assert_ne!(hash, hash_unlock_code(&code).unwrap(), "Hashes are salted");
+    }
+
+    #[test]
+    fn test_verify_rejects_malformed_hash() {
+        assert!(!verify_unlock_code("", ""));
+        assert!(!verify_unlock_code("code", "md5$salt$digest"));
+        assert!(!verify_unlock_code("code", "sha256$salt"));
+    }
+
+    #[test]
+    fn test_wait_unlocked_by_code() {
+        let temp_dir = TempDir::new().unwrap();
+        let history = History::new(temp_dir.path().join(HISTORY_FILE));
+        let hash = hash_unlock_code("ABCD-EFGH-JKLM").unwrap();
+        let input = std::io::Cursor::new("wrong\nabcd-efgh-jklm\n");
+        let deadline = SystemTime::now() + Duration::from_secs(60);
+
+        let unlocked = wait_for_deadline_or_unlock(input, deadline, &hash, &history).unwrap();
+
+        assert!(unlocked, "The right code should unlock the session");
+        let events: Vec<String> = history
+            .events()
+            .unwrap()
+            .iter()
+            .map(|event| event["event"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(events, vec![UNLOCK_FAILED, UNLOCK]);
+    }
+
+    #[test]
+    fn test_wait_unlocked_without_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let not_a_dir = temp_dir.path().join("not_a_dir");
+        std::fs::write(&not_a_dir, "").unwrap();
+        let history = History::new(not_a_dir.join(HISTORY_FILE));
+        let hash = hash_unlock_code("ABCD-EFGH-JKLM").unwrap();
+        let input = std::io::Cursor::new("wrong\nABCD-EFGH-JKLM\n");
+        let deadline = SystemTime::now() + Duration::from_secs(60);
+
+        let unlocked = wait_for_deadline_or_unlock(input, deadline, &hash, &history).unwrap();
+
+        assert!(unlocked, "A history that can't be written shouldn't matter");
+    }
+
+    #[test]
+    fn test_wait_until_deadline() {
+        let temp_dir = TempDir::new().unwrap();
+        let history = History::new(temp_dir.path().join(HISTORY_FILE));
+        let hash = hash_unlock_code("ABCD-EFGH-JKLM").unwrap();
+        let deadline = SystemTime::now() + Duration::from_millis(200);
+
+        let unlocked =
+            wait_for_deadline_or_unlock(std::io::empty(), deadline, &hash, &history).unwrap();
+
+        assert!(!unlocked);
+        assert!(
+            SystemTime::now() >= deadline,
+            "Should wait for the deadline"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const PROC_NET: &str = "/proc/net";
+/// The socket tables read, UDP for the browsers speaking QUIC.
+const SOCKET_TABLES: [&str; 4] = ["tcp", "tcp6", "udp", "udp6"];
+/// The state of an established TCP connection, or of a connected UDP socket.
+const ESTABLISHED: &str = "01";
+/// How long the addresses of a website are trusted before resolving it again.
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// How long before resolving again a website which had no usable address.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// How long the open connections are trusted before reading them again.
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tells whether websites are being used, to count the time of their budget.
+pub trait UsageProbe: Send {
+    /// Returns whether any of `sites` is used right now.
+    fn in_use(&mut self, sites: &[String]) -> bool;
+}
+
+/// The addresses of a website, as last resolved.
+#[derive(Debug, Default)]
+struct Resolution {
+    addresses: Vec<IpAddr>,
+    /// When `addresses` were resolved, `None` until a usable answer came
+    resolved: Option<Instant>,
+    /// When the last resolution started
+    started: Option<Instant>,
+    running: bool,
+}
+
+impl Resolution {
+    fn due(&self) -> bool {
+        !self.running
+            && self
+                .resolved
+                .is_none_or(|resolved| resolved.elapsed() >= RESOLVE_INTERVAL)
+            && self
+                .started
+                .is_none_or(|started| started.elapsed() >= RETRY_INTERVAL)
+    }
+}
+
+/// Looks for the connections open to the websites in `/proc/net`.
+///
+/// A website is used while a connection to one of its addresses is open, TCP or
+/// UDP for QUIC. The connections are read again every `PROBE_INTERVAL`.
+///
+/// The websites are resolved on their own threads, so that a slow DNS server
+/// doesn't hold the caller, and again every `RESOLVE_INTERVAL`. The loopback
+/// addresses they resolve to while they are blocked are ignored, a website
+/// without other address is resolved again after `RETRY_INTERVAL`.
+pub struct ConnectionProbe {
+    proc_net: PathBuf,
+    resolve: fn(&str) -> Vec<IpAddr>,
+    addresses: Arc<Mutex<HashMap<String, Resolution>>>,
+    connected: Vec<IpAddr>,
+    probed: Option<Instant>,
+}
+
+impl ConnectionProbe {
+    pub fn system() -> ConnectionProbe {
+        ConnectionProbe::with_proc_net(PathBuf::from(PROC_NET))
+    }
+
+    pub fn with_proc_net(proc_net: PathBuf) -> ConnectionProbe {
+        ConnectionProbe {
+            proc_net,
+            resolve,
+            addresses: Arc::new(Mutex::new(HashMap::new())),
+            connected: Vec::new(),
+            probed: None,
+        }
+    }
+
+    /// Resolves the websites with `resolve` instead of the system resolver.
+    #[cfg(test)]
+    fn with_resolver(mut self, resolve: fn(&str) -> Vec<IpAddr>) -> ConnectionProbe {
+        self.resolve = resolve;
+        self
+    }
+
+    /// Returns the known addresses of `site`, resolving it in the background when
+    /// they are too old.
+    fn addresses(&self, site: &str) -> Vec<IpAddr> {
+        let mut addresses = self.addresses.lock().unwrap();
+        let resolution = addresses.entry(site.to_string()).or_default();
+        if resolution.due() {
+            resolution.running = true;
+            resolution.started = Some(Instant::now());
+            let (shared, site, resolve) = (self.addresses.clone(), site.to_string(), self.resolve);
+            std::thread::spawn(move || {
+                let resolved = resolve(&site);
+                let mut addresses = shared.lock().unwrap();
+                let resolution = addresses.entry(site).or_default();
+                resolution.running = false;
+                if !resolved.is_empty() {
+                    resolution.addresses = resolved;
+                    resolution.resolved = Some(Instant::now());
+                }
+            });
+        }
+        resolution.addresses.clone()
+    }
+}
+
+impl UsageProbe for ConnectionProbe {
+    fn in_use(&mut self, sites: &[String]) -> bool {
+        if self
+            .probed
+            .is_none_or(|probed| probed.elapsed() >= PROBE_INTERVAL)
+        {
+            self.connected = connected_addresses(&self.proc_net);
+            self.probed = Some(Instant::now());
+        }
+        if self.connected.is_empty() {
+            return false;
+        }
+        sites.iter().any(|site| {
+            self.addresses(site)
+                .iter()
+                .any(|ip| self.connected.contains(ip))
+        })
+    }
+}
+
+/// Resolves `site` through the system resolver, without the loopback addresses.
+fn resolve(site: &str) -> Vec<IpAddr> {
+    (site, 443)
+        .to_socket_addrs()
+        .map(|addresses| {
+            addresses
+                .map(|address| address.ip())
+                .filter(|ip| !ip.is_loopback() && !ip.is_unspecified())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Lists the remote addresses of the established connections found in `proc_net`.
+///
+/// # Arguments
+///
+/// * `proc_net` - The network directory of the proc filesystem, `/proc/net` outside
+///   of tests
+pub fn connected_addresses(proc_net: &Path) -> Vec<IpAddr> {
+    SOCKET_TABLES
+        .iter()
+        .filter_map(|table| std::fs::read_to_string(proc_net.join(table)).ok())
+        .flat_map(|content| {
+            content
+                .lines()
+                .skip(1)
+                .filter_map(|line| {
+                    let fields: Vec<&str> = line.split_whitespace().collect();
+                    if fields.get(3) != Some(&ESTABLISHED) {
+                        return None;
+                    }
+                    parse_address(fields.get(2)?.split(':').next()?)
+                })
+                .collect::<Vec<IpAddr>>()
+        })
+        .collect()
+}
+
+/// Parses an address of the socket tables, 32 bit words written in host order.
+fn parse_address(hex: &str) -> Option<IpAddr> {
+    let words: Option<Vec<[u8; 4]>> = (0..hex.len())
+        .step_by(8)
+        .map(|start| {
+            let word = u32::from_str_radix(hex.get(start..start + 8)?, 16).ok()?;
+            Some(word.to_ne_bytes())
+        })
+        .collect();
+    let bytes: Vec<u8> = words?.concat();
+    match bytes.len() {
+        4 => Some(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?))),
+        16 => Some(IpAddr::V6(Ipv6Addr::from(
+            <[u8; 16]>::try_from(bytes).ok()?,
+        ))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    // Helper function writing a socket table with a connection per remote address
+    fn write_table(proc_net: &Path, table: &str, remotes: &[(&str, &str)]) {
+        let mut content =
+            "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when\n".to_string();
+        for (index, (remote, state)) in remotes.iter().enumerate() {
+            content.push_str(&format!(
+                "   {}: 0100007F:A1B2 {}:01BB {} 00000000:00000000 00:00000000\n",
+                index, remote, state
+            ));
+        }
+        std::fs::write(proc_net.join(table), content).unwrap();
+    }
+
+    fn host_order(ip: [u8; 4]) -> String {
+        format!("{:08X}", u32::from_ne_bytes(ip))
+    }
+
+    #[test]
+    fn test_connected_addresses() {
+        let proc_net = TempDir::new().unwrap();
+        let established = host_order([142, 250, 0, 1]);
+        let closing = host_order([142, 250, 0, 2]);
+        write_table(
+            proc_net.path(),
+            "tcp",
+            &[(&established, ESTABLISHED), (&closing, "06")],
+        );
+        let v6: String = [
+            [0x20, 0x01, 0x0d, 0xb8],
+            [0, 0, 0, 0],
+            [0, 0, 0, 0],
+            [0, 0, 0, 1],
+        ]
+        .iter()
+        .map(|word| host_order(*word))
+        .collect();
+        write_table(proc_net.path(), "udp6", &[(&v6, ESTABLISHED)]);
+
+        let connected = connected_addresses(proc_net.path());
+
+        assert_eq!(
+            connected,
+            vec![
+                "142.250.0.1".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+        assert!(connected_addresses(&proc_net.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn test_parse_address_rejects_garbage() {
+        assert_eq!(parse_address("0100007"), None);
+        assert_eq!(parse_address("0100007G"), None);
+        assert_eq!(parse_address(""), None);
+    }
+
+    #[test]
+    fn test_loopback_is_not_a_use() {
+        let proc_net = TempDir::new().unwrap();
+        write_table(
+            proc_net.path(),
+            "tcp",
+            &[(&host_order([127, 0, 0, 1]), ESTABLISHED)],
+        );
+        let mut probe = ConnectionProbe::with_proc_net(proc_net.path().to_path_buf());
+
+        // The block page of a blocked website is served on the loopback address
+        assert!(!probe.in_use(&["localhost".to_string()]));
+        assert!(resolve("localhost").is_empty());
+    }
+
+    // Helper function waiting for the resolutions started by `probe`
+    fn wait_for_resolutions(probe: &ConnectionProbe) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while probe
+            .addresses
+            .lock()
+            .unwrap()
+            .values()
+            .any(|resolution| resolution.running)
+        {
+            assert!(Instant::now() < deadline, "The resolution never ended");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_sites_are_resolved_in_the_background() {
+        let proc_net = TempDir::new().unwrap();
+        write_table(
+            proc_net.path(),
+            "tcp",
+            &[(&host_order([142, 250, 0, 1]), ESTABLISHED)],
+        );
+        let mut probe = ConnectionProbe::with_proc_net(proc_net.path().to_path_buf())
+            .with_resolver(|_| {
+                std::thread::sleep(Duration::from_millis(50));
+                vec!["142.250.0.1".parse().unwrap()]
+            });
+        let sites = ["www.youtube.com".to_string()];
+
+        assert!(!probe.in_use(&sites), "The site is not resolved yet");
+        wait_for_resolutions(&probe);
+        assert!(probe.in_use(&sites));
+
+        std::fs::remove_file(proc_net.path().join("tcp")).unwrap();
+        assert!(
+            probe.in_use(&sites),
+            "The connections are only read again after PROBE_INTERVAL"
+        );
+    }
+
+    #[test]
+    fn test_sites_without_address_are_not_cached() {
+        let proc_net = TempDir::new().unwrap();
+        write_table(
+            proc_net.path(),
+            "tcp",
+            &[(&host_order([142, 250, 0, 1]), ESTABLISHED)],
+        );
+        let mut probe = ConnectionProbe::with_proc_net(proc_net.path().to_path_buf())
+            .with_resolver(|_| Vec::new());
+        let sites = ["www.youtube.com".to_string()];
+
+        assert!(!probe.in_use(&sites));
+        wait_for_resolutions(&probe);
+
+        let addresses = probe.addresses.lock().unwrap();
+        let resolution = &addresses["www.youtube.com"];
+        assert_eq!(resolution.resolved, None);
+        assert!(!resolution.due(), "Resolved again after a while only");
+    }
+}
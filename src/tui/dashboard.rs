@@ -0,0 +1,766 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local};
+use serde_json::Value;
+
+use crate::daemon::protocol::{send_request, Request};
+use crate::session::state::{format_remaining, Session};
+use crate::session::status::status_json;
+use crate::tui::terminal::{is_tty, width, RawScreen};
+
+/// The flag showing the dashboard during a foreground session.
+pub const TUI_FLAG: &str = "--tui";
+/// How many minutes the `e` key adds to the session.
+pub const EXTEND_MINUTES: u64 = 5;
+/// How many minutes a peek asked with the `k` key lasts.
+pub const PEEK_MINUTES: u64 = 5;
+
+/// How often the status is asked again and the screen redrawn.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// The widest progress bar drawn, in characters.
+const MAX_BAR_WIDTH: usize = 60;
+
+/// Sends the requests of the dashboard, to the daemon or to a foreground session.
+pub trait Controls {
+    /// Sends `request`, `Request::Status` being replied with the status JSON.
+    ///
+    /// # Returns
+    ///
+    /// The reply, or an error if the request was refused
+    fn send(&self, request: &Request) -> Result<String, std::io::Error>;
+}
+
+/// Controls the session of the daemon through its socket, which applies the rules
+/// of strict sessions and of the friction.
+pub struct DaemonControls {
+    socket: PathBuf,
+}
+
+impl DaemonControls {
+    pub fn new(socket: PathBuf) -> DaemonControls {
+        DaemonControls { socket }
+    }
+}
+
+impl Controls for DaemonControls {
+    fn send(&self, request: &Request) -> Result<String, std::io::Error> {
+        send_request(&self.socket, request)
+    }
+}
+
+/// Controls a session run in the foreground, which can be extended and stopped
+/// unless it is strict or has friction.
+///
+/// The prompt of a strict session or of one with friction reads its commands from
+/// `input`: the unlock code, `stop`, the passage and `cancel` are written there.
+pub struct ForegroundControls {
+    session: Arc<Mutex<Session>>,
+    input: Option<Mutex<Box<dyn Write + Send>>>,
+    state_file: Option<PathBuf>,
+}
+
+impl ForegroundControls {
+    pub fn new(session: Arc<Mutex<Session>>) -> ForegroundControls {
+        ForegroundControls {
+            session,
+            input: None,
+            state_file: None,
+        }
+    }
+
+    /// Keeps the session saved for `status` in line with the changes, rewritten
+    /// when extended and removed when stopped.
+    ///
+    /// # Arguments
+    ///
+    /// * `state_file` - Where the session was saved when it started
+    pub fn with_state_file(mut self, state_file: PathBuf) -> ForegroundControls {
+        self.state_file = Some(state_file);
+        self
+    }
+
+    /// Sends the early stop commands to the prompt of the session.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - Read by the prompt as if the commands were typed
+    pub fn with_input(mut self, input: Box<dyn Write + Send>) -> ForegroundControls {
+        self.input = Some(Mutex::new(input));
+        self
+    }
+
+    /// Writes `line` to the prompt of the session.
+    fn forward(&self, line: &str) -> Result<String, std::io::Error> {
+        let Some(input) = &self.input else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "the session only ends early through its prompt",
+            ));
+        };
+        let mut input = input.lock().unwrap();
+        writeln!(input, "{}", line)?;
+        input.flush()?;
+        Ok("sent to the session".to_string())
+    }
+
+    /// Saves `session` again, or removes it once it ended, the failure being added
+    /// to `reply` as the session itself already changed.
+    fn save(&self, session: &Session, ended: bool, reply: String) -> String {
+        let Some(state_file) = &self.state_file else {
+            return reply;
+        };
+        let saved = if ended {
+            std::fs::remove_file(state_file)
+        } else {
+            std::fs::write(state_file, session.to_json().to_string())
+        };
+        match saved {
+            Ok(()) => reply,
+            Err(e) => format!("{}, but `status` can't see it: {}", reply, e),
+        }
+    }
+}
+
+impl Controls for ForegroundControls {
+    fn send(&self, request: &Request) -> Result<String, std::io::Error> {
+        let now = SystemTime::now();
+        let mut session = self.session.lock().unwrap();
+        let refused = |message: &str| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                message.to_string(),
+            ))
+        };
+        match request {
+            Request::Status => {
+                let hosts = std::fs::read_to_string(crate::FILE_PATH).ok();
+                Ok(status_json(Some(&session), now, hosts.as_deref()).to_string())
+            }
+            // Their prompt waits for the deadline they started with
+            Request::Extend { .. } if session.is_strict() || session.friction.is_some() => refused(
+                "only the sessions of the daemon can be extended once strict or with friction",
+            ),
+            Request::Extend { minutes } => {
+                let Some(deadline) = minutes
+                    .checked_mul(60)
+                    .and_then(|seconds| session.deadline.checked_add(Duration::from_secs(seconds)))
+                else {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "the deadline would be too far away",
+                    ));
+                };
+                session.deadline = deadline;
+                Ok(self.save(&session, false, format!("extended by {} minutes", minutes)))
+            }
+            Request::Unlock { code } if session.is_strict() => self.forward(code),
+            Request::Stop if session.is_strict() => {
+                refused("the session only ends early with its unlock code")
+            }
+            Request::Stop | Request::Cancel if session.friction.is_some() => {
+                self.forward(if *request == Request::Stop {
+                    "stop"
+                } else {
+                    "cancel"
+                })
+            }
+            Request::Answer { text } if session.friction.is_some() => self.forward(text),
+            Request::Stop => {
+                session.deadline = now.min(session.deadline);
+                Ok(self.save(&session, true, "stopped".to_string()))
+            }
+            _ => refused("only the sessions of the daemon can be paused or peeked at"),
+        }
+    }
+}
+
+/// What the dashboard shows of a session.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Phase {
+    Idle,
+    /// Between two scheduled blocks, until the next one starts
+    Break {
+        until: SystemTime,
+    },
+    Focus,
+    Paused,
+}
+
+/// The state of the session shown by the dashboard, read from its status.
+#[derive(Debug, Clone, PartialEq)]
+pub struct View {
+    pub phase: Phase,
+    pub sites: Vec<String>,
+    pub remaining: Duration,
+    /// From the start of the session to its deadline
+    pub total: Duration,
+    pub strict: bool,
+    /// Whether stopping early asks to type a passage first
+    pub challenge: bool,
+    pub friction: bool,
+    /// The websites peeked at and how long the peek has left
+    pub peek: Option<(Vec<String>, Duration)>,
+    /// How long before a stop asked for takes effect
+    pub stopping: Option<Duration>,
+}
+
+impl View {
+    /// Reads the view from a status built by `status_json`, with the keys the
+    /// daemon adds.
+    pub fn from_status(status: &Value) -> View {
+        let seconds = |value: &Value| value.as_u64().map(Duration::from_secs);
+        let strings = |value: &Value| -> Vec<String> {
+            value
+                .as_array()
+                .map(|items| {
+                    items
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let active = status["active"] == true;
+        let phase = match seconds(&status["break_until"]) {
+            _ if active && !status["paused_at"].is_null() => Phase::Paused,
+            _ if active => Phase::Focus,
+            Some(until) => Phase::Break {
+                until: SystemTime::UNIX_EPOCH + until,
+            },
+            None => Phase::Idle,
+        };
+        let total = seconds(&status["deadline"])
+            .zip(seconds(&status["started_at"]))
+            .map(|(deadline, started_at)| deadline.saturating_sub(started_at))
+            .unwrap_or_default();
+        View {
+            phase,
+            sites: strings(&status["sites"]),
+            remaining: seconds(&status["remaining_seconds"]).unwrap_or_default(),
+            total,
+            strict: status["strict"] == true,
+            challenge: status["friction"]["challenge_words"]
+                .as_u64()
+                .is_some_and(|words| words > 0),
+            friction: !status["friction"].is_null(),
+            peek: seconds(&status["peek"]["remaining_seconds"])
+                .map(|left| (strings(&status["peek"]["sites"]), left)),
+            stopping: seconds(&status["stopping_in_seconds"]),
+        }
+    }
+
+    /// Returns how much of the session is over, from 0 to 1.
+    pub fn progress(&self) -> f64 {
+        if self.total.is_zero() {
+            return 0.0;
+        }
+        let done = self.total.saturating_sub(self.remaining);
+        (done.as_secs_f64() / self.total.as_secs_f64()).clamp(0.0, 1.0)
+    }
+
+    fn title(&self) -> String {
+        match self.phase {
+            Phase::Idle => "No session is running".to_string(),
+            Phase::Break { until } => format!("Break until {}", local_time(until)),
+            Phase::Focus => "Focus".to_string(),
+            Phase::Paused => "Paused".to_string(),
+        }
+    }
+}
+
+/// What a key press asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Send(Request),
+    /// Asks for the website and the reason of a peek
+    Peek,
+    /// Asks for the emergency unlock code of a strict session
+    Unlock,
+    /// Asks for the passage of a stop with a challenge
+    Answer,
+    Quit,
+}
+
+/// Returns the keys available in `view` with their label, e.g. `('p', "pause")`.
+///
+/// The keys follow what the session allows: strict sessions only end early with
+/// their unlock code, and neither strict sessions nor those with friction can be
+/// paused.
+pub fn keys(view: &View) -> Vec<(char, &'static str)> {
+    let mut keys = Vec::new();
+    match view.phase {
+        Phase::Focus => {
+            if !view.strict && !view.friction {
+                keys.push(('p', "pause"));
+            }
+            keys.push(('e', "extend"));
+            if !view.strict {
+                keys.push(('k', "peek"));
+            }
+            if view.strict {
+                keys.push(('u', "unlock"));
+            } else if view.stopping.is_none() {
+                keys.push(('s', "stop"));
+            }
+            if view.challenge && view.stopping.is_none() {
+                keys.push(('a', "answer"));
+            }
+            if view.stopping.is_some() {
+                keys.push(('c', "cancel"));
+            }
+        }
+        Phase::Paused => {
+            keys.push(('p', "resume"));
+            keys.push(('e', "extend"));
+            keys.push(('s', "stop"));
+        }
+        Phase::Idle | Phase::Break { .. } => {}
+    }
+    keys.push(('q', "quit"));
+    keys
+}
+
+/// Returns what pressing `key` asks for in `view`, `None` for an unavailable key.
+pub fn action(key: char, view: &View) -> Option<Action> {
+    let key = key.to_ascii_lowercase();
+    if !keys(view).iter().any(|(available, _)| *available == key) {
+        return None;
+    }
+    Some(match key {
+        'p' if view.phase == Phase::Paused => Action::Send(Request::Resume),
+        'p' => Action::Send(Request::Pause),
+        'e' => Action::Send(Request::Extend {
+            minutes: EXTEND_MINUTES,
+        }),
+        's' => Action::Send(Request::Stop),
+        'c' => Action::Send(Request::Cancel),
+        'k' => Action::Peek,
+        'u' => Action::Unlock,
+        'a' => Action::Answer,
+        _ => Action::Quit,
+    })
+}
+
+/// Draws a progress bar of `width` characters, brackets included.
+pub fn progress_bar(progress: f64, width: usize) -> String {
+    let inner = width.saturating_sub(2);
+    let filled = ((progress.clamp(0.0, 1.0) * inner as f64).round() as usize).min(inner);
+    format!("[{}{}]", "#".repeat(filled), "-".repeat(inner - filled))
+}
+
+/// Renders the dashboard of `view` for a terminal `width` columns wide.
+///
+/// # Arguments
+///
+/// * `view` - The session to show
+/// * `message` - The reply to the last key press, if any
+/// * `width` - The width of the terminal
+pub fn render(view: &View, message: Option<&str>, width: usize) -> Vec<String> {
+    let mut lines = vec![view.title(), String::new()];
+    if matches!(view.phase, Phase::Focus | Phase::Paused) {
+        let progress = view.progress();
+        lines.push(format!(
+            "{} {:>3}%",
+            progress_bar(progress, width.saturating_sub(5).min(MAX_BAR_WIDTH)),
+            (progress * 100.0).round() as u64
+        ));
+        lines.push(format!("{} left", format_remaining(view.remaining)));
+        lines.push(format!("Blocking {}", view.sites.join(", ")));
+        if view.strict {
+            lines.push("Strict session, it only ends early with its unlock code".to_string());
+        }
+        if let Some((sites, left)) = &view.peek {
+            lines.push(format!(
+                "Peeking at {}, blocked again in {}",
+                sites.join(", "),
+                format_remaining(*left)
+            ));
+        }
+        if let Some(left) = view.stopping {
+            lines.push(format!("Stopping in {}", format_remaining(left)));
+        }
+    }
+    lines.push(String::new());
+    let keys: Vec<String> = keys(view)
+        .iter()
+        .map(|(key, label)| format!("{} {}", key, label))
+        .collect();
+    lines.push(keys.join("  "));
+    if let Some(message) = message {
+        lines.push(message.to_string());
+    }
+    lines
+}
+
+/// Formats `view` as a single line, to the minute, for an output which is not a
+/// terminal.
+pub fn status_line(view: &View) -> String {
+    match view.phase {
+        Phase::Idle | Phase::Break { .. } => view.title(),
+        Phase::Focus | Phase::Paused => format!(
+            "{}: {} min left, blocking {}",
+            view.title(),
+            view.remaining.as_secs().div_ceil(60),
+            view.sites.join(", ")
+        ),
+    }
+}
+
+/// The dashboard shown on a thread during a foreground session.
+pub struct Dashboard {
+    done: Arc<AtomicBool>,
+    thread: JoinHandle<Result<(), std::io::Error>>,
+}
+
+impl Dashboard {
+    /// Shows the dashboard of `controls` on a thread, see `run`.
+    pub fn spawn<C: Controls + Send + 'static>(controls: C) -> Dashboard {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let done = done.clone();
+            std::thread::spawn(move || run(&controls, &done))
+        };
+        Dashboard { done, thread }
+    }
+
+    /// Closes the dashboard, printing why it failed if it did.
+    pub fn close(self) {
+        self.done.store(true, Ordering::Relaxed);
+        match self.thread.join() {
+            Ok(Err(e)) => println!("The dashboard failed: {}", e),
+            Err(_) => println!("The dashboard panicked"),
+            Ok(Ok(())) => {}
+        }
+    }
+}
+
+/// Returns whether the dashboard reads keys, stdin and stdout being a terminal.
+pub fn is_interactive() -> bool {
+    is_tty(libc::STDIN_FILENO) && is_tty(libc::STDOUT_FILENO)
+}
+
+/// Shows the dashboard of a session until `done` is set or `q` is pressed.
+///
+/// When stdin or stdout is not a terminal, a line is printed instead each time the
+/// minutes left change, without key bindings.
+///
+/// # Arguments
+///
+/// * `controls` - Where the status is read and the requests sent
+/// * `done` - Set once the dashboard should close, e.g. at the end of the session
+pub fn run(controls: &dyn Controls, done: &AtomicBool) -> Result<(), std::io::Error> {
+    if !is_interactive() {
+        return run_lines(controls, done);
+    }
+    let screen = RawScreen::enter()?;
+    let mut message: Option<String> = None;
+    while !done.load(Ordering::Relaxed) {
+        let view = View::from_status(&status(controls)?);
+        screen.draw(&render(&view, message.as_deref(), width()))?;
+        let Some(key) = screen.read_key(REFRESH_INTERVAL)? else {
+            continue;
+        };
+        // The prompts give up once the session is over
+        let prompt = |question| screen.prompt(question, done);
+        let request = match action(key, &view) {
+            None => continue,
+            Some(Action::Quit) => break,
+            Some(Action::Send(request)) => request,
+            Some(Action::Peek) => {
+                let (Some(site), Some(reason)) =
+                    (prompt("Website to peek at:")?, prompt("Reason:")?)
+                else {
+                    break;
+                };
+                Request::Peek {
+                    minutes: PEEK_MINUTES,
+                    site,
+                    reason,
+                }
+            }
+            Some(Action::Unlock) => match prompt("Emergency unlock code:")? {
+                Some(code) => Request::Unlock { code },
+                None => break,
+            },
+            Some(Action::Answer) => match prompt("Passage:")? {
+                Some(text) => Request::Answer { text },
+                None => break,
+            },
+        };
+        message = Some(match controls.send(&request) {
+            Ok(reply) => reply,
+            Err(e) => e.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn run_lines(controls: &dyn Controls, done: &AtomicBool) -> Result<(), std::io::Error> {
+    let mut last = String::new();
+    while !done.load(Ordering::Relaxed) {
+        let line = status_line(&View::from_status(&status(controls)?));
+        if line != last {
+            println!("{}", line);
+            last = line;
+        }
+        std::thread::sleep(REFRESH_INTERVAL);
+    }
+    Ok(())
+}
+
+fn status(controls: &dyn Controls) -> Result<Value, std::io::Error> {
+    let reply = controls.send(&Request::Status)?;
+    serde_json::from_str(&reply)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+fn local_time(time: SystemTime) -> String {
+    DateTime::<Local>::from(time).format("%H:%M").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::friction::Friction;
+    use crate::session::state::unix_seconds;
+    use serde_json::json;
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    fn session(now: SystemTime) -> Session {
+        Session::new(
+            Vec::new(),
+            vec!["netflix.com".to_string(), "www.youtube.com".to_string()],
+            now - minutes(15),
+            minutes(25),
+        )
+    }
+
+    fn view(session: &Session, now: SystemTime) -> View {
+        View::from_status(&status_json(Some(session), now, None))
+    }
+
+    #[test]
+    fn test_view_from_status() {
+        let now = SystemTime::now();
+        let focus = view(&session(now), now);
+
+        assert_eq!(focus.phase, Phase::Focus);
+        assert_eq!(focus.sites, vec!["netflix.com", "www.youtube.com"]);
+        assert_eq!(focus.remaining, minutes(10));
+        assert_eq!(focus.total, minutes(25));
+        assert!((focus.progress() - 0.6).abs() < 0.01);
+        assert!(!focus.strict && !focus.friction);
+
+        let mut paused = session(now);
+        paused.pause(now);
+        assert_eq!(view(&paused, now).phase, Phase::Paused);
+
+        let idle = View::from_status(&json!({ "active": false }));
+        assert_eq!(idle.phase, Phase::Idle);
+        assert_eq!(idle.progress(), 0.0);
+        let on_break = View::from_status(&json!({ "active": false, "break_until": 1000 }));
+        assert_eq!(
+            on_break.phase,
+            Phase::Break {
+                until: SystemTime::UNIX_EPOCH + Duration::from_secs(1000)
+            }
+        );
+
+        let mut status = status_json(Some(&session(now)), now, None);
+        status["peek"] = json!({ "sites": ["www.youtube.com"], "remaining_seconds": 120 });
+        status["stopping_in_seconds"] = json!(60);
+        let view = View::from_status(&status);
+        assert_eq!(
+            view.peek,
+            Some((vec!["www.youtube.com".to_string()], minutes(2)))
+        );
+        assert_eq!(view.stopping, Some(minutes(1)));
+    }
+
+    #[test]
+    fn test_render() {
+        let now = SystemTime::now();
+        let lines = render(&view(&session(now), now), Some("extended by 5 minutes"), 27);
+
+        assert_eq!(
+            lines,
+            vec![
+                "Focus",
+                "",
+                "[############--------]  60%",
+                "10m 00s left",
+                "Blocking netflix.com, www.youtube.com",
+                "",
+                "p pause  e extend  k peek  s stop  q quit",
+                "extended by 5 minutes",
+            ]
+        );
+        let idle = render(&View::from_status(&json!({ "active": false })), None, 80);
+        assert_eq!(idle, vec!["No session is running", "", "", "q quit"]);
+    }
+
+    #[test]
+    fn test_progress_bar() {
+        assert_eq!(progress_bar(0.0, 7), "[-----]");
+        assert_eq!(progress_bar(0.5, 12), "[#####-----]");
+        assert_eq!(progress_bar(2.0, 7), "[#####]");
+        assert_eq!(progress_bar(0.5, 1), "[]");
+    }
+
+    #[test]
+    fn test_status_line() {
+        let now = SystemTime::now();
+        let mut session = session(now);
+        assert_eq!(
+            status_line(&view(&session, now)),
+            "Focus: 10 min left, blocking netflix.com, www.youtube.com"
+        );
+        session.deadline += Duration::from_secs(30);
+        assert_eq!(
+            status_line(&view(&session, now)),
+            "Focus: 11 min left, blocking netflix.com, www.youtube.com",
+            "The minutes should be rounded up"
+        );
+        assert_eq!(
+            status_line(&View::from_status(&json!({ "active": false }))),
+            "No session is running"
+        );
+    }
+
+    #[test]
+    fn test_keys_follow_the_rules_of_the_session() {
+        let now = SystemTime::now();
+        let mut strict = session(now);
+        strict.unlock_hash = Some("hash".to_string());
+        let strict = view(&strict, now);
+        assert_eq!(
+            action('s', &strict),
+            None,
+            "Strict sessions can't be stopped"
+        );
+        assert_eq!(action('p', &strict), None);
+        assert_eq!(action('k', &strict), None);
+        assert_eq!(action('u', &strict), Some(Action::Unlock));
+
+        let mut friction = session(now);
+        friction.friction = Some(Friction {
+            cooldown: minutes(5),
+            challenge_words: 20,
+        });
+        let mut status = status_json(Some(&friction), now, None);
+        let friction = View::from_status(&status);
+        assert_eq!(action('p', &friction), None, "Friction forbids pausing");
+        assert_eq!(action('S', &friction), Some(Action::Send(Request::Stop)));
+        assert_eq!(action('a', &friction), Some(Action::Answer));
+        status["stopping_in_seconds"] = json!(300);
+        let stopping = View::from_status(&status);
+        assert_eq!(action('s', &stopping), None);
+        assert_eq!(action('c', &stopping), Some(Action::Send(Request::Cancel)));
+
+        let mut paused = session(now);
+        paused.pause(now);
+        let paused = view(&paused, now);
+        assert_eq!(action('p', &paused), Some(Action::Send(Request::Resume)));
+        assert_eq!(
+            action('e', &paused),
+            Some(Action::Send(Request::Extend { minutes: 5 }))
+        );
+        assert_eq!(action('x', &paused), None);
+        assert_eq!(action('q', &paused), Some(Action::Quit));
+    }
+
+    #[test]
+    fn test_foreground_controls() {
+        let now = SystemTime::now();
+        let session = Arc::new(Mutex::new(session(now)));
+        let deadline = session.lock().unwrap().deadline;
+        let controls = ForegroundControls::new(session.clone());
+
+        let status: Value =
+            serde_json::from_str(&controls.send(&Request::Status).unwrap()).unwrap();
+        assert_eq!(status["active"], true);
+        controls.send(&Request::Extend { minutes: 5 }).unwrap();
+        assert_eq!(session.lock().unwrap().deadline, deadline + minutes(5));
+        assert!(controls.send(&Request::Pause).is_err());
+        assert!(controls
+            .send(&Request::Extend { minutes: u64::MAX })
+            .is_err());
+        assert_eq!(session.lock().unwrap().deadline, deadline + minutes(5));
+
+        session.lock().unwrap().unlock_hash = Some("hash".to_string());
+        assert!(controls.send(&Request::Stop).is_err());
+        session.lock().unwrap().unlock_hash = None;
+        controls.send(&Request::Stop).unwrap();
+        assert_eq!(
+            session.lock().unwrap().remaining(SystemTime::now()),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_foreground_controls_save_the_session() {
+        let now = SystemTime::now();
+        let session = Arc::new(Mutex::new(session(now)));
+        let deadline = session.lock().unwrap().deadline;
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("foreground.json");
+        let controls = ForegroundControls::new(session.clone()).with_state_file(state_file.clone());
+
+        controls.send(&Request::Extend { minutes: 5 }).unwrap();
+        let saved: Value =
+            serde_json::from_str(&std::fs::read_to_string(&state_file).unwrap()).unwrap();
+        assert_eq!(saved["deadline"], unix_seconds(deadline + minutes(5)));
+
+        controls.send(&Request::Stop).unwrap();
+        assert!(!state_file.exists(), "A stopped session is not shown");
+    }
+
+    #[test]
+    fn test_foreground_controls_forward_to_the_prompt() {
+        let now = SystemTime::now();
+        let session = Arc::new(Mutex::new(session(now)));
+        let (mut reader, writer) = std::io::pipe().unwrap();
+        let controls = ForegroundControls::new(session.clone()).with_input(Box::new(writer));
+
+        session.lock().unwrap().unlock_hash = Some("hash".to_string());
+        controls
+            .send(&Request::Unlock {
+                code: "ABCD-EFGH".to_string(),
+            })
+            .unwrap();
+        assert!(controls.send(&Request::Stop).is_err());
+        assert!(
+            controls.send(&Request::Extend { minutes: 5 }).is_err(),
+            "The prompt waits for the deadline it started with"
+        );
+
+        let mut session = session.lock().unwrap();
+        session.unlock_hash = None;
+        session.friction = Some(Friction {
+            cooldown: minutes(5),
+            challenge_words: 20,
+        });
+        drop(session);
+        controls.send(&Request::Stop).unwrap();
+        controls
+            .send(&Request::Answer {
+                text: "some words".to_string(),
+            })
+            .unwrap();
+        controls.send(&Request::Cancel).unwrap();
+        drop(controls);
+
+        let mut forwarded = String::new();
+        std::io::Read::read_to_string(&mut reader, &mut forwarded).unwrap();
+        assert_eq!(forwarded, "ABCD-EFGH\nstop\nsome words\ncancel\n");
+    }
+}
@@ -0,0 +1,188 @@
+use std::io::{BufRead, Write};
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// The width used when the size of the terminal can't be read.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Switches to the alternate screen and hides the cursor.
+const ENTER_SCREEN: &str = "\x1b[?1049h\x1b[?25l";
+/// Shows the cursor and goes back to the main screen.
+const LEAVE_SCREEN: &str = "\x1b[?25h\x1b[?1049l";
+/// Moves the cursor to the top left corner and clears the screen.
+const CLEAR_SCREEN: &str = "\x1b[H\x1b[J";
+/// How often a prompt checks whether it should give up.
+const PROMPT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Returns whether `fd` is a terminal, e.g. `libc::STDOUT_FILENO`.
+pub fn is_tty(fd: RawFd) -> bool {
+    // SAFETY: isatty only reads the file descriptor
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+/// Returns the number of columns of the terminal of stdout.
+pub fn width() -> usize {
+    // SAFETY: winsize is plain old data, filled by the ioctl on success
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: TIOCGWINSZ only writes the winsize given
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result == 0 && size.ws_col > 0 {
+        size.ws_col as usize
+    } else {
+        DEFAULT_WIDTH
+    }
+}
+
+/// The terminal of stdin in raw mode on the alternate screen, restored when dropped.
+///
+/// Keys are read one at a time without echo. The output processing is kept, so
+/// `\n` still starts a new line.
+pub struct RawScreen {
+    original: libc::termios,
+}
+
+impl RawScreen {
+    /// Enters raw mode and the alternate screen.
+    ///
+    /// # Returns
+    ///
+    /// The guard restoring the terminal, or an error if stdin is not a terminal
+    pub fn enter() -> Result<RawScreen, std::io::Error> {
+        // SAFETY: termios is plain old data, filled by tcgetattr on success
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: tcgetattr only writes the termios given
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let screen = RawScreen { original };
+        screen.raw()?;
+        print!("{}", ENTER_SCREEN);
+        std::io::stdout().flush()?;
+        Ok(screen)
+    }
+
+    /// Replaces the screen with `lines`.
+    pub fn draw(&self, lines: &[String]) -> Result<(), std::io::Error> {
+        let mut stdout = std::io::stdout().lock();
+        write!(stdout, "{}{}", CLEAR_SCREEN, lines.join("\n"))?;
+        stdout.flush()
+    }
+
+    /// Waits up to `timeout` for a key press.
+    ///
+    /// # Returns
+    ///
+    /// The key, `None` if none was pressed in time
+    pub fn read_key(&self, timeout: Duration) -> Result<Option<char>, std::io::Error> {
+        if !wait_for_input(timeout)? {
+            return Ok(None);
+        }
+        let mut key = [0u8; 1];
+        // SAFETY: read writes at most one byte to the buffer
+        let read = unsafe { libc::read(libc::STDIN_FILENO, key.as_mut_ptr().cast(), 1) };
+        Ok((read == 1).then_some(key[0] as char))
+    }
+
+    /// Asks for a line of text below the screen, echoed as it is typed.
+    ///
+    /// # Arguments
+    ///
+    /// * `question` - Shown before the answer
+    /// * `done` - Gives up waiting for the answer once set, e.g. at the end of the
+    ///   session
+    ///
+    /// # Returns
+    ///
+    /// The answer, `None` if `done` was set before it was typed
+    pub fn prompt(
+        &self,
+        question: &str,
+        done: &AtomicBool,
+    ) -> Result<Option<String>, std::io::Error> {
+        self.cooked()?;
+        print!("\n\x1b[?25h{} ", question);
+        std::io::stdout().flush()?;
+        let answer = read_answer(done);
+        print!("\x1b[?25l");
+        self.raw()?;
+        answer
+    }
+
+    fn raw(&self) -> Result<(), std::io::Error> {
+        let mut raw = self.original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        self.set(&raw)
+    }
+
+    fn cooked(&self) -> Result<(), std::io::Error> {
+        self.set(&self.original)
+    }
+
+    fn set(&self, termios: &libc::termios) -> Result<(), std::io::Error> {
+        // SAFETY: tcsetattr only reads the termios given
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Reads a line of stdin in canonical mode, polling so that `done` is noticed.
+fn read_answer(done: &AtomicBool) -> Result<Option<String>, std::io::Error> {
+    while !done.load(Ordering::Relaxed) {
+        // The terminal only reports stdin readable once a whole line was typed
+        if wait_for_input(PROMPT_POLL_INTERVAL)? {
+            let mut answer = String::new();
+            std::io::stdin().lock().read_line(&mut answer)?;
+            return Ok(Some(answer.trim().to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// Waits up to `timeout` for stdin to be readable.
+fn wait_for_input(timeout: Duration) -> Result<bool, std::io::Error> {
+    let mut poll = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: poll reads and writes the single pollfd given
+    let ready = unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) };
+    match ready {
+        -1 if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {
+            Ok(false)
+        }
+        -1 => Err(std::io::Error::last_os_error()),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+impl Drop for RawScreen {
+    fn drop(&mut self) {
+        let _ = self.cooked();
+        print!("{}", LEAVE_SCREEN);
+        let _ = std::io::stdout().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipes_are_not_terminals() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        assert!(!is_tty(fds[0]));
+        assert!(!is_tty(fds[1]));
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::process::Command;
+
+/// Runs the external commands of a session, e.g. to flush the DNS cache.
+///
+/// Tests record the commands instead of running them.
+pub trait CommandRunner: Send + Sync {
+    /// Runs `program` with `arguments` and waits for it to exit.
+    ///
+    /// # Returns
+    ///
+    /// An error when the program can't be started or exits with a failure status
+    fn run(&self, program: &str, arguments: &[&str]) -> Result<(), std::io::Error>;
+}
+
+/// Runs the commands on the system.
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, arguments: &[&str]) -> Result<(), std::io::Error> {
+        let output = Command::new(program).args(arguments).output()?;
+        if output.status.success() {
+            return Ok(());
+        }
+        Err(std::io::Error::other(format!(
+            "{} {} failed: {}",
+            program,
+            arguments.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// Records the commands instead of running them, failing on demand.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingRunner {
+    calls: std::sync::Mutex<Vec<String>>,
+    fail: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(test)]
+impl RecordingRunner {
+    /// The commands run so far, with their arguments.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Makes the following commands fail to start, or succeed again.
+    pub fn fail(&self, fail: bool) {
+        self.fail.store(fail, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+impl CommandRunner for RecordingRunner {
+    fn run(&self, program: &str, arguments: &[&str]) -> Result<(), std::io::Error> {
+        let mut command = vec![program];
+        command.extend(arguments);
+        self.calls.lock().unwrap().push(command.join(" "));
+        if self.fail.load(std::sync::atomic::Ordering::Relaxed) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("injected failure of {}", program),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_runner() {
+        assert!(SystemRunner.run("true", &[]).is_ok());
+        assert!(SystemRunner.run("false", &[]).is_err());
+        assert!(SystemRunner
+            .run("rust_can_make_you_focus_missing_program", &[])
+            .is_err());
+    }
+
+    #[test]
+    fn test_recording_runner() {
+        let runner = RecordingRunner::default();
+        runner.run("dscacheutil", &["-flushcache"]).unwrap();
+        runner.fail(true);
+        assert!(runner.run("killall", &["-HUP", "mDNSResponder"]).is_err());
+        assert_eq!(
+            runner.calls(),
+            vec!["dscacheutil -flushcache", "killall -HUP mDNSResponder"]
+        );
+    }
+}